@@ -0,0 +1,42 @@
+#![no_main]
+
+use aegis_rs::{
+    otp::{calculate_remaining_time, generate_otp_at},
+    vault::{parse_untrusted, DecryptOptions, PasswordGetter, UntrustedLimits},
+};
+use color_eyre::eyre::Result;
+use libfuzzer_sys::fuzz_target;
+
+/// Password of the encrypted seed in the corpus
+struct CorpusPassword;
+
+impl PasswordGetter for CorpusPassword {
+    fn get_password(&self) -> Result<String> {
+        Ok("test".to_string())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(contents) = std::str::from_utf8(data) else {
+        return;
+    };
+    let options = DecryptOptions {
+        kdf_threads: Some(1),
+        ..Default::default()
+    };
+    // Small enough for the scrypt parameters of the corpus, fuzzing slows down a lot otherwise
+    let limits = UntrustedLimits {
+        max_kdf_memory: 1 << 20,
+        ..Default::default()
+    };
+    let Ok(db) = parse_untrusted(contents, CorpusPassword, &options, &limits) else {
+        return;
+    };
+    // Entries which passed validation must not panic when used
+    for entry in db.into_lazy_entries() {
+        if let Ok(entry) = entry.deserialize() {
+            let _ = generate_otp_at(&entry.info, 0);
+            let _ = calculate_remaining_time(&entry.info);
+        }
+    }
+});
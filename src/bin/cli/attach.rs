@@ -0,0 +1,191 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::{fs, path::Path, path::PathBuf};
+
+use aegis_rs::vault::{
+    guess_mime, write_atomic, write_vault, Attachment, Database, MasterKey, Plan,
+};
+
+use crate::debug::find_entry;
+
+#[derive(Subcommand)]
+pub enum AttachCommand {
+    /// Attach a file to an entry
+    Add(AddArgs),
+    /// List the attachments of an entry
+    List(EntryArgs),
+    /// Write an attachment to a file, or to stdout
+    Get(GetArgs),
+    /// Remove an attachment from an entry
+    Remove(NameArgs),
+}
+
+#[derive(Args)]
+pub struct EntryArgs {
+    /// Issuer or name of the entry, or "uuid:<uuid>"
+    entry: String,
+}
+
+#[derive(Args)]
+pub struct AddArgs {
+    #[clap(flatten)]
+    entry: EntryArgs,
+    /// File to attach
+    file: PathBuf,
+    /// Name of the attachment [default: file name]
+    #[clap(long)]
+    name: Option<String>,
+    /// MIME type [default: guessed from the name]
+    #[clap(long)]
+    mime: Option<String>,
+}
+
+#[derive(Args)]
+pub struct NameArgs {
+    #[clap(flatten)]
+    entry: EntryArgs,
+    /// Name of the attachment
+    name: String,
+}
+
+#[derive(Args)]
+pub struct GetArgs {
+    #[clap(flatten)]
+    attachment: NameArgs,
+    /// Output file
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Vault file the database was read from
+pub struct Target<'a> {
+    pub path: &'a Path,
+    pub contents: &'a str,
+    pub master_key: Option<&'a MasterKey>,
+    /// Print the planned changes instead of writing them
+    pub dry_run: bool,
+    /// Print the planned changes of a dry run in JSON
+    pub json: bool,
+}
+
+impl Target<'_> {
+    /// Apply `plan` to `db` and write the vault, returns false if it was a dry run
+    ///
+    /// A dry run prints the changes instead, the vault file is left as it is.
+    pub fn commit(&self, mut db: Database, plan: Plan) -> Result<bool> {
+        if self.dry_run {
+            if self.json {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            } else {
+                for op in plan.ops() {
+                    println!("{}", op);
+                }
+                println!("Dry run, {} changes not written", plan.ops().len());
+            }
+            return Ok(false);
+        }
+        if plan.is_empty() {
+            return Ok(true);
+        }
+        db.apply(plan)?;
+        let contents = write_vault(self.contents, &db, self.master_key)?;
+        write_atomic(self.path, contents.as_bytes())?;
+        Ok(true)
+    }
+}
+
+pub fn run(command: &AttachCommand, db: Database, vault: &Target) -> Result<()> {
+    let query = match command {
+        AttachCommand::Add(args) => &args.entry.entry,
+        AttachCommand::List(args) => &args.entry,
+        AttachCommand::Get(args) => &args.attachment.entry.entry,
+        AttachCommand::Remove(args) => &args.entry.entry,
+    };
+    let position = {
+        let entry = find_entry(db.entries(), query)?;
+        db.entries()
+            .iter()
+            .position(|e| std::ptr::eq(e, entry))
+            .ok_or(eyre!("Entry not found"))?
+    };
+    let entry = &db.entries()[position];
+    let label = format!("{} ({})", entry.issuer().trim(), entry.name().trim());
+    let mut attachments = entry.attachments()?;
+
+    match command {
+        AttachCommand::List(_) => {
+            if attachments.is_empty() {
+                println!("{} has no attachments", label);
+            }
+            for attachment in &attachments {
+                println!(
+                    "{}  {}  {} bytes",
+                    attachment.name,
+                    attachment.mime,
+                    attachment.data.len()
+                );
+            }
+            return Ok(());
+        }
+        AttachCommand::Get(args) => {
+            let attachment = attachments
+                .iter()
+                .find(|a| a.name == args.attachment.name)
+                .ok_or(eyre!(
+                    "{} has no attachment {}",
+                    label,
+                    args.attachment.name
+                ))?;
+            match &args.output {
+                Some(output) => fs::write(output, &attachment.data)
+                    .map_err(|e| eyre!("Failed to write {}: {}", output.display(), e))?,
+                None => std::io::Write::write_all(&mut std::io::stdout(), &attachment.data)?,
+            }
+            return Ok(());
+        }
+        AttachCommand::Add(args) => {
+            let name = match &args.name {
+                Some(name) => name.clone(),
+                None => args
+                    .file
+                    .file_name()
+                    .ok_or(eyre!("{} is not a file", args.file.display()))?
+                    .to_string_lossy()
+                    .to_string(),
+            };
+            if attachments.iter().any(|a| a.name == name) {
+                return Err(eyre!("{} already has an attachment {}", label, name));
+            }
+            let data = fs::read(&args.file)
+                .map_err(|e| eyre!("Failed to read {}: {}", args.file.display(), e))?;
+            let mime = args
+                .mime
+                .clone()
+                .unwrap_or_else(|| guess_mime(&name).to_string());
+            attachments.push(Attachment { name, mime, data });
+        }
+        AttachCommand::Remove(args) => {
+            let before = attachments.len();
+            attachments.retain(|a| a.name != args.name);
+            if attachments.len() == before {
+                return Err(eyre!("{} has no attachment {}", label, args.name));
+            }
+        }
+    }
+
+    let added = attachments
+        .last()
+        .map(|a| a.name.clone())
+        .unwrap_or_default();
+    let mut plan = Plan::default();
+    plan.set_attachments(entry, attachments)?;
+    if !vault.commit(db, plan)? {
+        return Ok(());
+    }
+    match command {
+        AttachCommand::Add(_) => println!("Attached {} to {}", added, label),
+        AttachCommand::Remove(args) => println!("Removed {} from {}", args.name, label),
+        _ => {}
+    }
+    Ok(())
+}
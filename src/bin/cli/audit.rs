@@ -0,0 +1,311 @@
+use clap::Subcommand;
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use aegis_rs::vault::{create_private_dir, AuditLog, AuditRecord, LazyEntry, MasterKey};
+
+use crate::{config, interrupt, notify, theme};
+
+#[derive(Subcommand)]
+pub enum AuditCommand {
+    /// Print the recorded codes, oldest first
+    Show,
+    /// Remove old records
+    Prune {
+        /// Age of the records to remove, like "90d", "12h" or "30m"
+        #[clap(long, value_parser = parse_age)]
+        older_than: Duration,
+    },
+}
+
+/// Parse an age like `parse_timeout`, which also accepts days
+fn parse_age(input: &str) -> Result<Duration, String> {
+    match input.trim().strip_suffix('d') {
+        Some(days) => days
+            .parse::<u64>()
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+            .map_err(|_| format!("Invalid age \"{}\"", input)),
+        None => interrupt::parse_timeout(input),
+    }
+}
+
+/// Audit log of the vault with `master_key`
+pub fn log_path(master_key: Option<&MasterKey>) -> Result<PathBuf> {
+    let name = match master_key {
+        Some(master_key) => master_key.id(),
+        None => "plain".to_string(),
+    };
+    let dir = config::data_dir()?.join("audit");
+    create_private_dir(&dir)?;
+    Ok(dir.join(format!("{}.log", name)))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// UTC date and time of `time` seconds since the epoch, like "2024-01-31 12:00:00Z"
+pub fn format_time(time: u64) -> String {
+    let (days, seconds) = (time / 86400, time % 86400);
+    // Civil date from days since the epoch, by Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Most codes of an entry shown a minute, to slow down a process harvesting codes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Case insensitive part of the issuer, every entry if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Codes of each matching entry a minute
+    pub per_minute: usize,
+}
+
+impl RateLimit {
+    fn matches(&self, entry: &LazyEntry) -> bool {
+        self.issuer.as_ref().is_none_or(|issuer| {
+            entry
+                .issuer()
+                .to_lowercase()
+                .contains(&issuer.to_lowercase())
+        })
+    }
+}
+
+/// Records generated codes if the audit log is enabled
+pub struct Recorder<'a> {
+    log: Option<AuditLog<'a>>,
+    limits: &'a [RateLimit],
+}
+
+impl<'a> Recorder<'a> {
+    /// Recorder of the vault with `master_key`, rate `limits` are counted in its log
+    pub fn new(
+        enabled: bool,
+        master_key: Option<&'a MasterKey>,
+        limits: &'a [RateLimit],
+    ) -> Result<Recorder<'a>> {
+        if !enabled && !limits.is_empty() {
+            return Err(eyre!(
+                "The rate_limits config setting counts the codes in the audit log, set audit_log \
+                as well and don't pass --no-cache"
+            ));
+        }
+        let log = match enabled {
+            true => Some(AuditLog::new(&log_path(master_key)?, master_key)),
+            false => None,
+        };
+        Ok(Recorder { log, limits })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.log.is_some()
+    }
+
+    /// Times codes of `entry` were recorded, by its UUID or else its issuer and name
+    fn times(&self, entry: &LazyEntry) -> Result<Vec<u64>> {
+        let Some(log) = &self.log else {
+            return Ok(Vec::new());
+        };
+        Ok(log
+            .read()?
+            .iter()
+            .filter(|record| match (entry.uuid(), &record.uuid) {
+                (Some(uuid), Some(recorded)) => uuid == recorded,
+                _ => record.issuer == entry.issuer() && record.name == entry.name(),
+            })
+            .map(|record| record.time)
+            .collect())
+    }
+
+    /// Time a code of `entry` was last recorded
+    pub fn last_use(&self, entry: &LazyEntry) -> Result<Option<u64>> {
+        Ok(self.times(entry)?.into_iter().max())
+    }
+
+    /// Refuse a code of `entry` if the strictest of its rate limits is reached
+    ///
+    /// The user is told with a desktop notification as well, as the refusal may only be seen by
+    /// the process asking for the code.
+    pub fn check(&self, entry: &LazyEntry) -> Result<()> {
+        self.check_codes(entry, 1)
+    }
+
+    /// Refuse `count` codes of `entry` at once if they would exceed its rate limit, see
+    /// [Recorder::check]
+    pub fn check_codes(&self, entry: &LazyEntry, count: usize) -> Result<()> {
+        match self.exceeded(entry, count)? {
+            Some(message) => {
+                if let Err(e) = notify::desktop("aegis-rs rate limit reached", &message) {
+                    eprintln!("{}", e);
+                }
+                Err(eyre!(message))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Why `count` more codes of `entry` would exceed its rate limit, if they would
+    fn exceeded(&self, entry: &LazyEntry, count: usize) -> Result<Option<String>> {
+        let Some(limit) = self
+            .limits
+            .iter()
+            .filter(|limit| limit.matches(entry))
+            .map(|limit| limit.per_minute)
+            .min()
+        else {
+            return Ok(None);
+        };
+        let since = now().saturating_sub(60);
+        let recent = self
+            .times(entry)?
+            .into_iter()
+            .filter(|time| *time > since)
+            .count();
+        Ok((recent + count > limit).then(|| {
+            format!(
+                "{} codes of {} ({}) in the last minute, {} more would exceed the rate limit of {}",
+                recent,
+                entry.issuer().trim(),
+                entry.name().trim(),
+                count,
+                limit
+            )
+        }))
+    }
+
+    /// Record a code of `entry` shown by `frontend`, failures are only reported
+    pub fn record(&self, entry: &LazyEntry, frontend: &str) {
+        if let Some(log) = &self.log {
+            let record = AuditRecord {
+                time: now(),
+                uuid: entry.uuid().map(str::to_string),
+                issuer: entry.issuer().to_string(),
+                name: entry.name().to_string(),
+                frontend: frontend.to_string(),
+            };
+            if let Err(e) = log.append(&record) {
+                eprintln!("Failed to record the code in the audit log: {}", e);
+            }
+        }
+    }
+}
+
+pub fn run(command: &AuditCommand, master_key: Option<&MasterKey>) -> Result<()> {
+    let log = AuditLog::new(&log_path(master_key)?, master_key);
+    match command {
+        AuditCommand::Show => {
+            let records = log.read()?;
+            if records.is_empty() {
+                println!("No codes recorded, the audit_log config setting enables recording");
+            }
+            for record in records {
+                println!(
+                    "{}  {:<12} {} ({})",
+                    theme::current().dim.apply_to(format_time(record.time)),
+                    record.frontend,
+                    record.issuer.trim(),
+                    record.name.trim()
+                );
+            }
+        }
+        AuditCommand::Prune { older_than } => {
+            let removed = log.prune(now().saturating_sub(older_than.as_secs()))?;
+            println!("Removed {} records", removed);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use aegis_rs::vault::{AuditLog, AuditRecord, DecryptOptions, Vault};
+    use color_eyre::eyre::Result;
+
+    use super::{now, RateLimit, Recorder};
+
+    #[test]
+    fn rate_limits() {
+        let db = Vault::parse(include_str!("../../../res/aegis_plain.json"))
+            .unwrap()
+            .unlock(
+                || Result::<String>::Ok(String::new()),
+                &DecryptOptions::default(),
+            )
+            .unwrap();
+        let (deno, spdx) = (&db.entries()[0], &db.entries()[1]);
+        let path = env::temp_dir().join(format!("aegis-rs-rate-{}.log", std::process::id()));
+        let limits = [
+            RateLimit {
+                issuer: None,
+                per_minute: 3,
+            },
+            RateLimit {
+                issuer: Some("deno".to_string()),
+                per_minute: 2,
+            },
+        ];
+        let recorder = Recorder {
+            log: Some(AuditLog::new(&path, None)),
+            limits: &limits,
+        };
+        // Codes of over a minute ago don't count
+        let old = AuditRecord {
+            time: now() - 120,
+            uuid: deno.uuid().map(str::to_string),
+            issuer: deno.issuer().to_string(),
+            name: deno.name().to_string(),
+            frontend: "cli list".to_string(),
+        };
+        AuditLog::new(&path, None).append(&old).unwrap();
+        AuditLog::new(&path, None).append(&old).unwrap();
+
+        recorder.record(deno, "cli list");
+        assert!(recorder.exceeded(deno, 1).unwrap().is_none());
+        recorder.record(deno, "cli list");
+        // The stricter limit of the issuer applies, other entries are counted on their own
+        assert!(recorder.exceeded(deno, 1).unwrap().is_some());
+        recorder.record(spdx, "cli list");
+        recorder.record(spdx, "cli list");
+        assert!(recorder.exceeded(spdx, 1).unwrap().is_none());
+        recorder.record(spdx, "cli list");
+        assert!(recorder.check(deno).is_err());
+        // Several codes at once count all together
+        assert!(recorder.exceeded(&db.entries()[3], 3).unwrap().is_none());
+        assert!(recorder.exceeded(&db.entries()[3], 4).unwrap().is_some());
+        assert!(recorder
+            .exceeded(spdx, 1)
+            .unwrap()
+            .unwrap()
+            .starts_with("3 codes of SPDX (James)"));
+        fs::remove_file(&path).unwrap();
+    }
+}
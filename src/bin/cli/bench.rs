@@ -0,0 +1,194 @@
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::time::{Duration, Instant};
+
+use aegis_rs::{
+    otp::generate_otp,
+    vault::{aes_backend, scrypt_key, DecryptOptions, PasswordGetter, Vault},
+};
+
+/// scrypt parameters of password slots created by Aegis
+const AEGIS_SCRYPT_N: u32 = 1 << 15;
+const AEGIS_SCRYPT_R: u32 = 8;
+const AEGIS_SCRYPT_P: u32 = 1;
+
+#[derive(Subcommand)]
+pub enum BenchCommand {
+    /// Measure the throughput of the vault database decryption steps
+    Crypto(CryptoArgs),
+    /// Measure every step from parsing a vault to generating its codes
+    All(AllArgs),
+}
+
+#[derive(Args)]
+pub struct CryptoArgs {
+    /// Size of the synthetic database in MiB
+    #[clap(long, default_value_t = 8)]
+    size: usize,
+    /// Number of measurements, the fastest one is reported
+    #[clap(long, default_value_t = 5)]
+    iterations: u32,
+}
+
+#[derive(Args)]
+pub struct AllArgs {
+    #[clap(flatten)]
+    crypto: CryptoArgs,
+    /// Number of entries in the synthetic vault
+    #[clap(long, default_value_t = 1000)]
+    entries: usize,
+}
+
+pub fn run(command: &BenchCommand) -> Result<()> {
+    match command {
+        BenchCommand::Crypto(args) => bench_crypto(args),
+        BenchCommand::All(args) => bench_all(args),
+    }
+}
+
+/// Run `f` `iterations` times and return the fastest duration
+pub fn measure<T>(iterations: u32, mut f: impl FnMut() -> Result<T>) -> Result<Duration> {
+    let mut fastest = Duration::MAX;
+    for _ in 0..iterations.max(1) {
+        let start = Instant::now();
+        f()?;
+        fastest = fastest.min(start.elapsed());
+    }
+    Ok(fastest)
+}
+
+/// Print a measurement as the rate of `count` operations
+pub fn print_rate(name: &str, count: usize, unit: &str, duration: Duration) {
+    println!(
+        "{:<24} {:>10.0} {}/s ({:.2?})",
+        name,
+        count as f64 / duration.as_secs_f64(),
+        unit,
+        duration
+    );
+}
+
+/// Print a measurement as throughput of `bytes`
+pub fn print_throughput(name: &str, bytes: usize, duration: Duration) {
+    let mib = bytes as f64 / (1 << 20) as f64;
+    println!(
+        "{:<24} {:>10.1} MiB/s ({:.2?})",
+        name,
+        mib / duration.as_secs_f64(),
+        duration
+    );
+}
+
+fn bench_crypto(args: &CryptoArgs) -> Result<()> {
+    let size = args.size << 20;
+    // Base64 encoded JSON database is what an encrypted vault contains
+    let plaintext: Vec<u8> = (0..size).map(|i| b"{\"entries\":[]}"[i % 14]).collect();
+    let key = [0x42u8; 32];
+    let nonce = [0x24u8; 12];
+    let cipher = Aes256Gcm::new(&key.into());
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|e| eyre!("Failed to encrypt: {}", e))?;
+    let encoded = general_purpose::STANDARD.encode(&ciphertext);
+
+    println!("AES implementation: {}", aes_backend());
+    println!("Database size: {} MiB", args.size);
+
+    let duration = measure(args.iterations, || {
+        general_purpose::STANDARD
+            .decode(&encoded)
+            .map_err(|e| eyre!("Failed to decode: {}", e))
+    })?;
+    print_throughput("Base64 decode", size, duration);
+
+    let duration = measure(args.iterations, || {
+        cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|e| eyre!("Failed to decrypt: {}", e))
+    })?;
+    print_throughput("AES-256-GCM decrypt", size, duration);
+
+    Ok(())
+}
+
+/// Plain vault with `count` TOTP entries using all hash algorithms
+fn synthetic_vault(count: usize) -> String {
+    let entries: Vec<String> = (0..count)
+        .map(|i| {
+            format!(
+                r#"{{"type":"totp","uuid":"00000000-0000-4000-8000-{:012}","name":"user{}@example.com","issuer":"Issuer {}","note":"","favorite":false,"icon":null,"info":{{"secret":"JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP","algo":"{}","digits":6,"period":30}}}}"#,
+                i,
+                i,
+                i,
+                ["SHA1", "SHA256", "SHA512"][i % 3]
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"version":1,"header":{{"slots":null,"params":null}},"db":{{"version":2,"entries":[{}]}}}}"#,
+        entries.join(",")
+    )
+}
+
+fn bench_all(args: &AllArgs) -> Result<()> {
+    let iterations = args.crypto.iterations;
+    let contents = synthetic_vault(args.entries);
+    println!(
+        "Vault: {} entries, {} KiB",
+        args.entries,
+        contents.len() >> 10
+    );
+
+    let duration = measure(iterations, || {
+        Vault::parse(&contents)?.unlock(NoPassword, &DecryptOptions::default())
+    })?;
+    print_rate("Parse", args.entries, "entries", duration);
+
+    let db = Vault::parse(&contents)?.unlock(NoPassword, &DecryptOptions::default())?;
+    let duration = measure(iterations, || {
+        db.entries()
+            .iter()
+            .map(|e| e.deserialize())
+            .collect::<Result<Vec<_>>>()
+    })?;
+    print_rate("Deserialize entries", args.entries, "entries", duration);
+
+    let entries = db.into_entries()?;
+    let duration = measure(iterations, || {
+        entries
+            .iter()
+            .map(|e| generate_otp(&e.info))
+            .collect::<Result<Vec<String>>>()
+    })?;
+    print_rate("Generate codes", args.entries, "codes", duration);
+
+    // Key derivation is slow by design, a single measurement suffices
+    let options = DecryptOptions::default();
+    let duration = measure(1, || {
+        scrypt_key(
+            b"password",
+            &[0u8; 32],
+            AEGIS_SCRYPT_N,
+            AEGIS_SCRYPT_R,
+            AEGIS_SCRYPT_P,
+            &options,
+        )
+    })?;
+    println!(
+        "{:<24} {:>10.2?} (scrypt N={} r={} p={})",
+        "Key derivation", duration, AEGIS_SCRYPT_N, AEGIS_SCRYPT_R, AEGIS_SCRYPT_P
+    );
+
+    bench_crypto(&args.crypto)
+}
+
+/// The synthetic vault is in plain text
+struct NoPassword;
+
+impl PasswordGetter for NoPassword {
+    fn get_password(&self) -> Result<String> {
+        Err(eyre!("The synthetic vault is not encrypted"))
+    }
+}
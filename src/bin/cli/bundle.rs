@@ -0,0 +1,165 @@
+use base64::{engine::general_purpose, Engine as _};
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use dialoguer::Password;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use aegis_rs::vault::{seal, unseal, write_atomic, DecryptOptions, PasswordGetter};
+
+use crate::{
+    config::{self, Config},
+    interrupt, theme,
+};
+
+/// Directories of the data directory which are moved along with the config
+const BUNDLED_DIRS: &[&str] = &["recovery"];
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Write the config and recovery codes to a password encrypted file
+    Export(ExportArgs),
+    /// Restore the config and recovery codes from an exported file
+    Import(ImportArgs),
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// File to write
+    #[clap(short, long)]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// File written by `config export`
+    bundle: PathBuf,
+    /// Replace an existing config file and recovery codes
+    #[clap(long)]
+    force: bool,
+}
+
+/// Contents of the file, before encryption
+#[derive(Deserialize, Serialize)]
+struct Bundle {
+    config: Config,
+    /// Files of the data directory by their relative path, base64 encoded
+    files: BTreeMap<String, String>,
+}
+
+/// Password of the file, asked for when importing
+struct BundlePassword;
+
+impl PasswordGetter for BundlePassword {
+    fn get_password(&self) -> Result<String> {
+        interrupt::prompt(|| {
+            Password::with_theme(theme::dialog())
+                .with_prompt("Password of the exported config")
+                .interact()
+                .map_err(|e| eyre!("Failed to read the password: {}", e))
+        })
+    }
+}
+
+pub fn run(
+    command: &ConfigCommand,
+    config: Config,
+    config_path: &Path,
+    options: &DecryptOptions,
+) -> Result<()> {
+    match command {
+        ConfigCommand::Export(args) => export(args, config, options),
+        ConfigCommand::Import(args) => import(args, config_path, options),
+    }
+}
+
+fn export(args: &ExportArgs, config: Config, options: &DecryptOptions) -> Result<()> {
+    let data_dir = config::data_dir()?;
+    let mut files = BTreeMap::new();
+    for dir in BUNDLED_DIRS {
+        let entries = match fs::read_dir(data_dir.join(dir)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+                files.insert(
+                    name,
+                    general_purpose::STANDARD.encode(fs::read(entry.path())?),
+                );
+            }
+        }
+    }
+
+    let password = interrupt::prompt(|| {
+        Password::with_theme(theme::dialog())
+            .with_prompt("Password for the exported config")
+            .with_confirmation("Repeat the password", "The passwords don't match")
+            .interact()
+    })?;
+    let count = files.len();
+    let bundle = serde_json::to_vec(&Bundle { config, files })?;
+    write_atomic(&args.output, seal(&password, &bundle, options)?.as_bytes())?;
+    println!(
+        "Exported the config and {} files to {}",
+        count,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn import(args: &ImportArgs, config_path: &Path, options: &DecryptOptions) -> Result<()> {
+    let contents = fs::read_to_string(&args.bundle)
+        .map_err(|e| eyre!("Failed to read {}: {}", args.bundle.display(), e))?;
+    let data = unseal(&contents, BundlePassword, options)?;
+    let bundle: Bundle = serde_json::from_slice(&data)?;
+
+    // Everything is checked before anything is written
+    let data_dir = config::data_dir()?;
+    let mut files = Vec::new();
+    for (name, contents) in &bundle.files {
+        let relative = Path::new(name);
+        let bundled = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+            && BUNDLED_DIRS.iter().any(|dir| relative.starts_with(dir));
+        if !bundled {
+            return Err(eyre!("Unexpected file {} in the exported config", name));
+        }
+        let contents = general_purpose::STANDARD.decode(contents)?;
+        let path = data_dir.join(relative);
+        if !args.force && fs::read(&path).is_ok_and(|existing| existing != contents) {
+            return Err(eyre!(
+                "{} already exists, use --force to replace it",
+                path.display()
+            ));
+        }
+        files.push((path, contents));
+    }
+    if !args.force && config_path.exists() {
+        return Err(eyre!(
+            "{} already exists, use --force to replace it",
+            config_path.display()
+        ));
+    }
+
+    bundle.config.save(config_path)?;
+    for (path, contents) in &files {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(path, contents)?;
+    }
+    println!(
+        "Imported the config to {} and {} files",
+        config_path.display(),
+        files.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use serde_json::json;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use aegis_rs::vault::{DecryptOptions, PasswordGetter, Vault, VaultFile};
+
+use crate::notify::{self, Webhook};
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// Days after which the backup counts as stale
+    #[clap(long, default_value_t = 7)]
+    max_age_days: u64,
+    /// Print nothing if the backup is fine
+    #[clap(long)]
+    quiet: bool,
+    /// Show a desktop notification if the check fails
+    #[clap(long)]
+    notify: bool,
+    /// URL to POST a JSON event to if the check fails, e.g. a mail or chat gateway, signed like
+    /// the configured webhook
+    #[clap(long, env = "AEGIS_CHECK_WEBHOOK")]
+    webhook: Option<String>,
+    /// Key of the signature of the --webhook events [default: the secret of the configured
+    /// webhook]
+    #[clap(
+        long,
+        env = "AEGIS_CHECK_WEBHOOK_SECRET",
+        hide_env_values = true,
+        requires = "webhook"
+    )]
+    webhook_secret: Option<String>,
+}
+
+/// Check that the latest backup decrypts and isn't stale, for cron jobs and systemd timers
+///
+/// `vault_file` is the vault or a directory of backups, whose newest file is checked. Failures
+/// are reported as asked for by the arguments and to the configured webhook, returns whether the
+/// check passed.
+pub fn run(
+    args: &CheckArgs,
+    vault_file: &Path,
+    password: impl PasswordGetter,
+    options: &DecryptOptions,
+    configured_webhook: Option<&Webhook>,
+) -> Result<bool> {
+    let (backup, problem) = match check(args, vault_file, password, options) {
+        Ok((backup, summary)) => {
+            if !args.quiet {
+                println!("{}: {}", backup.display(), summary);
+            }
+            return Ok(true);
+        }
+        Err((backup, e)) => (backup, e.to_string()),
+    };
+    eprintln!("{}: {}", backup.display(), problem);
+    if args.notify {
+        if let Err(e) = notify::desktop("Aegis backup check failed", &problem) {
+            eprintln!("{}", e);
+        }
+    }
+    let fields = json!({ "backup": backup, "problem": problem });
+    for webhook in webhooks(args, configured_webhook) {
+        webhook.send("backup_check_failed", vault_file, fields.clone());
+    }
+    Ok(false)
+}
+
+/// The webhook of `--webhook` and the configured one, a URL given twice is only sent to once
+fn webhooks(args: &CheckArgs, configured_webhook: Option<&Webhook>) -> Vec<Webhook> {
+    let given = args.webhook.as_ref().map(|url| Webhook {
+        url: url.clone(),
+        secret: args
+            .webhook_secret
+            .clone()
+            .or_else(|| configured_webhook.and_then(|webhook| webhook.secret.clone())),
+    });
+    let configured = configured_webhook
+        .filter(|webhook| Some(&webhook.url) != args.webhook.as_ref())
+        .cloned();
+    given.into_iter().chain(configured).collect()
+}
+
+/// The checked backup with a summary, or with the problem found
+fn check(
+    args: &CheckArgs,
+    vault_file: &Path,
+    password: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<(PathBuf, String), (PathBuf, color_eyre::Report)> {
+    let failed = |e| (vault_file.to_path_buf(), e);
+    let backup = match vault_file.is_dir() {
+        true => latest_backup(vault_file).map_err(failed)?,
+        false => vault_file.to_path_buf(),
+    };
+    let failed = |e| (backup.clone(), e);
+
+    let age = fs::metadata(&backup)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| failed(eyre!("Failed to read the backup: {}", e)))?
+        .elapsed()
+        .unwrap_or_default();
+    let days = age.as_secs() / (24 * 60 * 60);
+    if age > Duration::from_secs(args.max_age_days * 24 * 60 * 60) {
+        return Err(failed(eyre!(
+            "The backup is {} days old, more than {}",
+            days,
+            args.max_age_days
+        )));
+    }
+    let contents = VaultFile::open(&backup, false)
+        .map_err(|e| failed(eyre!("Failed to read the backup: {}", e)))?
+        .decompressed()
+        .map_err(failed)?;
+    let db = contents
+        .as_str()
+        .and_then(Vault::parse)
+        .and_then(|vault| vault.unlock(password, options))
+        .map_err(|e| failed(eyre!("The backup doesn't decrypt: {}", e)))?;
+    Ok((
+        backup.clone(),
+        format!(
+            "decrypts, {} entries, written {} days ago",
+            db.entries().len(),
+            days
+        ),
+    ))
+}
+
+/// Newest JSON or gzipped JSON file in the directory, like the automatic backups of the app
+fn latest_backup(dir: &Path) -> Result<PathBuf> {
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_none_or(|extension| extension != "json" && extension != "gz")
+        {
+            continue;
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        if newest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            newest = Some((modified, path));
+        }
+    }
+    newest
+        .map(|(_, path)| path)
+        .ok_or(eyre!("No backups in {}", dir.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{webhooks, CheckArgs};
+    use crate::notify::Webhook;
+
+    #[test]
+    fn given_webhooks_are_signed() {
+        let args = |url: Option<&str>, secret: Option<&str>| CheckArgs {
+            max_age_days: 7,
+            quiet: false,
+            notify: false,
+            webhook: url.map(str::to_string),
+            webhook_secret: secret.map(str::to_string),
+        };
+        let configured = Webhook {
+            url: "https://alerts.example.com".to_string(),
+            secret: Some("configured".to_string()),
+        };
+        let sent = |args: &CheckArgs, configured: Option<&Webhook>| {
+            webhooks(args, configured)
+                .into_iter()
+                .map(|webhook| (webhook.url, webhook.secret))
+                .collect::<Vec<_>>()
+        };
+        let given = |secret: Option<&str>| ("https://mail".to_string(), secret.map(str::to_string));
+
+        // The flag's URL takes the configured secret unless it has its own
+        assert_eq!(
+            sent(&args(Some("https://mail"), None), Some(&configured)),
+            [
+                given(Some("configured")),
+                (configured.url.clone(), configured.secret.clone())
+            ]
+        );
+        assert_eq!(
+            sent(&args(Some("https://mail"), Some("own")), None),
+            [given(Some("own"))]
+        );
+        // Without any secret it stays unsigned, the configured URL is only sent to once
+        assert_eq!(sent(&args(Some("https://mail"), None), None), [given(None)]);
+        assert_eq!(
+            sent(&args(Some(&configured.url), None), Some(&configured)),
+            [(configured.url.clone(), configured.secret.clone())]
+        );
+        assert!(sent(&args(None, None), None).is_empty());
+    }
+}
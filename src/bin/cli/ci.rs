@@ -0,0 +1,140 @@
+use clap::Args;
+use color_eyre::eyre::{eyre, Report};
+use std::path::Path;
+
+use aegis_rs::{
+    otp::{generate_otp, EntryInfo},
+    vault::{DecryptOptions, PasswordGetter, Selector, Vault, VaultFile},
+};
+
+use crate::{audit, config::Config, PasswordInput};
+
+/// Exit code if no vault was given or it can't be read
+pub const EXIT_VAULT: i32 = 3;
+/// Exit code if the vault is encrypted and no password was given
+pub const EXIT_NO_PASSWORD: i32 = 4;
+/// Exit code if the password is wrong or the vault can't be decrypted
+pub const EXIT_UNLOCK: i32 = 5;
+/// Exit code if no entry has the UUID
+pub const EXIT_NO_ENTRY: i32 = 6;
+/// Exit code if the entry gives no code, like HOTP entries whose counter has to be written
+pub const EXIT_CODE: i32 = 7;
+/// Exit code if the config can't be read or a rate limit refuses the code
+pub const EXIT_RATE_LIMIT: i32 = 8;
+
+#[derive(Args)]
+pub struct CiArgs {
+    /// UUID of the entry to print the code of
+    #[clap(long)]
+    entry_uuid: String,
+    /// Contents of the vault, used instead of the vault file
+    #[clap(long, env = "AEGIS_VAULT", hide_env_values = true)]
+    vault_json: Option<String>,
+}
+
+/// Failure of `ci`, with the exit code of its kind
+struct Failure(i32, Report);
+
+/// Print the code of one entry and nothing else, returns the exit code
+///
+/// Nothing is ever asked for, the password source and vault of the config are not used, so a
+/// missing vault or password fails instead of waiting for a terminal that isn't there. Only the
+/// audit log and rate limits of the `config` apply, like for every other code.
+pub fn run(
+    args: &CiArgs,
+    vault_file: Option<&Path>,
+    password: PasswordInput,
+    options: &DecryptOptions,
+    config: Result<&Config, &Report>,
+) -> i32 {
+    match code(args, vault_file, password, options, config) {
+        Ok(code) => {
+            println!("{}", code);
+            0
+        }
+        Err(Failure(exit_code, e)) => {
+            eprintln!("{}", e);
+            exit_code
+        }
+    }
+}
+
+fn code(
+    args: &CiArgs,
+    vault_file: Option<&Path>,
+    mut password: PasswordInput,
+    options: &DecryptOptions,
+    config: Result<&Config, &Report>,
+) -> Result<String, Failure> {
+    let config = config.map_err(|e| Failure(EXIT_RATE_LIMIT, eyre!("{}", e)))?;
+    let vault_error = |e| Failure(EXIT_VAULT, e);
+    let contents = match (&args.vault_json, vault_file) {
+        (Some(contents), _) => VaultFile::Buffered(contents.clone().into_bytes()),
+        (None, Some(path)) if path == Path::new("-") => {
+            crate::read_stdin_vault(&mut password).map_err(vault_error)?
+        }
+        (None, Some(path)) => VaultFile::open(path, false).map_err(|e| {
+            Failure(
+                EXIT_VAULT,
+                eyre!("Failed to read {}: {}", path.display(), e),
+            )
+        })?,
+        (None, None) => {
+            return Err(Failure(
+                EXIT_VAULT,
+                eyre!("No vault, give the vault file or set AEGIS_VAULT_FILE or AEGIS_VAULT"),
+            ))
+        }
+    };
+    let vault = contents
+        .as_str()
+        .and_then(Vault::parse)
+        .map_err(vault_error)?;
+
+    let given =
+        password.password.is_some() || password.password_file.is_some() || password.password_stdin;
+    if vault.is_encrypted() && !given {
+        return Err(Failure(
+            EXIT_NO_PASSWORD,
+            eyre!("The vault is encrypted, set AEGIS_PASSWORD or AEGIS_PASSWORD_FILE"),
+        ));
+    }
+    let password = match vault.is_encrypted() {
+        true => Some(
+            password
+                .get_password()
+                .map_err(|e| Failure(EXIT_NO_PASSWORD, e))?,
+        ),
+        false => None,
+    };
+    let (db, master_key) = vault
+        .unlock_with_key(|| password.clone().ok_or(eyre!("No password")), options)
+        .map_err(|e| Failure(EXIT_UNLOCK, e))?;
+    let audit = audit::Recorder::new(config.audit_log, master_key.as_ref(), &config.rate_limits)
+        .map_err(|e| Failure(EXIT_RATE_LIMIT, e))?;
+
+    let lazy_entry = match db.find(&Selector::Uuid(args.entry_uuid.clone()))[..] {
+        [entry] => entry,
+        _ => {
+            return Err(Failure(
+                EXIT_NO_ENTRY,
+                eyre!("No entry with the UUID {}", args.entry_uuid),
+            ))
+        }
+    };
+    let entry = lazy_entry
+        .deserialize()
+        .map_err(|e| Failure(EXIT_CODE, e))?;
+    if let EntryInfo::Hotp(_) = entry.info {
+        return Err(Failure(
+            EXIT_CODE,
+            eyre!("HOTP codes need the counter written to the vault, use entry next-hotp"),
+        ));
+    }
+    audit
+        .check(lazy_entry)
+        .map_err(|e| Failure(EXIT_RATE_LIMIT, e))?;
+    let code = generate_otp(&entry.info).map_err(|e| Failure(EXIT_CODE, e))?;
+    audit.record(lazy_entry, "cli ci");
+    Ok(code)
+}
@@ -0,0 +1,268 @@
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use aegis_rs::{
+    otp::{FormatRule, Provider},
+    vault::{create_private_dir, write_private},
+};
+
+use crate::{audit::RateLimit, export::Profile, hooks::Hooks, notify::Webhook, theme::ThemeName};
+
+/// User configuration read from a JSON file
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Vault used when none is given on the command line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vault_file: Option<PathBuf>,
+    /// Vault opened instead when the password doesn't open the vault, for when forced to unlock
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoy_vault_file: Option<PathBuf>,
+    /// Where the password comes from when none is given on the command line
+    pub password_source: PasswordSource,
+    /// Copy generated codes to the clipboard
+    pub clipboard: bool,
+    /// Code formatting rules, tried before the built-in rules
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub format_rules: Vec<FormatRule>,
+    /// Providers for `entry add --well-known`, tried before the built-in ones
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<Provider>,
+    /// Commit every change of the vault file to a local git repository
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub history: bool,
+    /// Record every generated code in an encrypted log, shown by `audit-log show`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub audit_log: bool,
+    /// Most codes of an entry shown a minute, counted in the audit log
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rate_limits: Vec<RateLimit>,
+    /// Colors of prompts and messages, by default by the terminal background
+    #[serde(skip_serializing_if = "is_auto")]
+    pub theme: ThemeName,
+    /// Shell commands run around unlocking, code generation and vault writes
+    #[serde(skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Webhook receiving unlocks, failed unlocks and failed backup checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<Webhook>,
+    /// Redaction profiles for `export --profile`, by name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub export_profiles: BTreeMap<String, Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            vault_file: None,
+            decoy_vault_file: None,
+            password_source: PasswordSource::default(),
+            clipboard: true,
+            format_rules: Vec::new(),
+            providers: Vec::new(),
+            history: false,
+            audit_log: false,
+            rate_limits: Vec::new(),
+            theme: ThemeName::Auto,
+            hooks: Hooks::default(),
+            webhook: None,
+            export_profiles: BTreeMap::new(),
+        }
+    }
+}
+
+fn is_auto(theme: &ThemeName) -> bool {
+    *theme == ThemeName::Auto
+}
+
+/// Source of the vault password
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PasswordSource {
+    /// Ask for the password every time
+    #[default]
+    Prompt,
+    /// Read the password from a file
+    File { path: PathBuf },
+    /// Look the password up in the system keyring, stored by `setup`
+    Keyring,
+}
+
+impl Config {
+    /// Default location of the configuration file
+    ///
+    /// `$XDG_CONFIG_HOME/aegis-rs/config.json`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                PathBuf::from(env::var_os("HOME").filter(|home| !home.is_empty())?).join(".config")
+            }
+        };
+        Some(config_home.join("aegis-rs").join("config.json"))
+    }
+
+    /// Load configuration from `path`, or from the default location if not given
+    ///
+    /// A missing file at the default location results in the default configuration.
+    pub fn load(path: Option<&PathBuf>) -> Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (path.clone(), true),
+            None => match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        if !required && !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| eyre!("Failed to read config file {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| eyre!("Failed to parse config file {}: {}", path.display(), e))
+    }
+
+    /// Write the configuration to `path`, creating its directory if needed
+    ///
+    /// The file is only readable by the user, as it may hold the webhook secret. New directories
+    /// are private too, existing ones like the current directory are left as they are.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() && !dir.exists() {
+                create_private_dir(dir)?;
+            }
+        }
+        let contents = serde_json::to_string_pretty(self)? + "\n";
+        // Also files older versions wrote 0644 are replaced by one only the user can read
+        write_private(path, contents.as_bytes())
+    }
+
+    /// Configured formatting rules followed by the built-in ones
+    pub fn format_rules(&self) -> Vec<FormatRule> {
+        let mut rules = self.format_rules.clone();
+        rules.extend(FormatRule::builtin());
+        rules
+    }
+
+    /// Configured providers followed by the built-in ones
+    pub fn providers(&self) -> Vec<Provider> {
+        let mut providers = self.providers.clone();
+        providers.extend(Provider::builtin());
+        providers
+    }
+}
+
+/// Data directory given on the command line, `None` if no data is to be kept
+static DATA_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Use `dir` as data directory, or keep no data at all if `stateless`
+pub fn init_data_dir(dir: Option<PathBuf>, stateless: bool) {
+    let dir = match stateless {
+        true => None,
+        false => match dir {
+            Some(dir) => Some(dir),
+            None => return,
+        },
+    };
+    let _ = DATA_DIR.set(dir);
+}
+
+/// Directory for data kept by aegis-rs, like pulled backups
+///
+/// `--data-dir`, or `$XDG_DATA_HOME/aegis-rs` falling back to `~/.local/share`. It's created if
+/// needed and only accessible by the user.
+pub fn data_dir() -> Result<PathBuf> {
+    let dir = match DATA_DIR.get() {
+        Some(Some(dir)) => dir.clone(),
+        Some(None) => return Err(eyre!("No data is kept with --no-cache")),
+        None => {
+            let data_home = match (env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+                (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir),
+                (_, Some(home)) if !home.is_empty() => {
+                    PathBuf::from(home).join(".local").join("share")
+                }
+                _ => {
+                    return Err(eyre!(
+                        "No data directory, neither XDG_DATA_HOME nor HOME is set. Pass \
+                        --data-dir or set AEGIS_DATA_DIR, or keep no data with --no-cache"
+                    ))
+                }
+            };
+            data_home.join("aegis-rs")
+        }
+    };
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Directory for temporary files, separate for every user of the machine
+///
+/// `$XDG_RUNTIME_DIR/aegis-rs`, falling back to `aegis-rs-<uid>` in the temporary directory.
+/// It's only accessible by the user, a directory with that name created by someone else is
+/// refused.
+pub fn runtime_dir() -> Result<PathBuf> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("aegis-rs"),
+        #[cfg(unix)]
+        // SAFETY: geteuid has no preconditions and can't fail
+        _ => env::temp_dir().join(format!("aegis-rs-{}", unsafe { libc::geteuid() })),
+        #[cfg(not(unix))]
+        _ => env::temp_dir().join("aegis-rs"),
+    };
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Absolute path of `vault_file`, for keeping data about it
+///
+/// Only the directory is resolved, as the file may not exist yet.
+pub fn absolute_vault_path(vault_file: &Path) -> PathBuf {
+    match (vault_file.parent(), vault_file.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            parent
+                .canonicalize()
+                .map(|parent| parent.join(name))
+                .unwrap_or_else(|_| vault_file.to_path_buf())
+        }
+        _ => vault_file.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::Config;
+
+    #[cfg(unix)]
+    #[test]
+    fn saved_config_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = env::temp_dir().join(format!("aegis-rs-config-{}", std::process::id()));
+        let path = root.join("aegis-rs").join("config.json");
+        Config::default().save(&path).unwrap();
+        let mode =
+            |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+
+        // Files written by older versions are restricted
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        Config::default().save(&path).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
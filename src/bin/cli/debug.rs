@@ -0,0 +1,210 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aegis_rs::{
+    otp::{counter_at, format_code, generate_otp_at, EntryInfo, FormatRule},
+    vault::{EntryIndex, LazyEntry, Selector},
+};
+
+use crate::audit;
+
+#[derive(Subcommand)]
+pub enum DebugCommand {
+    /// Print the codes of an entry over a time range
+    Codes(CodesArgs),
+}
+
+#[derive(Args)]
+pub struct CodesArgs {
+    /// Issuer or name of the entry, or "uuid:<uuid>"
+    entry: String,
+    /// Start of the range, e.g. "-5m", "now" or a unix timestamp
+    #[clap(long, default_value = "-5m", allow_hyphen_values = true)]
+    from: String,
+    /// End of the range, e.g. "+5m", "now" or a unix timestamp
+    #[clap(long, default_value = "+5m", allow_hyphen_values = true)]
+    to: String,
+}
+
+pub fn run(
+    command: &DebugCommand,
+    entries: &[LazyEntry],
+    format_rules: &[FormatRule],
+    audit: &audit::Recorder,
+) -> Result<()> {
+    match command {
+        DebugCommand::Codes(args) => print_codes(args, entries, format_rules, audit),
+    }
+}
+
+/// Find the single entry matching the selector in `query` exactly, or else whose issuer or
+/// name contains `query`
+pub fn find_entry<'a>(entries: &'a [LazyEntry], query: &str) -> Result<&'a LazyEntry> {
+    let selector: Selector = query.parse()?;
+    let exact = EntryIndex::new(entries).find(&selector);
+    if let [i] = exact.as_slice() {
+        return Ok(&entries[*i]);
+    }
+
+    let query = query.to_lowercase();
+    let matches: Vec<&LazyEntry> = entries
+        .iter()
+        .filter(|e| {
+            e.issuer().to_lowercase().contains(&query) || e.name().to_lowercase().contains(&query)
+        })
+        .collect();
+    match matches.as_slice() {
+        [entry] => Ok(entry),
+        [] => Err(eyre!("No entry matches \"{}\"", query)),
+        _ => Err(eyre!(
+            "Multiple entries match \"{}\": {}",
+            query,
+            matches
+                .iter()
+                .map(|e| format!("{} ({})", e.issuer().trim(), e.name().trim()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Codes of the range, every one of them counts against the rate limit and is recorded
+fn print_codes(
+    args: &CodesArgs,
+    entries: &[LazyEntry],
+    format_rules: &[FormatRule],
+    audit: &audit::Recorder,
+) -> Result<()> {
+    let lazy_entry = find_entry(entries, &args.entry)?;
+    let entry = lazy_entry.deserialize()?;
+    let entry = &entry;
+    let period = match &entry.info {
+        EntryInfo::Totp(info) if info.period > 0 => info.period as i64,
+        EntryInfo::Steam(info) if info.period > 0 => info.period as i64,
+        EntryInfo::Yandex(info) if info.period > 0 => info.period as i64,
+        EntryInfo::Motp(info) if info.period > 0 => info.period as i64,
+        EntryInfo::Hotp(_) => return Err(eyre!("HOTP entries don't depend on the time")),
+        _ => return Err(eyre!("Entry has an invalid period")),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as i64;
+    let from = parse_time(&args.from, now)?;
+    let to = parse_time(&args.to, now)?;
+    if from > to {
+        return Err(eyre!("Start of the range is after its end"));
+    }
+
+    println!("{} ({})", entry.issuer.trim(), entry.name.trim());
+    println!(
+        "{:<22} {:>12} {:>10} {:>12}  Code",
+        "Time (UTC)", "Timestamp", "Offset", "Counter"
+    );
+    // Start at the beginning of the time step containing `from`
+    let mut timestamp = from - from.rem_euclid(period);
+    let count = usize::try_from((to - timestamp) / period + 1)?;
+    audit.check_codes(lazy_entry, count)?;
+    while timestamp <= to {
+        let code = format_code(
+            &generate_otp_at(&entry.info, timestamp)?,
+            entry,
+            format_rules,
+        );
+        let marker = if (timestamp..timestamp + period).contains(&now) {
+            "  <- now"
+        } else {
+            ""
+        };
+        println!(
+            "{:<22} {:>12} {:>10} {:>12}  {}{}",
+            format_utc(timestamp),
+            timestamp,
+            format_offset(timestamp - now),
+            counter_at(&entry.info, timestamp)?,
+            code,
+            marker
+        );
+        audit.record(lazy_entry, "cli debug");
+        timestamp += period;
+    }
+    Ok(())
+}
+
+/// Parse "now", a signed offset like "-90s", "+5m", "1h" or "2d", or a unix timestamp
+fn parse_time(input: &str, now: i64) -> Result<i64> {
+    let input = input.trim();
+    if input == "now" {
+        return Ok(now);
+    }
+
+    let (sign, rest) = match input.as_bytes().first() {
+        Some(b'+') => (1, &input[1..]),
+        Some(b'-') => (-1, &input[1..]),
+        _ => match input.parse::<i64>() {
+            Ok(timestamp) => return Ok(timestamp),
+            Err(_) => (1, input),
+        },
+    };
+    let (amount, multiplier) = match rest.char_indices().last() {
+        Some((i, 's')) => (&rest[..i], 1),
+        Some((i, 'm')) => (&rest[..i], 60),
+        Some((i, 'h')) => (&rest[..i], 60 * 60),
+        Some((i, 'd')) => (&rest[..i], 24 * 60 * 60),
+        _ => (rest, 1),
+    };
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| eyre!("Invalid time \"{}\"", input))?;
+    Ok(now + sign * amount * multiplier)
+}
+
+fn format_offset(seconds: i64) -> String {
+    let sign = if seconds < 0 { "-" } else { "+" };
+    let seconds = seconds.abs();
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+
+    let parts = [
+        (seconds / 86400, "d"),
+        (seconds % 86400 / 3600, "h"),
+        (seconds % 3600 / 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let offset: String = parts
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .collect();
+    format!("{}{}", sign, offset)
+}
+
+/// Format a unix timestamp as "YYYY-MM-DD HH:MM:SS"
+fn format_utc(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
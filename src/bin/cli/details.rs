@@ -0,0 +1,148 @@
+use color_eyre::eyre::Result;
+use serde_json::Value;
+
+use aegis_rs::{
+    otp::{format_code, generate_otp_at, Entry, EntryInfo, FormatRule},
+    qr::QrCode,
+    vault::{Group, LazyEntry},
+};
+
+use crate::{audit, i18n::tr, terminal, theme};
+
+/// Number of upcoming codes shown
+const NEXT_CODES: i64 = 2;
+
+/// Print everything about the selected entry: note, groups, code parameters, when a code was
+/// last shown, its QR code and the next codes
+pub fn print(
+    lazy_entry: &LazyEntry,
+    entry: &Entry,
+    groups: &[Group],
+    format_rules: &[FormatRule],
+    audit: &audit::Recorder,
+) -> Result<()> {
+    audit.check(lazy_entry)?;
+    let dim = &theme::current().dim;
+    let label = |text: String| dim.apply_to(text).to_string();
+
+    println!("{} ({})", entry.issuer.trim(), entry.name.trim());
+    println!(
+        "{}: {}",
+        label(tr!("details-type")),
+        lazy_entry.entry_type()
+    );
+    for (name, value) in parameters(&entry.info) {
+        println!("{}: {}", dim.apply_to(name), value);
+    }
+    let group_names = entry_groups(lazy_entry, groups);
+    if !group_names.is_empty() {
+        println!(
+            "{}: {}",
+            label(tr!("details-groups")),
+            group_names.join(", ")
+        );
+    }
+    if !entry.note.trim().is_empty() {
+        println!("{}: {}", label(tr!("details-note")), entry.note.trim());
+    }
+    let last_use = match audit.last_use(lazy_entry) {
+        _ if !audit.is_enabled() => tr!("details-not-recorded"),
+        Ok(Some(time)) => audit::format_time(time),
+        Ok(None) => tr!("details-never-used"),
+        Err(e) => e.to_string(),
+    };
+    println!("{}: {}", label(tr!("details-last-use")), last_use);
+
+    terminal::print_qr_code(&QrCode::encode(entry.to_otpauth_uri().as_bytes())?);
+
+    let codes = next_codes(&entry.info)?
+        .iter()
+        .map(|code| format_code(code, entry, format_rules))
+        .collect::<Vec<String>>();
+    println!("{}: {}", label(tr!("details-next-codes")), codes.join("  "));
+    audit.record(lazy_entry, "cli details");
+    Ok(())
+}
+
+/// Algorithm, digits, period or counter and the like, everything about the codes but secrets
+fn parameters(info: &EntryInfo) -> Vec<(String, String)> {
+    let fields = match serde_json::to_value(info) {
+        Ok(Value::Object(mut info)) => info.remove("info"),
+        _ => None,
+    };
+    match fields {
+        Some(Value::Object(fields)) => fields
+            .into_iter()
+            .filter(|(name, _)| name != "secret" && name != "pin")
+            .map(|(name, value)| match value {
+                Value::String(value) => (name, value),
+                value => (name, value.to_string()),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Names of the groups of the entry, or the group of databases before version 3
+fn entry_groups(entry: &LazyEntry, groups: &[Group]) -> Vec<String> {
+    let names = entry
+        .groups()
+        .iter()
+        .map(|uuid| {
+            groups
+                .iter()
+                .find(|group| &group.uuid == uuid)
+                .map_or_else(|| uuid.clone(), |group| group.name.clone())
+        })
+        .collect::<Vec<String>>();
+    match names.is_empty() {
+        true => entry.group_name().into_iter().collect(),
+        false => names,
+    }
+}
+
+/// Codes following the current one, of the next periods or of the next counter values
+///
+/// HOTP codes are only shown, the counter in the vault stays as it is.
+fn next_codes(info: &EntryInfo) -> Result<Vec<String>> {
+    let now = audit::now() as i64;
+    (1..=NEXT_CODES)
+        .map(|step| match info {
+            EntryInfo::Hotp(hotp) => {
+                let mut hotp = hotp.clone();
+                hotp.counter = hotp.counter.saturating_add(step as u64);
+                generate_otp_at(&EntryInfo::Hotp(hotp), now)
+            }
+            EntryInfo::Totp(totp) => generate_otp_at(info, now + step * totp.period as i64),
+            EntryInfo::Steam(steam) => generate_otp_at(info, now + step * steam.period as i64),
+            EntryInfo::Yandex(yandex) => generate_otp_at(info, now + step * yandex.period as i64),
+            EntryInfo::Motp(motp) => generate_otp_at(info, now + step * motp.period as i64),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use aegis_rs::otp::{EntryInfo, EntryInfoHotp, HashAlgorithm};
+
+    use super::{next_codes, parameters};
+
+    #[test]
+    fn hotp_details() {
+        let info = EntryInfo::Hotp(EntryInfoHotp {
+            secret: "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 6,
+            counter: 0,
+        });
+        let parameters = parameters(&info);
+        assert!(parameters.iter().all(|(name, _)| name != "secret"));
+        assert!(parameters.contains(&("counter".to_string(), "0".to_string())));
+        // RFC 4226 codes of the counters 1 and 2, the counter itself is not moved
+        assert_eq!(next_codes(&info).unwrap(), ["287082", "359152"]);
+        assert!(matches!(
+            info,
+            EntryInfo::Hotp(EntryInfoHotp { counter: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aegis_rs::{
+    otp,
+    vault::{aes_backend, Vault},
+};
+
+use crate::{terminal, theme};
+
+/// Timestamp of 2024-01-01, system clocks before it are surely wrong
+const MIN_PLAUSIBLE_TIME: u64 = 1704067200;
+
+/// Outcome of a successful check
+enum Status {
+    Passed(String),
+    /// Usable, but with reduced functionality
+    Warning(String),
+}
+
+/// Run environment checks and report each result
+///
+/// Returns an error if any of the checks failed.
+pub fn run(vault_file: Option<&Path>) -> Result<()> {
+    let mut checks: Vec<(&str, Result<Status>)> = vec![
+        ("Code generation", check_self_test()),
+        ("System clock", check_clock()),
+        ("Clipboard", check_clipboard()),
+        ("Terminal", check_terminal()),
+        ("AES implementation", check_aes()),
+    ];
+    if let Some(vault_file) = vault_file {
+        checks.push(("Vault file", check_vault_file(vault_file)));
+    }
+
+    let mut failed = 0;
+    for (name, result) in checks {
+        match result {
+            Ok(Status::Passed(message)) => println!(
+                "{} {}: {}",
+                theme::current()
+                    .success
+                    .apply_to(terminal::symbol("✔", "+")),
+                name,
+                message
+            ),
+            Ok(Status::Warning(message)) => println!(
+                "{} {}: {}",
+                theme::current().warning.apply_to("!"),
+                name,
+                message
+            ),
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "{} {}: {}",
+                    theme::current().error.apply_to(terminal::symbol("✘", "x")),
+                    name,
+                    e
+                );
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(eyre!("{} check(s) failed", failed));
+    }
+    Ok(())
+}
+
+fn check_self_test() -> Result<Status> {
+    let checked = otp::self_test()?;
+    Ok(Status::Passed(format!(
+        "{} RFC 6238/4226 test vectors passed",
+        checked
+    )))
+}
+
+fn check_clock() -> Result<Status> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| eyre!("System time is before the unix epoch"))?
+        .as_secs();
+    if now < MIN_PLAUSIBLE_TIME {
+        return Err(eyre!(
+            "System time {} is implausible, codes will not match",
+            now
+        ));
+    }
+    Ok(Status::Passed(format!(
+        "{} seconds since the unix epoch",
+        now
+    )))
+}
+
+fn check_clipboard() -> Result<Status> {
+    Ok(match terminal::system_clipboard() {
+        Ok(_) => Status::Passed("available".to_string()),
+        Err(_) if terminal::capabilities().osc52 => {
+            Status::Passed("copied through the terminal with OSC 52".to_string())
+        }
+        Err(e) => Status::Warning(format!("unavailable, codes will not be copied ({})", e)),
+    })
+}
+
+fn check_terminal() -> Result<Status> {
+    let capabilities = terminal::capabilities();
+    Ok(match capabilities.unicode {
+        true => Status::Passed(format!(
+            "unicode symbols, OSC 52 clipboard {}",
+            if capabilities.osc52 {
+                "supported"
+            } else {
+                "not known to work"
+            }
+        )),
+        false => Status::Warning("no unicode, ASCII symbols are shown instead".to_string()),
+    })
+}
+
+fn check_aes() -> Result<Status> {
+    Ok(match aes_backend() {
+        "software" => Status::Warning(
+            "software, large vaults decrypt slowly without hardware acceleration".to_string(),
+        ),
+        backend => Status::Passed(backend.to_string()),
+    })
+}
+
+fn check_vault_file(path: &Path) -> Result<Status> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+    let vault: Vault = serde_json::from_str(&contents)
+        .map_err(|e| eyre!("Failed to parse {}: {}", path.display(), e))?;
+    Ok(Status::Passed(format!(
+        "{} is a version {} vault",
+        path.display(),
+        vault.version
+    )))
+}
@@ -0,0 +1,981 @@
+use clap::{ArgGroup, Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use dialoguer::{Confirm, Input, Password, Select};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use zeroize::Zeroizing;
+
+use aegis_rs::{
+    import::{parse_2fas, parse_andotp, parse_freeotp_plus},
+    otp::{
+        format_code, generate_hotp, random_secret, Entry, EntryInfo, EntryInfoTotp, FormatRule,
+        HashAlgorithm, Provider, SECRET_BYTES,
+    },
+    otpauth::OtpauthUri,
+    steam::SteamAccount,
+    vault::{guess_mime, write_atomic, Database, NewEntry, Plan, RecoveryCodes},
+};
+
+use crate::{attach::Target, audit, config, debug::find_entry, interrupt, recovery, theme};
+
+#[derive(Subcommand)]
+pub enum EntryCommand {
+    /// Add a TOTP entry, asking for everything if no options are given
+    Add(AddArgs),
+    /// List the providers known to `add --well-known`
+    WellKnown,
+    /// Add a Steam Guard entry from a Steam Desktop Authenticator maFile or a steam:// URI
+    ImportSteam(ImportSteamArgs),
+    /// Print the next code of an HOTP entry, saving its incremented counter to the vault
+    NextHotp(NextHotpArgs),
+    /// Add the entries of all QR codes in the images of a directory, or of a backup of another app
+    Import(ImportArgs),
+}
+
+#[derive(Args)]
+#[clap(group(ArgGroup::new("source").required(true)))]
+pub struct ImportArgs {
+    /// Directory of screenshots or photos of QR codes
+    #[clap(long, group = "source")]
+    qr_dir: Option<PathBuf>,
+    /// Backup of andOTP, plain or encrypted
+    #[clap(long, group = "source")]
+    andotp: Option<PathBuf>,
+    /// JSON backup of FreeOTP+
+    #[clap(long, group = "source")]
+    freeotp_plus: Option<PathBuf>,
+    /// Backup of 2FAS Auth, plain or encrypted
+    #[clap(long = "2fas", group = "source")]
+    two_fas: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct NextHotpArgs {
+    /// Issuer or name of the entry, or "uuid:<uuid>"
+    entry: String,
+}
+
+#[derive(Args)]
+pub struct ImportSteamArgs {
+    /// maFile or steam:// URI
+    source: String,
+    /// Account name [default: from the maFile]
+    #[clap(long)]
+    name: Option<String>,
+}
+
+#[derive(Args)]
+pub struct AddArgs {
+    /// Provider to take the issuer, code parameters and icon from, e.g. "github"
+    #[clap(long, value_name = "PROVIDER")]
+    well_known: Option<String>,
+    /// Account name, e.g. the user name or email address
+    #[clap(long)]
+    name: Option<String>,
+    /// Issuer [default: from the provider]
+    #[clap(long)]
+    issuer: Option<String>,
+    /// Base32 secret, asked for if not given so it doesn't end up in the shell history
+    #[clap(long, env = "AEGIS_NEW_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+    /// Code length [default: from the provider or 6]
+    #[clap(long)]
+    digits: Option<i32>,
+    /// Seconds a code is valid [default: from the provider or 30]
+    #[clap(long)]
+    period: Option<i32>,
+    /// SHA1, SHA256 or SHA512 [default: from the provider or SHA1]
+    #[clap(long, value_parser = parse_algo)]
+    algo: Option<HashAlgorithm>,
+    /// Icon image file [default: from the provider]
+    #[clap(long)]
+    icon: Option<PathBuf>,
+    /// Select the QR code on the screen, with grim and slurp on Wayland or maim on X11
+    #[clap(long, conflicts_with_all = ["secret", "digits", "period", "algo"])]
+    qr_screen: bool,
+    /// Scan the QR code with the webcam, with zbarcam
+    #[clap(long, conflicts_with_all = ["secret", "digits", "period", "algo", "qr_screen"])]
+    qr_camera: bool,
+    /// Read the secret from a picture of it with tesseract, then ask for the rest
+    #[clap(
+        long,
+        value_name = "IMAGE",
+        conflicts_with_all = [
+            "well_known", "name", "issuer", "secret", "digits", "period", "algo", "icon",
+            "qr_screen", "qr_camera",
+        ]
+    )]
+    ocr_image: Option<PathBuf>,
+    /// Add several entries with new random secrets from a JSON template, or the entries of a
+    /// CSV file of issuer,name,secret rows, whose empty secrets are made up as well
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "well_known", "name", "issuer", "secret", "digits", "period", "algo", "qr_screen",
+            "qr_camera", "ocr_image",
+        ]
+    )]
+    template: Option<PathBuf>,
+    /// Number of entries to add from a JSON template, "{n}" in its name counts them
+    #[clap(long, requires = "template", default_value_t = 1)]
+    count: usize,
+    /// Write the otpauth:// URIs of the added entries to a file, to set up the server side with
+    #[clap(long, value_name = "FILE", requires = "template")]
+    uris: Option<PathBuf>,
+}
+
+/// Entries added with `add --template`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Template {
+    issuer: String,
+    /// Account name, "{n}" is replaced with the number of the entry
+    name: String,
+    #[serde(default = "Template::default_digits")]
+    digits: i32,
+    #[serde(default = "Template::default_period")]
+    period: i32,
+    #[serde(default)]
+    algo: HashAlgorithm,
+}
+
+impl Template {
+    fn default_digits() -> i32 {
+        6
+    }
+
+    fn default_period() -> i32 {
+        30
+    }
+}
+
+pub fn parse_algo(algo: &str) -> Result<HashAlgorithm, String> {
+    serde_json::from_value(algo.to_uppercase().into())
+        .map_err(|_| format!("Unsupported algorithm {}", algo))
+}
+
+/// Base32 `secret`, asked for if not given, in the form used in vaults
+pub fn read_secret(secret: Option<&str>) -> Result<String> {
+    let secret = match secret {
+        Some(secret) => secret.to_string(),
+        None => interrupt::prompt(|| {
+            Password::with_theme(theme::dialog())
+                .with_prompt("Secret")
+                .interact()
+        })?,
+    };
+    // Secrets are often shown in groups, with padding or in lower case
+    Ok(secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .collect::<String>()
+        .to_uppercase())
+}
+
+/// Print the providers, configured ones first
+pub fn well_known(providers: &[Provider]) {
+    for provider in providers {
+        println!(
+            "{:<12} {:<12} {} {} digits {}s",
+            provider.id,
+            provider.issuer,
+            format!("{:?}", provider.algo).to_uppercase(),
+            provider.digits,
+            provider.period
+        );
+    }
+}
+
+/// Add an entry to the database and write the vault
+pub fn add(args: &AddArgs, providers: &[Provider], db: Database, vault: &Target) -> Result<()> {
+    if let Some(template) = &args.template {
+        return add_from_template(args, template, db, vault);
+    }
+    let no_options = args.well_known.is_none()
+        && args.name.is_none()
+        && args.issuer.is_none()
+        && args.secret.is_none()
+        && args.digits.is_none()
+        && args.period.is_none()
+        && args.algo.is_none()
+        && args.icon.is_none();
+    let entry = if args.qr_screen {
+        entry_from_uri(args, providers, &qr_code_on_screen()?)?
+    } else if args.qr_camera {
+        entry_from_uri(args, providers, &scan_qr_code()?)?
+    } else if no_options || args.ocr_image.is_some() {
+        let recognized = match &args.ocr_image {
+            Some(image) => Some(recognize_secret(image)?),
+            None => None,
+        };
+        match wizard(providers, recognized)? {
+            Some(entry) => entry,
+            None => {
+                println!("Nothing added");
+                return Ok(());
+            }
+        }
+    } else {
+        entry_from_args(args, providers)?
+    };
+    let label = format!("{} ({})", entry.issuer, entry.name);
+    let mut plan = Plan::default();
+    plan.add_entry(&db, entry)?;
+    if vault.commit(db, plan)? {
+        println!("Added {}", label);
+    }
+    Ok(())
+}
+
+/// Add the entries of a JSON template or a CSV file, writing their URIs if asked to
+///
+/// The URIs are only written once the vault is, so they never hold secrets the vault doesn't.
+fn add_from_template(args: &AddArgs, template: &Path, db: Database, vault: &Target) -> Result<()> {
+    let contents = Zeroizing::new(
+        fs::read_to_string(template)
+            .map_err(|e| eyre!("Failed to read {}: {}", template.display(), e))?,
+    );
+    let csv = template
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let entries = match csv {
+        true if args.count != 1 => {
+            return Err(eyre!(
+                "--count only works with JSON templates, CSV files list every entry"
+            ))
+        }
+        true => csv_entries(&contents)?,
+        false => template_entries(&contents, args.count)
+            .map_err(|e| eyre!("Invalid template {}: {}", template.display(), e))?,
+    };
+    let icon = match &args.icon {
+        Some(path) => Some(read_icon(path)?),
+        None => None,
+    };
+
+    let mut plan = Plan::default();
+    let mut uris = Zeroizing::new(String::new());
+    for entry in entries {
+        entry
+            .info
+            .validate()
+            .map_err(|e| eyre!("Invalid entry {} ({}): {}", entry.issuer, entry.name, e))?;
+        uris.push_str(&entry.to_otpauth_uri());
+        uris.push('\n');
+        plan.add_entry(
+            &db,
+            NewEntry {
+                issuer: entry.issuer,
+                name: entry.name,
+                info: entry.info,
+                icon: icon.clone(),
+            },
+        )?;
+    }
+    let added: Vec<String> = plan.ops().iter().map(|op| op.label.clone()).collect();
+    if !vault.commit(db, plan)? {
+        return Ok(());
+    }
+    for label in &added {
+        println!("Added {}", label);
+    }
+    if let Some(path) = &args.uris {
+        write_atomic(path, uris.as_bytes())?;
+        println!(
+            "Wrote the URIs to {}, it holds the secrets unencrypted",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// `count` entries with random secrets from a JSON template
+fn template_entries(contents: &str, count: usize) -> Result<Vec<Entry>> {
+    let template: Template = serde_json::from_str(contents)?;
+    if count > 1 && !template.name.contains("{n}") {
+        return Err(eyre!(
+            "The name needs \"{{n}}\" to tell {} entries apart",
+            count
+        ));
+    }
+    Ok((1..=count)
+        .map(|n| {
+            let info = EntryInfo::Totp(EntryInfoTotp {
+                secret: random_secret(SECRET_BYTES),
+                algo: template.algo,
+                digits: template.digits,
+                period: template.period,
+            });
+            Entry::new(
+                template.issuer.clone(),
+                template.name.replace("{n}", &n.to_string()),
+                info,
+            )
+        })
+        .collect())
+}
+
+/// Entries of issuer,name,secret rows, the header row is optional
+///
+/// Values can't be quoted, so they can't contain commas. An empty secret is made up.
+fn csv_entries(contents: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let header = fields
+            .iter()
+            .map(|field| field.to_lowercase())
+            .eq(["issuer", "name", "secret"]);
+        if line.trim().is_empty() || (number == 0 && header) {
+            continue;
+        }
+        let [issuer, name, secret] = fields[..] else {
+            return Err(eyre!(
+                "Line {} has {} values instead of issuer,name,secret",
+                number + 1,
+                fields.len()
+            ));
+        };
+        let secret = match secret {
+            "" => random_secret(SECRET_BYTES),
+            secret => read_secret(Some(secret))?,
+        };
+        let info = EntryInfo::Totp(EntryInfoTotp {
+            secret,
+            algo: HashAlgorithm::Sha1,
+            digits: 6,
+            period: 30,
+        });
+        entries.push(Entry::new(issuer.to_string(), name.to_string(), info));
+    }
+    Ok(entries)
+}
+
+fn entry_from_args(args: &AddArgs, providers: &[Provider]) -> Result<NewEntry> {
+    let provider = match &args.well_known {
+        Some(id) => Some(Provider::find(providers, id).ok_or_else(|| {
+            eyre!(
+                "Unknown provider {}, `entry well-known` lists the known ones",
+                id
+            )
+        })?),
+        None => None,
+    };
+    let name = args
+        .name
+        .clone()
+        .ok_or(eyre!("--name is needed, or no options at all to be asked"))?;
+    let issuer = args
+        .issuer
+        .clone()
+        .or_else(|| provider.map(|p| p.issuer.clone()))
+        .ok_or(eyre!("Either --issuer or --well-known is needed"))?;
+
+    let secret = read_secret(args.secret.as_deref())?;
+
+    let icon = match args
+        .icon
+        .as_ref()
+        .or(provider.and_then(|p| p.icon.as_ref()))
+    {
+        Some(path) => Some(read_icon(path)?),
+        None => None,
+    };
+
+    Ok(NewEntry {
+        name,
+        issuer,
+        info: EntryInfo::Totp(EntryInfoTotp {
+            secret,
+            algo: args.algo.or(provider.map(|p| p.algo)).unwrap_or_default(),
+            digits: args.digits.or(provider.map(|p| p.digits)).unwrap_or(6),
+            period: args.period.or(provider.map(|p| p.period)).unwrap_or(30),
+        }),
+        icon,
+    })
+}
+
+/// Entry of a scanned QR code, the options override what its URI tells
+fn entry_from_uri(args: &AddArgs, providers: &[Provider], uri: &str) -> Result<NewEntry> {
+    let uri = OtpauthUri::parse(uri)?;
+
+    let provider = match &args.well_known {
+        Some(id) => {
+            Some(Provider::find(providers, id).ok_or_else(|| eyre!("Unknown provider {}", id))?)
+        }
+        None => None,
+    };
+    let issuer = args
+        .issuer
+        .clone()
+        .or(uri.issuer)
+        .or_else(|| provider.map(|p| p.issuer.clone()))
+        .ok_or(eyre!("The QR code has no issuer, give it with --issuer"))?;
+    let name = args
+        .name
+        .clone()
+        .or(Some(uri.name).filter(|name| !name.is_empty()))
+        .ok_or(eyre!(
+            "The QR code has no account name, give it with --name"
+        ))?;
+    let provider = provider.or(Provider::match_issuer(providers, &issuer));
+    let icon = match args
+        .icon
+        .as_ref()
+        .or(provider.and_then(|p| p.icon.as_ref()))
+    {
+        Some(path) => Some(read_icon(path)?),
+        None => None,
+    };
+    Ok(NewEntry {
+        name,
+        issuer,
+        info: EntryInfo::Totp(uri.info),
+        icon,
+    })
+}
+
+/// otpauth:// URI of the QR code in a region of the screen the user selects
+fn qr_code_on_screen() -> Result<String> {
+    let screenshot = config::runtime_dir()?.join(format!("qr-{}.png", std::process::id()));
+    let uri = capture_screen(&screenshot).and_then(|_| decode_qr_code(&screenshot));
+    // The screenshot shows the secret
+    let _ = fs::remove_file(&screenshot);
+    uri
+}
+
+/// otpauth:// URI of the first QR code zbarcam sees, which shows a preview if it can
+fn scan_qr_code() -> Result<String> {
+    let mut command = Command::new("zbarcam");
+    command.args(["--oneshot", "--raw"]);
+    if !graphical_session() {
+        command.arg("--nodisplay");
+        eprintln!("Hold the QR code in front of the camera, Ctrl-C cancels");
+    }
+    let output = command
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| eyre!("Failed to run zbarcam, which scans QR codes: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre!("Scanning failed, is there a camera?"));
+    }
+    String::from_utf8(output.stdout)?
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+        .ok_or(eyre!("No QR code scanned"))
+}
+
+fn graphical_session() -> bool {
+    ["WAYLAND_DISPLAY", "DISPLAY"]
+        .iter()
+        .any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+/// Let the user select a region of the screen and save it as PNG at `path`
+fn capture_screen(path: &Path) -> Result<()> {
+    let session = |name: &str| env::var_os(name).is_some_and(|value| !value.is_empty());
+    let status = if session("WAYLAND_DISPLAY") {
+        let region = Command::new("slurp")
+            .output()
+            .map_err(|e| eyre!("Failed to run slurp, which selects the region: {}", e))?;
+        if !region.status.success() {
+            return Err(eyre!("No region selected"));
+        }
+        let region = String::from_utf8(region.stdout)?;
+        Command::new("grim")
+            .args(["-g", region.trim()])
+            .arg(path)
+            .status()
+            .map_err(|e| eyre!("Failed to run grim, which takes the screenshot: {}", e))?
+    } else if session("DISPLAY") {
+        Command::new("maim")
+            .args(["--select", "--hidecursor"])
+            .arg(path)
+            .status()
+            .map_err(|e| eyre!("Failed to run maim, which takes the screenshot: {}", e))?
+    } else {
+        return Err(eyre!("No graphical session to take a screenshot of"));
+    };
+    match status.success() {
+        true => Ok(()),
+        false => Err(eyre!("Taking the screenshot failed or was cancelled")),
+    }
+}
+
+/// MIME type and contents of an icon image file
+fn read_icon(path: &Path) -> Result<(String, Vec<u8>)> {
+    let data =
+        fs::read(path).map_err(|e| eyre!("Failed to read icon {}: {}", path.display(), e))?;
+    let mime = guess_mime(&path.to_string_lossy());
+    if !mime.starts_with("image/") {
+        return Err(eyre!("{} is not an image", path.display()));
+    }
+    Ok((mime.to_string(), data))
+}
+
+/// What looks most like a base32 secret in the text of the image at `path`, read by tesseract
+fn recognize_secret(path: &Path) -> Result<String> {
+    let output = Command::new("tesseract")
+        .arg(path)
+        .arg("stdout")
+        .stderr(std::process::Stdio::null())
+        .output()
+        .map_err(|e| eyre!("Failed to run tesseract, which reads text in images: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre!("Failed to read the text in {}", path.display()));
+    }
+    let text = Zeroizing::new(String::from_utf8_lossy(&output.stdout).to_string());
+    find_base32(&text).ok_or(eyre!("No secret found in {}", path.display()))
+}
+
+/// Longest run of base32 in `text`, either a single long word or groups like "JBSW Y3DP"
+///
+/// Digits base32 doesn't have are taken for the letters OCR mistakes them for.
+fn find_base32(text: &str) -> Option<String> {
+    let base32 = |word: &str| -> Option<String> {
+        word.chars()
+            .map(|c| match c.to_ascii_uppercase() {
+                '0' => Some('O'),
+                '1' => Some('I'),
+                '8' => Some('B'),
+                c @ ('A'..='Z' | '2'..='7') => Some(c),
+                _ => None,
+            })
+            .collect()
+    };
+    let mut best: Option<String> = None;
+    for line in text.lines() {
+        let mut run: Vec<String> = Vec::new();
+        // A word that isn't base32 ends the run, followed by the end of the line
+        for word in line.split_whitespace().map(Some).chain([None]) {
+            let word = word.and_then(base32);
+            let grouped = match (&word, run.first()) {
+                (Some(word), Some(first)) => word.len() <= first.len() && first.len() <= 8,
+                _ => false,
+            };
+            if grouped {
+                run.push(word.unwrap_or_default());
+                continue;
+            }
+            let candidate = run.concat();
+            let long_enough = candidate.len() >= 16 && (run.len() > 1 || run[0].len() >= 16);
+            if long_enough
+                && best
+                    .as_ref()
+                    .is_none_or(|best| candidate.len() > best.len())
+            {
+                best = Some(candidate);
+            }
+            run = word.into_iter().collect();
+        }
+    }
+    best
+}
+
+/// otpauth:// URI of the QR code in the image at `path`, decoded by zbarimg
+fn decode_qr_code(path: &Path) -> Result<String> {
+    decode_qr_codes(path)?
+        .into_iter()
+        .find(|code| code.starts_with("otpauth://"))
+        .ok_or(eyre!(
+            "The QR code in {} is not an otpauth:// URI",
+            path.display()
+        ))
+}
+
+/// Contents of all QR codes in the image at `path`, decoded by zbarimg
+fn decode_qr_codes(path: &Path) -> Result<Vec<String>> {
+    let output = Command::new("zbarimg")
+        .args(["--quiet", "--raw"])
+        .arg(path)
+        .output()
+        .map_err(|e| eyre!("Failed to run zbarimg, which decodes QR codes: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre!("No QR code found in {}", path.display()));
+    }
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Ask for the entry step by step, returns `None` if it's not confirmed at the end
+///
+/// A `recognized` secret is shown for correcting it, instead of asking for it hidden.
+fn wizard(providers: &[Provider], recognized: Option<String>) -> Result<Option<NewEntry>> {
+    let theme = theme::dialog();
+
+    // Secrets may also come as URI or QR code, which tell the rest of the entry as well
+    let (secret, uri) = loop {
+        let input = interrupt::prompt(|| match &recognized {
+            Some(secret) => Input::<String>::with_theme(theme)
+                .with_prompt("Secret read from the image, correct it if needed")
+                .with_initial_text(secret)
+                .interact_text(),
+            None => Password::with_theme(theme)
+                .with_prompt("Secret, otpauth:// URI or QR code image file")
+                .interact(),
+        })?;
+        let input = input.trim();
+        let result = if input.starts_with("otpauth://") {
+            OtpauthUri::parse(input).map(|uri| (uri.info.secret.clone(), Some(uri)))
+        } else if Path::new(input).is_file() {
+            decode_qr_code(Path::new(input))
+                .and_then(|uri| OtpauthUri::parse(&uri))
+                .map(|uri| (uri.info.secret.clone(), Some(uri)))
+        } else {
+            read_secret(Some(input)).and_then(|secret| {
+                EntryInfo::Totp(EntryInfoTotp {
+                    secret: secret.clone(),
+                    algo: HashAlgorithm::default(),
+                    digits: 6,
+                    period: 30,
+                })
+                .validate()?;
+                Ok((secret, None))
+            })
+        };
+        match result {
+            Ok(result) => break result,
+            Err(e) => eprintln!("{}", e),
+        }
+    };
+
+    let non_empty = |input: &String| match input.trim().is_empty() {
+        true => Err("Can't be empty"),
+        false => Ok(()),
+    };
+    let mut issuer_prompt = Input::<String>::with_theme(theme)
+        .with_prompt("Issuer")
+        .validate_with(non_empty);
+    if let Some(issuer) = uri.as_ref().and_then(|uri| uri.issuer.clone()) {
+        issuer_prompt = issuer_prompt.with_initial_text(issuer);
+    }
+    let issuer = interrupt::prompt(|| issuer_prompt.interact_text())?;
+    let mut name_prompt = Input::<String>::with_theme(theme)
+        .with_prompt("Account name")
+        .validate_with(non_empty);
+    if let Some(uri) = uri.as_ref().filter(|uri| !uri.name.is_empty()) {
+        name_prompt = name_prompt.with_initial_text(uri.name.clone());
+    }
+    let name = interrupt::prompt(|| name_prompt.interact_text())?;
+
+    // Parameters of URIs are taken as they are, otherwise the provider's are suggested
+    let provider = Provider::match_issuer(providers, &issuer);
+    let mut info = match uri {
+        Some(uri) => uri.info,
+        None => EntryInfoTotp {
+            secret,
+            algo: provider.map(|p| p.algo).unwrap_or_default(),
+            digits: provider.map(|p| p.digits).unwrap_or(6),
+            period: provider.map(|p| p.period).unwrap_or(30),
+        },
+    };
+    let advanced = interrupt::prompt(|| {
+        Confirm::with_theme(theme)
+            .with_prompt(format!(
+                "Change the code parameters ({} digits, {}s, {})?",
+                info.digits,
+                info.period,
+                format!("{:?}", info.algo).to_uppercase()
+            ))
+            .default(false)
+            .interact()
+    })?;
+    if advanced {
+        info.digits = interrupt::prompt(|| {
+            Input::<i32>::with_theme(theme)
+                .with_prompt("Digits")
+                .default(info.digits)
+                .validate_with(|digits: &i32| match (1..=10).contains(digits) {
+                    true => Ok(()),
+                    false => Err("Must be between 1 and 10"),
+                })
+                .interact_text()
+        })?;
+        info.period = interrupt::prompt(|| {
+            Input::<i32>::with_theme(theme)
+                .with_prompt("Seconds a code is valid")
+                .default(info.period)
+                .validate_with(|period: &i32| match *period > 0 {
+                    true => Ok(()),
+                    false => Err("Must be greater than zero"),
+                })
+                .interact_text()
+        })?;
+        let algos = [
+            HashAlgorithm::Sha1,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha512,
+        ];
+        let names: Vec<String> = algos
+            .iter()
+            .map(|algo| format!("{:?}", algo).to_uppercase())
+            .collect();
+        let selected = interrupt::prompt(|| {
+            Select::with_theme(theme)
+                .with_prompt("Algorithm")
+                .items(&names)
+                .default(algos.iter().position(|a| *a == info.algo).unwrap_or(0))
+                .interact()
+        })?;
+        info.algo = algos[selected];
+    }
+    let info = EntryInfo::Totp(info);
+    info.validate()?;
+
+    let icon = match provider.and_then(|p| p.icon.as_ref()) {
+        Some(path) => Some(read_icon(path)?),
+        None => None,
+    };
+    let confirmed = interrupt::prompt(|| {
+        Confirm::with_theme(theme)
+            .with_prompt(format!("Add {} ({})?", issuer.trim(), name.trim()))
+            .default(true)
+            .interact()
+    })?;
+    Ok(confirmed.then(|| NewEntry {
+        name: name.trim().to_string(),
+        issuer: issuer.trim().to_string(),
+        info,
+        icon,
+    }))
+}
+
+/// Add a Steam entry, keeping the revocation code of a maFile as recovery code
+pub fn import_steam(args: &ImportSteamArgs, db: Database, vault: &Target) -> Result<()> {
+    let account = if args.source.starts_with("steam://") {
+        SteamAccount::from_uri(&args.source)?
+    } else {
+        let contents = fs::read_to_string(&args.source)
+            .map_err(|e| eyre!("Failed to read {}: {}", args.source, e))?;
+        SteamAccount::from_mafile(&contents)?
+    };
+    let name = args
+        .name
+        .clone()
+        .or(account.account_name.clone())
+        .ok_or(eyre!("The account name is unknown, give it with --name"))?;
+
+    let entry = NewEntry {
+        name: name.clone(),
+        issuer: "Steam".to_string(),
+        info: account.info(),
+        icon: None,
+    };
+    let mut plan = Plan::default();
+    let uuid = plan.add_entry(&db, entry)?;
+    if !vault.commit(db, plan)? {
+        return Ok(());
+    }
+    println!("Added Steam ({})", name);
+    // Only kept once the entry is in the vault, so no code is kept for an entry that isn't
+    if let Some(code) = &account.revocation_code {
+        let save = || -> Result<()> {
+            let path = recovery::codes_path(vault.master_key)?;
+            let mut codes = RecoveryCodes::load(&path, vault.master_key)?;
+            codes.add(&uuid, [code.clone()]);
+            codes.save(&path, vault.master_key)
+        };
+        // The URI holds the secret, it's not repeated
+        let source = match args.source.starts_with("steam://") {
+            true => "the steam:// URI",
+            false => &args.source,
+        };
+        save().map_err(|e| {
+            eyre!(
+                "Failed to keep the revocation code as recovery code, it is still in {}: {}",
+                source,
+                e
+            )
+        })?;
+        println!("The revocation code is kept as recovery code, `recovery show` prints it");
+    }
+    Ok(())
+}
+
+/// Print the next code of an HOTP entry
+///
+/// The vault is written before the code is printed, so a failed write never hands out a code twice.
+/// A dry run prints no code, as the counter isn't saved.
+pub fn next_hotp(
+    args: &NextHotpArgs,
+    format_rules: &[FormatRule],
+    db: Database,
+    vault: &Target,
+    audit: &audit::Recorder,
+) -> Result<()> {
+    let position = {
+        let entry = find_entry(db.entries(), &args.entry)?;
+        db.entries()
+            .iter()
+            .position(|e| std::ptr::eq(e, entry))
+            .ok_or(eyre!("Entry not found"))?
+    };
+    let lazy_entry = db.entries()[position].clone();
+    let mut entry = lazy_entry.deserialize()?;
+    let mut plan = Plan::default();
+    let code = match &mut entry.info {
+        EntryInfo::Hotp(info) => {
+            audit.check(&lazy_entry)?;
+            let code = generate_hotp(info)?;
+            plan.set_counter(&db.entries()[position], info.counter)?;
+            code
+        }
+        _ => {
+            return Err(eyre!(
+                "{} ({}) is not an HOTP entry",
+                entry.issuer,
+                entry.name
+            ))
+        }
+    };
+    if vault.commit(db, plan)? {
+        println!("{}", format_code(&code, &entry, format_rules));
+        audit.record(&lazy_entry, "cli next-hotp");
+    }
+    Ok(())
+}
+
+/// Entries of an `otpauth://` URI, or of an export QR code of Google Authenticator
+fn entries_of_code(code: &str) -> Result<Vec<Entry>> {
+    if code.starts_with("otpauth-migration://") {
+        #[cfg(feature = "google-migration")]
+        return aegis_rs::migration::parse_migration_uri(code);
+        #[cfg(not(feature = "google-migration"))]
+        return Err(eyre!(
+            "export QR codes of Google Authenticator need the google-migration feature"
+        ));
+    }
+    Ok(vec![Entry::from_otpauth_uri(code)?])
+}
+
+/// Add the entries of a backup of another app, or of the QR codes in `args.qr_dir`
+pub fn import(args: &ImportArgs, db: Database, vault: &Target) -> Result<()> {
+    let read =
+        |path: &Path| fs::read(path).map_err(|e| eyre!("Failed to read {}: {}", path.display(), e));
+    if let Some(path) = &args.andotp {
+        let entries = parse_andotp(&read(path)?, || backup_password("andOTP"))?;
+        return import_backup(entries, db, vault);
+    }
+    if let Some(path) = &args.freeotp_plus {
+        let contents = String::from_utf8(read(path)?)
+            .map_err(|_| eyre!("{} is not a FreeOTP+ backup", path.display()))?;
+        return import_backup(parse_freeotp_plus(&contents)?, db, vault);
+    }
+    if let Some(path) = &args.two_fas {
+        let contents = String::from_utf8(read(path)?)
+            .map_err(|_| eyre!("{} is not a 2FAS backup", path.display()))?;
+        let entries = parse_2fas(&contents, || backup_password("2FAS"))?;
+        return import_backup(entries, db, vault);
+    }
+    match &args.qr_dir {
+        Some(qr_dir) => import_qr_codes(qr_dir, db, vault),
+        None => Err(eyre!("Nothing to import")),
+    }
+}
+
+/// Password of an encrypted backup of `app`
+fn backup_password(app: &str) -> Result<String> {
+    Ok(interrupt::prompt(|| {
+        Password::with_theme(theme::dialog())
+            .with_prompt(format!("{} backup password", app))
+            .interact()
+    })?)
+}
+
+/// Add all entries of a backup, nothing is added if one of them can't be read
+fn import_backup(entries: Vec<Entry>, db: Database, vault: &Target) -> Result<()> {
+    let mut added = Vec::new();
+    let mut plan = Plan::default();
+    for entry in entries {
+        added.push(format!("{} ({})", entry.issuer, entry.name));
+        plan.add_entry(
+            &db,
+            NewEntry {
+                issuer: entry.issuer,
+                name: entry.name,
+                info: entry.info,
+                icon: None,
+            },
+        )?;
+    }
+    if !vault.commit(db, plan)? {
+        return Ok(());
+    }
+    for label in &added {
+        println!("Added {}", label);
+    }
+    println!("Added {} entries from the backup", added.len());
+    Ok(())
+}
+
+/// Add the entries of the QR codes in `qr_dir`, reporting the images without any
+fn import_qr_codes(qr_dir: &Path, db: Database, vault: &Target) -> Result<()> {
+    let mut images: Vec<PathBuf> = fs::read_dir(qr_dir)
+        .map_err(|e| eyre!("Failed to read {}: {}", qr_dir.display(), e))?
+        .filter_map(|file| file.ok().map(|file| file.path()))
+        .filter(|path| path.is_file() && guess_mime(&path.to_string_lossy()).starts_with("image/"))
+        .collect();
+    images.sort();
+
+    let mut added = Vec::new();
+    let mut failed = Vec::new();
+    let mut plan = Plan::default();
+    for image in &images {
+        let codes = match decode_qr_codes(image) {
+            Ok(codes) => codes,
+            Err(e) => {
+                failed.push(e.to_string());
+                continue;
+            }
+        };
+        for code in codes {
+            match entries_of_code(&code) {
+                Ok(entries) => {
+                    for entry in entries {
+                        added.push(format!("{} ({})", entry.issuer, entry.name));
+                        plan.add_entry(
+                            &db,
+                            NewEntry {
+                                issuer: entry.issuer,
+                                name: entry.name,
+                                info: entry.info,
+                                icon: None,
+                            },
+                        )?;
+                    }
+                }
+                Err(e) => failed.push(format!("{}: {}", image.display(), e)),
+            }
+        }
+    }
+
+    let written = vault.commit(db, plan)?;
+    if written {
+        for label in &added {
+            println!("Added {}", label);
+        }
+    }
+    for failure in &failed {
+        eprintln!("{}", failure);
+    }
+    if written {
+        println!(
+            "Added {} entries from {} images, {} QR codes or images failed",
+            added.len(),
+            images.len(),
+            failed.len()
+        );
+    }
+    Ok(())
+}
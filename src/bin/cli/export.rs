@@ -0,0 +1,608 @@
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{eyre, Result};
+use dialoguer::Password;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+use zeroize::Zeroizing;
+
+use aegis_rs::{
+    import::{to_2fas, to_bitwarden_csv, to_bitwarden_json, to_kdbx, KDBX_ROUNDS},
+    otp::{Entry, EntryInfo},
+    vault::{create_private_dir, write_atomic, Database, LazyEntry, Vault},
+};
+
+use crate::{
+    config,
+    icons::{self, file_name_part},
+    interrupt, theme,
+};
+
+/// Page which decrypts the embedded vault in the browser
+const VIEWER_TEMPLATE: &str = include_str!("viewer.html");
+const VAULT_PLACEHOLDER: &str = "/*VAULT*/null";
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Single HTML file which decrypts the vault in the browser and shows live codes
+    StaticHtml,
+    /// Password protected 7z archive with the plain vault, an otpauth:// URI and the icon of
+    /// every entry. Zip archives are refused, the ZipCrypto of `zip -e` falls to known plaintext
+    Archive,
+    /// Unencrypted backup for importing the TOTP, HOTP and Steam entries into 2FAS Auth
+    #[value(name = "2fas")]
+    TwoFas,
+    /// Unencrypted Bitwarden JSON export, or CSV if the output ends in .csv, with a login for
+    /// every TOTP and Steam entry holding its code in the TOTP field
+    Bitwarden,
+    /// KeePass KDBX 4 database with a password of its own, with an entry for every TOTP and
+    /// Steam entry holding its settings in the otp attribute of KeePassXC
+    Kdbx,
+    /// Plain vault as exported by Aegis, which it imports again
+    Json,
+    /// Table of the entries with their code parameters, one row each
+    Csv,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Export format
+    #[clap(long, value_enum)]
+    format: Format,
+    /// Output file
+    #[clap(short, long)]
+    output: PathBuf,
+    /// Also write a manifest to check the export with, to the output file name with
+    /// .manifest.json appended, and into archives
+    #[clap(long)]
+    manifest: bool,
+    /// Leave out the secrets, PINs and attachments of the json and csv formats, e.g. for
+    /// listing the accounts in an audit
+    #[clap(long)]
+    redact_secrets: bool,
+    /// Redact the export with a profile of the export_profiles config setting
+    #[clap(long, conflicts_with = "redact_secrets")]
+    profile: Option<String>,
+}
+
+/// Which entries of an export keep their secrets, set in the config by name
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Names of the groups whose entries keep their secrets
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Leave the other entries out instead of redacting them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub leave_out_others: bool,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Exported file
+    export: PathBuf,
+    /// Manifest written with the export, by default next to it
+    #[clap(long)]
+    manifest: Option<PathBuf>,
+}
+
+/// What an export contained, to tell later whether it's intact and up to date
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    /// Hash of the export file, in the manifest next to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    /// Files in the archive
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<FileHash>,
+    entries: usize,
+    /// UUIDs of the exported entries, no secrets
+    uuids: BTreeSet<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileHash {
+    path: String,
+    sha256: String,
+}
+
+impl Manifest {
+    fn new(format: &str, db: &Database, files: Vec<FileHash>) -> Manifest {
+        Manifest {
+            format: format.to_string(),
+            sha256: None,
+            files,
+            entries: db.entries().len(),
+            uuids: db
+                .entries()
+                .iter()
+                .filter_map(|entry| entry.uuid())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Where the manifest of `export` is written
+fn manifest_path(export: &Path) -> PathBuf {
+    let mut path = OsString::from(export);
+    path.push(".manifest.json");
+    PathBuf::from(path)
+}
+
+/// Export `db` of the vault `vault_contents` was read from, after its password has been checked
+///
+/// The HTML page only embeds the encrypted vault, a plain vault is refused for it. Archives and
+/// KeePass databases are encrypted with a password of their own. 2FAS backups and Bitwarden
+/// exports contain the secrets in plain text, as the apps import them, and so do JSON and CSV
+/// exports unless they are redacted, which is warned about. Profiles of `profiles` apply to
+/// every format but the HTML page.
+pub fn run(
+    args: &ExportArgs,
+    vault_contents: &str,
+    db: &Database,
+    profiles: &BTreeMap<String, Profile>,
+) -> Result<()> {
+    let plain = matches!(args.format, Format::Json | Format::Csv);
+    if args.redact_secrets && !plain {
+        return Err(eyre!(
+            "--redact-secrets only works with the json and csv formats"
+        ));
+    }
+    let profile = match &args.profile {
+        Some(name) => Some(
+            profiles
+                .get(name)
+                .ok_or(eyre!("No export profile {} in the config", name))?,
+        ),
+        None => None,
+    };
+    if profile.is_some() && matches!(args.format, Format::StaticHtml) {
+        return Err(eyre!(
+            "Profiles don't work with the static-html format, it holds the encrypted vault"
+        ));
+    }
+    let redacted = match profile {
+        Some(profile) => Some(apply_profile(profile, db, plain)?),
+        None if args.redact_secrets => Some(apply_profile(&Profile::default(), db, plain)?),
+        None => None,
+    };
+    let db = redacted.as_ref().unwrap_or(db);
+    if plain && !args.redact_secrets {
+        eprintln!("The export holds the secrets unencrypted, --redact-secrets leaves them out");
+    }
+    let (exported, files) = match args.format {
+        Format::StaticHtml => {
+            if !Vault::parse(vault_contents)?.is_encrypted() {
+                return Err(eyre!(
+                    "The vault is not encrypted, only encrypted vaults can be exported"
+                ));
+            }
+            (static_html(vault_contents)?.into_bytes(), Vec::new())
+        }
+        Format::Archive => archive(vault_contents, db, &args.output, args.manifest)?,
+        Format::TwoFas => (two_fas(db)?, Vec::new()),
+        Format::Bitwarden => (bitwarden(db, &args.output)?, Vec::new()),
+        Format::Kdbx => (kdbx(db)?, Vec::new()),
+        Format::Json => {
+            let json = serde_json::to_string_pretty(&plain_vault(vault_contents, db)?)?;
+            (json.into_bytes(), Vec::new())
+        }
+        Format::Csv => (csv(db)?.into_bytes(), Vec::new()),
+    };
+    write_atomic(&args.output, &exported)?;
+    println!("Exported the vault to {}", args.output.display());
+
+    if args.manifest {
+        let format = args.format.to_possible_value().expect("no skipped formats");
+        let mut manifest = Manifest::new(format.get_name(), db, files);
+        manifest.sha256 = Some(sha256(&exported));
+        let path = manifest_path(&args.output);
+        write_atomic(&path, serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+        println!("Wrote the manifest to {}", path.display());
+    }
+    Ok(())
+}
+
+/// 2FAS backup of the entries 2FAS has, the others are listed
+fn two_fas(db: &Database) -> Result<Vec<u8>> {
+    let entries = supported_entries(db, "2FAS", |info| {
+        matches!(
+            info,
+            EntryInfo::Totp(_) | EntryInfo::Hotp(_) | EntryInfo::Steam(_)
+        )
+    })?;
+    warn_unencrypted("2FAS");
+    Ok(to_2fas(&entries)?.into_bytes())
+}
+
+/// Bitwarden export of the entries it makes codes of, CSV if `output` ends in .csv
+fn bitwarden(db: &Database, output: &Path) -> Result<Vec<u8>> {
+    let entries = supported_entries(db, "Bitwarden", |info| {
+        matches!(info, EntryInfo::Totp(_) | EntryInfo::Steam(_))
+    })?;
+    warn_unencrypted("Bitwarden");
+    let csv = output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    let exported = match csv {
+        true => to_bitwarden_csv(&entries)?,
+        false => to_bitwarden_json(&entries)?,
+    };
+    Ok(exported.into_bytes())
+}
+
+/// Plain vault of `db` as exported by Aegis, which it imports again
+fn plain_vault(vault_contents: &str, db: &Database) -> Result<Value> {
+    Ok(json!({
+        "version": Vault::parse(vault_contents)?.version,
+        "header": {"slots": null, "params": null},
+        "db": db,
+    }))
+}
+
+/// Copy of `db` in which only the entries of the profile's groups keep their secrets
+///
+/// The other entries are redacted, or left out if the profile says so or the export isn't
+/// `plain`, as the apps of the other formats can't import entries without a secret.
+fn apply_profile(profile: &Profile, db: &Database, plain: bool) -> Result<Database> {
+    let groups: BTreeMap<String, String> = db
+        .groups()?
+        .into_iter()
+        .map(|group| (group.uuid, group.name))
+        .collect();
+    let group_names = |entry: &LazyEntry| -> Vec<String> {
+        let names = entry
+            .groups()
+            .into_iter()
+            .filter_map(|uuid| groups.get(&uuid).cloned());
+        names.chain(entry.group_name()).collect()
+    };
+    let known: BTreeSet<String> = groups
+        .values()
+        .cloned()
+        .chain(db.entries().iter().filter_map(LazyEntry::group_name))
+        .collect();
+    for name in &profile.groups {
+        if !known.contains(name) {
+            return Err(eyre!(
+                "The profile names the group {}, the vault has none",
+                name
+            ));
+        }
+    }
+    let kept = |entry: &LazyEntry| {
+        group_names(entry)
+            .iter()
+            .any(|name| profile.groups.contains(name))
+    };
+
+    if profile.leave_out_others || !plain {
+        for entry in db.entries().iter().filter(|entry| !kept(entry)) {
+            eprintln!(
+                "Left out {} ({}), the profile redacts it",
+                entry.issuer(),
+                entry.name()
+            );
+        }
+        return db.subset(kept);
+    }
+    let mut redacted = db.subset(|_| true)?;
+    for entry in redacted
+        .entries_mut()
+        .iter_mut()
+        .filter(|entry| !kept(entry))
+    {
+        entry.redact_secrets()?;
+    }
+    Ok(redacted)
+}
+
+/// Row of every entry, with the code parameters its type has and its secret unless redacted
+fn csv(db: &Database) -> Result<String> {
+    const COLUMNS: [&str; 7] = [
+        "algo", "digits", "period", "counter", "secret", "pin", "note",
+    ];
+    let mut csv = format!("uuid,type,issuer,name,{}\n", COLUMNS.join(","));
+    for lazy in db.entries() {
+        let entry = lazy.deserialize()?;
+        let fields = serde_json::to_value(&entry.info)?;
+        let info = &fields["info"];
+        let mut row = vec![
+            entry.uuid.clone().unwrap_or_default(),
+            lazy.entry_type().to_string(),
+            entry.issuer.clone(),
+            entry.name.clone(),
+        ];
+        for column in COLUMNS {
+            let value = match column {
+                "note" => Value::from(entry.note.clone()),
+                _ => info[column].clone(),
+            };
+            row.push(match value {
+                Value::String(text) => text,
+                Value::Null => String::new(),
+                value => value.to_string(),
+            });
+        }
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// Quote the field if it contains a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// KeePass database of the entries KeePassXC makes codes of, with a new password
+fn kdbx(db: &Database) -> Result<Vec<u8>> {
+    let entries = supported_entries(db, "KeePassXC", |info| {
+        matches!(info, EntryInfo::Totp(_) | EntryInfo::Steam(_))
+    })?;
+    let password = Zeroizing::new(interrupt::prompt(|| {
+        Password::with_theme(theme::dialog())
+            .with_prompt("Password for the KeePass database")
+            .with_confirmation("Repeat the password", "The passwords don't match")
+            .interact()
+    })?);
+    to_kdbx(&entries, &password, KDBX_ROUNDS)
+}
+
+/// Entries of `db` which `app` has, the others are listed
+fn supported_entries(
+    db: &Database,
+    app: &str,
+    supported: impl Fn(&EntryInfo) -> bool,
+) -> Result<Vec<Entry>> {
+    let (entries, skipped): (Vec<Entry>, Vec<Entry>) = db
+        .entries()
+        .iter()
+        .map(LazyEntry::deserialize)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .partition(|entry| supported(&entry.info));
+    for entry in &skipped {
+        eprintln!(
+            "Left out {} ({}), {} has no such entries",
+            entry.issuer, entry.name, app
+        );
+    }
+    Ok(entries)
+}
+
+fn warn_unencrypted(app: &str) {
+    eprintln!(
+        "The export holds the secrets unencrypted, delete it once {} imported it",
+        app
+    );
+}
+
+/// Check that an export matches its manifest, and still has the entries of the vault `db`
+pub fn verify(args: &VerifyArgs, db: &Database) -> Result<()> {
+    let manifest_path = args
+        .manifest
+        .clone()
+        .unwrap_or_else(|| manifest_path(&args.export));
+    let manifest: Manifest = serde_json::from_slice(
+        &fs::read(&manifest_path)
+            .map_err(|e| eyre!("Failed to read manifest {}: {}", manifest_path.display(), e))?,
+    )
+    .map_err(|e| eyre!("Invalid manifest {}: {}", manifest_path.display(), e))?;
+    let exported = fs::read(&args.export)
+        .map_err(|e| eyre!("Failed to read {}: {}", args.export.display(), e))?;
+    if manifest.sha256.as_deref() != Some(sha256(&exported).as_str()) {
+        return Err(eyre!(
+            "{} doesn't match its manifest, it was changed or damaged",
+            args.export.display()
+        ));
+    }
+    println!(
+        "{} matches its manifest, {} entries",
+        args.export.display(),
+        manifest.entries
+    );
+
+    let current = Manifest::new(&manifest.format, db, Vec::new());
+    let added: Vec<String> = db
+        .entries()
+        .iter()
+        .filter(|entry| {
+            entry
+                .uuid()
+                .is_some_and(|uuid| !manifest.uuids.contains(uuid))
+        })
+        .map(|entry| format!("{} ({})", entry.issuer().trim(), entry.name().trim()))
+        .collect();
+    let removed = manifest.uuids.difference(&current.uuids).count();
+    if added.is_empty() && removed == 0 && current.entries == manifest.entries {
+        println!("The vault has the same entries as the export");
+        return Ok(());
+    }
+    for label in &added {
+        println!("Added to the vault since: {}", label);
+    }
+    Err(eyre!(
+        "The export is outdated, the vault has {} entries, {} added and {} removed since",
+        current.entries,
+        added.len(),
+        removed
+    ))
+}
+
+fn static_html(vault_contents: &str) -> Result<String> {
+    // Compact, and without "</" so the vault can't end the script element
+    let vault: serde_json::Value = serde_json::from_str(vault_contents)?;
+    let vault = serde_json::to_string(&vault)?.replace("</", "<\\/");
+    Ok(VIEWER_TEMPLATE.replacen(VAULT_PLACEHOLDER, &vault, 1))
+}
+
+/// Archive for `output` and its files, which are staged in the private runtime directory and
+/// deleted after
+fn archive(
+    vault_contents: &str,
+    db: &Database,
+    output: &Path,
+    manifest: bool,
+) -> Result<(Vec<u8>, Vec<FileHash>)> {
+    if output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+    {
+        return Err(eyre!(
+            "Zip archives are encrypted with ZipCrypto, which known plaintext attacks break, \
+            write a .7z archive instead"
+        ));
+    }
+    let staging = config::runtime_dir()?.join(format!("export-{}", process::id()));
+    let result = stage(&staging.join("aegis-export"), vault_contents, db, manifest)
+        .and_then(|files| compress(&staging).map(|archive| (archive, files)));
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Write the plain vault, the URIs and the icons to `dir`, with a manifest of them if asked to
+fn stage(dir: &Path, vault_contents: &str, db: &Database, manifest: bool) -> Result<Vec<FileHash>> {
+    create_private_dir(&dir.join("entries"))?;
+    create_private_dir(&dir.join("icons"))?;
+    let mut files = Vec::new();
+    let mut write = |path: String, data: &[u8]| -> Result<()> {
+        fs::write(dir.join(&path), data)?;
+        files.push(FileHash {
+            path,
+            sha256: sha256(data),
+        });
+        Ok(())
+    };
+    write(
+        "vault.json".to_string(),
+        serde_json::to_string_pretty(&plain_vault(vault_contents, db)?)?.as_bytes(),
+    )?;
+
+    for (i, lazy) in db.entries().iter().enumerate() {
+        let file_name = format!(
+            "{:02} {} ({})",
+            i + 1,
+            file_name_part(lazy.issuer()),
+            file_name_part(lazy.name())
+        );
+        let entry = lazy.deserialize()?;
+        write(
+            format!("entries/{}.txt", file_name),
+            format!("{}\n", entry.to_otpauth_uri()).as_bytes(),
+        )?;
+
+        if let Some((mime, data)) = entry.icon()? {
+            write(
+                format!("icons/{}.{}", file_name, icons::extension(&mime)),
+                &data,
+            )?;
+        }
+    }
+
+    if manifest {
+        let manifest = Manifest::new("archive", db, files);
+        fs::write(
+            dir.join("manifest.json"),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+        files = manifest.files;
+    }
+    Ok(files)
+}
+
+/// Archive the `aegis-export` directory in `staging` with 7z, which asks for the password
+fn compress(staging: &Path) -> Result<Vec<u8>> {
+    let archive = "archive.7z";
+    let mut command = Command::new("7z");
+    // -mhe encrypts the file names too
+    command.args(["a", "-bso0", "-p", "-mhe=on", archive, "aegis-export"]);
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command.current_dir(staging).status().map_err(|e| {
+        eyre!(
+            "Failed to run {}, which creates the archive: {}",
+            program,
+            e
+        )
+    })?;
+    if !status.success() {
+        return Err(eyre!("{} failed to create the archive", program));
+    }
+    Ok(fs::read(staging.join(archive))?)
+}
+
+#[cfg(test)]
+mod test {
+    use aegis_rs::{
+        otp::{EntryInfo, EntryInfoMotp, EntryInfoYandex, HashAlgorithm, MotpAlgorithm},
+        vault::{DecryptOptions, NewEntry, Vault},
+    };
+    use color_eyre::eyre::Result;
+
+    use super::{apply_profile, csv, Profile};
+
+    #[test]
+    fn redact_pins() {
+        let mut db = Vault::parse(include_str!("../../../res/aegis_plain.json"))
+            .unwrap()
+            .unlock(
+                || Result::<String>::Ok(String::new()),
+                &DecryptOptions::default(),
+            )
+            .unwrap();
+        let entry = |issuer: &str, info| NewEntry {
+            name: "Mason".to_string(),
+            issuer: issuer.to_string(),
+            info,
+            icon: None,
+        };
+        db.add_entry(entry(
+            "Yandex",
+            EntryInfo::Yandex(EntryInfoYandex {
+                secret: "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAA".to_string(),
+                algo: HashAlgorithm::Sha256,
+                digits: 8,
+                period: 30,
+                pin: "5239".to_string(),
+            }),
+        ))
+        .unwrap();
+        db.add_entry(entry(
+            "mOTP",
+            EntryInfo::Motp(EntryInfoMotp {
+                secret: "MNSTGMJVGJQWMZLF".to_string(),
+                algo: MotpAlgorithm::Md5,
+                digits: 6,
+                period: 10,
+                pin: "1234".to_string(),
+            }),
+        ))
+        .unwrap();
+
+        let redacted = apply_profile(&Profile::default(), &db, true).unwrap();
+        let exported = csv(&redacted).unwrap();
+        assert!(!exported.contains("5239") && !exported.contains("1234"));
+        assert!(!exported.contains("LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAA"));
+        let rows: Vec<&str> = exported.lines().collect();
+        assert_eq!(rows.len(), 7);
+        assert!(rows[5].contains(",Yandex,Mason,SHA256,8,30,,,,"));
+        assert!(rows[6].contains(",mOTP,Mason,MD5,6,10,,,,"));
+    }
+}
@@ -0,0 +1,135 @@
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use aegis_rs::vault::{write_atomic, Vault};
+
+use crate::config;
+
+/// Name of the vault file inside of the history repository
+const VAULT_NAME: &str = "vault.json";
+
+#[derive(Args)]
+pub struct CheckoutArgs {
+    /// Revision to restore, as shown by `history`
+    rev: String,
+}
+
+/// Git repository keeping the versions of one vault file
+///
+/// Only the vault file itself is committed, and only if it's encrypted.
+struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    fn open(vault_file: &Path) -> Result<History> {
+        let path = config::absolute_vault_path(vault_file);
+        let id = hex::encode(Sha256::digest(path.to_string_lossy().as_bytes()));
+        let dir = config::data_dir()?.join("history").join(&id[..16]);
+        Ok(History { dir })
+    }
+
+    fn git(&self, args: &[&str]) -> Result<Output> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            // Commits are made without relying on a configured identity
+            .args([
+                "-c",
+                "user.name=aegis-rs",
+                "-c",
+                "user.email=aegis-rs@localhost",
+            ])
+            .args(["-c", "commit.gpgsign=false"])
+            .args(args)
+            .output()
+            .map_err(|e| eyre!("Failed to run git: {}", e))?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output)
+    }
+
+    fn exists(&self) -> bool {
+        self.dir.join(".git").exists()
+    }
+
+    /// Commit `contents` as the current version, unless it's unchanged
+    fn commit(&self, contents: &str, message: &str) -> Result<bool> {
+        if !self.exists() {
+            fs::create_dir_all(&self.dir)?;
+            self.git(&["init", "--quiet"])?;
+        }
+        write_atomic(&self.dir.join(VAULT_NAME), contents.as_bytes())?;
+        self.git(&["add", VAULT_NAME])?;
+        let unchanged = self.git(&["diff", "--cached", "--quiet"]).is_ok();
+        if unchanged {
+            return Ok(false);
+        }
+        self.git(&["commit", "--quiet", "--message", message])?;
+        Ok(true)
+    }
+}
+
+/// Commit the current state of `vault_file` if history is enabled
+///
+/// Called before and after commands which write the vault, so changes from elsewhere, e.g. a new
+/// backup from the phone, are kept apart from the ones made here.
+pub fn record(enabled: bool, vault_file: &Path, message: &str) -> Result<()> {
+    if !enabled || !vault_file.exists() {
+        return Ok(());
+    }
+    let contents = fs::read_to_string(vault_file)
+        .map_err(|e| eyre!("Failed to read {}: {}", vault_file.display(), e))?;
+    if !Vault::parse(&contents)?.is_encrypted() {
+        eprintln!("Not recording the history of a plain vault");
+        return Ok(());
+    }
+    History::open(vault_file)?.commit(&contents, message)?;
+    Ok(())
+}
+
+/// Print the recorded versions of `vault_file`, newest first
+pub fn log(vault_file: &Path) -> Result<()> {
+    let history = History::open(vault_file)?;
+    if !history.exists() {
+        println!("No history, set \"history\": true in the config file to record it");
+        return Ok(());
+    }
+    let output = history.git(&["log", "--date=iso", "--format=%h  %ad  %s"])?;
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    Ok(())
+}
+
+/// Restore the version `args.rev` of `vault_file`
+///
+/// The current version is recorded first, so a checkout can be undone.
+pub fn checkout(args: &CheckoutArgs, vault_file: &Path) -> Result<()> {
+    let history = History::open(vault_file)?;
+    if !history.exists() {
+        return Err(eyre!("No history of {}", vault_file.display()));
+    }
+    if args.rev.starts_with('-') {
+        return Err(eyre!("Invalid revision {}", args.rev));
+    }
+    let output = history.git(&["show", &format!("{}:{}", args.rev, VAULT_NAME)])?;
+    let contents =
+        String::from_utf8(output.stdout).map_err(|_| eyre!("Recorded vault is not UTF-8"))?;
+    Vault::parse(&contents)?;
+
+    record(true, vault_file, "Vault changed outside of aegis-rs")?;
+    write_atomic(vault_file, contents.as_bytes())?;
+    history.commit(&contents, &format!("Checkout {}", args.rev))?;
+    println!("Restored {} from {}", vault_file.display(), args.rev);
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use clap::CommandFactory;
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{ffi::OsString, fs, path::Path, process::Command, time::SystemTime};
+
+use aegis_rs::vault::LazyEntry;
+
+/// Environment variables which may hold secrets and are removed for hooks, along with the ones
+/// of arguments hiding their values, see [secret_variables]
+const SECRET_VARIABLES: [&str; 6] = [
+    "AEGIS_PASSWORD",
+    "AEGIS_PASSWORD_FILE",
+    "AEGIS_REMOTE_PASSWORD",
+    "AEGIS_NEW_SECRET",
+    "AEGIS_OTP_SECRET",
+    "AEGIS_VAULT",
+];
+
+/// Variables removed for hooks: [SECRET_VARIABLES] and those of every argument whose value clap
+/// hides, as they hold passwords, secrets or vaults
+fn secret_variables() -> Vec<OsString> {
+    fn collect(command: &clap::Command, variables: &mut Vec<OsString>) {
+        for arg in command.get_arguments() {
+            if let (Some(env), true) = (arg.get_env(), arg.is_hide_env_values_set()) {
+                variables.push(env.to_os_string());
+            }
+        }
+        for subcommand in command.get_subcommands() {
+            collect(subcommand, variables);
+        }
+    }
+    let mut variables: Vec<OsString> = SECRET_VARIABLES.iter().map(OsString::from).collect();
+    collect(&crate::Cli::command(), &mut variables);
+    variables
+}
+
+/// Shell commands run at points of the vault's use
+///
+/// Hooks get metadata in `AEGIS_*` environment variables, never passwords, secrets or codes.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Run before the vault is unlocked, a failure aborts the unlock
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_unlock: Option<String>,
+    /// Run after a code has been generated for an entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_code: Option<String>,
+    /// Run after the vault file has been written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_write: Option<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_unlock.is_none() && self.post_code.is_none() && self.post_write.is_none()
+    }
+}
+
+/// Hooks of one vault
+pub struct Context<'a> {
+    pub hooks: &'a Hooks,
+    pub vault_file: &'a Path,
+}
+
+impl Context<'_> {
+    fn run(&self, name: &str, command: &str, env: &[(&str, &str)]) -> Result<()> {
+        let mut shell = Command::new("sh");
+        shell
+            .args(["-c", command])
+            .env("AEGIS_HOOK", name)
+            .env("AEGIS_VAULT_FILE", self.vault_file);
+        for variable in secret_variables() {
+            shell.env_remove(variable);
+        }
+        let status = shell
+            .envs(env.iter().copied())
+            .status()
+            .map_err(|e| eyre!("Failed to run {} hook: {}", name, e))?;
+        if !status.success() {
+            return Err(eyre!("{} hook failed: {}", name, status));
+        }
+        Ok(())
+    }
+
+    pub fn pre_unlock(&self) -> Result<()> {
+        match &self.hooks.pre_unlock {
+            Some(command) => self.run("pre-unlock", command, &[]),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the post-code hook for `entry`, failures are only reported
+    pub fn post_code(&self, entry: &LazyEntry) {
+        if let Some(command) = &self.hooks.post_code {
+            let env = [
+                ("AEGIS_ENTRY_UUID", entry.uuid().unwrap_or_default()),
+                ("AEGIS_ENTRY_TYPE", entry.entry_type()),
+                ("AEGIS_ENTRY_ISSUER", entry.issuer()),
+                ("AEGIS_ENTRY_NAME", entry.name()),
+            ];
+            if let Err(e) = self.run("post-code", command, &env) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Run `write`, followed by the post-write hook if the vault file was changed
+    ///
+    /// `change` describes the write for the hook, in `AEGIS_CHANGE`.
+    pub fn watch_write<T>(&self, change: &str, write: impl FnOnce() -> Result<T>) -> Result<T> {
+        let before = modified(self.vault_file);
+        let result = write()?;
+        if let Some(command) = &self.hooks.post_write {
+            if modified(self.vault_file) != before {
+                if let Err(e) = self.run("post-write", command, &[("AEGIS_CHANGE", change)]) {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Modification time of `path`, `None` if it doesn't exist
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::secret_variables;
+
+    #[test]
+    fn hidden_variables_are_secret() {
+        let variables = secret_variables();
+        for variable in [
+            "AEGIS_PASSWORD",
+            "AEGIS_NEW_SECRET",
+            "AEGIS_OTP_SECRET",
+            "AEGIS_VAULT",
+        ] {
+            assert!(variables.iter().any(|v| v == variable), "{}", variable);
+        }
+        assert!(!variables.iter().any(|v| v == "AEGIS_VAULT_FILE"));
+    }
+}
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, env, sync::OnceLock};
+
+/// Message files by language, English is the fallback for missing messages
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("locales/en.ftl")),
+    ("de", include_str!("locales/de.ftl")),
+];
+
+/// Messages of one language, by id
+type Bundle = HashMap<&'static str, String>;
+
+/// Translated message `id`, with `{ $name }` placeables replaced by the arguments
+///
+/// ```ignore
+/// tr!("vault-open-failed", error = e)
+/// ```
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::i18n::message($id, &[])
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::message($id, &[$((stringify!($name), $value.to_string())),+])
+    };
+}
+pub(crate) use tr;
+
+/// Parse the subset of Fluent used by the message files
+///
+/// Only simple messages are supported, `id = text` with variables as `{ $name }`. Indented lines
+/// continue the previous message.
+fn parse(source: &'static str) -> Bundle {
+    let mut bundle = Bundle::new();
+    let mut last = None;
+    for line in source.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with(' ') {
+            if let Some(text) = last.and_then(|id| bundle.get_mut(id)) {
+                text.push('\n');
+                text.push_str(line.trim());
+            }
+        } else if let Some((id, text)) = line.split_once('=') {
+            let id = id.trim();
+            bundle.insert(id, text.trim().to_string());
+            last = Some(id);
+        }
+    }
+    bundle
+}
+
+/// Language of messages, from `LC_ALL`, `LC_MESSAGES` or `LANG` like gettext
+fn language() -> String {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    // e.g. de_DE.UTF-8@euro
+    locale
+        .split(['_', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+struct Messages {
+    selected: Option<Bundle>,
+    fallback: Bundle,
+}
+
+fn messages() -> &'static Messages {
+    static MESSAGES: OnceLock<Messages> = OnceLock::new();
+    MESSAGES.get_or_init(|| {
+        let language = language();
+        Messages {
+            selected: LOCALES
+                .iter()
+                .find(|(name, _)| *name == language && *name != "en")
+                .map(|(_, source)| parse(source)),
+            fallback: parse(LOCALES[0].1),
+        }
+    })
+}
+
+pub fn message(id: &str, args: &[(&str, String)]) -> String {
+    let messages = messages();
+    let text = messages
+        .selected
+        .as_ref()
+        .and_then(|bundle| bundle.get(id))
+        .or_else(|| messages.fallback.get(id))
+        .map(String::as_str)
+        .unwrap_or(id);
+    let mut message = text.to_string();
+    for (name, value) in args {
+        message = message.replace(&format!("{{ ${} }}", name), value);
+    }
+    message
+}
@@ -0,0 +1,85 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::{collections::BTreeSet, fs, path::PathBuf};
+
+use aegis_rs::vault::{write_atomic, LazyEntry};
+
+#[derive(Subcommand)]
+pub enum IconsCommand {
+    /// Write the icon of every entry to a directory, named after the issuer and name
+    Export(ExportArgs),
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Directory to write the icons to, created if missing
+    dir: PathBuf,
+    /// Replace existing files
+    #[clap(long)]
+    force: bool,
+}
+
+pub fn run(command: &IconsCommand, entries: &[LazyEntry]) -> Result<()> {
+    match command {
+        IconsCommand::Export(args) => export(args, entries),
+    }
+}
+
+fn export(args: &ExportArgs, entries: &[LazyEntry]) -> Result<()> {
+    fs::create_dir_all(&args.dir)
+        .map_err(|e| eyre!("Failed to create {}: {}", args.dir.display(), e))?;
+    let mut names = BTreeSet::new();
+    for lazy in entries.iter().filter(|entry| entry.has_icon()) {
+        let entry = lazy.deserialize()?;
+        let Some((mime, data)) = entry.icon()? else {
+            continue;
+        };
+        let base = format!(
+            "{} ({})",
+            file_name_part(&entry.issuer),
+            file_name_part(&entry.name)
+        );
+        // Entries of the same account get a number
+        let name = (1..)
+            .map(|i| match i {
+                1 => format!("{}.{}", base, extension(&mime)),
+                i => format!("{} {}.{}", base, i, extension(&mime)),
+            })
+            .find(|name| !names.contains(name))
+            .expect("a free name");
+        let path = args.dir.join(&name);
+        if path.exists() && !args.force {
+            return Err(eyre!("{} exists, replace it with --force", path.display()));
+        }
+        write_atomic(&path, &data)?;
+        println!("{}", path.display());
+        names.insert(name);
+    }
+    if names.is_empty() {
+        println!("No entry has an icon");
+    }
+    Ok(())
+}
+
+/// File name extension of icons of the MIME type
+pub fn extension(mime: &str) -> String {
+    match mime {
+        "image/jpeg" => "jpg".to_string(),
+        "image/svg+xml" => "svg".to_string(),
+        mime => file_name_part(mime.strip_prefix("image/").unwrap_or("bin")),
+    }
+}
+
+/// `text` without characters file systems or shells trip over
+pub fn file_name_part(text: &str) -> String {
+    let part: String = text
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    part.trim_start_matches('.').to_string()
+}
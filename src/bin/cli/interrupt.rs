@@ -0,0 +1,149 @@
+use console::Term;
+use std::{
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, OnceLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::i18n::tr;
+
+/// Exit code after Ctrl-C, as shells report for processes killed by SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+/// Exit code after a prompt wasn't answered in time, the same as of `timeout`
+pub const EXIT_PROMPT_TIMEOUT: i32 = 124;
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+/// Set while the main thread reacts to the interruption itself
+static DEFERRED: AtomicBool = AtomicBool::new(false);
+static PROMPT_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Install the Ctrl-C handler and return the flag it sets
+///
+/// The handler restores the terminal, as an interrupted password prompt leaves echo disabled
+/// and the watch mode hides the cursor, and exits. During [defer] it only sets the flag.
+pub fn install() -> Arc<AtomicBool> {
+    let interrupted = INTERRUPTED.get_or_init(Arc::default).clone();
+    terminal::save();
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || {
+        flag.store(true, Ordering::SeqCst);
+        restore_terminal();
+        if !DEFERRED.load(Ordering::SeqCst) {
+            exit(EXIT_INTERRUPTED);
+        }
+    })
+    .expect("Setting SIGINT handler");
+    interrupted
+}
+
+/// Run a cancellable operation, which has to return once the flag from [install] is set
+///
+/// Exits afterwards if interrupted, after the result of `f` has been dropped so secrets in it
+/// are zeroized.
+pub fn defer<T>(f: impl FnOnce() -> T) -> T {
+    DEFERRED.store(true, Ordering::SeqCst);
+    let result = f();
+    DEFERRED.store(false, Ordering::SeqCst);
+    if INTERRUPTED
+        .get()
+        .is_some_and(|interrupted| interrupted.load(Ordering::SeqCst))
+    {
+        drop(result);
+        eprintln!("{}", tr!("interrupted"));
+        exit(EXIT_INTERRUPTED);
+    }
+    result
+}
+
+/// Run `f` with Ctrl-C exiting right away, e.g. for a prompt inside of [defer]
+pub fn immediate<T>(f: impl FnOnce() -> T) -> T {
+    let deferred = DEFERRED.swap(false, Ordering::SeqCst);
+    let result = f();
+    DEFERRED.store(deferred, Ordering::SeqCst);
+    result
+}
+
+/// Make [prompt] exit when a prompt isn't answered within `timeout`
+pub fn set_prompt_timeout(timeout: Duration) {
+    let _ = PROMPT_TIMEOUT.set(timeout);
+}
+
+/// Run the interactive prompt `f` like [immediate], exiting once the prompt timeout is reached
+pub fn prompt<T>(f: impl FnOnce() -> T) -> T {
+    let (answered, waiting) = mpsc::channel::<()>();
+    if let Some(&timeout) = PROMPT_TIMEOUT.get() {
+        thread::spawn(move || {
+            // Returns early once the sender is dropped after the prompt
+            if let Err(mpsc::RecvTimeoutError::Timeout) = waiting.recv_timeout(timeout) {
+                restore_terminal();
+                eprintln!();
+                eprintln!("{}", tr!("prompt-timeout"));
+                exit(EXIT_PROMPT_TIMEOUT);
+            }
+        });
+    }
+    let result = immediate(f);
+    drop(answered);
+    result
+}
+
+/// Parse a timeout like "30s", "2m" or "1h", plain numbers are seconds
+pub fn parse_timeout(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (amount, multiplier) = match input.char_indices().last() {
+        Some((i, 's')) => (&input[..i], 1),
+        Some((i, 'm')) => (&input[..i], 60),
+        Some((i, 'h')) => (&input[..i], 60 * 60),
+        _ => (input, 1),
+    };
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid timeout \"{}\"", input))?;
+    Ok(Duration::from_secs(amount * multiplier))
+}
+
+fn restore_terminal() {
+    terminal::restore();
+    let term = Term::stdout();
+    if term.is_term() {
+        let _ = term.show_cursor();
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::sync::OnceLock;
+
+    /// Terminal attributes of stdin before any prompt changed them
+    static SAVED: OnceLock<libc::termios> = OnceLock::new();
+
+    pub fn save() {
+        // SAFETY: termios is plain data, filled in by tcgetattr which only reads stdin's state
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+                let _ = SAVED.set(termios);
+            }
+        }
+    }
+
+    pub fn restore() {
+        if let Some(termios) = SAVED.get() {
+            // SAFETY: Attributes previously returned by tcgetattr for the same descriptor
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod terminal {
+    pub fn save() {}
+
+    pub fn restore() {}
+}
@@ -0,0 +1,132 @@
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Service name of the stored passwords
+const SERVICE: &str = "aegis-rs";
+
+/// Keyring account of a vault, its absolute path
+fn account(vault_file: &Path) -> String {
+    vault_file
+        .canonicalize()
+        .unwrap_or_else(|_| vault_file.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// Command line tool to access the keyring with
+fn tool() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "security"
+    } else if cfg!(windows) {
+        "powershell"
+    } else {
+        "secret-tool"
+    }
+}
+
+/// PowerShell loading the Credential Locker of the Windows Credential Manager as `$vault`
+const WINDOWS_VAULT: &str = "[Windows.Security.Credentials.PasswordVault, \
+    Windows.Security.Credentials, ContentType = WindowsRuntime] | Out-Null; \
+    $vault = New-Object Windows.Security.Credentials.PasswordVault;";
+
+/// PowerShell running `script` after [`WINDOWS_VAULT`]
+///
+/// The service and the account are passed in environment variables, so they need no quoting.
+fn powershell(script: &str, account: &str) -> Command {
+    let mut command = Command::new(tool());
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command"])
+        .arg(format!("{} {}", WINDOWS_VAULT, script))
+        .env("AEGIS_RS_KEYRING_SERVICE", SERVICE)
+        .env("AEGIS_RS_KEYRING_ACCOUNT", account);
+    command
+}
+
+/// Look up the password of `vault_file` in the system keyring
+///
+/// Uses `secret-tool` of libsecret on Linux, `security` on macOS and the Credential Manager,
+/// through PowerShell, on Windows.
+pub fn lookup(vault_file: &Path) -> Result<String> {
+    let account = account(vault_file);
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new(tool());
+        command.args(["find-generic-password", "-s", SERVICE, "-a", &account, "-w"]);
+        command
+    } else if cfg!(windows) {
+        powershell(
+            "$credential = $vault.Retrieve($env:AEGIS_RS_KEYRING_SERVICE, \
+                $env:AEGIS_RS_KEYRING_ACCOUNT); $credential.RetrievePassword(); \
+                [Console]::Out.Write($credential.Password)",
+            &account,
+        )
+    } else {
+        let mut command = Command::new(tool());
+        command.args(["lookup", "service", SERVICE, "vault", &account]);
+        command
+    };
+    let output = command
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| eyre!("Failed to run {}: {}", tool(), e))?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(eyre!(
+            "No password for {} in the system keyring, store it with setup",
+            account
+        ));
+    }
+    let password = String::from_utf8(output.stdout)
+        .map_err(|_| eyre!("Password in the system keyring is not UTF-8"))?;
+    Ok(password.trim_end_matches('\n').to_string())
+}
+
+/// Store the password of `vault_file` in the system keyring
+pub fn store(vault_file: &Path, password: &str) -> Result<()> {
+    let account = account(vault_file);
+    let status = if cfg!(target_os = "macos") {
+        // `security` has no way to read the password from stdin, ask for it again instead of
+        // putting it on the command line
+        println!("Enter the vault password again for the macOS keychain");
+        Command::new(tool())
+            .args([
+                "add-generic-password",
+                "-U",
+                "-s",
+                SERVICE,
+                "-a",
+                &account,
+                "-w",
+            ])
+            .status()
+    } else {
+        let mut command = if cfg!(windows) {
+            powershell(
+                "$vault.Add((New-Object Windows.Security.Credentials.PasswordCredential( \
+                    $env:AEGIS_RS_KEYRING_SERVICE, $env:AEGIS_RS_KEYRING_ACCOUNT, \
+                    [Console]::In.ReadToEnd())))",
+                &account,
+            )
+        } else {
+            let label = format!("aegis-rs password for {}", account);
+            let mut command = Command::new(tool());
+            command.args([
+                "store", "--label", &label, "service", SERVICE, "vault", &account,
+            ]);
+            command
+        };
+        command.stdin(Stdio::piped()).spawn().and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(password.as_bytes())?;
+            }
+            child.wait()
+        })
+    }
+    .map_err(|e| eyre!("Failed to run {}: {}", tool(), e))?;
+    if !status.success() {
+        return Err(eyre!("Failed to store the password in the system keyring"));
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+# Meldungen der aegis-rs Kommandozeile, in Fluent-Syntax
+
+password-prompt = Aegis-Passwort eingeben
+pinentry-description = Passwort zum Entsperren des Aegis-Tresors eingeben
+password-failed = Passwort konnte nicht gelesen werden: { $error }
+password-file-failed = Passwortdatei { $path } konnte nicht gelesen werden: { $error }
+new-password-prompt = Neues Tresorpasswort
+new-password-repeat = Passwort wiederholen
+new-password-mismatch = Die Passwörter stimmen nicht überein
+password-strength = Passwortstärke: { $score }/{ $max }
+weak-password-confirm = Das Passwort ist schwach, trotzdem verwenden?
+weak-password-rejected = Passwort nicht geändert, ein stärkeres wählen
+code-time-left = { $code } (noch { $seconds } s)
+no-entries = Keine Einträge gefunden
+no-matching-entries = Keine Einträge passen zu den Filtern und unterstützten Eintragstypen
+no-selection = Nichts ausgewählt
+no-config-location = Kein Ort für die Konfiguration, --config angeben oder HOME setzen
+setup-failed = Einrichtung fehlgeschlagen: { $error }
+no-vault-file = Keine Tresordatei angegeben, als Argument übergeben, AEGIS_VAULT_FILE setzen oder setup ausführen
+vault-read-failed = Tresordatei konnte nicht gelesen werden: { $error }
+vault-stdin-read-only = Ein von stdin gelesener Tresor kann nur gelesen werden, zum Ändern die Tresordatei angeben
+vault-export-read-only = Ein komprimierter oder geteilter Export kann nur gelesen werden, zum Ändern als einzelne Tresordatei schreiben
+dry-run-unsupported = --dry-run geht nur mit den Befehlen entry, attach und tidy
+vault-open-failed = Tresor konnte nicht geöffnet werden: { $error }
+info-file = Datei: { $path }
+info-version = Tresorversion: { $version }
+info-encrypted = Verschlüsselt: { $encrypted }
+info-slot = Slot: { $slot }
+info-written = Zuletzt geschrieben von: { $tool } { $version } am { $time }
+info-written-by-app = Zuletzt geschrieben von: der Aegis-App, oder einem anderen Programm als aegis-rs
+info-weakness = Schwachstelle: { $weakness }
+vault-weakness = Warnung: { $weakness }
+vault-weakness-hint = `vault rekey` schreibt neue Parameter, die Sicherung in der App bleibt unverändert
+yes = ja
+no = nein
+prompt-timeout = Keine rechtzeitige Antwort, ohne Terminal das Passwort mit --password-file oder AEGIS_PASSWORD angeben
+interrupted = Abgebrochen
+details-type = Typ
+details-groups = Gruppen
+details-note = Notiz
+details-last-use = Zuletzt gezeigter Code
+details-never-used = nie
+details-not-recorded = nicht aufgezeichnet, die Einstellung audit_log zeichnet auf
+details-next-codes = Nächste Codes
+list-hotp = (HOTP, `entry next-hotp` erzeugt den nächsten Code)
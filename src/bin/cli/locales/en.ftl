@@ -0,0 +1,45 @@
+# Messages of the aegis-rs command line, in Fluent syntax
+
+password-prompt = Insert Aegis Password
+pinentry-description = Enter the password to unlock the Aegis vault
+password-failed = Failed to get password: { $error }
+password-file-failed = Failed to read password file { $path }: { $error }
+new-password-prompt = New vault password
+new-password-repeat = Repeat the password
+new-password-mismatch = The passwords don't match
+password-strength = Password strength: { $score }/{ $max }
+weak-password-confirm = The password is weak, use it anyway?
+weak-password-rejected = Password not changed, choose a stronger one
+code-time-left = { $code } ({ $seconds }s left)
+no-entries = No entries found
+no-matching-entries = Found no matching entries based on filters and supported vault entries
+no-selection = No selection
+no-config-location = No config location, pass --config or set HOME
+setup-failed = Setup failed: { $error }
+no-vault-file = No vault file given, pass it as an argument, set AEGIS_VAULT_FILE or run setup
+vault-read-failed = Failed to read vault file: { $error }
+vault-stdin-read-only = A vault read from stdin can only be read, pass the vault file to change it
+vault-export-read-only = A compressed or split export can only be read, write it as a single vault file to change it
+dry-run-unsupported = --dry-run only works with the entry, attach and tidy commands
+vault-open-failed = Failed to open vault: { $error }
+info-file = File: { $path }
+info-version = Vault version: { $version }
+info-encrypted = Encrypted: { $encrypted }
+info-slot = Slot: { $slot }
+info-written = Last written by: { $tool } { $version } at { $time }
+info-written-by-app = Last written by: the Aegis app, or a tool other than aegis-rs
+info-weakness = Weakness: { $weakness }
+vault-weakness = Warning: { $weakness }
+vault-weakness-hint = `vault rekey` writes new parameters, the backup in the app stays as it is
+yes = yes
+no = no
+prompt-timeout = No answer to the prompt in time, give the password with --password-file or AEGIS_PASSWORD when not running interactively
+interrupted = Interrupted
+details-type = Type
+details-groups = Groups
+details-note = Note
+details-last-use = Last code shown
+details-never-used = never
+details-not-recorded = not recorded, the audit_log config setting enables recording
+details-next-codes = Next codes
+list-hotp = (HOTP, `entry next-hotp` takes its next code)
@@ -0,0 +1,1353 @@
+use clap::{crate_version, Args, Parser, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use console::Term;
+use dialoguer::{FuzzySelect, Password};
+use std::{
+    collections::BTreeMap, env, ffi::OsString, fs, path::PathBuf, process::exit, time::Duration,
+};
+use zeroize::Zeroizing;
+
+use aegis_rs::{
+    otp::{calculate_remaining_time, format_code, generate_otp, Entry, FormatRule, Generator},
+    qr::QrCode,
+    vault::{
+        join_split_vault, CachedPassword, Database, DecryptOptions, Group, LazyEntry, MasterKey,
+        PasswordGetter, StdinPassword, Vault, VaultFile,
+    },
+};
+
+use crate::{
+    attach::AttachCommand,
+    audit::AuditCommand,
+    bench::BenchCommand,
+    bundle::ConfigCommand,
+    check::CheckArgs,
+    ci::CiArgs,
+    config::{Config, PasswordSource},
+    debug::DebugCommand,
+    entry::EntryCommand,
+    export::{ExportArgs, VerifyArgs},
+    history::CheckoutArgs,
+    i18n::tr,
+    icons::IconsCommand,
+    otp::{OtpArgs, SecretCommand},
+    password::{NewPasswordArgs, PasswordCommand},
+    qr::QrArgs,
+    recovery::RecoveryCommand,
+    server::{PamHelperArgs, VerifyServerArgs},
+    sync::{Direction, SyncArgs},
+    tidy::TidyArgs,
+    vaults::VaultCommand,
+};
+
+mod attach;
+mod audit;
+mod bench;
+mod bundle;
+mod check;
+mod ci;
+mod config;
+mod debug;
+mod details;
+mod doctor;
+mod entry;
+mod export;
+mod history;
+mod hooks;
+mod i18n;
+mod icons;
+mod interrupt;
+mod keyring;
+mod notify;
+mod otp;
+mod password;
+mod pinentry;
+mod plugin;
+mod qr;
+mod recovery;
+mod server;
+mod setup;
+mod sync;
+mod terminal;
+mod theme;
+mod tidy;
+mod vaults;
+
+#[derive(Parser)]
+#[clap(
+    name = "aegis-rs",
+    about = "OTP generator for Aegis vaults",
+    version = crate_version!()
+)]
+struct Cli {
+    #[clap(
+        help = "Path to the vault file, \"-\" reads it from stdin",
+        env = "AEGIS_VAULT_FILE"
+    )]
+    vault_file: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Database file of a split export, the vault file then only needs to hold the header"
+    )]
+    db: Option<PathBuf>,
+    #[clap(long, env = "AEGIS_CONFIG_FILE", help = "Path to the config file")]
+    config: Option<PathBuf>,
+    #[clap(
+        long,
+        conflicts_with = "config",
+        help = "Don't read the config file, use the default settings"
+    )]
+    no_config: bool,
+    #[clap(
+        long,
+        env = "AEGIS_DATA_DIR",
+        help = "Directory for the history, recovery codes and other data [default: $XDG_DATA_HOME/aegis-rs]"
+    )]
+    data_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        conflicts_with = "data_dir",
+        help = "Keep no data, no history or audit log is recorded"
+    )]
+    no_cache: bool,
+    #[clap(flatten)]
+    password_input: PasswordInput,
+    #[clap(
+        long,
+        env = "AEGIS_RAW_KEY_FILE",
+        conflicts_with_all = ["password", "password_file", "password_stdin"],
+        help = "File with the master key or the key of a raw or biometric slot in hex, used instead of the password"
+    )]
+    raw_key_file: Option<PathBuf>,
+    #[clap(flatten, help = "Filter by issuer name")]
+    entry_filter: EntryFilter,
+    #[clap(long, help = "Print to stdout in JSON")]
+    json: bool,
+    #[clap(
+        long,
+        help = "Print the changes to the vault instead of writing it, in JSON with --json"
+    )]
+    dry_run: bool,
+    #[clap(long, help = "Show extra entry fields, e.g. Battle.net serials")]
+    show_extra: bool,
+    #[clap(
+        long,
+        conflicts_with = "json",
+        help = "Show the selected entry as a QR code, for scanning it into another phone"
+    )]
+    qr: bool,
+    #[clap(
+        long,
+        conflicts_with_all = ["json", "qr"],
+        help = "Show everything about the selected entry: note, groups, parameters, last use, QR code and the next codes"
+    )]
+    details: bool,
+    #[clap(
+        long,
+        env = "AEGIS_KDF_THREADS",
+        help = "Maximum number of threads used to derive the vault key [default: CPU cores]"
+    )]
+    kdf_threads: Option<usize>,
+    #[clap(
+        long,
+        env = "AEGIS_MMAP",
+        help = "Memory map the vault file instead of reading it, for very large backups"
+    )]
+    mmap: bool,
+    #[clap(
+        long,
+        env = "AEGIS_PROMPT_TIMEOUT",
+        value_parser = interrupt::parse_timeout,
+        help = "Exit with code 124 if a prompt isn't answered in time, e.g. \"30s\" or \"2m\""
+    )]
+    prompt_timeout: Option<Duration>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Tools for diagnosing code mismatches
+    #[clap(subcommand)]
+    Debug(DebugCommand),
+    /// Check that the environment produces correct codes
+    Doctor,
+    /// Performance measurements
+    #[clap(subcommand)]
+    Bench(BenchCommand),
+    /// Show vault information, without asking for the password
+    Info,
+    /// Export the encrypted vault for reading it elsewhere
+    Export(ExportArgs),
+    /// Check an export against its manifest and the entries of the vault
+    VerifyExport(VerifyArgs),
+    /// Icon images of the entries
+    #[clap(subcommand)]
+    Icons(IconsCommand),
+    /// Files attached to entries, stored in the vault
+    #[clap(subcommand)]
+    Attach(AttachCommand),
+    /// Recovery codes of entries, kept outside of the vault
+    #[clap(subcommand)]
+    Recovery(RecoveryCommand),
+    /// Records of generated codes, kept if the audit_log config setting is on
+    #[clap(subcommand)]
+    AuditLog(AuditCommand),
+    /// Choose a vault, password source and clipboard preference and write the config file
+    Setup,
+    /// Move the config and recovery codes to another machine
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// List the matching entries with their current codes
+    List(ListArgs),
+    /// Upload the encrypted vault, merging remote changes first
+    Push(SyncArgs),
+    /// Download the encrypted vault, merging local changes
+    Pull(SyncArgs),
+    /// Show the recorded versions of the vault file
+    History,
+    /// Restore a recorded version of the vault file
+    Checkout(CheckoutArgs),
+    /// Create a new encrypted vault without entries
+    Init(NewPasswordArgs),
+    /// Change the vault password
+    Passwd(NewPasswordArgs),
+    /// Check the vault password against known breaches
+    #[clap(subcommand)]
+    Password(PasswordCommand),
+    /// Add entries to the vault
+    #[clap(subcommand)]
+    Entry(EntryCommand),
+    /// Write QR codes of the entries as PNG or SVG images, for printed backups
+    Qr(QrArgs),
+    /// Suggest canonical issuer names and icons from the provider database
+    Tidy(TidyArgs),
+    /// Print the code of a secret given on the command line, without a vault
+    Otp(OtpArgs),
+    /// Make secrets for the server side of new enrollments, without a vault
+    #[clap(subcommand)]
+    Secret(SecretCommand),
+    /// Print the code of one entry for CI jobs, never asking for anything
+    Ci(CiArgs),
+    /// Check that the latest backup decrypts and isn't stale, for cron jobs and systemd timers
+    Check(CheckArgs),
+    /// Check a code submitted to a self-hosted service like the service would, refusing replays
+    VerifyServer(VerifyServerArgs),
+    /// Check the code on stdin for the PAM user, for requiring codes at login with pam_exec
+    PamHelper(PamHelperArgs),
+    /// Write new vault files from this one
+    #[clap(subcommand)]
+    Vault(VaultCommand),
+    /// Subcommands run by `aegis-rs-<name>` executables on PATH
+    #[clap(external_subcommand)]
+    External(Vec<OsString>),
+}
+
+#[derive(Args)]
+struct ListArgs {
+    /// Only print issuers and names, no codes are generated
+    #[clap(long)]
+    names_only: bool,
+}
+
+#[derive(Args)]
+struct PasswordInput {
+    #[clap(
+        short,
+        long,
+        env = "AEGIS_PASSWORD_FILE",
+        help = "Path to the password file"
+    )]
+    password_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "AEGIS_PASSWORD",
+        help = "Password to unlock vault",
+        conflicts_with = "password_file",
+        hide_env_values = true
+    )]
+    password: Option<String>,
+    #[clap(
+        long,
+        conflicts_with_all = ["password", "password_file"],
+        help = "Read the password from the first line of stdin, before the vault if it's read from stdin too"
+    )]
+    password_stdin: bool,
+    #[clap(
+        long,
+        env = "AEGIS_PINENTRY",
+        help = "Ask for the password with a pinentry program, e.g. /usr/bin/pinentry-gnome3"
+    )]
+    pinentry: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct EntryFilter {
+    #[clap(long, help = "Filter by entry issuer")]
+    issuer: Option<String>,
+    #[clap(long, help = "Filter by entry name")]
+    name: Option<String>,
+    #[clap(
+        long,
+        help = "Search issuers and names, terms like g:work, n:recovery and u:3f2a search groups, notes and UUID prefixes"
+    )]
+    search: Option<String>,
+}
+
+impl EntryFilter {
+    /// `groups` are the groups of the database, for resolving the group UUIDs of entries
+    fn matches(&self, entry: &LazyEntry, groups: &[Group]) -> bool {
+        if let Some(issuer) = &self.issuer {
+            if !entry
+                .issuer()
+                .to_lowercase()
+                .contains(&issuer.to_lowercase())
+            {
+                return false;
+            }
+        }
+        if let Some(name) = &self.name {
+            if !entry.name().to_lowercase().contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(search) = &self.search {
+            let search = search.to_lowercase();
+            return search
+                .split_whitespace()
+                .all(|term| Self::matches_term(entry, groups, term));
+        }
+        true
+    }
+
+    /// Whether one term of a search matches, all of them have to
+    fn matches_term(entry: &LazyEntry, groups: &[Group], term: &str) -> bool {
+        if let Some(group) = term.strip_prefix("g:") {
+            let uuids = entry.groups();
+            groups
+                .iter()
+                .filter(|g| uuids.contains(&g.uuid))
+                .map(|g| g.name.clone())
+                .chain(entry.group_name())
+                .any(|name| name.to_lowercase().contains(group))
+        } else if let Some(note) = term.strip_prefix("n:") {
+            entry.note().to_lowercase().contains(note)
+        } else if let Some(uuid) = term.strip_prefix("u:") {
+            entry
+                .uuid()
+                .is_some_and(|u| u.to_lowercase().starts_with(uuid))
+        } else {
+            entry.issuer().to_lowercase().contains(term)
+                || entry.name().to_lowercase().contains(term)
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct CalculatedOtp {
+    issuer: String,
+    name: String,
+    otp: String,
+    remaining_time: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra: Option<BTreeMap<String, String>>,
+}
+
+/// Password from the command line, falling back to the configured source
+struct ConfiguredPassword<'a> {
+    input: PasswordInput,
+    source: &'a PasswordSource,
+    vault_file: &'a std::path::Path,
+}
+
+impl PasswordGetter for ConfiguredPassword<'_> {
+    fn get_password(&self) -> Result<String> {
+        if self.input.password.is_some()
+            || self.input.password_file.is_some()
+            || self.input.password_stdin
+        {
+            return self.input.get_password();
+        }
+        match self.source {
+            PasswordSource::Prompt => self.input.get_password(),
+            PasswordSource::File { path } => {
+                let password = fs::read_to_string(path).map_err(|e| {
+                    eyre!(tr!(
+                        "password-file-failed",
+                        path = path.display(),
+                        error = e
+                    ))
+                })?;
+                Ok(password.trim().to_string())
+            }
+            PasswordSource::Keyring => keyring::lookup(self.vault_file),
+        }
+    }
+}
+
+impl PasswordGetter for PasswordInput {
+    fn get_password(&self) -> Result<String> {
+        if self.password_stdin {
+            return StdinPassword.get_password();
+        }
+        match (&self.password, &self.password_file) {
+            (Some(password), None) => Ok(password.clone()),
+            (None, Some(password_file)) => {
+                let password = fs::read_to_string(password_file)?;
+                Ok(password.trim().to_string())
+            }
+            _ => interrupt::prompt(|| match &self.pinentry {
+                Some(program) => pinentry::get_password(
+                    program,
+                    &tr!("pinentry-description"),
+                    &tr!("password-prompt"),
+                ),
+                None => Password::with_theme(theme::dialog())
+                    .with_prompt(tr!("password-prompt"))
+                    .interact()
+                    .map_err(|e| eyre!(tr!("password-failed", error = e))),
+            }),
+        }
+    }
+}
+
+/// Vault read from stdin, after the password on the first line with `--password-stdin`
+fn read_stdin_vault(input: &mut PasswordInput) -> Result<VaultFile> {
+    let mut contents = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::stdin(), &mut contents)?;
+    if input.password_stdin {
+        let end = contents
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or(eyre!("No vault after the password on stdin"))?;
+        let line: Vec<u8> = contents.drain(..=end).collect();
+        let password = String::from_utf8(line).map_err(|_| eyre!("The password is not UTF-8"))?;
+        input.password = Some(password.trim_end_matches(['\r', '\n']).to_string());
+        input.password_stdin = false;
+    }
+    Ok(VaultFile::Buffered(contents))
+}
+
+fn print_otp_every_second(
+    lazy_entry: &LazyEntry,
+    entry: &Entry,
+    format_rules: &[FormatRule],
+    clipboard: bool,
+    hooks: &hooks::Context,
+    audit: &audit::Recorder,
+) -> Result<()> {
+    let entry_info = &entry.info;
+    let term = Term::stdout();
+    term.hide_cursor()?;
+
+    let mut clipboard = clipboard.then(terminal::Clipboard::new).flatten();
+    let mut generator = Generator::default();
+    let mut otp_code = String::new();
+    let mut last_remaining_time = 0;
+
+    loop {
+        let remaining_time = calculate_remaining_time(entry_info)?;
+        if last_remaining_time < remaining_time {
+            audit.check(lazy_entry)?;
+            otp_code = generator.generate(entry_info)?;
+            if let Some(clipboard) = clipboard.as_mut() {
+                clipboard.set_text(&otp_code)?;
+            }
+            hooks.post_code(lazy_entry);
+            audit.record(lazy_entry, "cli select");
+        }
+
+        let style = match remaining_time {
+            0..=5 => &theme::current().error,
+            6..=15 => &theme::current().warning,
+            _ => &theme::current().success,
+        };
+        let line = style.clone().bold().apply_to(tr!(
+            "code-time-left",
+            code = format_code(&otp_code, entry, format_rules),
+            seconds = remaining_time
+        ));
+        term.write_line(line.to_string().as_str())?;
+        std::thread::sleep(Duration::from_secs(1));
+        term.clear_last_lines(1)?;
+        last_remaining_time = remaining_time;
+    }
+}
+
+fn entries_to_json(
+    entries: &[LazyEntry],
+    show_extra: bool,
+    format_rules: &[FormatRule],
+    hooks: &hooks::Context,
+    audit: &audit::Recorder,
+) -> Result<()> {
+    let output: Vec<CalculatedOtp> = entries
+        .iter()
+        .map(|lazy_entry| {
+            let entry = lazy_entry.deserialize()?;
+            let entry = &entry;
+            audit.check(lazy_entry)?;
+            let otp = CalculatedOtp {
+                issuer: entry.issuer.clone(),
+                name: entry.name.clone(),
+                otp: format_code(&generate_otp(&entry.info)?, entry, format_rules),
+                remaining_time: calculate_remaining_time(&entry.info)?,
+                extra: show_extra.then(|| entry.extra_fields().into_iter().collect()),
+            };
+            hooks.post_code(lazy_entry);
+            audit.record(lazy_entry, "cli json");
+            Ok(otp)
+        })
+        .collect::<Result<Vec<CalculatedOtp>>>()?;
+    if output.is_empty() {
+        println!("{}", tr!("no-entries"));
+    } else {
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    }
+    Ok(())
+}
+
+fn print_info(vault_file: &std::path::Path, vault: &Vault) {
+    println!("{}", tr!("info-file", path = vault_file.display()));
+    println!("{}", tr!("info-version", version = vault.version));
+    let encrypted = if vault.is_encrypted() {
+        tr!("yes")
+    } else {
+        tr!("no")
+    };
+    println!("{}", tr!("info-encrypted", encrypted = encrypted));
+    for slot in vault.slots() {
+        println!("{}", tr!("info-slot", slot = slot));
+    }
+    match &vault.provenance {
+        Some(provenance) => println!(
+            "{}",
+            tr!(
+                "info-written",
+                tool = provenance.tool,
+                version = provenance.version,
+                time = audit::format_time(provenance.written_at)
+            )
+        ),
+        None => println!("{}", tr!("info-written-by-app")),
+    }
+    for weakness in vault.weaknesses() {
+        println!("{}", tr!("info-weakness", weakness = weakness));
+    }
+}
+
+fn list_entries(
+    entries: &[LazyEntry],
+    list_args: &ListArgs,
+    format_rules: &[FormatRule],
+    hooks: &hooks::Context,
+    audit: &audit::Recorder,
+) -> Result<()> {
+    for lazy_entry in entries {
+        let label = format!(
+            "{} ({})",
+            lazy_entry.issuer().trim(),
+            lazy_entry.name().trim()
+        );
+        if list_args.names_only {
+            println!("{}", label);
+            continue;
+        }
+        if lazy_entry.entry_type() == "hotp" {
+            // No code in its place, the counter in the vault stays as it is
+            let dim = &theme::current().dim;
+            println!(
+                "{}  {}  {}",
+                dim.apply_to("-"),
+                label,
+                dim.apply_to(tr!("list-hotp"))
+            );
+            continue;
+        }
+        let entry = lazy_entry.deserialize()?;
+        audit.check(lazy_entry)?;
+        let code = format_code(&generate_otp(&entry.info)?, &entry, format_rules);
+        println!("{}  {}", code, label);
+        hooks.post_code(lazy_entry);
+        audit.record(lazy_entry, "cli list");
+    }
+    Ok(())
+}
+
+fn print_extra_fields(entry: &Entry) {
+    for (label, value) in entry.extra_fields() {
+        println!("{}: {}", theme::current().dim.apply_to(label), value);
+    }
+}
+
+/// What is shown of the entry picked in the fuzzy selection
+enum View<'a> {
+    Codes,
+    Qr,
+    /// Everything about the entry, with the groups of the vault for their names
+    Details(&'a [Group]),
+}
+
+fn fuzzy_select(
+    entries: &[LazyEntry],
+    show_extra: bool,
+    view: View,
+    format_rules: &[FormatRule],
+    clipboard: bool,
+    hooks: &hooks::Context,
+    audit: &audit::Recorder,
+) -> Result<()> {
+    let items: Vec<String> = entries
+        .iter()
+        .map(|entry| format!("{} ({})", entry.issuer().trim(), entry.name().trim()))
+        .collect();
+    let selection = interrupt::prompt(|| {
+        FuzzySelect::with_theme(theme::dialog())
+            .items(&items)
+            .default(0)
+            .interact_opt()
+    })?;
+    match selection {
+        Some(index) => {
+            let lazy_entry = entries.get(index).unwrap();
+            let entry = lazy_entry.deserialize()?;
+            match view {
+                View::Codes => {
+                    if show_extra {
+                        print_extra_fields(&entry);
+                    }
+                    print_otp_every_second(
+                        lazy_entry,
+                        &entry,
+                        format_rules,
+                        clipboard,
+                        hooks,
+                        audit,
+                    )?
+                }
+                View::Qr => {
+                    audit.check(lazy_entry)?;
+                    terminal::print_qr_code(&QrCode::encode(entry.to_otpauth_uri().as_bytes())?);
+                    audit.record(lazy_entry, "cli qr");
+                }
+                View::Details(groups) => {
+                    details::print(lazy_entry, &entry, groups, format_rules, audit)?
+                }
+            }
+        }
+        None => {
+            println!("{}", tr!("no-selection"));
+        }
+    }
+    Ok(())
+}
+
+/// Unlock the decoy vault at `path` with the password which didn't open the real one
+fn unlock_decoy(
+    path: &std::path::Path,
+    password: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<(VaultFile, Database, Option<MasterKey>)> {
+    let contents = VaultFile::open(path, false)?;
+    let (db, master_key) = Vault::parse(contents.as_str()?)?.unlock_with_key(password, options)?;
+    Ok((contents, db, master_key))
+}
+
+/// Arguments of an external subcommand together with the vault file given for it
+///
+/// A plugin name without a vault file before it is parsed as the vault file, it's taken as the
+/// subcommand if no such file exists but the plugin does.
+fn plugin_command(args: &Cli) -> Option<(Vec<OsString>, Option<&std::path::Path>)> {
+    let vault_file = args.vault_file.as_deref();
+    let misparsed = vault_file
+        .filter(|vault_file| !vault_file.exists())
+        .filter(|vault_file| plugin::find(vault_file.as_os_str()).is_some());
+    match (&args.command, misparsed) {
+        (Some(Command::External(external)), Some(name)) => {
+            let mut plugin_args = vec![name.as_os_str().to_os_string()];
+            plugin_args.extend(external.iter().cloned());
+            Some((plugin_args, None))
+        }
+        (Some(Command::External(external)), None) => Some((external.clone(), vault_file)),
+        (None, Some(name)) => Some((vec![name.as_os_str().to_os_string()], None)),
+        _ => None,
+    }
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = Cli::parse();
+    let loaded = match args.no_config {
+        true => Ok(Config::default()),
+        false => Config::load(args.config.as_ref()),
+    }
+    .map(|mut config| {
+        if args.no_cache {
+            config.history = false;
+            config.audit_log = false;
+        }
+        config
+    });
+    config::init_data_dir(args.data_dir.clone(), args.no_cache);
+    // Nothing of the config applies but the audit log and its rate limits
+    if let Some(Command::Ci(ci_args)) = &args.command {
+        let options = DecryptOptions {
+            kdf_threads: args.kdf_threads,
+            ..Default::default()
+        };
+        exit(ci::run(
+            ci_args,
+            args.vault_file.as_deref(),
+            args.password_input,
+            &options,
+            loaded.as_ref(),
+        ));
+    }
+    let interrupted = interrupt::install();
+    if let Some(timeout) = args.prompt_timeout {
+        interrupt::set_prompt_timeout(timeout);
+    }
+    let config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    theme::init(config.theme);
+    let format_rules = config.format_rules();
+
+    if let Some((plugin_args, vault_file)) = plugin_command(&args) {
+        let context = plugin::Context {
+            vault_file: vault_file.or(config.vault_file.as_deref()),
+            config_file: args.config.as_deref(),
+        };
+        match interrupt::defer(|| plugin::run(&plugin_args, &context)) {
+            Ok(code) => exit(code),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::Config(command)) = &args.command {
+        let config_path = match args.config.clone().or_else(Config::default_path) {
+            Some(path) => path,
+            None => {
+                eprintln!("{}", tr!("no-config-location"));
+                exit(1);
+            }
+        };
+        let options = DecryptOptions {
+            kdf_threads: args.kdf_threads,
+            cancel: Some(interrupted.clone().into()),
+            ..Default::default()
+        };
+        if let Err(e) = bundle::run(command, config, &config_path, &options) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Setup) = &args.command {
+        let config_path = match args.config.clone().or_else(Config::default_path) {
+            Some(path) => path,
+            None => {
+                eprintln!("{}", tr!("no-config-location"));
+                exit(1);
+            }
+        };
+        if let Err(e) = setup::run(config, &config_path) {
+            eprintln!("{}", tr!("setup-failed", error = e));
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    let vault_file = args.vault_file.clone().or(config.vault_file.clone());
+    // Commands which don't need the vault entries
+    let result = match &args.command {
+        Some(Command::Doctor) => Some(doctor::run(vault_file.as_deref())),
+        Some(Command::Bench(command)) => Some(bench::run(command)),
+        Some(Command::Password(PasswordCommand::BuildFilter(filter_args))) => {
+            Some(password::build_filter(filter_args))
+        }
+        Some(Command::Otp(otp_args)) => Some(otp::run(otp_args, args.json)),
+        Some(Command::Secret(SecretCommand::New(secret_args))) => {
+            Some(otp::new_secret(secret_args, args.json))
+        }
+        Some(Command::Entry(EntryCommand::WellKnown)) => {
+            entry::well_known(&config.providers());
+            Some(Ok(()))
+        }
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    let vault_file = match &vault_file {
+        Some(vault_file) => vault_file,
+        None => {
+            eprintln!("{}", tr!("no-vault-file"));
+            exit(1);
+        }
+    };
+    let stdin_vault = vault_file == std::path::Path::new("-");
+    let read_only = matches!(
+        args.command,
+        None | Some(
+            Command::List(_)
+                | Command::Info
+                | Command::Debug(_)
+                | Command::Export(_)
+                | Command::VerifyExport(_)
+                | Command::Icons(_)
+                | Command::Recovery(_)
+                | Command::Qr(_)
+                | Command::VerifyServer(_)
+        )
+    );
+    // Dry runs never write the vault
+    let changes_entries = matches!(
+        args.command,
+        Some(Command::Entry(_) | Command::Attach(_) | Command::Tidy(_))
+    );
+    if args.dry_run && !changes_entries {
+        eprintln!("{}", tr!("dry-run-unsupported"));
+        exit(1);
+    }
+    if stdin_vault && !read_only && !args.dry_run {
+        eprintln!("{}", tr!("vault-stdin-read-only"));
+        exit(1);
+    }
+    let hooks = hooks::Context {
+        hooks: &config.hooks,
+        vault_file,
+    };
+    let decrypt_options = DecryptOptions {
+        kdf_threads: args.kdf_threads,
+        cancel: Some(interrupted.into()),
+        ..Default::default()
+    };
+
+    // Commands which may create or replace the vault file, so it's not read here
+    let result = match &args.command {
+        Some(Command::History) => Some(history::log(vault_file)),
+        Some(Command::Init(init_args)) => {
+            Some(password::init(init_args, vault_file, &decrypt_options))
+        }
+        Some(Command::Checkout(checkout_args)) => {
+            Some(hooks.watch_write("checkout", || history::checkout(checkout_args, vault_file)))
+        }
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+    let sync = match &args.command {
+        Some(Command::Push(sync_args)) => Some((Direction::Push, sync_args)),
+        Some(Command::Pull(sync_args)) => Some((Direction::Pull, sync_args)),
+        _ => None,
+    };
+    if let Some((direction, sync_args)) = sync {
+        let password = ConfiguredPassword {
+            input: args.password_input,
+            source: &config.password_source,
+            vault_file,
+        };
+        let name = match direction {
+            Direction::Push => "Push to the remote vault",
+            Direction::Pull => "Pull from the remote vault",
+        };
+        let result = interrupt::defer(|| {
+            history::record(
+                config.history,
+                vault_file,
+                "Vault changed outside of aegis-rs",
+            )?;
+            hooks.watch_write(name, || {
+                sync::run(direction, sync_args, vault_file, password, &decrypt_options)
+            })?;
+            history::record(config.history, vault_file, name)
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Check(check_args)) = &args.command {
+        let password = ConfiguredPassword {
+            input: args.password_input,
+            source: &config.password_source,
+            vault_file,
+        };
+        let webhook = config.webhook.as_ref();
+        match check::run(check_args, vault_file, password, &decrypt_options, webhook) {
+            Ok(true) => return Ok(()),
+            Ok(false) => exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+
+    let opened = match stdin_vault {
+        true => read_stdin_vault(&mut args.password_input),
+        false => VaultFile::open(vault_file, args.mmap),
+    };
+    // Compressed and split exports are joined into the vault JSON of a single file
+    let compressed = opened.as_ref().is_ok_and(VaultFile::is_compressed);
+    if (compressed || args.db.is_some()) && !read_only && !args.dry_run {
+        eprintln!("{}", tr!("vault-export-read-only"));
+        exit(1);
+    }
+    let opened = opened
+        .and_then(VaultFile::decompressed)
+        .and_then(|header| match &args.db {
+            Some(db) => {
+                let db = VaultFile::open(db, false)?.decompressed()?;
+                let joined = join_split_vault(header.as_bytes(), db.as_bytes())?;
+                Ok(VaultFile::Buffered(joined.into_bytes()))
+            }
+            None => Ok(header),
+        });
+    let file_contents = match opened {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("{}", tr!("vault-read-failed", error = e));
+            exit(1);
+        }
+    };
+    let vault = match file_contents.as_str().and_then(Vault::parse) {
+        Ok(vault) => vault,
+        Err(e) => {
+            eprintln!("{}", tr!("vault-open-failed", error = e));
+            exit(1);
+        }
+    };
+    if let Some(Command::Info) = &args.command {
+        print_info(vault_file, &vault);
+        return Ok(());
+    }
+    // Broken parameters are warned about on every use, until the vault is rekeyed
+    let weaknesses = vault.weaknesses();
+    let warning = theme::current().warning.clone().bold();
+    for weakness in &weaknesses {
+        eprintln!(
+            "{}",
+            warning.apply_to(tr!("vault-weakness", weakness = weakness))
+        );
+    }
+    if !weaknesses.is_empty() {
+        eprintln!("{}", tr!("vault-weakness-hint"));
+    }
+
+    // The password is only requested here, once the entries are needed
+    let password = CachedPassword::new(ConfiguredPassword {
+        input: args.password_input,
+        source: &config.password_source,
+        vault_file,
+    });
+    if let Err(e) = hooks.pre_unlock() {
+        eprintln!("{}", e);
+        exit(1);
+    }
+    // Ctrl-C cancels the key derivation, the database is dropped before exiting
+    let unlocked = interrupt::defer(|| {
+        if let Some(path) = &args.raw_key_file {
+            let key = fs::read_to_string(path)
+                .map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+            let key = Zeroizing::new(key);
+            let key = MasterKey::from_hex(&key)?;
+            return vault
+                .unlock_with_raw_key(&key)
+                .map(|(db, master_key)| (None, db, master_key));
+        }
+        let error = match vault.unlock_with_key(&password, &decrypt_options) {
+            Ok((db, master_key)) => return Ok((None, db, master_key)),
+            Err(e) => e,
+        };
+        // A password which doesn't open the vault may open the decoy, without telling so
+        match &config.decoy_vault_file {
+            Some(decoy) => unlock_decoy(decoy, &password, &decrypt_options)
+                .map(|(contents, db, master_key)| (Some((decoy, contents)), db, master_key))
+                .map_err(|_| error),
+            None => Err(error),
+        }
+    });
+    // The webhook isn't told whether the decoy was unlocked, just like everything else
+    let (decoy, db, master_key) = match unlocked {
+        Ok(unlocked) => {
+            if let Some(webhook) = &config.webhook {
+                webhook.send("unlocked", vault_file, serde_json::json!({}));
+            }
+            unlocked
+        }
+        Err(e) => {
+            if let Some(webhook) = &config.webhook {
+                let fields = serde_json::json!({ "error": e.to_string() });
+                webhook.send("unlock_failed", vault_file, fields);
+            }
+            eprintln!("{}", tr!("vault-open-failed", error = e));
+            exit(1);
+        }
+    };
+    // Commands writing the vault work on the decoy if it was unlocked
+    let (vault_file, file_contents) = match decoy {
+        Some((decoy, contents)) => (decoy, contents),
+        None => (vault_file, file_contents),
+    };
+
+    let audit =
+        match audit::Recorder::new(config.audit_log, master_key.as_ref(), &config.rate_limits) {
+            Ok(audit) => audit,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        };
+
+    // Commands working on the whole vault
+    if let Some(Command::Export(export_args)) = &args.command {
+        let profiles = &config.export_profiles;
+        let exported = export::run(export_args, file_contents.as_str()?, &db, profiles);
+        drop(db);
+        if let Err(e) = exported {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Command::VerifyExport(verify_args)) = &args.command {
+        let verified = export::verify(verify_args, &db);
+        drop(db);
+        if let Err(e) = verified {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Attach(command)) = &args.command {
+        let vault = attach::Target {
+            path: vault_file,
+            contents: file_contents.as_str()?,
+            master_key: master_key.as_ref(),
+            dry_run: args.dry_run,
+            json: args.json,
+        };
+        let result = history::record(
+            config.history,
+            vault_file,
+            "Vault changed outside of aegis-rs",
+        )
+        .and_then(|_| hooks.watch_write("attach", || attach::run(command, db, &vault)))
+        .and_then(|_| history::record(config.history, vault_file, "Change attachments"));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Entry(command)) = &args.command {
+        let vault = attach::Target {
+            path: vault_file,
+            contents: file_contents.as_str()?,
+            master_key: master_key.as_ref(),
+            dry_run: args.dry_run,
+            json: args.json,
+        };
+        let providers = config.providers();
+        let result = history::record(
+            config.history,
+            vault_file,
+            "Vault changed outside of aegis-rs",
+        )
+        .and_then(|_| match command {
+            EntryCommand::Add(add_args) => {
+                hooks.watch_write("entry add", || entry::add(add_args, &providers, db, &vault))
+            }
+            EntryCommand::ImportSteam(import_args) => hooks
+                .watch_write("entry import-steam", || {
+                    entry::import_steam(import_args, db, &vault)
+                }),
+            EntryCommand::NextHotp(next_args) => hooks.watch_write("entry next-hotp", || {
+                entry::next_hotp(next_args, &format_rules, db, &vault, &audit)
+            }),
+            EntryCommand::Import(import_args) => {
+                hooks.watch_write("entry import", || entry::import(import_args, db, &vault))
+            }
+            EntryCommand::WellKnown => unreachable!("Handled before unlocking the vault"),
+        })
+        .and_then(|_| {
+            let message = match command {
+                EntryCommand::NextHotp(_) => "Increment an HOTP counter",
+                EntryCommand::Import(_) => "Import entries",
+                _ => "Add an entry",
+            };
+            history::record(config.history, vault_file, message)
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Tidy(tidy_args)) = &args.command {
+        let vault = attach::Target {
+            path: vault_file,
+            contents: file_contents.as_str()?,
+            master_key: master_key.as_ref(),
+            dry_run: args.dry_run,
+            json: args.json,
+        };
+        let providers = config.providers();
+        let result = history::record(
+            config.history,
+            vault_file,
+            "Vault changed outside of aegis-rs",
+        )
+        .and_then(|_| hooks.watch_write("tidy", || tidy::run(tidy_args, &providers, db, &vault)))
+        .and_then(|_| history::record(config.history, vault_file, "Tidy issuers and icons"));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Passwd(passwd_args)) = &args.command {
+        drop(db);
+        let contents = file_contents.as_str()?;
+        let result = history::record(
+            config.history,
+            vault_file,
+            "Vault changed outside of aegis-rs",
+        )
+        .and_then(|_| {
+            hooks.watch_write("passwd", || {
+                password::passwd(
+                    passwd_args,
+                    vault_file,
+                    contents,
+                    master_key.as_ref(),
+                    &decrypt_options,
+                )
+            })
+        })
+        .and_then(|_| history::record(config.history, vault_file, "Change the password"));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Vault(VaultCommand::Rekey(rekey_args))) = &args.command {
+        let contents = file_contents.as_str()?;
+        let result = history::record(
+            config.history,
+            vault_file,
+            "Vault changed outside of aegis-rs",
+        )
+        .and_then(|_| {
+            hooks.watch_write("rekey", || {
+                vaults::rekey(
+                    rekey_args,
+                    vault_file,
+                    contents,
+                    &db,
+                    master_key.as_ref(),
+                    &password,
+                    &decrypt_options,
+                )
+            })
+        })
+        .and_then(|_| history::record(config.history, vault_file, "Replace the master key"));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Password(PasswordCommand::Check(check_args))) = &args.command {
+        let result = match master_key {
+            Some(_) => (&password)
+                .get_password()
+                .and_then(|password| password::check(check_args, &password)),
+            None => Err(eyre!("The vault is not encrypted, it has no password")),
+        };
+        match result {
+            Ok(false) => return Ok(()),
+            Ok(true) => exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(2);
+            }
+        }
+    }
+
+    if let Some(Command::Vault(VaultCommand::Reencrypt(reencrypt_args))) = &args.command {
+        let result = file_contents.as_str().and_then(|contents| {
+            vaults::reencrypt(
+                reencrypt_args,
+                contents,
+                &db,
+                master_key.as_ref(),
+                &decrypt_options,
+            )
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::VerifyServer(verify_args)) = &args.command {
+        match server::run(verify_args, &db, args.json) {
+            Ok(exit_code) => exit(exit_code),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(server::EXIT_FAILED);
+            }
+        }
+    }
+
+    if let Some(Command::PamHelper(pam_args)) = &args.command {
+        match server::pam_helper(pam_args, &db) {
+            Ok(exit_code) => exit(exit_code),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(server::EXIT_FAILED);
+            }
+        }
+    }
+
+    if let Some(Command::Vault(VaultCommand::Extract(extract_args))) = &args.command {
+        let result = vaults::extract(
+            extract_args,
+            &db,
+            master_key.as_ref(),
+            &password,
+            &decrypt_options,
+        );
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::AuditLog(command)) = &args.command {
+        drop(db);
+        if let Err(e) = audit::run(command, master_key.as_ref()) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+    // Entries are only deserialized once they are used
+    let groups = db.groups().unwrap_or_default();
+    let entries = db
+        .into_lazy_entries()
+        .into_iter()
+        // HOTP codes come from `entry next-hotp`, generating one moves the counter forward and
+        // has to be saved to the vault. `list` and `--details` show HOTP entries without taking
+        // a code, recovery codes, icons and QR codes don't need codes to be generated
+        .filter(|e| {
+            ["totp", "steam", "yandex", "motp"].contains(&e.entry_type())
+                || (args.details && e.entry_type() == "hotp")
+                || matches!(
+                    args.command,
+                    Some(
+                        Command::Recovery(_)
+                            | Command::Icons(_)
+                            | Command::Qr(_)
+                            | Command::List(_)
+                    )
+                )
+        })
+        .filter(|e| args.entry_filter.matches(e, &groups))
+        .collect::<Vec<LazyEntry>>();
+
+    if entries.is_empty() {
+        println!("{}", tr!("no-matching-entries"));
+        return Ok(());
+    }
+
+    match &args.command {
+        Some(Command::Debug(command)) => debug::run(command, &entries, &format_rules, &audit)?,
+        Some(Command::Recovery(command)) => recovery::run(command, &entries, master_key.as_ref())?,
+        Some(Command::Icons(command)) => icons::run(command, &entries)?,
+        Some(Command::Qr(qr_args)) => qr::run(qr_args, &entries, &audit)?,
+        Some(Command::List(list_args)) => {
+            list_entries(&entries, list_args, &format_rules, &hooks, &audit)?
+        }
+        Some(
+            Command::Doctor
+            | Command::Bench(_)
+            | Command::Info
+            | Command::Setup
+            | Command::Config(_)
+            | Command::Attach(_)
+            | Command::Export(_)
+            | Command::VerifyExport(_)
+            | Command::Push(_)
+            | Command::Pull(_)
+            | Command::History
+            | Command::Checkout(_)
+            | Command::Init(_)
+            | Command::Passwd(_)
+            | Command::Password(_)
+            | Command::Entry(_)
+            | Command::Tidy(_)
+            | Command::AuditLog(_)
+            | Command::Vault(_)
+            | Command::Otp(_)
+            | Command::Secret(_)
+            | Command::Ci(_)
+            | Command::Check(_)
+            | Command::VerifyServer(_)
+            | Command::PamHelper(_)
+            | Command::External(_),
+        ) => {
+            unreachable!("Handled before unlocking the vault")
+        }
+        None if args.json => {
+            entries_to_json(&entries, args.show_extra, &format_rules, &hooks, &audit)?
+        }
+        None => fuzzy_select(
+            &entries,
+            args.show_extra,
+            match (args.qr, args.details) {
+                (true, _) => View::Qr,
+                (_, true) => View::Details(&groups),
+                _ => View::Codes,
+            },
+            &format_rules,
+            config.clipboard,
+            &hooks,
+            &audit,
+        )?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use color_eyre::eyre::{eyre, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+/// Webhook receiving events of the vault's use, like unlocks and failed backup checks
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    /// URL the events are POSTed to as JSON
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature of the body, sent in the X-Aegis-Signature header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl Webhook {
+    /// POST the event with `fields` added, failures are only reported
+    pub fn send(&self, event: &str, vault_file: &Path, fields: Value) {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut body = json!({ "event": event, "vault_file": vault_file, "time": time });
+        if let (Some(body), Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+        if let Err(e) = webhook(&self.url, &body, self.secret.as_deref()) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Show a desktop notification, with notify-send on Linux and osascript on macOS
+pub fn desktop(title: &str, body: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let quote = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification \"{}\" with title \"{}\"",
+            quote(body),
+            quote(title)
+        ));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--urgency", "critical", "--app-name", "aegis-rs"]);
+        command.arg(title).arg(body);
+        command
+    };
+    let name = command.get_program().to_string_lossy().to_string();
+    let output = command
+        .output()
+        .map_err(|e| eyre!("Failed to run {}: {}", name, e))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// POST the event as JSON to the URL with curl, which reads the body from stdin
+///
+/// With a `secret`, the body is signed like GitHub webhooks, in `X-Aegis-Signature: sha256=<hex>`.
+fn webhook(url: &str, event: &Value, secret: Option<&str>) -> Result<()> {
+    let body = event.to_string();
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        // Events are sent on every unlock, an unreachable host mustn't hold up the command
+        .args(["--connect-timeout", "5", "--max-time", "10"])
+        .args(["--header", "Content-Type: application/json"]);
+    if let Some(secret) = secret {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key size");
+        mac.update(body.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        command.args([
+            "--header",
+            &format!("X-Aegis-Signature: sha256={}", signature),
+        ]);
+    }
+    let mut child = command
+        .args(["--data-binary", "@-"])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| eyre!("Failed to run curl: {}", e))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(body.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Request to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::Result;
+
+use aegis_rs::{
+    otp::{
+        calculate_remaining_time, generate_otp, random_secret, Entry, EntryInfo, EntryInfoTotp,
+        HashAlgorithm, SECRET_BYTES,
+    },
+    qr::QrCode,
+};
+
+use crate::{
+    entry::{parse_algo, read_secret},
+    terminal,
+};
+
+#[derive(Args)]
+pub struct OtpArgs {
+    /// Base32 secret, asked for if not given so it doesn't end up in the shell history
+    #[clap(long, env = "AEGIS_OTP_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+    /// Code length
+    #[clap(long, default_value_t = 6)]
+    digits: i32,
+    /// Seconds a code is valid
+    #[clap(long, default_value_t = 30)]
+    period: i32,
+    /// SHA1, SHA256 or SHA512
+    #[clap(long, value_parser = parse_algo, default_value = "SHA1")]
+    algo: HashAlgorithm,
+}
+
+#[derive(Subcommand)]
+pub enum SecretCommand {
+    /// Print a new random base32 secret, with its otpauth:// URI if the account is given
+    New(NewSecretArgs),
+}
+
+#[derive(Args)]
+pub struct NewSecretArgs {
+    /// Length of the secret before base32 encoding
+    #[clap(long, default_value_t = SECRET_BYTES, value_parser = parse_bytes)]
+    bytes: usize,
+    /// Issuer of the otpauth:// URI
+    #[clap(long, requires = "name")]
+    issuer: Option<String>,
+    /// Account name of the otpauth:// URI
+    #[clap(long)]
+    name: Option<String>,
+    /// Code length of the otpauth:// URI
+    #[clap(long, default_value_t = 6, requires = "name")]
+    digits: i32,
+    /// Seconds a code of the otpauth:// URI is valid
+    #[clap(long, default_value_t = 30, requires = "name")]
+    period: i32,
+    /// SHA1, SHA256 or SHA512
+    #[clap(long, value_parser = parse_algo, default_value = "SHA1", requires = "name")]
+    algo: HashAlgorithm,
+    /// Also show the otpauth:// URI as a QR code to scan
+    #[clap(long, requires = "name")]
+    qr: bool,
+}
+
+fn parse_bytes(bytes: &str) -> Result<usize, String> {
+    match bytes.parse() {
+        Ok(bytes @ 10..=64) => Ok(bytes),
+        _ => Err("Secrets have 10 to 64 bytes".to_string()),
+    }
+}
+
+/// Print a new secret, for the server side of an enrollment
+pub fn new_secret(args: &NewSecretArgs, json: bool) -> Result<()> {
+    let secret = random_secret(args.bytes);
+    let uri = match &args.name {
+        Some(name) => {
+            let info = EntryInfo::Totp(EntryInfoTotp {
+                secret: secret.clone(),
+                algo: args.algo,
+                digits: args.digits,
+                period: args.period,
+            });
+            info.validate()?;
+            let issuer = args.issuer.clone().unwrap_or_default();
+            Some(Entry::new(issuer, name.clone(), info).to_otpauth_uri())
+        }
+        None => None,
+    };
+    if json {
+        let output = serde_json::json!({"secret": secret, "uri": uri});
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        println!("{}", secret);
+        if let Some(uri) = &uri {
+            println!("{}", uri);
+        }
+    }
+    if let Some(uri) = uri.as_ref().filter(|_| args.qr) {
+        terminal::print_qr_code(&QrCode::encode(uri.as_bytes())?);
+    }
+    Ok(())
+}
+
+/// Print the current TOTP code of a secret, without any vault
+pub fn run(args: &OtpArgs, json: bool) -> Result<()> {
+    let info = EntryInfo::Totp(EntryInfoTotp {
+        secret: read_secret(args.secret.as_deref())?,
+        algo: args.algo,
+        digits: args.digits,
+        period: args.period,
+    });
+    info.validate()?;
+    let code = generate_otp(&info)?;
+    if json {
+        let output = serde_json::json!({
+            "otp": code,
+            "remaining_time": calculate_remaining_time(&info)?,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        println!("{}", code);
+    }
+    Ok(())
+}
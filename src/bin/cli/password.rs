@@ -0,0 +1,189 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use dialoguer::{Confirm, Password};
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use zeroize::Zeroizing;
+
+use aegis_rs::{
+    breach::{count_in_range, password_hash, range_prefix, BloomFilter},
+    strength,
+    vault::{create_vault, set_password, write_atomic, DecryptOptions, MasterKey},
+};
+
+use crate::{i18n::tr, interrupt, theme};
+
+#[derive(Args)]
+pub struct NewPasswordArgs {
+    /// Lowest strength score from 0 to 4 accepted without asking, 0 accepts any password
+    #[clap(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=4))]
+    min_strength: u8,
+}
+
+#[derive(Subcommand)]
+pub enum PasswordCommand {
+    /// Check whether the vault password appears in known breaches
+    Check(CheckArgs),
+    /// Build a bloom filter for `check --offline` from a Have I Been Pwned SHA-1 hash list
+    BuildFilter(BuildFilterArgs),
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// Look the password up in a bloom filter file instead of asking the range API
+    #[clap(long, value_name = "FILTER")]
+    offline: Option<PathBuf>,
+    /// Range API, only the first 5 characters of the password's SHA-1 hash are sent
+    #[clap(
+        long,
+        env = "AEGIS_BREACH_API",
+        default_value = "https://api.pwnedpasswords.com/range/"
+    )]
+    api: String,
+}
+
+#[derive(Args)]
+pub struct BuildFilterArgs {
+    /// Hash list with a `HASH:COUNT` line per breached password
+    hashes: PathBuf,
+    /// Bloom filter file to write
+    #[clap(short, long)]
+    output: PathBuf,
+    /// Share of passwords which are wrongly reported as breached
+    #[clap(long, default_value_t = 0.001)]
+    false_positive_rate: f64,
+}
+
+/// Ask for a new password twice and make sure it's strong enough, or meant to be weak
+pub fn prompt_new_password(args: &NewPasswordArgs) -> Result<Zeroizing<String>> {
+    let theme = theme::dialog();
+    let password = Zeroizing::new(interrupt::prompt(|| {
+        Password::with_theme(theme)
+            .with_prompt(tr!("new-password-prompt"))
+            .with_confirmation(tr!("new-password-repeat"), tr!("new-password-mismatch"))
+            .interact()
+    })?);
+
+    let strength = strength::estimate(&password);
+    println!(
+        "{}",
+        tr!("password-strength", score = strength.score, max = 4)
+    );
+    if let Some(warning) = strength.warning {
+        println!("{}", warning);
+    }
+    if strength.score < args.min_strength {
+        let accepted = interrupt::prompt(|| {
+            Confirm::with_theme(theme)
+                .with_prompt(tr!("weak-password-confirm"))
+                .default(false)
+                .interact()
+        })?;
+        if !accepted {
+            return Err(eyre!(tr!("weak-password-rejected")));
+        }
+    }
+    Ok(password)
+}
+
+/// Create a new encrypted vault without entries at `vault_file`
+pub fn init(args: &NewPasswordArgs, vault_file: &Path, options: &DecryptOptions) -> Result<()> {
+    if vault_file.exists() {
+        return Err(eyre!("{} already exists", vault_file.display()));
+    }
+    let password = prompt_new_password(args)?;
+    let contents = create_vault(&password, options)?;
+    write_atomic(vault_file, contents.as_bytes())?;
+    println!("Created {}", vault_file.display());
+    Ok(())
+}
+
+/// Replace the password of the vault at `vault_file`, which has been unlocked with `master_key`
+pub fn passwd(
+    args: &NewPasswordArgs,
+    vault_file: &Path,
+    contents: &str,
+    master_key: Option<&MasterKey>,
+    options: &DecryptOptions,
+) -> Result<()> {
+    let master_key = master_key.ok_or(eyre!("The vault is not encrypted, it has no password"))?;
+    let password = prompt_new_password(args)?;
+    let contents = set_password(contents, master_key, &password, options)?;
+    write_atomic(vault_file, contents.as_bytes())?;
+    println!("Changed the password of {}", vault_file.display());
+    Ok(())
+}
+
+/// Build a bloom filter from a hash list, without reading the vault
+pub fn build_filter(args: &BuildFilterArgs) -> Result<()> {
+    if !(args.false_positive_rate > 0.0 && args.false_positive_rate < 1.0) {
+        return Err(eyre!("The false positive rate has to be between 0 and 1"));
+    }
+    let open = || {
+        fs::File::open(&args.hashes)
+            .map(BufReader::new)
+            .map_err(|e| eyre!("Failed to open {}: {}", args.hashes.display(), e))
+    };
+    // The list is read twice, the filter is sized by the number of hashes
+    let items = open()?.lines().count();
+    let mut filter = BloomFilter::new(items, args.false_positive_rate);
+    for line in open()?.lines() {
+        let line = line?;
+        let hash = line.split(':').next().unwrap_or_default().trim();
+        if !hash.is_empty() {
+            filter.insert(hash)?;
+        }
+    }
+    write_atomic(&args.output, &filter.to_bytes())?;
+    println!(
+        "Wrote a filter of {} hashes to {}",
+        items,
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Check `password` against known breaches, returns whether it was found
+pub fn check(args: &CheckArgs, password: &str) -> Result<bool> {
+    let hash = password_hash(password);
+    if let Some(filter_file) = &args.offline {
+        let filter = BloomFilter::from_bytes(&fs::read(filter_file)?)?;
+        let found = filter.contains(&hash)?;
+        if found {
+            println!("The vault password is probably in the breach list, change it with passwd");
+        } else {
+            println!("The vault password is not in the breach list");
+        }
+        return Ok(found);
+    }
+
+    let url = format!("{}{}", args.api, range_prefix(&hash));
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        // Padding hides the number of hashes with the prefix from observers
+        .args(["--header", "Add-Padding: true"])
+        .arg(&url)
+        .output()
+        .map_err(|e| eyre!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Request to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let count = count_in_range(&String::from_utf8_lossy(&output.stdout), &hash)?;
+    if count > 0 {
+        println!(
+            "The vault password appears {} times in known breaches, change it with passwd",
+            count
+        );
+    } else {
+        println!("The vault password was not found in known breaches");
+    }
+    Ok(count > 0)
+}
@@ -0,0 +1,141 @@
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+use zeroize::Zeroizing;
+
+/// Assuan error code of a dialog closed without entering anything
+const CANCELLED: &str = "83886179";
+
+/// Connection to a pinentry program, speaking the Assuan protocol over its stdin and stdout
+struct Pinentry {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Pinentry {
+    fn spawn(program: &Path) -> Result<Pinentry> {
+        let mut child = Command::new(program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| eyre!("Failed to run {}: {}", program.display(), e))?;
+        let stdin = child.stdin.take().ok_or(eyre!("No stdin of pinentry"))?;
+        let stdout = BufReader::new(child.stdout.take().ok_or(eyre!("No stdout of pinentry"))?);
+        let mut pinentry = Pinentry {
+            child,
+            stdin,
+            stdout,
+        };
+        // The greeting is answered like a command
+        pinentry.response()?;
+        Ok(pinentry)
+    }
+
+    /// Send a command with an argument, returns the data of the response
+    fn command(&mut self, command: &str, argument: Option<&str>) -> Result<Zeroizing<Vec<u8>>> {
+        let line = match argument {
+            Some(argument) => format!("{} {}\n", command, escape(argument)),
+            None => format!("{}\n", command),
+        };
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()?;
+        self.response()
+    }
+
+    /// Read the response lines up to OK or ERR, collecting the data lines
+    fn response(&mut self) -> Result<Zeroizing<Vec<u8>>> {
+        let mut data = Zeroizing::new(Vec::new());
+        loop {
+            let mut line = Zeroizing::new(String::new());
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(eyre!("pinentry exited unexpectedly"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line == "OK" || line.starts_with("OK ") {
+                return Ok(data);
+            } else if let Some(error) = line.strip_prefix("ERR ") {
+                let (code, message) = error.split_once(' ').unwrap_or((error, ""));
+                return Err(match code {
+                    CANCELLED => eyre!("Password entry was cancelled"),
+                    _ => eyre!("pinentry failed: {}", message),
+                });
+            } else if let Some(chunk) = line.strip_prefix("D ") {
+                unescape(chunk, &mut data);
+            }
+            // Status lines and comments are of no interest
+        }
+    }
+}
+
+impl Drop for Pinentry {
+    fn drop(&mut self) {
+        let _ = self.stdin.write_all(b"BYE\n");
+        let _ = self.child.wait();
+    }
+}
+
+/// Percent-encode the characters Assuan doesn't allow in arguments
+fn escape(argument: &str) -> String {
+    argument
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Decode the percent-encoded characters of a data line into `decoded`
+fn unescape(data: &str, decoded: &mut Vec<u8>) {
+    let bytes = data.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Terminal of stdin, for pinentry programs drawing their dialog in the terminal
+fn tty_name() -> Option<String> {
+    #[cfg(unix)]
+    {
+        // SAFETY: ttyname returns a pointer to a static buffer or null, which is copied at once
+        let name = unsafe { libc::ttyname(libc::STDIN_FILENO) };
+        if !name.is_null() {
+            // SAFETY: non-null results of ttyname are nul terminated strings
+            let name = unsafe { std::ffi::CStr::from_ptr(name) };
+            return Some(name.to_string_lossy().to_string());
+        }
+    }
+    None
+}
+
+/// Ask for a password with the pinentry `program`, e.g. `/usr/bin/pinentry-gnome3`
+pub fn get_password(program: &Path, description: &str, prompt: &str) -> Result<String> {
+    let mut pinentry = Pinentry::spawn(program)?;
+    if let Some(tty) = tty_name() {
+        pinentry.command("OPTION", Some(&format!("ttyname={}", tty)))?;
+    }
+    if let Ok(term) = env::var("TERM") {
+        pinentry.command("OPTION", Some(&format!("ttytype={}", term)))?;
+    }
+    pinentry.command("SETTITLE", Some("aegis-rs"))?;
+    pinentry.command("SETDESC", Some(description))?;
+    pinentry.command("SETPROMPT", Some(prompt))?;
+    let mut pin = pinentry.command("GETPIN", None)?;
+    String::from_utf8(std::mem::take(&mut *pin)).map_err(|_| eyre!("The password is not UTF-8"))
+}
@@ -0,0 +1,81 @@
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Prefix of plugin executables, `aegis-rs-<name>` implements the subcommand `<name>`
+const PREFIX: &str = "aegis-rs-";
+
+/// Context passed to plugins in environment variables
+pub struct Context<'a> {
+    pub vault_file: Option<&'a Path>,
+    pub config_file: Option<&'a Path>,
+}
+
+/// Executable of the plugin `name` on `PATH`
+pub fn find(name: &OsStr) -> Option<PathBuf> {
+    if name.is_empty() || Path::new(name).components().count() != 1 {
+        return None;
+    }
+    let mut file_name = OsString::from(PREFIX);
+    file_name.push(name);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Run the plugin for `args`, the subcommand name followed by its arguments
+///
+/// Returns the exit code of the plugin. Plugins get the vault file in `AEGIS_VAULT_FILE`, the
+/// config file in `AEGIS_CONFIG_FILE` and this executable in `AEGIS_RS`, to call back into it.
+pub fn run(args: &[OsString], context: &Context) -> Result<i32> {
+    let (name, args) = args.split_first().ok_or(eyre!("No subcommand given"))?;
+    let executable = find(name).ok_or(eyre!(
+        "Unknown command {}, no {}{} found on PATH",
+        name.to_string_lossy(),
+        PREFIX,
+        name.to_string_lossy()
+    ))?;
+
+    let mut command = Command::new(&executable);
+    command.args(args);
+    if let Some(vault_file) = context.vault_file {
+        command.env("AEGIS_VAULT_FILE", vault_file);
+    }
+    if let Some(config_file) = context.config_file {
+        command.env("AEGIS_CONFIG_FILE", config_file);
+    }
+    if let Ok(current) = env::current_exe() {
+        command.env("AEGIS_RS", current);
+    }
+    let status = command
+        .status()
+        .map_err(|e| eyre!("Failed to run {}: {}", executable.display(), e))?;
+    // Plugins killed by a signal are reported like a shell does
+    Ok(status.code().unwrap_or(128 + signal(&status)))
+}
+
+#[cfg(unix)]
+fn signal(status: &std::process::ExitStatus) -> i32 {
+    std::os::unix::process::ExitStatusExt::signal(status).unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &std::process::ExitStatus) -> i32 {
+    0
+}
@@ -0,0 +1,74 @@
+use clap::{Args, ValueEnum};
+use color_eyre::eyre::{eyre, Result};
+use std::{collections::BTreeSet, path::PathBuf};
+
+use aegis_rs::{
+    qr::QrCode,
+    vault::{create_private_dir, write_atomic, LazyEntry},
+};
+
+use crate::{audit, icons::file_name_part};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+#[derive(Args)]
+pub struct QrArgs {
+    /// Directory to write the QR codes to, created if missing
+    #[clap(short, long)]
+    out: PathBuf,
+    /// Image format
+    #[clap(long, value_enum, default_value = "png")]
+    format: ImageFormat,
+    /// Pixels per module of PNG images
+    #[clap(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..=64))]
+    scale: u16,
+    /// Replace existing files
+    #[clap(long)]
+    force: bool,
+}
+
+/// Write a QR code of the otpauth:// URI of every entry to a directory, for printed backups
+pub fn run(args: &QrArgs, entries: &[LazyEntry], audit: &audit::Recorder) -> Result<()> {
+    create_private_dir(&args.out)?;
+    let extension = match args.format {
+        ImageFormat::Png => "png",
+        ImageFormat::Svg => "svg",
+    };
+    let mut names = BTreeSet::new();
+    for lazy in entries {
+        let entry = lazy.deserialize()?;
+        audit.check(lazy)?;
+        let code = QrCode::encode(entry.to_otpauth_uri().as_bytes())?;
+        let base = format!(
+            "{} ({})",
+            file_name_part(&entry.issuer),
+            file_name_part(&entry.name)
+        );
+        // Entries of the same account get a number
+        let name = (1..)
+            .map(|i| match i {
+                1 => format!("{}.{}", base, extension),
+                i => format!("{} {}.{}", base, i, extension),
+            })
+            .find(|name| !names.contains(name))
+            .expect("a free name");
+        let path = args.out.join(&name);
+        if path.exists() && !args.force {
+            return Err(eyre!("{} exists, replace it with --force", path.display()));
+        }
+        let image = match args.format {
+            ImageFormat::Png => code.to_png(args.scale.into()),
+            ImageFormat::Svg => code.to_svg().into_bytes(),
+        };
+        write_atomic(&path, &image)?;
+        audit.record(lazy, "cli qr");
+        println!("{}", path.display());
+        names.insert(name);
+    }
+    eprintln!("The QR codes hold the secrets unencrypted, keep them like the vault password");
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::{io::BufRead, path::PathBuf};
+
+use aegis_rs::vault::{LazyEntry, MasterKey, RecoveryCodes};
+
+use crate::{config, debug::find_entry, theme};
+
+#[derive(Subcommand)]
+pub enum RecoveryCommand {
+    /// Store recovery codes of an entry, read from stdin if none are given
+    Add(AddArgs),
+    /// Show the recovery codes of an entry
+    Show(EntryArgs),
+    /// Print the next unused recovery code of an entry and mark it as used
+    Use(EntryArgs),
+}
+
+#[derive(Args)]
+pub struct EntryArgs {
+    /// Issuer or name of the entry, or "uuid:<uuid>"
+    entry: String,
+}
+
+#[derive(Args)]
+pub struct AddArgs {
+    #[clap(flatten)]
+    entry: EntryArgs,
+    /// Recovery codes
+    codes: Vec<String>,
+}
+
+/// File of the recovery codes of the vault with `master_key`
+pub fn codes_path(master_key: Option<&MasterKey>) -> Result<PathBuf> {
+    let name = match master_key {
+        Some(master_key) => master_key.id(),
+        None => "plain".to_string(),
+    };
+    Ok(config::data_dir()?
+        .join("recovery")
+        .join(format!("{}.json", name)))
+}
+
+pub fn run(
+    command: &RecoveryCommand,
+    entries: &[LazyEntry],
+    master_key: Option<&MasterKey>,
+) -> Result<()> {
+    let path = codes_path(master_key)?;
+    let mut codes = RecoveryCodes::load(&path, master_key)?;
+    let entry_args = match command {
+        RecoveryCommand::Add(args) => &args.entry,
+        RecoveryCommand::Show(args) | RecoveryCommand::Use(args) => args,
+    };
+    let entry = find_entry(entries, &entry_args.entry)?;
+    let uuid = entry.uuid().ok_or(eyre!(
+        "Entry {} ({}) has no UUID to store recovery codes for",
+        entry.issuer(),
+        entry.name()
+    ))?;
+
+    match command {
+        RecoveryCommand::Add(args) => {
+            let new_codes = if args.codes.is_empty() {
+                std::io::stdin()
+                    .lock()
+                    .lines()
+                    .collect::<Result<Vec<String>, _>>()?
+            } else {
+                args.codes.clone()
+            };
+            let added = codes.add(uuid, new_codes);
+            codes.save(&path, master_key)?;
+            println!(
+                "Added {} recovery code(s) to {} ({})",
+                added,
+                entry.issuer(),
+                entry.name()
+            );
+        }
+        RecoveryCommand::Show(_) => {
+            if codes.codes(uuid).is_empty() {
+                println!("No recovery codes stored");
+            }
+            for code in codes.codes(uuid) {
+                if code.used {
+                    println!(
+                        "{}",
+                        theme::current()
+                            .dim
+                            .clone()
+                            .strikethrough()
+                            .apply_to(&code.code)
+                    );
+                } else {
+                    println!("{}", code.code);
+                }
+            }
+        }
+        RecoveryCommand::Use(_) => {
+            let code = codes
+                .use_next(uuid)
+                .ok_or(eyre!("No unused recovery codes left"))?;
+            codes.save(&path, master_key)?;
+            println!("{}", code);
+            let left = codes.codes(uuid).iter().filter(|c| !c.used).count();
+            eprintln!("{} unused recovery code(s) left", left);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
+
+use aegis_rs::{
+    vault::{Database, Selector},
+    verify::{Verdict, VerifyState},
+};
+
+/// Exit code if the code is wrong
+pub const EXIT_REJECTED: i32 = 1;
+/// Exit code if the code was accepted before
+pub const EXIT_REPLAYED: i32 = 2;
+/// Exit code if the code couldn't be checked, like when the entry or state can't be read
+pub const EXIT_FAILED: i32 = 3;
+/// Exit code of `pam-helper` if the user has no entry
+pub const EXIT_UNMAPPED: i32 = 4;
+
+#[derive(Args)]
+pub struct VerifyServerArgs {
+    /// UUID of the entry the code belongs to
+    #[clap(long)]
+    entry_uuid: String,
+    /// File of the counters accepted last, created if missing
+    #[clap(long)]
+    state: PathBuf,
+    /// Periods before and after the current one, or HOTP counters after the next one, to accept
+    /// codes of
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(0..=10))]
+    skew: u64,
+    /// Submitted code
+    code: String,
+}
+
+#[derive(Args)]
+pub struct PamHelperArgs {
+    /// JSON object of the users with the UUIDs of their entries, like {"alice": "3ae6f1ad-..."}
+    #[clap(long)]
+    map: PathBuf,
+    /// File of the counters accepted last, created if missing
+    #[clap(long)]
+    state: PathBuf,
+    /// Periods before and after the current one, or HOTP counters after the next one, to accept
+    /// codes of
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(0..=10))]
+    skew: u64,
+    /// User logging in, set by pam_exec
+    #[clap(long, env = "PAM_USER")]
+    user: String,
+}
+
+/// Check a submitted code like a server would, returns the exit code
+pub fn run(args: &VerifyServerArgs, db: &Database, json: bool) -> Result<i32> {
+    let verdict = check(db, &args.entry_uuid, &args.code, &args.state, args.skew)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&verdict)?);
+    } else {
+        match verdict {
+            Verdict::Accepted { .. } => println!("Accepted"),
+            Verdict::Replayed { .. } => println!("Rejected, the code was accepted before"),
+            Verdict::Rejected => println!("Rejected"),
+        }
+    }
+    Ok(exit_code(verdict))
+}
+
+/// Check the code on stdin for the user, for `pam_exec expose_authtok`, returns the exit code
+///
+/// Nothing is printed unless the code can't be checked, pam_exec shows or logs the output.
+pub fn pam_helper(args: &PamHelperArgs, db: &Database) -> Result<i32> {
+    let contents = fs::read_to_string(&args.map)
+        .map_err(|e| eyre!("Failed to read {}: {}", args.map.display(), e))?;
+    let users: BTreeMap<String, String> = serde_json::from_str(&contents)
+        .map_err(|e| eyre!("Failed to parse {}: {}", args.map.display(), e))?;
+    let uuid = match users.get(&args.user) {
+        Some(uuid) => uuid,
+        None => {
+            eprintln!("{} has no entry in {}", args.user, args.map.display());
+            return Ok(EXIT_UNMAPPED);
+        }
+    };
+    let mut code = Zeroizing::new(String::new());
+    io::stdin().read_to_string(&mut code)?;
+    // pam_exec ends the token with a null byte
+    let code = code.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    let verdict = check(db, uuid, code, &args.state, args.skew)?;
+    Ok(exit_code(verdict))
+}
+
+/// Check the code against the entry, recording it in the state file if accepted
+fn check(db: &Database, uuid: &str, code: &str, state_path: &Path, skew: u64) -> Result<Verdict> {
+    let entry = match db.find(&Selector::Uuid(uuid.to_string()))[..] {
+        [entry] => entry.deserialize()?,
+        _ => return Err(eyre!("No entry with the UUID {}", uuid)),
+    };
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    // Locked until saved, so concurrent logins can't both accept the same code
+    let (mut state, lock) = VerifyState::load_locked(state_path)?;
+    let verdict = state.verify(uuid, &entry.info, code, timestamp, skew)?;
+    if let Verdict::Accepted { .. } = verdict {
+        state.save(state_path)?;
+    }
+    drop(lock);
+    Ok(verdict)
+}
+
+fn exit_code(verdict: Verdict) -> i32 {
+    match verdict {
+        Verdict::Accepted { .. } => 0,
+        Verdict::Replayed { .. } => EXIT_REPLAYED,
+        Verdict::Rejected => EXIT_REJECTED,
+    }
+}
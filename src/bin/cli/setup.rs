@@ -0,0 +1,215 @@
+use color_eyre::eyre::{eyre, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password, Select};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use aegis_rs::vault::{DecryptOptions, PasswordGetter, Vault};
+
+use crate::{
+    config::{self, Config, PasswordSource},
+    keyring, theme,
+};
+
+/// Folder Aegis suggests for automatic backups on the device
+const DEVICE_BACKUP_DIR: &str = "/sdcard/Documents/Aegis";
+
+struct Fixed(String);
+
+impl PasswordGetter for Fixed {
+    fn get_password(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Walk through choosing a vault, a password source and the clipboard preference, then write
+/// the config file at `config_path`
+///
+/// Settings already in `config`, like formatting rules, are kept.
+pub fn run(mut config: Config, config_path: &Path) -> Result<()> {
+    let theme = theme::dialog();
+
+    let vault_file = choose_vault(theme)?;
+    let contents = fs::read_to_string(&vault_file)
+        .map_err(|e| eyre!("Failed to read {}: {}", vault_file.display(), e))?;
+    let vault = Vault::parse(&contents)?;
+    println!(
+        "Found a version {} vault, {}",
+        vault.version,
+        if vault.is_encrypted() {
+            "encrypted"
+        } else {
+            "not encrypted"
+        }
+    );
+
+    config.password_source = if vault.is_encrypted() {
+        choose_password_source(theme, &vault_file, vault)?
+    } else {
+        PasswordSource::Prompt
+    };
+    config.clipboard = Confirm::with_theme(theme)
+        .with_prompt("Copy codes to the clipboard?")
+        .default(config.clipboard)
+        .interact()?;
+    config.vault_file = Some(vault_file.canonicalize().unwrap_or(vault_file));
+
+    if config_path.exists()
+        && !Confirm::with_theme(theme)
+            .with_prompt(format!("Overwrite {}?", config_path.display()))
+            .default(true)
+            .interact()?
+    {
+        println!("Nothing written");
+        return Ok(());
+    }
+    config.save(config_path)?;
+    println!("Wrote {}", config_path.display());
+    Ok(())
+}
+
+fn choose_vault(theme: &ColorfulTheme) -> Result<PathBuf> {
+    let candidates = find_backups();
+    let mut items: Vec<String> = candidates
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    items.push("Enter a path".to_string());
+    items.push("Pull the latest backup from a device with adb".to_string());
+
+    let selection = Select::with_theme(theme)
+        .with_prompt("Aegis backup")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    let enter_path = candidates.len();
+    if selection < enter_path {
+        Ok(candidates[selection].clone())
+    } else if selection == enter_path {
+        let path: String = Input::with_theme(theme)
+            .with_prompt("Path to the backup")
+            .interact_text()?;
+        Ok(PathBuf::from(path))
+    } else {
+        adb_pull(theme)
+    }
+}
+
+/// Aegis backups and exports in the usual download locations, newest first
+fn find_backups() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(".")];
+    if let Some(home) = env::var_os("HOME").map(PathBuf::from) {
+        dirs.extend(["Documents", "Downloads"].map(|dir| home.join(dir)));
+    }
+    let mut backups: Vec<PathBuf> = dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("aegis-") && name.ends_with(".json"))
+        })
+        .collect();
+    // Backup names contain their date
+    backups.sort_by_key(|path| std::cmp::Reverse(path.file_name().map(|n| n.to_owned())));
+    backups
+}
+
+fn adb_pull(theme: &ColorfulTheme) -> Result<PathBuf> {
+    let device_dir: String = Input::with_theme(theme)
+        .with_prompt("Backup folder on the device")
+        .default(DEVICE_BACKUP_DIR.to_string())
+        .interact_text()?;
+    let output = Command::new("adb")
+        .args(["shell", "ls", "-1", &device_dir])
+        .output()
+        .map_err(|e| eyre!("Failed to run adb: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to list {} on the device: {}",
+            device_dir,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let latest = listing
+        .lines()
+        .map(str::trim)
+        .filter(|name| name.starts_with("aegis-") && name.ends_with(".json"))
+        .max()
+        .ok_or(eyre!("No Aegis backups in {} on the device", device_dir))?;
+
+    let destination = config::data_dir()?;
+    fs::create_dir_all(&destination)?;
+    let status = Command::new("adb")
+        .arg("pull")
+        .arg(format!("{}/{}", device_dir.trim_end_matches('/'), latest))
+        .arg(&destination)
+        .status()
+        .map_err(|e| eyre!("Failed to run adb: {}", e))?;
+    if !status.success() {
+        return Err(eyre!("Failed to pull {} from the device", latest));
+    }
+    Ok(destination.join(latest))
+}
+
+fn choose_password_source(
+    theme: &ColorfulTheme,
+    vault_file: &Path,
+    vault: Vault,
+) -> Result<PasswordSource> {
+    let selection = Select::with_theme(theme)
+        .with_prompt("Password source")
+        .items(&[
+            "Ask every time",
+            "Password file",
+            "System keyring (secret-tool, macOS keychain or Windows Credential Manager)",
+        ])
+        .default(0)
+        .interact()?;
+    if selection == 0 {
+        return Ok(PasswordSource::Prompt);
+    }
+
+    // Check the password before storing it anywhere
+    let password = Password::with_theme(theme)
+        .with_prompt("Vault password")
+        .interact()?;
+    vault.unlock(Fixed(password.clone()), &DecryptOptions::default())?;
+
+    if selection == 1 {
+        let path: String = Input::with_theme(theme)
+            .with_prompt("Password file")
+            .default(config::data_dir()?.join("password").display().to_string())
+            .interact_text()?;
+        let path = PathBuf::from(path);
+        write_private(&path, &password)?;
+        println!("Wrote the password to {}", path.display());
+        Ok(PasswordSource::File { path })
+    } else {
+        keyring::store(vault_file, &password)?;
+        println!("Stored the password in the system keyring");
+        Ok(PasswordSource::Keyring)
+    }
+}
+
+/// Write `contents` to a file only the current user can read
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| eyre!("Failed to write {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())?;
+    Ok(())
+}
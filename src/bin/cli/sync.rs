@@ -0,0 +1,449 @@
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use aegis_rs::vault::{
+    create_private_dir, write_atomic, write_vault, CachedPassword, DecryptOptions, PasswordGetter,
+    Vault,
+};
+
+use crate::config;
+
+#[derive(Args)]
+pub struct SyncArgs {
+    /// Remote vault, e.g. "webdav://cloud.example.com/remote.php/dav/files/john/aegis.json"
+    #[clap(long, env = "AEGIS_REMOTE")]
+    remote: String,
+    /// Replace the local vault with the remote one on pull if they can't be merged
+    #[clap(long)]
+    force: bool,
+}
+
+/// Direction of a sync
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Push,
+    Pull,
+}
+
+/// State of the last sync with a remote
+#[derive(Default, Deserialize, Serialize)]
+struct SyncState {
+    /// ETag of the remote vault
+    etag: Option<String>,
+    /// SHA-256 of the local vault contents
+    local_hash: Option<String>,
+}
+
+/// Remote vault on a WebDAV server, accessed with curl
+///
+/// Credentials are taken from `AEGIS_REMOTE_USER` and `AEGIS_REMOTE_PASSWORD`, or `~/.netrc`.
+struct Remote {
+    url: String,
+}
+
+/// Precondition of an upload
+enum Expect<'a> {
+    /// The remote vault has the ETag
+    ETag(&'a str),
+    /// There is no remote vault yet
+    Missing,
+}
+
+/// Response of the server
+struct Response {
+    status: u32,
+    etag: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Remote {
+    fn new(remote: &str) -> Result<Remote> {
+        let url = if let Some(rest) = remote.strip_prefix("webdav://") {
+            format!("https://{}", rest)
+        } else if let Some(rest) = remote.strip_prefix("webdav+http://") {
+            format!("http://{}", rest)
+        } else if remote.starts_with("https://") || remote.starts_with("http://") {
+            remote.to_string()
+        } else {
+            return Err(eyre!(
+                "Unsupported remote {}, expected webdav://, webdav+http:// or https://",
+                remote
+            ));
+        };
+        Ok(Remote { url })
+    }
+
+    fn request(&self, method: &str, upload: Option<&Path>, headers: &[String]) -> Result<Response> {
+        let dir = config::runtime_dir()?;
+        let id = std::process::id();
+        let header_file = dir.join(format!("aegis-rs-{}-headers", id));
+        let body_file = dir.join(format!("aegis-rs-{}-body", id));
+
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--location", "--netrc-optional"])
+            .args(["--request", method, "--write-out", "%{http_code}"])
+            .arg("--dump-header")
+            .arg(&header_file)
+            .arg("--output")
+            .arg(&body_file)
+            // Credentials are passed as config on stdin, not on the command line
+            .args(["--config", "-"]);
+        if method == "HEAD" {
+            command.arg("--head");
+        }
+        if let Some(upload) = upload {
+            command.arg("--upload-file").arg(upload);
+        }
+        for header in headers {
+            command.args(["--header", header]);
+        }
+        command.arg(&self.url);
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| eyre!("Failed to run curl: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            if let (Ok(user), Ok(password)) = (
+                env::var("AEGIS_REMOTE_USER"),
+                env::var("AEGIS_REMOTE_PASSWORD"),
+            ) {
+                let credentials = format!("{}:{}", user, password)
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+                writeln!(stdin, "user = \"{}\"", credentials)?;
+            }
+        }
+        let output = child.wait_with_output()?;
+
+        let headers = fs::read_to_string(&header_file).unwrap_or_default();
+        let body = fs::read(&body_file).unwrap_or_default();
+        let _ = fs::remove_file(&header_file);
+        let _ = fs::remove_file(&body_file);
+        if !output.status.success() {
+            return Err(eyre!(
+                "Request to {} failed: {}",
+                self.url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let status = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .map_err(|_| eyre!("Unexpected curl output"))?;
+        // After redirects the headers of the last response come last
+        let etag = headers
+            .lines()
+            .rev()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("etag"))
+            .map(|(_, value)| value.trim().to_string());
+        Ok(Response { status, etag, body })
+    }
+
+    /// ETag of the remote vault, `None` if there is none
+    fn etag(&self) -> Result<Option<String>> {
+        let response = self.request("HEAD", None, &[])?;
+        match response.status {
+            200..=299 => Ok(Some(response.etag.ok_or(eyre!(
+                "The server doesn't send an ETag, changes can't be detected"
+            ))?)),
+            404 => Ok(None),
+            status => Err(eyre!("Failed to check {}: HTTP {}", self.url, status)),
+        }
+    }
+
+    fn download(&self) -> Result<(String, String)> {
+        let response = self.request("GET", None, &[])?;
+        match response.status {
+            200..=299 => {
+                let etag = response.etag.ok_or(eyre!(
+                    "The server doesn't send an ETag, changes can't be detected"
+                ))?;
+                let contents = String::from_utf8(response.body)
+                    .map_err(|_| eyre!("Remote vault is not UTF-8"))?;
+                Ok((contents, etag))
+            }
+            404 => Err(eyre!("There is no vault at {}", self.url)),
+            status => Err(eyre!("Failed to download {}: HTTP {}", self.url, status)),
+        }
+    }
+
+    /// Upload `path`, returning the new ETag if the server sends one
+    fn upload(&self, path: &Path, expect: Expect) -> Result<Option<String>> {
+        let condition = match expect {
+            Expect::ETag(etag) => format!("If-Match: {}", etag),
+            Expect::Missing => "If-None-Match: *".to_string(),
+        };
+        let response = self.request("PUT", Some(path), &[condition])?;
+        match response.status {
+            200..=299 => Ok(response.etag),
+            412 => Err(eyre!(
+                "The remote vault changed during the upload, run the command again"
+            )),
+            status => Err(eyre!("Failed to upload to {}: HTTP {}", self.url, status)),
+        }
+    }
+}
+
+fn hash(contents: &str) -> String {
+    hex::encode(Sha256::digest(contents.as_bytes()))
+}
+
+/// File keeping the sync state of `vault_file` with `remote`
+fn state_path(vault_file: &Path, remote: &Remote) -> Result<PathBuf> {
+    let key = hash(&format!(
+        "{}\n{}",
+        config::absolute_vault_path(vault_file).display(),
+        remote.url
+    ));
+    Ok(config::data_dir()?
+        .join("sync")
+        .join(format!("{}.json", &key[..16])))
+}
+
+fn load_state(path: &Path) -> Result<SyncState> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(_) => Ok(SyncState::default()),
+    }
+}
+
+fn save_state(path: &Path, state: &SyncState) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        create_private_dir(dir)?;
+    }
+    write_atomic(path, serde_json::to_string_pretty(state)?.as_bytes())
+}
+
+/// Add the entries of `remote_contents` which are missing locally
+///
+/// Returns the contents of the merged vault, encrypted with the local master key.
+fn merge(
+    local_contents: &str,
+    remote_contents: &str,
+    password: impl PasswordGetter + Copy,
+    options: &DecryptOptions,
+) -> Result<String> {
+    let (mut db, master_key) = Vault::parse(local_contents)?.unlock_with_key(password, options)?;
+    let remote_db = Vault::parse(remote_contents)?.unlock(password, options)?;
+    let report = db.merge(remote_db);
+    for added in &report.added {
+        println!("Added {} from the remote vault", added);
+    }
+    for group in &report.groups {
+        println!("Added the group {} from the remote vault", group);
+    }
+    for conflict in &report.conflicts {
+        println!(
+            "{} differs between the vaults, the local entry is kept",
+            conflict
+        );
+    }
+    write_vault(local_contents, &db, master_key.as_ref())
+}
+
+/// Contents the local vault gets on pull
+///
+/// Local changes since the last sync, or a local vault which was never synced, are merged with
+/// the remote vault. Where they can't be, the local vault is only replaced with `force`.
+fn pulled(
+    local_contents: Option<&str>,
+    local_changed: bool,
+    remote_contents: &str,
+    force: bool,
+    password: impl PasswordGetter + Copy,
+    options: &DecryptOptions,
+) -> Result<String> {
+    let local = match local_contents {
+        Some(local) if local_changed => local,
+        _ => return Ok(remote_contents.to_string()),
+    };
+    println!("The local vault changed, merging");
+    match merge(local, remote_contents, password, options) {
+        Ok(merged) => Ok(merged),
+        Err(e) if force => {
+            eprintln!("Failed to merge, replacing the local vault: {}", e);
+            Ok(remote_contents.to_string())
+        }
+        Err(e) => Err(eyre!(
+            "Failed to merge the vaults, --force replaces the local vault: {}",
+            e
+        )),
+    }
+}
+
+/// Check that `contents` is an encrypted vault, only those are ever uploaded or downloaded
+fn check_encrypted(contents: &str, name: &str) -> Result<()> {
+    if !Vault::parse(contents)?.is_encrypted() {
+        return Err(eyre!(
+            "The {} vault is not encrypted, only encrypted vaults are synced",
+            name
+        ));
+    }
+    Ok(())
+}
+
+pub fn run(
+    direction: Direction,
+    args: &SyncArgs,
+    vault_file: &Path,
+    password: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<()> {
+    let remote = Remote::new(&args.remote)?;
+    let state_path = state_path(vault_file, &remote)?;
+    let mut state = load_state(&state_path)?;
+    let password = CachedPassword::new(password);
+
+    let local_contents = match fs::read_to_string(vault_file) {
+        Ok(contents) => Some(contents),
+        Err(_) if direction == Direction::Pull => None,
+        Err(e) => return Err(eyre!("Failed to read {}: {}", vault_file.display(), e)),
+    };
+    if let Some(contents) = &local_contents {
+        check_encrypted(contents, "local")?;
+    }
+    let local_changed = match (&local_contents, &state.local_hash) {
+        (Some(contents), Some(local_hash)) => hash(contents) != *local_hash,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+
+    match direction {
+        Direction::Pull => {
+            let (remote_contents, etag) = remote.download()?;
+            check_encrypted(&remote_contents, "remote")?;
+            if state.etag.as_deref() == Some(etag.as_str()) {
+                println!("Already up to date");
+                return Ok(());
+            }
+            let contents = pulled(
+                local_contents.as_deref(),
+                local_changed,
+                &remote_contents,
+                args.force,
+                &password,
+                options,
+            )?;
+            write_atomic(vault_file, contents.as_bytes())?;
+            state.etag = Some(etag);
+            state.local_hash = Some(hash(&remote_contents));
+            println!("Pulled {}", remote.url);
+        }
+        Direction::Push => {
+            let mut contents = local_contents.ok_or(eyre!("No local vault"))?;
+            let remote_etag = remote.etag()?;
+            let expect = match (&remote_etag, &state.etag) {
+                (None, _) => Expect::Missing,
+                (Some(remote_etag), Some(synced)) if remote_etag == synced => {
+                    if !local_changed {
+                        println!("Already up to date");
+                        return Ok(());
+                    }
+                    Expect::ETag(remote_etag)
+                }
+                // Changed remotely since the last sync, or never synced
+                (Some(_), _) => {
+                    let (remote_contents, etag) = remote.download()?;
+                    check_encrypted(&remote_contents, "remote")?;
+                    println!("The remote vault changed, merging");
+                    contents = merge(&contents, &remote_contents, &password, options)?;
+                    write_atomic(vault_file, contents.as_bytes())?;
+                    state.etag = Some(etag);
+                    Expect::ETag(state.etag.as_deref().unwrap_or_default())
+                }
+            };
+            let etag = match remote.upload(vault_file, expect)? {
+                Some(etag) => etag,
+                None => remote
+                    .etag()?
+                    .ok_or(eyre!("The uploaded vault disappeared"))?,
+            };
+            state.etag = Some(etag);
+            state.local_hash = Some(hash(&contents));
+            println!("Pushed to {}", remote.url);
+        }
+    }
+    save_state(&state_path, &state)
+}
+
+#[cfg(test)]
+mod test {
+    use aegis_rs::vault::{write_vault, DecryptOptions, Vault};
+    use color_eyre::eyre::Result;
+
+    use super::{merge, pulled};
+
+    const VAULT: &str = include_str!("../../../res/aegis_encrypted.json");
+
+    fn password() -> Result<String> {
+        Ok("test".to_string())
+    }
+
+    fn issuers(contents: &str) -> Vec<String> {
+        let db = Vault::parse(contents)
+            .unwrap()
+            .unlock(password, &DecryptOptions::default())
+            .unwrap();
+        db.entries()
+            .iter()
+            .map(|e| e.issuer().to_string())
+            .collect()
+    }
+
+    /// The vault without its first entry, under the same master key
+    fn without_first_entry() -> String {
+        let options = DecryptOptions::default();
+        let (mut db, master_key) = Vault::parse(VAULT)
+            .unwrap()
+            .unlock_with_key(password, &options)
+            .unwrap();
+        let uuid = db.entries()[0].uuid().unwrap().to_string();
+        db.remove_entry(&uuid).unwrap();
+        write_vault(VAULT, &db, master_key.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn merge_adds_remote_entries() {
+        let options = DecryptOptions::default();
+        let local = without_first_entry();
+        let merged = merge(&local, VAULT, password, &options).unwrap();
+        assert_eq!(issuers(&merged), ["SPDX", "WWE", "Boeing", "Deno"]);
+    }
+
+    #[test]
+    fn first_pull_keeps_local_entries() {
+        let options = DecryptOptions::default();
+        let remote = without_first_entry();
+        // Never synced, so the existing local vault counts as changed
+        let contents = pulled(Some(VAULT), true, &remote, false, password, &options).unwrap();
+        assert!(issuers(&contents).contains(&"Deno".to_string()));
+
+        let contents = pulled(Some(VAULT), false, &remote, false, password, &options).unwrap();
+        assert_eq!(contents, remote);
+        let contents = pulled(None, false, &remote, false, password, &options).unwrap();
+        assert_eq!(contents, remote);
+    }
+
+    #[test]
+    fn pull_refuses_unmergeable_vaults_without_force() {
+        let options = DecryptOptions::default();
+        let wrong = || Ok("wrong".to_string());
+        let remote = without_first_entry();
+        assert!(pulled(Some(VAULT), true, &remote, false, wrong, &options).is_err());
+        let contents = pulled(Some(VAULT), true, &remote, true, wrong, &options).unwrap();
+        assert_eq!(contents, remote);
+    }
+}
@@ -0,0 +1,119 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use console::{style, Term};
+use std::{env, sync::OnceLock};
+
+use aegis_rs::qr::QrCode;
+
+/// What the terminal can do, guessed from the environment
+///
+/// Terminals can't be asked without waiting for answers they may never send, so the variables
+/// terminals set are looked at instead.
+#[derive(Debug)]
+pub struct Capabilities {
+    /// Symbols like ✔ and ❯ can be shown
+    pub unicode: bool,
+    /// Text can be copied to the clipboard with the OSC 52 escape sequence
+    pub osc52: bool,
+}
+
+impl Capabilities {
+    fn probe() -> Capabilities {
+        let var = |name: &str| env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        let term_program = var("TERM_PROGRAM");
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .map(|name| var(name))
+            .find(|value| !value.is_empty())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        // The Linux console only has the glyphs of its font
+        let unicode = cfg!(windows)
+            || (term != "linux" && (locale.contains("utf-8") || locale.contains("utf8")));
+        // tmux forwards OSC 52 to the terminal it runs in with set-clipboard enabled
+        let osc52 = env::var_os("KITTY_WINDOW_ID").is_some()
+            || env::var_os("WEZTERM_EXECUTABLE").is_some()
+            || env::var_os("TMUX").is_some()
+            || [
+                "xterm-kitty",
+                "wezterm",
+                "foot",
+                "alacritty",
+                "xterm-ghostty",
+            ]
+            .iter()
+            .any(|name| term.starts_with(name))
+            || ["WezTerm", "iTerm.app", "ghostty"].contains(&term_program.as_str());
+        Capabilities { unicode, osc52 }
+    }
+}
+
+pub fn capabilities() -> &'static Capabilities {
+    static CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+    CAPABILITIES.get_or_init(Capabilities::probe)
+}
+
+/// Print the QR code black on white, so it can be scanned on dark terminals as well
+pub fn print_qr_code(code: &QrCode) {
+    for line in code.to_unicode().lines() {
+        println!("{}", style(line).black().on_white());
+    }
+}
+
+/// `unicode` if the terminal can show it, otherwise `ascii`
+pub fn symbol(unicode: &'static str, ascii: &'static str) -> &'static str {
+    match capabilities().unicode {
+        true => unicode,
+        false => ascii,
+    }
+}
+
+/// Whether the clipboard of the desktop can be used, or why not
+pub fn system_clipboard() -> Result<(), String> {
+    #[cfg(feature = "clipboard")]
+    {
+        arboard::Clipboard::new()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+    #[cfg(not(feature = "clipboard"))]
+    {
+        Err("built without the clipboard feature".to_string())
+    }
+}
+
+/// Clipboard of the desktop, or of the terminal where there's none, like over SSH
+pub enum Clipboard {
+    #[cfg(feature = "clipboard")]
+    System(arboard::Clipboard),
+    Terminal(Term),
+}
+
+impl Clipboard {
+    /// Neither clipboard is available if `None`
+    pub fn new() -> Option<Clipboard> {
+        #[cfg(feature = "clipboard")]
+        if let Ok(clipboard) = arboard::Clipboard::new() {
+            return Some(Clipboard::System(clipboard));
+        }
+        let term = Term::stderr();
+        (capabilities().osc52 && term.is_term()).then_some(Clipboard::Terminal(term))
+    }
+
+    pub fn set_text(&mut self, text: &str) -> Result<()> {
+        match self {
+            #[cfg(feature = "clipboard")]
+            Clipboard::System(clipboard) => clipboard
+                .set_text(text)
+                .map_err(|e| eyre!("Failed to copy to the clipboard: {}", e)),
+            Clipboard::Terminal(term) => term
+                .write_str(&format!(
+                    "\x1b]52;c;{}\x07",
+                    general_purpose::STANDARD.encode(text)
+                ))
+                .map_err(|e| eyre!("Failed to copy to the clipboard: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use console::{style, Style, StyledObject};
+use dialoguer::theme::ColorfulTheme;
+use serde::{Deserialize, Serialize};
+use std::{env, sync::OnceLock};
+
+use crate::terminal;
+
+/// Color theme chosen in the config
+///
+/// All themes only use the 16 colors of the terminal palette, so they look like the terminal is
+/// configured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeName {
+    /// Monochrome with `NO_COLOR` set, otherwise light or dark by the terminal background
+    #[default]
+    Auto,
+    Dark,
+    Light,
+    /// No colors, only bold and dim text
+    Monochrome,
+    /// Bright and bold colors, without dim text
+    HighContrast,
+}
+
+impl ThemeName {
+    /// Theme for the terminal, if `Auto`
+    fn resolve(self) -> ThemeName {
+        if self != ThemeName::Auto {
+            return self;
+        }
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ThemeName::Monochrome;
+        }
+        // Set by rxvt, Konsole and others, like "15;0" for white on black
+        let background = env::var("COLORFGBG").ok().and_then(|colors| {
+            colors
+                .rsplit(';')
+                .next()
+                .and_then(|color| color.parse::<u8>().ok())
+        });
+        match background {
+            Some(7 | 9..=15) => ThemeName::Light,
+            _ => ThemeName::Dark,
+        }
+    }
+}
+
+/// Styles of the prompts and messages
+pub struct Theme {
+    pub dialog: ColorfulTheme,
+    pub warning: Style,
+    pub error: Style,
+    pub success: Style,
+    /// Less important text, like labels
+    pub dim: Style,
+}
+
+impl Theme {
+    fn new(name: ThemeName) -> Theme {
+        match name.resolve() {
+            ThemeName::Auto | ThemeName::Dark => Theme {
+                dialog: colorful_dialog(),
+                warning: Style::new().yellow(),
+                error: Style::new().red(),
+                success: Style::new().green(),
+                dim: Style::new().dim(),
+            },
+            // Cyan, yellow and the white fuzzy cursor are hard to read on white
+            ThemeName::Light => Theme {
+                dialog: ColorfulTheme {
+                    defaults_style: Style::new().for_stderr().blue(),
+                    prompt_prefix: style("?".to_string()).for_stderr().magenta(),
+                    active_item_style: Style::new().for_stderr().blue(),
+                    fuzzy_cursor_style: Style::new().for_stderr().white().on_black(),
+                    ..colorful_dialog()
+                },
+                warning: Style::new().magenta(),
+                error: Style::new().red(),
+                success: Style::new().green(),
+                dim: Style::new().dim(),
+            },
+            ThemeName::Monochrome => Theme {
+                dialog: monochrome_dialog(),
+                warning: Style::new(),
+                error: Style::new().bold(),
+                success: Style::new(),
+                dim: Style::new().dim(),
+            },
+            ThemeName::HighContrast => Theme {
+                dialog: high_contrast_dialog(),
+                warning: Style::new().yellow().bright(),
+                error: Style::new().red().bright(),
+                success: Style::new().green().bright(),
+                dim: Style::new(),
+            },
+        }
+    }
+}
+
+/// `unicode` symbol of a prompt, ASCII where the terminal can't show it
+fn symbol(unicode: &'static str, ascii: &'static str) -> StyledObject<String> {
+    style(terminal::symbol(unicode, ascii).to_string()).for_stderr()
+}
+
+/// Default colorful theme of dialoguer
+fn colorful_dialog() -> ColorfulTheme {
+    ColorfulTheme {
+        prompt_suffix: symbol("›", ">").black().bright(),
+        success_prefix: symbol("✔", "+").green(),
+        success_suffix: symbol("·", ":").black().bright(),
+        error_prefix: symbol("✘", "x").red(),
+        active_item_prefix: symbol("❯", ">").green(),
+        checked_item_prefix: symbol("✔", "[x]").green(),
+        unchecked_item_prefix: symbol("⬚", "[ ]").magenta(),
+        picked_item_prefix: symbol("❯", ">").green(),
+        ..ColorfulTheme::default()
+    }
+}
+
+/// Prompts with the symbols of the colorful theme, but no colors
+fn monochrome_dialog() -> ColorfulTheme {
+    let plain = || Style::new().for_stderr();
+    ColorfulTheme {
+        defaults_style: plain(),
+        prompt_style: plain().bold(),
+        prompt_prefix: symbol("?", "?"),
+        prompt_suffix: symbol("›", ">"),
+        success_prefix: symbol("✔", "+"),
+        success_suffix: symbol("·", ":"),
+        error_prefix: symbol("✘", "x"),
+        error_style: plain().bold(),
+        hint_style: plain().dim(),
+        values_style: plain(),
+        active_item_style: plain().bold(),
+        inactive_item_style: plain(),
+        active_item_prefix: symbol("❯", ">"),
+        inactive_item_prefix: symbol(" ", " "),
+        checked_item_prefix: symbol("✔", "[x]"),
+        unchecked_item_prefix: symbol("⬚", "[ ]"),
+        picked_item_prefix: symbol("❯", ">"),
+        unpicked_item_prefix: symbol(" ", " "),
+        fuzzy_cursor_style: plain().reverse(),
+        fuzzy_match_highlight_style: plain().underlined(),
+    }
+}
+
+fn high_contrast_dialog() -> ColorfulTheme {
+    let bright = || Style::new().for_stderr().bright().bold();
+    ColorfulTheme {
+        defaults_style: bright().cyan(),
+        prompt_style: bright().white(),
+        prompt_prefix: style("?".to_string()).for_stderr().yellow().bright().bold(),
+        prompt_suffix: symbol("›", ">").white().bright(),
+        success_suffix: symbol("·", ":").white().bright(),
+        error_style: bright().red(),
+        hint_style: Style::new().for_stderr().white(),
+        values_style: bright().green(),
+        active_item_style: bright().reverse(),
+        fuzzy_match_highlight_style: bright().underlined(),
+        ..colorful_dialog()
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// Use the theme `name`, anything printed before uses the automatic theme
+pub fn init(name: ThemeName) {
+    let _ = THEME.set(Theme::new(name));
+}
+
+pub fn current() -> &'static Theme {
+    THEME.get_or_init(|| Theme::new(ThemeName::Auto))
+}
+
+/// Theme of dialoguer prompts
+pub fn dialog() -> &'static ColorfulTheme {
+    &current().dialog
+}
@@ -0,0 +1,90 @@
+use clap::Args;
+use color_eyre::eyre::{eyre, Result};
+use dialoguer::Confirm;
+use std::fs;
+
+use aegis_rs::{
+    otp::Provider,
+    vault::{guess_mime, Database, Plan},
+};
+
+use crate::{attach::Target, interrupt, theme};
+
+#[derive(Args)]
+pub struct TidyArgs {
+    /// Apply all suggestions without asking
+    #[clap(long)]
+    yes: bool,
+    /// Only print the suggestions
+    #[clap(long, conflicts_with = "yes")]
+    dry_run: bool,
+}
+
+/// Change of an entry suggested from the provider database
+enum Suggestion {
+    Issuer(String),
+    /// Icon file of the provider
+    Icon(std::path::PathBuf),
+}
+
+/// Suggest canonical issuers and icons, and write the vault once if any were accepted
+pub fn run(args: &TidyArgs, providers: &[Provider], db: Database, vault: &Target) -> Result<()> {
+    let theme = theme::dialog();
+    let mut plan = Plan::default();
+    for entry in db.entries().iter() {
+        let provider = match Provider::match_issuer(providers, entry.issuer()) {
+            Some(provider) => provider,
+            None => continue,
+        };
+        let mut suggestions = Vec::new();
+        if entry.issuer() != provider.issuer {
+            suggestions.push(Suggestion::Issuer(provider.issuer.clone()));
+        }
+        if let Some(icon) = provider.icon.as_ref().filter(|_| !entry.has_icon()) {
+            suggestions.push(Suggestion::Icon(icon.clone()));
+        }
+
+        for suggestion in suggestions {
+            let label = format!("{} ({})", entry.issuer().trim(), entry.name().trim());
+            let question = match &suggestion {
+                Suggestion::Issuer(issuer) => format!("Rename {} to {}", label, issuer),
+                Suggestion::Icon(icon) => format!("Use {} as icon of {}", icon.display(), label),
+            };
+            let accepted = args.yes
+                || (!args.dry_run
+                    && interrupt::prompt(|| {
+                        Confirm::with_theme(theme)
+                            .with_prompt(format!("{}?", question))
+                            .default(true)
+                            .interact()
+                    })?);
+            if args.dry_run || (args.yes && !vault.dry_run) {
+                println!("{}", question);
+            }
+            if !accepted {
+                continue;
+            }
+            match suggestion {
+                Suggestion::Issuer(issuer) => plan.set_issuer(entry, &issuer)?,
+                Suggestion::Icon(icon) => {
+                    let data = fs::read(&icon)
+                        .map_err(|e| eyre!("Failed to read icon {}: {}", icon.display(), e))?;
+                    plan.set_icon(entry, guess_mime(&icon.to_string_lossy()), data)?;
+                }
+            }
+        }
+    }
+
+    if plan.is_empty() {
+        if !args.dry_run {
+            println!("Nothing changed");
+        }
+        return Ok(());
+    }
+    // Encrypted once for all changes
+    let changed = plan.ops().len();
+    if vault.commit(db, plan)? {
+        println!("Applied {} changes", changed);
+    }
+    Ok(())
+}
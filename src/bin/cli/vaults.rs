@@ -0,0 +1,246 @@
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use aegis_rs::vault::{
+    create_vault_with, rekey_vault, write_atomic, AuditLog, Database, DecryptOptions, LazyEntry,
+    MasterKey, PasswordGetter, RecoveryCodes, Vault,
+};
+
+use crate::{
+    audit,
+    password::{self, NewPasswordArgs},
+    recovery,
+};
+
+#[derive(Subcommand)]
+pub enum VaultCommand {
+    /// Write the selected entries to a new vault with a master key of its own
+    Extract(ExtractArgs),
+    /// Replace the master key, re-encrypting the database under new nonces and salts
+    Rekey(RekeyArgs),
+    /// Write an encrypted copy under a new master key and password, leaving this vault as it is
+    Reencrypt(ReencryptArgs),
+}
+
+#[derive(Args)]
+pub struct ExtractArgs {
+    /// Entries to extract, like "group=shared", "issuer=github", "name=john" or "uuid=...",
+    /// entries have to match all filters
+    #[clap(long, value_parser = parse_filter, required = true)]
+    filter: Vec<Filter>,
+    /// Vault file to write
+    #[clap(short, long)]
+    output: PathBuf,
+    /// Ask for a password for the new vault instead of using the password of this one
+    #[clap(long)]
+    new_password: bool,
+    #[clap(flatten)]
+    password_args: NewPasswordArgs,
+}
+
+#[derive(Args)]
+pub struct RekeyArgs {
+    /// Ask for a new password instead of keeping the current one
+    #[clap(long)]
+    new_password: bool,
+    #[clap(flatten)]
+    password_args: NewPasswordArgs,
+}
+
+#[derive(Args)]
+pub struct ReencryptArgs {
+    /// Vault file to write
+    #[clap(short, long)]
+    output: PathBuf,
+    #[clap(flatten)]
+    password_args: NewPasswordArgs,
+}
+
+/// Condition on the entries to extract
+#[derive(Clone)]
+pub enum Filter {
+    /// Name of a group the entry is in
+    Group(String),
+    /// Part of the issuer, ignoring case
+    Issuer(String),
+    /// Part of the name, ignoring case
+    Name(String),
+    Uuid(String),
+}
+
+fn parse_filter(input: &str) -> Result<Filter, String> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or_else(|| format!("Filter \"{}\" is not KEY=VALUE", input))?;
+    let value = value.to_string();
+    Ok(match key.trim() {
+        "group" => Filter::Group(value),
+        "issuer" => Filter::Issuer(value.to_lowercase()),
+        "name" => Filter::Name(value.to_lowercase()),
+        "uuid" => Filter::Uuid(value),
+        _ => {
+            return Err(format!(
+                "Unknown filter {}, use group, issuer, name or uuid",
+                key
+            ))
+        }
+    })
+}
+
+/// Group names are compared ignoring case and surrounding whitespace
+fn same_group(group: &str, name: &str) -> bool {
+    group.trim().eq_ignore_ascii_case(name.trim())
+}
+
+impl Filter {
+    /// `group_uuids` are the UUIDs of the groups with the name of a group filter
+    fn matches(&self, entry: &LazyEntry, group_uuids: &[String]) -> bool {
+        match self {
+            Filter::Group(name) => {
+                entry.groups().iter().any(|uuid| group_uuids.contains(uuid))
+                    || entry
+                        .group_name()
+                        .is_some_and(|group| same_group(&group, name))
+            }
+            Filter::Issuer(issuer) => entry.issuer().to_lowercase().contains(issuer),
+            Filter::Name(name) => entry.name().to_lowercase().contains(name),
+            Filter::Uuid(uuid) => entry.uuid() == Some(uuid),
+        }
+    }
+}
+
+/// Write the entries matching all filters to a new vault at the output path
+pub fn extract(
+    args: &ExtractArgs,
+    db: &Database,
+    master_key: Option<&MasterKey>,
+    password: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<()> {
+    if args.output.exists() {
+        return Err(eyre!("{} already exists", args.output.display()));
+    }
+    let groups = db.groups()?;
+    let mut filters = Vec::new();
+    for filter in &args.filter {
+        let group_uuids: Vec<String> = match filter {
+            Filter::Group(name) => {
+                let uuids: Vec<String> = groups
+                    .iter()
+                    .filter(|group| same_group(&group.name, name))
+                    .map(|group| group.uuid.clone())
+                    .collect();
+                // Entries of databases before version 3 name their group themselves
+                let named = db.entries().iter().any(|entry| {
+                    entry
+                        .group_name()
+                        .is_some_and(|group| same_group(&group, name))
+                });
+                if uuids.is_empty() && !named {
+                    return Err(eyre!("The vault has no group {}", name));
+                }
+                uuids
+            }
+            _ => Vec::new(),
+        };
+        filters.push((filter, group_uuids));
+    }
+
+    let subset = db.subset(|entry| {
+        filters
+            .iter()
+            .all(|(filter, group_uuids)| filter.matches(entry, group_uuids))
+    })?;
+    if subset.entries().is_empty() {
+        return Err(eyre!("No entries match the filters"));
+    }
+
+    let new_password = match (args.new_password, master_key) {
+        (true, _) => password::prompt_new_password(&args.password_args)?,
+        (false, Some(_)) => Zeroizing::new(password.get_password()?),
+        (false, None) => {
+            return Err(eyre!(
+                "The vault is not encrypted, use --new-password to encrypt the extracted one"
+            ))
+        }
+    };
+    let contents = create_vault_with(&new_password, &subset, options)?;
+    write_atomic(&args.output, contents.as_bytes())?;
+    println!(
+        "Extracted {} entries to {}",
+        subset.entries().len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Put the vault at `vault_file` under a new master key
+///
+/// Recovery codes and the audit log are copied to the new key before the vault is written,
+/// the copies under the old key stay for older backups of the vault.
+pub fn rekey(
+    args: &RekeyArgs,
+    vault_file: &Path,
+    contents: &str,
+    db: &Database,
+    master_key: Option<&MasterKey>,
+    password: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<()> {
+    let old_key = master_key.ok_or(eyre!("The vault is not encrypted, it has no master key"))?;
+    let new_password = match args.new_password {
+        true => password::prompt_new_password(&args.password_args)?,
+        false => Zeroizing::new(password.get_password()?),
+    };
+    let slots = Vault::parse(contents)?.slots().len();
+    let (rekeyed, new_key) = rekey_vault(contents, db, &new_password, options)?;
+
+    let codes_path = recovery::codes_path(Some(old_key))?;
+    if codes_path.exists() {
+        RecoveryCodes::load(&codes_path, Some(old_key))?
+            .save(&recovery::codes_path(Some(&new_key))?, Some(&new_key))?;
+    }
+    let log = AuditLog::new(&audit::log_path(Some(old_key))?, Some(old_key));
+    let new_log = AuditLog::new(&audit::log_path(Some(&new_key))?, Some(&new_key));
+    for record in log.read()? {
+        new_log.append(&record)?;
+    }
+
+    write_atomic(vault_file, rekeyed.as_bytes())?;
+    println!("Replaced the master key of {}", vault_file.display());
+    if slots > 1 {
+        println!(
+            "Removed {} other slots, like biometric unlocking, set them up again in the app",
+            slots - 1
+        );
+    }
+    Ok(())
+}
+
+/// Write the vault under a new master key and password to the output path
+///
+/// Unlike [rekey], the vault itself, its recovery codes and audit log stay under the old key.
+pub fn reencrypt(
+    args: &ReencryptArgs,
+    contents: &str,
+    db: &Database,
+    master_key: Option<&MasterKey>,
+    options: &DecryptOptions,
+) -> Result<()> {
+    if args.output.exists() {
+        return Err(eyre!("{} already exists", args.output.display()));
+    }
+    let new_password = password::prompt_new_password(&args.password_args)?;
+    let reencrypted = match master_key {
+        Some(_) => rekey_vault(contents, db, &new_password, options)?.0,
+        None => create_vault_with(&new_password, db, options)?,
+    };
+    write_atomic(&args.output, reencrypted.as_bytes())?;
+    println!(
+        "Wrote {} with a new master key and password",
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use color_eyre::eyre::{eyre, Result};
+use sha1::{Digest, Sha1};
+
+/// Magic bytes at the start of a bloom filter file
+const BLOOM_MAGIC: &[u8; 8] = b"AEGISBF1";
+
+/// SHA-1 of `password` as upper case hex, the form used by Have I Been Pwned
+pub fn password_hash(password: &str) -> String {
+    hex::encode_upper(Sha1::digest(password.as_bytes()))
+}
+
+/// Prefix of `hash` which is sent to the range API, the rest never leaves the machine
+pub fn range_prefix(hash: &str) -> &str {
+    &hash[..5]
+}
+
+/// Number of breaches `hash` appears in, according to a range API response for its prefix
+///
+/// Each line of the response is `SUFFIX:COUNT`. Padding lines have a count of 0.
+pub fn count_in_range(response: &str, hash: &str) -> Result<u64> {
+    let suffix = &hash[5..];
+    for line in response.lines() {
+        let (line_suffix, count) = line
+            .trim()
+            .split_once(':')
+            .ok_or(eyre!("Unexpected line in range response: {}", line))?;
+        if line_suffix.eq_ignore_ascii_case(suffix) {
+            return count
+                .parse()
+                .map_err(|_| eyre!("Unexpected count in range response: {}", count));
+        }
+    }
+    Ok(0)
+}
+
+/// Bloom filter of SHA-1 password hashes, for checking passwords offline
+///
+/// Stored as [`BLOOM_MAGIC`], the number of hash functions as a little endian u32, then the bits.
+/// The bit positions are derived from the SHA-1 hash itself, which is already uniform.
+pub struct BloomFilter {
+    hashes: u32,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    /// Empty filter sized for `items` hashes with a `false_positive_rate` between 0 and 1
+    pub fn new(items: usize, false_positive_rate: f64) -> BloomFilter {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(items.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let bytes = (bits as usize).div_ceil(8).max(1);
+        let hashes = ((bytes * 8) as f64 / items.max(1) as f64 * ln2).round();
+        BloomFilter {
+            hashes: (hashes as u32).clamp(1, 32),
+            bits: vec![0; bytes],
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<BloomFilter> {
+        let rest = bytes
+            .strip_prefix(BLOOM_MAGIC)
+            .ok_or(eyre!("Not an aegis-rs bloom filter file"))?;
+        if rest.len() < 5 {
+            return Err(eyre!("Bloom filter file is truncated"));
+        }
+        let (hashes, bits) = rest.split_at(4);
+        let hashes = u32::from_le_bytes(hashes.try_into()?);
+        if hashes == 0 {
+            return Err(eyre!("Bloom filter file uses no hash functions"));
+        }
+        Ok(BloomFilter {
+            hashes,
+            bits: bits.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = BLOOM_MAGIC.to_vec();
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Add a SHA-1 hash given as hex, like the lines of the downloadable hash lists
+    pub fn insert(&mut self, hash: &str) -> Result<()> {
+        for bit in self.positions(hash)? {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+        Ok(())
+    }
+
+    /// Whether the SHA-1 hash given as hex was probably added, never false for added hashes
+    pub fn contains(&self, hash: &str) -> Result<bool> {
+        Ok(self
+            .positions(hash)?
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0))
+    }
+
+    fn positions(&self, hash: &str) -> Result<impl Iterator<Item = usize>> {
+        let digest: [u8; 20] = hex::decode(hash)
+            .ok()
+            .and_then(|digest| digest.try_into().ok())
+            .ok_or(eyre!("Not a SHA-1 hash: {}", hash))?;
+        // Double hashing, with the two halves of the digest as the base hashes
+        let h1 = u64::from_le_bytes(digest[..8].try_into()?);
+        let h2 = u64::from_le_bytes(digest[8..16].try_into()?) | 1;
+        let bits = self.bits.len() as u64 * 8;
+        Ok((0..self.hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_in_range, password_hash, range_prefix, BloomFilter};
+
+    #[test]
+    fn range_lookup() {
+        let hash = password_hash("password");
+        assert_eq!(hash, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8");
+        assert_eq!(range_prefix(&hash), "5BAA6");
+
+        let response = "003D68EB55068C33ACE09247EE4C639306B:3\r\n\
+                        1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n\
+                        FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:0\r\n";
+        assert_eq!(count_in_range(response, &hash).unwrap(), 9545824);
+        assert_eq!(
+            count_in_range(response, &password_hash("rH7#qv!Tz0")).unwrap(),
+            0
+        );
+        assert!(count_in_range("garbage", &hash).is_err());
+    }
+
+    #[test]
+    fn bloom_filter() {
+        let breached: Vec<String> = (0..1000)
+            .map(|i| password_hash(&format!("breached {}", i)))
+            .collect();
+        let mut filter = BloomFilter::new(breached.len(), 0.001);
+        for hash in &breached {
+            filter.insert(hash).unwrap();
+        }
+
+        let filter = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert!(breached.iter().all(|hash| filter.contains(hash).unwrap()));
+        let false_positives = (0..1000)
+            .filter(|i| {
+                filter
+                    .contains(&password_hash(&format!("fine {}", i)))
+                    .unwrap()
+            })
+            .count();
+        assert!(false_positives < 10);
+
+        assert!(filter.contains("not hex").is_err());
+        assert!(BloomFilter::from_bytes(b"something else").is_err());
+    }
+}
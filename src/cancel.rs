@@ -0,0 +1,45 @@
+use color_eyre::eyre::{eyre, Result};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Flag for aborting long operations from another thread, clones share the flag
+///
+/// Operations check it regularly and return an error once it's set, e.g. when the user of a GUI
+/// closes the window during the key derivation.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Error "`operation` cancelled" once cancelled, for checking between steps
+    pub fn check(&self, operation: &str) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(eyre!("{} cancelled", operation)),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+/// Token set by whatever sets the flag, like a signal handler
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(flag: Arc<AtomicBool>) -> CancellationToken {
+        CancellationToken(flag)
+    }
+}
@@ -0,0 +1,20 @@
+/// Backups of andOTP, plain or encrypted
+mod andotp;
+
+/// JSON backups of FreeOTP+
+mod freeotp;
+
+/// Backups of 2FAS Auth, read and written
+mod twofas;
+
+/// Exports for loading the entries into the TOTP fields of Bitwarden
+mod bitwarden;
+
+/// KeePass databases with the TOTP settings KeePassXC reads
+mod kdbx;
+
+pub use andotp::parse_andotp;
+pub use bitwarden::{to_bitwarden_csv, to_bitwarden_json};
+pub use freeotp::parse_freeotp_plus;
+pub use kdbx::{to_kdbx, KDBX_ROUNDS};
+pub use twofas::{parse_2fas, to_2fas};
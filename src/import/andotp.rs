@@ -0,0 +1,196 @@
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use color_eyre::eyre::{eyre, Result};
+use pbkdf2::pbkdf2_hmac;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    otp::{Entry, EntryInfo, EntryInfoHotp, EntryInfoSteam, EntryInfoTotp, HashAlgorithm},
+    vault::PasswordGetter,
+};
+
+const ITERATIONS_SIZE: usize = 4;
+const SALT_SIZE: usize = 12;
+const NONCE_SIZE: usize = 12;
+/// Bounds on the PBKDF2 iterations, as the Aegis importer checks them
+const MAX_ITERATIONS: u32 = 10_000_000;
+
+/// Entry of an andOTP backup
+#[derive(Deserialize)]
+struct AndOtpEntry {
+    secret: String,
+    /// Missing in backups of old versions, which put "Issuer - Name" into the label
+    #[serde(default)]
+    issuer: Option<String>,
+    label: String,
+    digits: i32,
+    #[serde(rename = "type")]
+    otp_type: String,
+    #[serde(default)]
+    algorithm: HashAlgorithm,
+    #[serde(default)]
+    period: Option<i32>,
+    #[serde(default)]
+    counter: Option<u64>,
+}
+
+/// Entries of an andOTP backup, the JSON of a plain one or the contents of an encrypted one
+///
+/// The password is only asked for if the backup is encrypted. Both the current format, with a
+/// key derived by PBKDF2, and the old one, with the SHA-256 of the password as the key, are read.
+pub fn parse_andotp(contents: &[u8], password_getter: impl PasswordGetter) -> Result<Vec<Entry>> {
+    let json = match contents.trim_ascii_start().first() {
+        Some(b'[') => Zeroizing::new(contents.to_vec()),
+        _ => {
+            let password = Zeroizing::new(password_getter.get_password()?);
+            decrypt(contents, password.as_bytes())
+                .or_else(|_| decrypt_old(contents, password.as_bytes()))
+                .map_err(|_| eyre!("Failed to decrypt the andOTP backup, wrong password?"))?
+        }
+    };
+    let entries: Vec<AndOtpEntry> = serde_json::from_slice(&json)
+        .map_err(|e| eyre!("Failed to parse the andOTP backup: {}", e))?;
+    entries.into_iter().map(convert).collect()
+}
+
+/// Backup encrypted with a PBKDF2 key, after the iterations, salt and nonce
+fn decrypt(contents: &[u8], password: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let header = ITERATIONS_SIZE + SALT_SIZE + NONCE_SIZE;
+    if contents.len() < header {
+        return Err(eyre!("The andOTP backup is too short"));
+    }
+    let (iterations, rest) = contents.split_at(ITERATIONS_SIZE);
+    let iterations = u32::from_be_bytes(iterations.try_into()?);
+    if !(1..=MAX_ITERATIONS).contains(&iterations) {
+        return Err(eyre!("Invalid number of iterations {}", iterations));
+    }
+    let (salt, rest) = rest.split_at(SALT_SIZE);
+    let mut key = Zeroizing::new([0u8; 32]);
+    pbkdf2_hmac::<Sha1>(password, salt, iterations, key.as_mut());
+    open(&key, rest)
+}
+
+/// Backup of old versions, encrypted with the SHA-256 of the password, after the nonce
+fn decrypt_old(contents: &[u8], password: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let key = Zeroizing::new(<[u8; 32]>::from(Sha256::digest(password)));
+    open(&key, contents)
+}
+
+/// Decrypt the ciphertext with its tag after the nonce
+fn open(key: &[u8; 32], sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    if sealed.len() < NONCE_SIZE {
+        return Err(eyre!("The andOTP backup is too short"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| eyre!("Failed to decrypt the andOTP backup"))
+}
+
+fn convert(entry: AndOtpEntry) -> Result<Entry> {
+    let (issuer, name) = match entry.issuer {
+        Some(issuer) => (issuer, entry.label),
+        None => match entry.label.split_once(" - ") {
+            Some((issuer, name)) => (issuer.to_string(), name.to_string()),
+            None => (String::new(), entry.label),
+        },
+    };
+    let secret = entry.secret.trim_end_matches('=').to_uppercase();
+    let info = match entry.otp_type.to_lowercase().as_str() {
+        "totp" => EntryInfo::Totp(EntryInfoTotp {
+            secret,
+            algo: entry.algorithm,
+            digits: entry.digits,
+            period: entry.period.unwrap_or(30),
+        }),
+        "hotp" => EntryInfo::Hotp(EntryInfoHotp {
+            secret,
+            algo: entry.algorithm,
+            digits: entry.digits,
+            counter: entry.counter.ok_or(eyre!(
+                "HOTP entry {} ({}) has no counter",
+                issuer,
+                name
+            ))?,
+        }),
+        "steam" => EntryInfo::Steam(EntryInfoSteam {
+            secret,
+            algo: HashAlgorithm::Sha1,
+            digits: entry.digits,
+            period: entry.period.unwrap_or(30),
+        }),
+        otp_type => {
+            return Err(eyre!(
+                "Unsupported type {} of {} ({})",
+                otp_type,
+                issuer,
+                name
+            ))
+        }
+    };
+    info.validate()
+        .map_err(|e| eyre!("Invalid entry {} ({}): {}", issuer, name, e))?;
+    Ok(Entry::new(issuer, name, info))
+}
+
+#[cfg(test)]
+mod test {
+    use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+    use sha2::{Digest, Sha256};
+
+    use super::parse_andotp;
+    use crate::otp::{EntryInfo, EntryInfoHotp, HashAlgorithm};
+    use color_eyre::eyre::{eyre, Result};
+
+    fn no_password() -> Result<String> {
+        Err(eyre!("No password"))
+    }
+
+    #[test]
+    fn parse_backups() {
+        let plain =
+            parse_andotp(include_bytes!("../../res/andotp_plain.json"), no_password).unwrap();
+        let names: Vec<_> = plain
+            .iter()
+            .map(|entry| (entry.issuer.as_str(), entry.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [("Deno", "Mason"), ("WWE", "Mason"), ("Steam", "Sophia")]
+        );
+        assert_eq!(
+            plain[1].info,
+            EntryInfo::Hotp(EntryInfoHotp {
+                secret: "KUFQ4ORBXCU5L6KN".to_string(),
+                algo: HashAlgorithm::Sha256,
+                digits: 6,
+                counter: 7,
+            })
+        );
+
+        let encrypted = include_bytes!("../../res/andotp_encrypted.json.aes");
+        let decrypted = parse_andotp(encrypted, || Ok("test".to_string())).unwrap();
+        assert_eq!(decrypted, plain);
+        assert!(parse_andotp(encrypted, || Ok("wrong".to_string())).is_err());
+        assert!(parse_andotp(encrypted, no_password).is_err());
+
+        // Old versions wrote no header, the key is the SHA-256 of the password
+        let nonce = [7u8; 12];
+        let mut old = nonce.to_vec();
+        old.extend(
+            Aes256Gcm::new(&Sha256::digest(b"test"))
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    &include_bytes!("../../res/andotp_plain.json")[..],
+                )
+                .unwrap(),
+        );
+        assert_eq!(
+            parse_andotp(&old, || Ok("test".to_string())).unwrap(),
+            plain
+        );
+    }
+}
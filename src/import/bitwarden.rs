@@ -0,0 +1,134 @@
+use color_eyre::eyre::{eyre, Result};
+use serde_json::json;
+
+use crate::otp::{Entry, EntryInfo};
+
+/// Value of the TOTP field of a Bitwarden login, which knows TOTP and Steam codes only
+fn totp_field(entry: &Entry) -> Result<String> {
+    match &entry.info {
+        EntryInfo::Totp(_) => Ok(entry.to_otpauth_uri()),
+        EntryInfo::Steam(info) => Ok(format!("steam://{}", info.secret)),
+        _ => Err(eyre!(
+            "Bitwarden has no codes like those of {} ({})",
+            entry.issuer,
+            entry.name
+        )),
+    }
+}
+
+/// Name of the login, the issuer or the account name without one
+fn login_name(entry: &Entry) -> &str {
+    match entry.issuer.trim() {
+        "" => entry.name.trim(),
+        issuer => issuer,
+    }
+}
+
+/// Unencrypted Bitwarden JSON export with a login for every entry, its TOTP field set
+///
+/// Bitwarden generates TOTP and Steam codes only, other entries are an error.
+pub fn to_bitwarden_json(entries: &[Entry]) -> Result<String> {
+    let items = entries
+        .iter()
+        .map(|entry| {
+            Ok(json!({
+                "id": entry.uuid,
+                "organizationId": null,
+                "folderId": null,
+                // Login
+                "type": 1,
+                "reprompt": 0,
+                "name": login_name(entry),
+                "notes": Some(&entry.note).filter(|note| !note.is_empty()),
+                "favorite": entry.favorite,
+                "login": {
+                    "uris": [],
+                    "username": entry.name,
+                    "password": null,
+                    "totp": totp_field(entry)?,
+                },
+                "collectionIds": null,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let export = json!({
+        "encrypted": false,
+        "folders": [],
+        "items": items,
+    });
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+/// Bitwarden CSV export of the entries, see [to_bitwarden_json]
+pub fn to_bitwarden_csv(entries: &[Entry]) -> Result<String> {
+    let mut csv = String::from(
+        "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\n",
+    );
+    for entry in entries {
+        let row = [
+            "",
+            if entry.favorite { "1" } else { "" },
+            "login",
+            login_name(entry),
+            &entry.note,
+            "",
+            "0",
+            "",
+            &entry.name,
+            "",
+            &totp_field(entry)?,
+        ];
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// Quote the field if it contains a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_bitwarden_csv, to_bitwarden_json};
+    use crate::otp::Entry;
+
+    #[test]
+    fn write_exports() {
+        let vault: serde_json::Value =
+            serde_json::from_str(include_str!("../../res/aegis_plain.json")).unwrap();
+        let mut entries: Vec<Entry> = vault["db"]["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|raw| serde_json::from_value(raw.clone()).unwrap())
+            .filter(|entry: &Entry| entry.issuer != "WWE")
+            .collect();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&to_bitwarden_json(&entries).unwrap()).unwrap();
+        let items = json["items"].as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["name"], "Deno");
+        assert_eq!(items[0]["login"]["username"], "Mason");
+        assert_eq!(items[0]["login"]["totp"], entries[0].to_otpauth_uri());
+        assert!(items[2]["login"]["totp"]
+            .as_str()
+            .unwrap()
+            .starts_with("steam://"));
+
+        let mut with_comma = entries.remove(0);
+        with_comma.name = "Mason, \"Jr\"".to_string();
+        let csv = to_bitwarden_csv(&[with_comma]).unwrap();
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.starts_with(",,login,Deno,,,0,,\"Mason, \"\"Jr\"\"\",,otpauth://totp/"));
+
+        let hotp: Entry = serde_json::from_value(vault["db"]["entries"][2].clone()).unwrap();
+        assert!(to_bitwarden_json(&[hotp]).is_err());
+    }
+}
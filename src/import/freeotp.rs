@@ -0,0 +1,122 @@
+use color_eyre::eyre::{eyre, Result};
+use serde::Deserialize;
+
+use crate::otp::{Entry, EntryInfo, EntryInfoHotp, EntryInfoSteam, EntryInfoTotp, HashAlgorithm};
+
+/// JSON backup of FreeOTP+
+#[derive(Deserialize)]
+struct Backup {
+    tokens: Vec<Token>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Token {
+    /// Bytes of the secret as the signed bytes of Java, -128 to 127
+    secret: Vec<i8>,
+    #[serde(default)]
+    issuer_ext: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    algo: HashAlgorithm,
+    digits: i32,
+    #[serde(rename = "type")]
+    otp_type: String,
+    #[serde(default)]
+    period: Option<i32>,
+    #[serde(default)]
+    counter: u64,
+}
+
+/// Entries of a JSON backup of FreeOTP+
+///
+/// TOTP tokens of the issuer "Steam" are Steam Guard entries, as FreeOTP+ shows them.
+pub fn parse_freeotp_plus(contents: &str) -> Result<Vec<Entry>> {
+    let backup: Backup = serde_json::from_str(contents.trim_start_matches('\u{feff}'))
+        .map_err(|e| eyre!("Failed to parse the FreeOTP+ backup: {}", e))?;
+    backup.tokens.into_iter().map(convert).collect()
+}
+
+fn convert(token: Token) -> Result<Entry> {
+    let (issuer, name) = (
+        token.issuer_ext.trim().to_string(),
+        token.label.trim().to_string(),
+    );
+    let bytes: Vec<u8> = token.secret.iter().map(|&byte| byte as u8).collect();
+    let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes);
+    let info = match token.otp_type.to_lowercase().as_str() {
+        "totp" if issuer == "Steam" => EntryInfo::Steam(EntryInfoSteam {
+            secret,
+            algo: HashAlgorithm::Sha1,
+            digits: 5,
+            period: token.period.unwrap_or(30),
+        }),
+        "totp" => EntryInfo::Totp(EntryInfoTotp {
+            secret,
+            algo: token.algo,
+            digits: token.digits,
+            period: token.period.unwrap_or(30),
+        }),
+        "hotp" => EntryInfo::Hotp(EntryInfoHotp {
+            secret,
+            algo: token.algo,
+            digits: token.digits,
+            counter: token.counter,
+        }),
+        otp_type => {
+            return Err(eyre!(
+                "Unsupported type {} of {} ({})",
+                otp_type,
+                issuer,
+                name
+            ))
+        }
+    };
+    info.validate()
+        .map_err(|e| eyre!("Invalid entry {} ({}): {}", issuer, name, e))?;
+    Ok(Entry::new(issuer, name, info))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_freeotp_plus;
+    use crate::otp::{EntryInfo, EntryInfoHotp, EntryInfoTotp, HashAlgorithm};
+
+    #[test]
+    fn parse_backups() {
+        let entries = parse_freeotp_plus(include_str!("../../res/freeotp_plus.json")).unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.issuer.as_str(), entry.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [("Deno", "Mason"), ("WWE", "Mason"), ("Steam", "Sophia")]
+        );
+        assert_eq!(
+            entries[0].info,
+            EntryInfo::Totp(EntryInfoTotp {
+                secret: "JBSWY3DPEHPK3PXP".to_string(),
+                algo: HashAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+            })
+        );
+        assert!(matches!(
+            entries[1].info,
+            EntryInfo::Hotp(EntryInfoHotp {
+                counter: 7,
+                digits: 8,
+                algo: HashAlgorithm::Sha256,
+                ..
+            })
+        ));
+        assert!(matches!(entries[2].info, EntryInfo::Steam(_)));
+
+        assert!(parse_freeotp_plus(
+            r#"{"tokens": [{"secret": [300], "digits": 6, "type": "TOTP"}]}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes256,
+};
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::Zeroizing;
+
+use crate::otp::{Entry, EntryInfo, EntryInfoTotp, HashAlgorithm};
+
+/// Rounds of AES-KDF for new databases, the more the slower guessing the password is
+pub const KDBX_ROUNDS: u64 = 2_000_000;
+
+/// UUID of the AES-256-CBC outer cipher
+const CIPHER_AES256: [u8; 16] = [
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+/// UUID of AES-KDF
+const KDF_AES: [u8; 16] = [
+    0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea,
+];
+/// Salsa20, the inner stream for protected values, of which there are none here
+const INNER_STREAM_SALSA20: u32 = 2;
+/// Size of the HMAC protected blocks of the payload
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// KeePass KDBX 4 database protected by `password` with an entry for every entry, its TOTP
+/// settings in the otp attribute KeePassXC reads
+///
+/// KeePassXC generates TOTP and Steam codes only, other entries are an error. The database is
+/// encrypted with AES-256 under a key derived with `rounds` of AES-KDF, see [KDBX_ROUNDS].
+pub fn to_kdbx(entries: &[Entry], password: &str, rounds: u64) -> Result<Vec<u8>> {
+    let xml = Zeroizing::new(database_xml(entries)?);
+
+    let mut master_seed = [0u8; 32];
+    let mut iv = [0u8; 16];
+    let mut kdf_seed = [0u8; 32];
+    let mut stream_key = [0u8; 32];
+    for random in [
+        &mut master_seed[..],
+        &mut iv,
+        &mut kdf_seed,
+        &mut stream_key,
+    ] {
+        OsRng.fill_bytes(random);
+    }
+
+    let mut kdf_parameters = vec![0x00, 0x01];
+    variant(&mut kdf_parameters, 0x42, "$UUID", &KDF_AES);
+    variant(&mut kdf_parameters, 0x05, "R", &rounds.to_le_bytes());
+    variant(&mut kdf_parameters, 0x42, "S", &kdf_seed);
+    kdf_parameters.push(0x00);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&0x9aa2d903u32.to_le_bytes());
+    header.extend_from_slice(&0xb54bfb67u32.to_le_bytes());
+    // Version 4.0
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header_field(&mut header, 2, &CIPHER_AES256);
+    // Not compressed
+    header_field(&mut header, 3, &0u32.to_le_bytes());
+    header_field(&mut header, 4, &master_seed);
+    header_field(&mut header, 7, &iv);
+    header_field(&mut header, 11, &kdf_parameters);
+    header_field(&mut header, 0, b"\r\n\r\n");
+
+    let key = aes_kdf(password, &kdf_seed, rounds);
+    let encryption_key = Sha256::new()
+        .chain_update(master_seed)
+        .chain_update(key.as_slice())
+        .finalize();
+    let hmac_key = Zeroizing::new(
+        Sha512::new()
+            .chain_update(master_seed)
+            .chain_update(key.as_slice())
+            .chain_update([1])
+            .finalize()
+            .to_vec(),
+    );
+
+    let mut payload = Zeroizing::new(Vec::new());
+    header_field(&mut payload, 1, &INNER_STREAM_SALSA20.to_le_bytes());
+    header_field(&mut payload, 2, &stream_key);
+    header_field(&mut payload, 0, &[]);
+    payload.extend_from_slice(xml.as_bytes());
+    let encrypted = aes_cbc(&encryption_key.into(), &iv, &payload);
+
+    let mut file = header.clone();
+    file.extend_from_slice(&Sha256::digest(&header));
+    file.extend_from_slice(&block_hmac(&hmac_key, u64::MAX, &header)?);
+    let blocks = encrypted.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+    for (index, block) in blocks.enumerate() {
+        let size = (block.len() as i32).to_le_bytes();
+        let mut authenticated = Vec::with_capacity(block.len() + 12);
+        authenticated.extend_from_slice(&(index as u64).to_le_bytes());
+        authenticated.extend_from_slice(&size);
+        authenticated.extend_from_slice(block);
+        file.extend_from_slice(&block_hmac(&hmac_key, index as u64, &authenticated)?);
+        file.extend_from_slice(&size);
+        file.extend_from_slice(block);
+    }
+    Ok(file)
+}
+
+/// Header field with a 4 byte length, as in the outer and inner headers of KDBX 4
+fn header_field(header: &mut Vec<u8>, id: u8, data: &[u8]) {
+    header.push(id);
+    header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    header.extend_from_slice(data);
+}
+
+/// Item of a KDBX variant dictionary
+fn variant(dictionary: &mut Vec<u8>, value_type: u8, name: &str, value: &[u8]) {
+    dictionary.push(value_type);
+    dictionary.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    dictionary.extend_from_slice(name.as_bytes());
+    dictionary.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    dictionary.extend_from_slice(value);
+}
+
+/// Key of the password, the composite key encrypted `rounds` times with AES-256 under `seed`
+fn aes_kdf(password: &str, seed: &[u8; 32], rounds: u64) -> Zeroizing<[u8; 32]> {
+    let composite = Sha256::digest(Sha256::digest(password.as_bytes()));
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&composite);
+    let cipher = Aes256::new(seed.into());
+    for half in key.chunks_exact_mut(16) {
+        let block = GenericArray::from_mut_slice(half);
+        for _ in 0..rounds {
+            cipher.encrypt_block(block);
+        }
+    }
+    let transformed = Sha256::digest(key.as_slice());
+    key.copy_from_slice(&transformed);
+    key
+}
+
+/// AES-256-CBC with PKCS#7 padding
+fn aes_cbc(key: &[u8; 32], iv: &[u8; 16], plaintext: &[u8]) -> Vec<u8> {
+    let cipher = Aes256::new(key.into());
+    let padding = 16 - plaintext.len() % 16;
+    let mut data = plaintext.to_vec();
+    data.resize(plaintext.len() + padding, padding as u8);
+    let mut previous = *iv;
+    for block in data.chunks_exact_mut(16) {
+        for (byte, chained) in block.iter_mut().zip(previous) {
+            *byte ^= chained;
+        }
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        previous.copy_from_slice(block);
+    }
+    data
+}
+
+/// HMAC-SHA256 of a block, under the key of its index
+fn block_hmac(hmac_key: &[u8], index: u64, data: &[u8]) -> Result<[u8; 32]> {
+    let key = Sha512::new()
+        .chain_update(index.to_le_bytes())
+        .chain_update(hmac_key)
+        .finalize();
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).map_err(|e| eyre!("{}", e))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().into())
+}
+
+/// KeePass XML of a group holding the entries
+fn database_xml(entries: &[Entry]) -> Result<String> {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n",
+        "<KeePassFile>\n",
+        "\t<Meta>\n\t\t<Generator>aegis-rs</Generator>\n",
+        "\t\t<DatabaseName>Aegis</DatabaseName>\n\t</Meta>\n",
+        "\t<Root>\n\t\t<Group>\n",
+    ));
+    xml.push_str(&format!("\t\t\t<UUID>{}</UUID>\n", random_uuid()));
+    xml.push_str("\t\t\t<Name>Aegis</Name>\n");
+    for entry in entries {
+        let uuid = entry
+            .uuid
+            .as_deref()
+            .and_then(|uuid| hex::decode(uuid.replace('-', "")).ok())
+            .filter(|bytes| bytes.len() == 16)
+            .map(|bytes| general_purpose::STANDARD.encode(bytes))
+            .unwrap_or_else(random_uuid);
+        xml.push_str(&format!("\t\t\t<Entry>\n\t\t\t\t<UUID>{}</UUID>\n", uuid));
+        for (key, value) in [
+            ("Title", entry.issuer.as_str()),
+            ("UserName", &entry.name),
+            ("Password", ""),
+            ("URL", ""),
+            ("Notes", &entry.note),
+            ("otp", &otp_attribute(entry)?),
+        ] {
+            xml.push_str(&format!(
+                "\t\t\t\t<String>\n\t\t\t\t\t<Key>{}</Key>\n\t\t\t\t\t<Value>{}</Value>\n\t\t\t\t</String>\n",
+                key,
+                escape(value)
+            ));
+        }
+        xml.push_str("\t\t\t</Entry>\n");
+    }
+    xml.push_str("\t\t</Group>\n\t</Root>\n</KeePassFile>\n");
+    Ok(xml)
+}
+
+/// The otp attribute, an `otpauth://` URI in which KeePassXC marks Steam codes with an encoder
+fn otp_attribute(entry: &Entry) -> Result<String> {
+    match &entry.info {
+        EntryInfo::Totp(_) => Ok(entry.to_otpauth_uri()),
+        EntryInfo::Steam(info) => {
+            let totp = EntryInfo::Totp(EntryInfoTotp {
+                secret: info.secret.clone(),
+                algo: HashAlgorithm::Sha1,
+                digits: info.digits,
+                period: info.period,
+            });
+            let uri = Entry::new(entry.issuer.clone(), entry.name.clone(), totp).to_otpauth_uri();
+            Ok(format!("{}&encoder=steam", uri))
+        }
+        _ => Err(eyre!(
+            "KeePassXC has no codes like those of {} ({})",
+            entry.issuer,
+            entry.name
+        )),
+    }
+}
+
+/// New base64 UUID, for groups and entries without one
+fn random_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::STANDARD.encode(bytes)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{aes_cbc, aes_kdf, to_kdbx};
+    use crate::otp::Entry;
+
+    #[test]
+    fn encrypt_like_keepass() {
+        // NIST SP 800-38A F.2.5
+        let key = hex::decode("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+            .unwrap();
+        let iv = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let encrypted = aes_cbc(
+            &key.try_into().unwrap(),
+            &iv.try_into().unwrap(),
+            &hex::decode("6bc1bee22e409f96e93d7e117393172a").unwrap(),
+        );
+        assert_eq!(
+            hex::encode(&encrypted[..16]),
+            "f58c4c04d6e5f1ba779eabfb5f7bfbd6"
+        );
+        assert_eq!(encrypted.len(), 32);
+
+        // Without rounds the key is the composite key hashed once more
+        let key = aes_kdf("test", &[0; 32], 0);
+        assert_eq!(
+            hex::encode(key.as_slice()),
+            "be2ef4971b24405df0541f95fe6158ac661e69971337ff7260b219d1056ebc3b"
+        );
+    }
+
+    #[test]
+    fn write_databases() {
+        let vault: serde_json::Value =
+            serde_json::from_str(include_str!("../../res/aegis_plain.json")).unwrap();
+        let entries: Vec<Entry> = vault["db"]["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|raw| serde_json::from_value(raw.clone()).unwrap())
+            .collect();
+        assert!(to_kdbx(&entries, "test", 10).is_err());
+
+        let entries: Vec<Entry> = entries
+            .into_iter()
+            .filter(|entry| entry.issuer != "WWE")
+            .collect();
+        let kdbx = to_kdbx(&entries, "test", 10).unwrap();
+        assert_eq!(kdbx[..12], hex::decode("03d9a29a67fb4bb500000400").unwrap());
+        assert!(!kdbx
+            .windows(26)
+            .any(|window| window == b"4SJHB4GSD43FZBAI7C2HLRJGPQ"));
+    }
+}
@@ -0,0 +1,317 @@
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use crate::{
+    otp::{Entry, EntryInfo, EntryInfoHotp, EntryInfoSteam, EntryInfoTotp, HashAlgorithm},
+    vault::PasswordGetter,
+};
+
+/// Iterations of PBKDF2 the key of encrypted backups is derived with
+const ITERATIONS: u32 = 10_000;
+/// Backup format version written by current versions of the app
+const SCHEMA_VERSION: u32 = 4;
+
+/// Backup of 2FAS Auth, plain or with the services encrypted
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Backup {
+    #[serde(default)]
+    services: Vec<Service>,
+    /// Base64 of the encrypted services with their tag, the salt and the nonce, split by ':'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    services_encrypted: Option<String>,
+    #[serde(default)]
+    groups: Vec<Value>,
+    #[serde(default)]
+    updated_at: u64,
+    schema_version: u32,
+    #[serde(default)]
+    app_version_code: u64,
+    #[serde(default)]
+    app_version_name: String,
+    #[serde(default)]
+    app_origin: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Service {
+    name: String,
+    secret: String,
+    #[serde(default)]
+    updated_at: u64,
+    otp: Otp,
+    #[serde(default)]
+    order: Order,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Otp {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    digits: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    period: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    algorithm: Option<HashAlgorithm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    counter: Option<u64>,
+    /// TOTP, HOTP or STEAM, TOTP if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token_type: Option<String>,
+    #[serde(default)]
+    source: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Order {
+    #[serde(default)]
+    position: usize,
+}
+
+/// Entries of a backup of 2FAS Auth
+///
+/// The password is only asked for if the services of the backup are encrypted.
+pub fn parse_2fas(contents: &str, password_getter: impl PasswordGetter) -> Result<Vec<Entry>> {
+    let backup: Backup = serde_json::from_str(contents)
+        .map_err(|e| eyre!("Failed to parse the 2FAS backup: {}", e))?;
+    let services = match &backup.services_encrypted {
+        Some(encrypted) => {
+            let password = Zeroizing::new(password_getter.get_password()?);
+            let json = decrypt(encrypted, password.as_bytes())?;
+            serde_json::from_slice(&json)
+                .map_err(|e| eyre!("Failed to parse the services of the 2FAS backup: {}", e))?
+        }
+        None => backup.services,
+    };
+    services.into_iter().map(convert).collect()
+}
+
+fn decrypt(encrypted: &str, password: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let parts: Vec<Vec<u8>> = encrypted
+        .split(':')
+        .map(|part| general_purpose::STANDARD.decode(part))
+        .collect::<Result<_, _>>()
+        .map_err(|_| eyre!("The encrypted services of the 2FAS backup are not base64"))?;
+    let [ciphertext, salt, nonce] = &parts[..] else {
+        return Err(eyre!("Invalid encrypted services in the 2FAS backup"));
+    };
+    if nonce.len() != 12 {
+        return Err(eyre!("Nonce has invalid length {}", nonce.len()));
+    }
+    let mut key = Zeroizing::new([0u8; 32]);
+    pbkdf2_hmac::<Sha256>(password, salt, ITERATIONS, key.as_mut());
+    Aes256Gcm::new(key.as_ref().into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext.as_slice())
+        .map(Zeroizing::new)
+        .map_err(|_| eyre!("Failed to decrypt the 2FAS backup, wrong password?"))
+}
+
+fn convert(service: Service) -> Result<Entry> {
+    let otp = service.otp;
+    let issuer = otp
+        .issuer
+        .filter(|issuer| !issuer.trim().is_empty())
+        .unwrap_or(service.name)
+        .trim()
+        .to_string();
+    // Labels of otpauth:// URIs have the issuer in front
+    let name = match (otp.account, otp.label) {
+        (Some(account), _) if !account.trim().is_empty() => account,
+        (_, Some(label)) => match label.split_once(':') {
+            Some((_, name)) => name.to_string(),
+            None => label,
+        },
+        _ => String::new(),
+    }
+    .trim()
+    .to_string();
+
+    let secret = service.secret.trim_end_matches('=').to_uppercase();
+    let algo = otp.algorithm.unwrap_or_default();
+    let digits = otp.digits.unwrap_or(6);
+    let token_type = otp.token_type.unwrap_or_else(|| "TOTP".to_string());
+    let info = match token_type.to_lowercase().as_str() {
+        "totp" => EntryInfo::Totp(EntryInfoTotp {
+            secret,
+            algo,
+            digits,
+            period: otp.period.unwrap_or(30),
+        }),
+        "hotp" => EntryInfo::Hotp(EntryInfoHotp {
+            secret,
+            algo,
+            digits,
+            counter: otp.counter.unwrap_or(0),
+        }),
+        "steam" => EntryInfo::Steam(EntryInfoSteam {
+            secret,
+            algo: HashAlgorithm::Sha1,
+            digits: 5,
+            period: otp.period.unwrap_or(30),
+        }),
+        otp_type => {
+            return Err(eyre!(
+                "Unsupported type {} of {} ({})",
+                otp_type,
+                issuer,
+                name
+            ))
+        }
+    };
+    info.validate()
+        .map_err(|e| eyre!("Invalid entry {} ({}): {}", issuer, name, e))?;
+    Ok(Entry::new(issuer, name, info))
+}
+
+/// Unencrypted 2FAS Auth backup of the entries, for importing them into the app
+///
+/// 2FAS knows TOTP, HOTP and Steam entries only, other entries are an error.
+pub fn to_2fas(entries: &[Entry]) -> Result<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64);
+    let services = entries
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| {
+            let (token_type, secret, algorithm, digits, period, counter) = match &entry.info {
+                EntryInfo::Totp(info) => (
+                    "TOTP",
+                    &info.secret,
+                    info.algo,
+                    info.digits,
+                    Some(info.period),
+                    None,
+                ),
+                EntryInfo::Hotp(info) => (
+                    "HOTP",
+                    &info.secret,
+                    info.algo,
+                    info.digits,
+                    None,
+                    Some(info.counter),
+                ),
+                EntryInfo::Steam(info) => (
+                    "STEAM",
+                    &info.secret,
+                    info.algo,
+                    info.digits,
+                    Some(info.period),
+                    None,
+                ),
+                EntryInfo::Yandex(_) | EntryInfo::Motp(_) => {
+                    return Err(eyre!(
+                        "2FAS has no entries like {} ({})",
+                        entry.issuer,
+                        entry.name
+                    ))
+                }
+            };
+            let issuer = entry.issuer.trim();
+            Ok(Service {
+                name: match issuer {
+                    "" => entry.name.clone(),
+                    issuer => issuer.to_string(),
+                },
+                secret: secret.clone(),
+                updated_at: now,
+                otp: Otp {
+                    label: Some(format!("{}:{}", issuer, entry.name)),
+                    account: Some(entry.name.clone()),
+                    issuer: Some(issuer.to_string()),
+                    digits: Some(digits),
+                    period,
+                    algorithm: Some(algorithm),
+                    counter,
+                    token_type: Some(token_type.to_string()),
+                    source: "Manual".to_string(),
+                },
+                order: Order { position },
+            })
+        })
+        .collect::<Result<_>>()?;
+    let backup = Backup {
+        services,
+        services_encrypted: None,
+        groups: Vec::new(),
+        updated_at: now,
+        schema_version: SCHEMA_VERSION,
+        app_version_code: 0,
+        app_version_name: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        app_origin: "android".to_string(),
+    };
+    Ok(serde_json::to_string_pretty(&backup)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_2fas, to_2fas};
+    use crate::otp::{Entry, EntryInfo, EntryInfoHotp, HashAlgorithm};
+    use color_eyre::eyre::{eyre, Result};
+
+    fn no_password() -> Result<String> {
+        Err(eyre!("No password"))
+    }
+
+    #[test]
+    fn parse_backups() {
+        let plain = parse_2fas(include_str!("../../res/2fas_plain.2fas"), no_password).unwrap();
+        let names: Vec<_> = plain
+            .iter()
+            .map(|entry| (entry.issuer.as_str(), entry.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [("Deno", "Mason"), ("WWE", "Mason"), ("Steam", "Sophia")]
+        );
+        assert_eq!(
+            plain[1].info,
+            EntryInfo::Hotp(EntryInfoHotp {
+                secret: "KUFQ4ORBXCU5L6KN".to_string(),
+                algo: HashAlgorithm::Sha256,
+                digits: 8,
+                counter: 7,
+            })
+        );
+
+        let encrypted = include_str!("../../res/2fas_encrypted.2fas");
+        assert_eq!(
+            parse_2fas(encrypted, || Ok("test".to_string())).unwrap(),
+            plain
+        );
+        assert!(parse_2fas(encrypted, || Ok("wrong".to_string())).is_err());
+        assert!(parse_2fas(encrypted, no_password).is_err());
+    }
+
+    #[test]
+    fn write_backups() {
+        let plain = parse_2fas(include_str!("../../res/2fas_plain.2fas"), no_password).unwrap();
+        let written = to_2fas(&plain).unwrap();
+        assert_eq!(parse_2fas(&written, no_password).unwrap(), plain);
+
+        let yandex: Entry = serde_json::from_str(
+            r#"{
+                "type": "yandex",
+                "name": "john",
+                "issuer": "Yandex",
+                "info": {"secret": "JBSWY3DPEHPK3PXP", "digits": 8, "period": 30, "pin": "1234"}
+            }"#,
+        )
+        .unwrap();
+        assert!(to_2fas(&[yandex]).is_err());
+    }
+}
@@ -6,3 +6,31 @@ pub mod otp;
 
 /// Aegis vault backup data structures and parsing
 pub mod vault;
+
+/// Aborting long operations, like key derivations, from other threads
+pub mod cancel;
+
+/// Estimating how hard a password is to guess
+pub mod strength;
+
+/// Checking passwords against lists of breached passwords
+pub mod breach;
+
+/// Importing Steam Guard secrets from the formats Steam users have
+pub mod steam;
+
+/// Checking codes submitted to self-hosted services, with replay protection
+pub mod verify;
+
+/// Drawing QR codes
+pub mod qr;
+
+/// Parsing otpauth:// URIs of QR codes
+pub mod otpauth;
+
+/// Decoding the export QR codes of Google Authenticator
+#[cfg(feature = "google-migration")]
+pub mod migration;
+
+/// Reading the backups of other authenticator apps, and writing those of 2FAS, Bitwarden and KeePassXC
+pub mod import;
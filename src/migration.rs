@@ -0,0 +1,233 @@
+use base64::{
+    alphabet,
+    engine::{general_purpose, DecodePaddingMode},
+    Engine as _,
+};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::{
+    otp::{Entry, EntryInfo, EntryInfoHotp, EntryInfoTotp, HashAlgorithm},
+    otpauth::percent_decode,
+};
+
+/// Entries of an `otpauth-migration://offline?data=...` URI of the export QR codes of Google
+/// Authenticator
+///
+/// The data is a base64 encoded protocol buffers `MigrationPayload`, which is decoded here by
+/// hand as only a few fields are of use. Large exports are split over several QR codes, each
+/// URI holds a part of the entries.
+pub fn parse_migration_uri(uri: &str) -> Result<Vec<Entry>> {
+    let query = uri
+        .trim()
+        .strip_prefix("otpauth-migration://offline?")
+        .ok_or(eyre!("Not an otpauth-migration:// URI"))?;
+    let data = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("data="))
+        .ok_or(eyre!("No data in the migration URI"))?;
+    // Unescaped + of the base64 would be decoded as space
+    let data = percent_decode(data)?.replace(' ', "+");
+    // Some apps showing the QR codes again drop the padding
+    let engine = general_purpose::GeneralPurpose::new(
+        &alphabet::STANDARD,
+        general_purpose::PAD.with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
+    let payload = engine
+        .decode(data)
+        .map_err(|_| eyre!("The data of the migration URI is not base64"))?;
+
+    let mut entries = Vec::new();
+    let mut fields = Fields(&payload);
+    while let Some((number, value)) = fields.next_field()? {
+        // 1 is the repeated OtpParameters, the others are the version and batch numbers
+        if let (1, Value::Bytes(parameters)) = (number, value) {
+            entries.push(parse_parameters(parameters)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Entry of an `OtpParameters` message
+fn parse_parameters(message: &[u8]) -> Result<Entry> {
+    let mut secret = Vec::new();
+    let mut name = String::new();
+    let mut issuer = String::new();
+    let (mut algorithm, mut digits, mut otp_type, mut counter) = (0, 0, 0, 0);
+    let text = |bytes: &[u8]| {
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| eyre!("Text of the migration URI is not UTF-8"))
+    };
+    let mut fields = Fields(message);
+    while let Some((number, value)) = fields.next_field()? {
+        match (number, value) {
+            (1, Value::Bytes(bytes)) => secret = bytes.to_vec(),
+            (2, Value::Bytes(bytes)) => name = text(bytes)?,
+            (3, Value::Bytes(bytes)) => issuer = text(bytes)?,
+            (4, Value::Varint(value)) => algorithm = value,
+            (5, Value::Varint(value)) => digits = value,
+            (6, Value::Varint(value)) => otp_type = value,
+            (7, Value::Varint(value)) => counter = value,
+            _ => {}
+        }
+    }
+
+    // The name repeats the issuer like in the labels of otpauth:// URIs
+    let name = match name.split_once(':') {
+        Some((prefix, rest)) if issuer.is_empty() || prefix.trim() == issuer.trim() => {
+            if issuer.is_empty() {
+                issuer = prefix.trim().to_string();
+            }
+            rest.trim().to_string()
+        }
+        _ => name.trim().to_string(),
+    };
+    let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret);
+    let algo = match algorithm {
+        0 | 1 => HashAlgorithm::Sha1,
+        2 => HashAlgorithm::Sha256,
+        3 => HashAlgorithm::Sha512,
+        _ => return Err(eyre!("Unsupported algorithm of {} ({})", issuer, name)),
+    };
+    let digits = match digits {
+        2 => 8,
+        _ => 6,
+    };
+    let info = match otp_type {
+        1 => EntryInfo::Hotp(EntryInfoHotp {
+            secret,
+            algo,
+            digits,
+            counter,
+        }),
+        _ => EntryInfo::Totp(EntryInfoTotp {
+            secret,
+            algo,
+            digits,
+            period: 30,
+        }),
+    };
+    info.validate()?;
+    Ok(Entry::new(issuer, name, info))
+}
+
+/// Value of a protocol buffers field, fixed size values are skipped
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Fields of a protocol buffers message
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .0
+                .split_first()
+                .ok_or(eyre!("Truncated migration data"))?;
+            self.0 = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(eyre!("Invalid number in the migration data"))
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.0.len() < length {
+            return Err(eyre!("Truncated migration data"));
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    /// Field number and value, `None` at the end of the message
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let length = self.varint()? as usize;
+                Value::Bytes(self.take(length)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            wire_type => {
+                return Err(eyre!(
+                    "Unsupported wire type {} in the migration data",
+                    wire_type
+                ))
+            }
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_migration_uri;
+    use crate::otp::{EntryInfo, EntryInfoHotp, EntryInfoTotp, HashAlgorithm};
+
+    #[test]
+    fn parse_migration_uris() {
+        let entries = parse_migration_uri(
+            "otpauth-migration://offline?data=CjEKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZTAC",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].issuer, "Example");
+        assert_eq!(entries[0].name, "alice@google.com");
+        assert_eq!(
+            entries[0].info,
+            EntryInfo::Totp(EntryInfoTotp {
+                secret: "JBSWY3DPEHPK3PXP".to_string(),
+                algo: HashAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+            })
+        );
+
+        let entries = parse_migration_uri(
+            "otpauth-migration://offline?data=CikKCkhlbGxvId6tvu8SDEFDTUUgQ286am9obhoHQUNNRSBDbyACKAIwAgocCgpIZWxsbyHerb7vEgVNYXNvbiABKAEwATisAhABGAEgACjAxAc%3D",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].issuer.as_str(), entries[0].name.as_str()),
+            ("ACME Co", "john")
+        );
+        assert!(matches!(
+            entries[0].info,
+            EntryInfo::Totp(EntryInfoTotp {
+                algo: HashAlgorithm::Sha256,
+                digits: 8,
+                ..
+            })
+        ));
+        assert_eq!(
+            (entries[1].issuer.as_str(), entries[1].name.as_str()),
+            ("", "Mason")
+        );
+        assert!(matches!(
+            entries[1].info,
+            EntryInfo::Hotp(EntryInfoHotp { counter: 300, .. })
+        ));
+
+        assert!(parse_migration_uri("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(parse_migration_uri("otpauth-migration://offline?data=CjEKCkhl").is_err());
+    }
+}
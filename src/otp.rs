@@ -1,11 +1,30 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base64::{engine::general_purpose, Engine as _};
 use color_eyre::eyre::{eyre, Result};
-use libreauth::{hash::HashFunction, oath::TOTPBuilder};
-use serde::Deserialize;
-use std::time::{SystemTime, UNIX_EPOCH};
+use hmac::{digest::KeyInit, Hmac, Mac};
+use libreauth::{
+    hash::HashFunction,
+    oath::{HOTPBuilder, TOTPBuilder},
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+use crate::cancel::CancellationToken;
+
+mod md5;
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Default)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum HashAlgorithm {
+    #[default]
     Sha1,
     Sha256,
     Sha512,
@@ -21,7 +40,7 @@ impl From<HashAlgorithm> for HashFunction {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct EntryInfoHotp {
     pub secret: String,
     pub algo: HashAlgorithm,
@@ -29,7 +48,7 @@ pub struct EntryInfoHotp {
     pub counter: u64,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct EntryInfoTotp {
     pub secret: String,
     pub algo: HashAlgorithm,
@@ -37,34 +56,111 @@ pub struct EntryInfoTotp {
     pub period: i32,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct EntryInfoSteam {
     pub secret: String,
-    /// Only Sha1 is supported
+    /// Only Sha1 is supported, the Android app expects it to be written anyway
+    #[serde(default)]
+    pub algo: HashAlgorithm,
     pub digits: i32,
     pub period: i32,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-pub struct EntryInfoYandex {}
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct EntryInfoYandex {
+    pub secret: String,
+    #[serde(default = "yandex_algo")]
+    pub algo: HashAlgorithm,
+    pub digits: i32,
+    pub period: i32,
+    /// PIN the user chose, part of the key
+    pub pin: String,
+}
 
-#[derive(Debug, Deserialize, PartialEq)]
+fn yandex_algo() -> HashAlgorithm {
+    HashAlgorithm::Sha256
+}
+
+/// Hash of mOTP entries, which is always MD5
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum MotpAlgorithm {
+    #[default]
+    #[serde(rename = "MD5")]
+    Md5,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct EntryInfoMotp {
+    pub secret: String,
+    #[serde(default)]
+    pub algo: MotpAlgorithm,
+    pub digits: i32,
+    pub period: i32,
+    /// PIN the user chose, part of the hashed message
+    pub pin: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type", content = "info")]
 pub enum EntryInfo {
-    /// Not implemented.
-    ///
     /// [RFC 4226](https://datatracker.ietf.org/doc/html/rfc4226)
     Hotp(EntryInfoHotp),
 
     /// [RFC 6238](https://datatracker.ietf.org/doc/html/rfc6238)
     Totp(EntryInfoTotp),
 
-    /// Not implemented.
+    /// Steam Guard, TOTP written with letters and digits
     Steam(EntryInfoSteam),
 
-    /// Not implemented.
+    /// Yandex.Key, TOTP with a PIN written with lower case letters
     Yandex(EntryInfoYandex),
+
+    /// [Mobile-OTP](https://motp.sourceforge.net/), MD5 of the time, the secret and a PIN
+    Motp(EntryInfoMotp),
+}
+
+/// Largest number of digits a HOTP based code can have
+const MAX_DIGITS: i32 = 10;
+
+/// Length of new secrets, the 160 bits RFC 4226 recommends
+pub const SECRET_BYTES: usize = 20;
+
+/// New random base32 secret of `bytes` bytes, for accounts whose secret is made here instead
+/// of by the service
+pub fn random_secret(bytes: usize) -> String {
+    let mut secret = Zeroizing::new(vec![0u8; bytes]);
+    OsRng.fill_bytes(&mut secret);
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+}
+
+impl EntryInfo {
+    /// Check the parameters for values no code can be generated with
+    pub fn validate(&self) -> Result<()> {
+        let (secret, digits, period) = match self {
+            EntryInfo::Hotp(info) => (&info.secret, info.digits, None),
+            EntryInfo::Totp(info) => (&info.secret, info.digits, Some(info.period)),
+            EntryInfo::Steam(info) => (&info.secret, info.digits, Some(info.period)),
+            EntryInfo::Yandex(info) => (&info.secret, info.digits, Some(info.period)),
+            EntryInfo::Motp(info) => (&info.secret, info.digits, Some(info.period)),
+        };
+        if base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret).is_none() {
+            return Err(eyre!("Invalid base32 secret"));
+        }
+        if !(1..=MAX_DIGITS).contains(&digits) {
+            return Err(eyre!(
+                "Digits must be between 1 and {}, got {}",
+                MAX_DIGITS,
+                digits
+            ));
+        }
+        if let Some(period) = period {
+            if period <= 0 {
+                return Err(eyre!("Period must be greater than zero, got {}", period));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Entry with metadata and information used to generate one time codes
@@ -72,57 +168,670 @@ pub enum EntryInfo {
 pub struct Entry {
     #[serde(flatten)]
     pub info: EntryInfo,
-    // pub uuid: String,
+    #[serde(default)]
+    pub uuid: Option<String>,
     pub name: String,
     pub issuer: String,
-    // pub note: String,
-    // pub favorite: bool,
-    // pub icon: String,
+    #[serde(default, deserialize_with = "deserialize_or_default")]
+    pub note: String,
+    #[serde(default, deserialize_with = "deserialize_or_default")]
+    pub favorite: bool,
+    /// Base64 encoded icon image
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// MIME type of the icon, e.g. "image/png"
+    #[serde(default)]
+    pub icon_mime: Option<String>,
+    /// Hex encoded SHA-256 hash of the icon image
+    #[serde(default)]
+    pub icon_hash: Option<String>,
+    /// UUIDs of the groups the entry is in, see [`crate::vault::Database::groups`]
+    ///
+    /// Databases before version 3 name a single group in the field "group" instead, see
+    /// [`crate::vault::LazyEntry::group_name`].
+    #[serde(default, deserialize_with = "deserialize_or_default")]
+    pub groups: Vec<String>,
+    /// Fields not documented by Aegis, e.g. added by importers or other tools
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Entry fields documented by Aegis that are not treated as extra fields
+const DOCUMENTED_FIELDS: &[&str] = &[
+    "uuid",
+    "note",
+    "favorite",
+    "icon",
+    "icon_mime",
+    "icon_hash",
+    "groups",
+    // The single group of databases before version 3, see LazyEntry::group_name
+    "group",
+    // Attachments added by aegis-rs
+    "aegis_rs_attachments",
+];
+
+/// Null as the default value, Aegis writes null for some missing fields
+fn deserialize_or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn deserialize_extra<'de, D>(deserializer: D) -> Result<BTreeMap<String, Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut fields = BTreeMap::<String, Value>::deserialize(deserializer)?;
+    fields.retain(|key, _| !DOCUMENTED_FIELDS.contains(&key.as_str()));
+    Ok(fields)
+}
+
+/// Extra fields well known for a set of issuers
+struct IssuerExtraFields {
+    /// Lowercase issuer names to match
+    issuers: &'static [&'static str],
+    /// Field names and their display labels, in display order
+    fields: &'static [(&'static str, &'static str)],
+}
+
+const ISSUER_EXTRA_FIELDS: &[IssuerExtraFields] = &[IssuerExtraFields {
+    issuers: &["battle.net", "blizzard"],
+    fields: &[("serial", "Serial"), ("restore_code", "Restore code")],
+}];
+
+impl Entry {
+    /// Entry without a UUID or any of the other metadata, like one of an importer
+    pub fn new(issuer: String, name: String, info: EntryInfo) -> Entry {
+        Entry {
+            info,
+            uuid: None,
+            name,
+            issuer,
+            note: String::new(),
+            favorite: false,
+            icon: None,
+            icon_mime: None,
+            icon_hash: None,
+            groups: Vec::new(),
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// MIME type and decoded image of the icon, `None` if the entry has none
+    ///
+    /// Icons of old vaults have no MIME type, it's guessed from the image then.
+    pub fn icon(&self) -> Result<Option<(String, Vec<u8>)>> {
+        let Some(icon) = &self.icon else {
+            return Ok(None);
+        };
+        let data = general_purpose::STANDARD.decode(icon.trim()).map_err(|_| {
+            eyre!(
+                "The icon of {} ({}) is not base64",
+                self.issuer.trim(),
+                self.name.trim()
+            )
+        })?;
+        let mime = match &self.icon_mime {
+            Some(mime) => mime.clone(),
+            None if data.starts_with(b"\x89PNG") => "image/png".to_string(),
+            None if data.starts_with(b"\xff\xd8") => "image/jpeg".to_string(),
+            None if data.trim_ascii_start().starts_with(b"<") => "image/svg+xml".to_string(),
+            None => "application/octet-stream".to_string(),
+        };
+        Ok(Some((mime, data)))
+    }
+
+    /// Extra fields of the entry as label and value pairs
+    ///
+    /// Fields known for the entry's issuer, like the serial of a Battle.net authenticator, are
+    /// listed first with a readable label. The rest follow with their raw field name.
+    pub fn extra_fields(&self) -> Vec<(String, String)> {
+        let issuer = self.issuer.to_lowercase();
+        let known_fields = ISSUER_EXTRA_FIELDS
+            .iter()
+            .find(|known| known.issuers.iter().any(|i| issuer.contains(i)))
+            .map(|known| known.fields)
+            .unwrap_or_default();
+
+        let display = |value: &Value| match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let mut fields: Vec<(String, String)> = known_fields
+            .iter()
+            .filter_map(|(key, label)| {
+                self.extra
+                    .get(*key)
+                    .map(|value| (label.to_string(), display(value)))
+            })
+            .collect();
+        fields.extend(
+            self.extra
+                .iter()
+                .filter(|(key, _)| !known_fields.iter().any(|(k, _)| k == key))
+                .map(|(key, value)| (key.clone(), display(value))),
+        );
+        fields
+    }
+}
+
+/// Formatting rule for displaying codes
+///
+/// A rule applies when all of its set conditions match the entry and code.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Default)]
+#[serde(default)]
+pub struct FormatRule {
+    /// Case insensitive part of the issuer name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Length of the code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digits: Option<usize>,
+    /// Sizes of the space separated groups, remaining characters form a last group
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<usize>,
+    /// Display letters in uppercase
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub uppercase: bool,
+}
+
+impl FormatRule {
+    /// Rules following common issuer conventions
+    pub fn builtin() -> Vec<FormatRule> {
+        vec![
+            FormatRule {
+                issuer: Some("steam".to_string()),
+                uppercase: true,
+                ..Default::default()
+            },
+            FormatRule {
+                digits: Some(8),
+                groups: vec![4, 4],
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn matches(&self, entry: &Entry, code: &str) -> bool {
+        if let Some(issuer) = &self.issuer {
+            if !entry.issuer.to_lowercase().contains(&issuer.to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(digits) = self.digits {
+            if code.chars().count() != digits {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn apply(&self, code: &str) -> String {
+        let code = if self.uppercase {
+            code.to_uppercase()
+        } else {
+            code.to_string()
+        };
+        let mut chars = code.chars();
+        let mut groups: Vec<String> = self
+            .groups
+            .iter()
+            .map(|size| chars.by_ref().take(*size).collect::<String>())
+            .filter(|group| !group.is_empty())
+            .collect();
+        let rest: String = chars.collect();
+        if !rest.is_empty() {
+            groups.push(rest);
+        }
+        groups.join(" ")
+    }
+}
+
+/// Issuer and code parameters of a service, for adding its entries without typing them
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Provider {
+    /// Short name the provider is selected by, e.g. "github"
+    pub id: String,
+    pub issuer: String,
+    #[serde(default = "Provider::default_digits")]
+    pub digits: i32,
+    #[serde(default = "Provider::default_period")]
+    pub period: i32,
+    #[serde(default)]
+    pub algo: HashAlgorithm,
+    /// Image file used as the icon of new entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<PathBuf>,
+    /// Other names issuers use for the service, e.g. "gmail" for Google
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+impl Provider {
+    fn default_digits() -> i32 {
+        6
+    }
+
+    fn default_period() -> i32 {
+        30
+    }
+
+    /// Well-known services, most of which use the defaults of the TOTP RFC
+    pub fn builtin() -> Vec<Provider> {
+        let provider = |id: &str, issuer: &str, digits, aliases: &[&str]| Provider {
+            id: id.to_string(),
+            issuer: issuer.to_string(),
+            digits,
+            period: Provider::default_period(),
+            algo: HashAlgorithm::Sha1,
+            icon: None,
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+        };
+        vec![
+            provider("amazon", "Amazon", 6, &[]),
+            provider("battlenet", "Battle.net", 8, &["blizzard"]),
+            provider("bitwarden", "Bitwarden", 6, &[]),
+            provider("cloudflare", "Cloudflare", 6, &[]),
+            provider("discord", "Discord", 6, &[]),
+            provider("dropbox", "Dropbox", 6, &[]),
+            provider("facebook", "Facebook", 6, &["meta"]),
+            provider("github", "GitHub", 6, &[]),
+            provider("gitlab", "GitLab", 6, &[]),
+            provider("google", "Google", 6, &["gmail"]),
+            provider("microsoft", "Microsoft", 6, &["outlook", "hotmail", "live"]),
+            provider("proton", "Proton", 6, &["protonmail"]),
+            provider("reddit", "Reddit", 6, &[]),
+            provider("x", "X", 6, &["twitter"]),
+        ]
+    }
+
+    /// First provider with the id, ignoring case
+    pub fn find<'a>(providers: &'a [Provider], id: &str) -> Option<&'a Provider> {
+        providers.iter().find(|p| p.id.eq_ignore_ascii_case(id))
+    }
+
+    /// First provider an issuer name refers to, e.g. "google.com" or "GMail" for Google
+    ///
+    /// Names are compared without case, punctuation and spaces. Domains are also compared by
+    /// the label before the top level domain, so "accounts.google.com" is Google as well.
+    pub fn match_issuer<'a>(providers: &'a [Provider], issuer: &str) -> Option<&'a Provider> {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+        let mut candidates = vec![normalize(issuer)];
+        let host = issuer
+            .trim()
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        let host = host.split('/').next().unwrap_or_default();
+        if !host.contains(' ') {
+            let labels: Vec<&str> = host.split('.').collect();
+            if labels.len() >= 2 {
+                candidates.push(normalize(labels[labels.len() - 2]));
+            }
+        }
+        candidates.retain(|candidate| !candidate.is_empty());
+        providers.iter().find(|provider| {
+            std::iter::once(&provider.id)
+                .chain(std::iter::once(&provider.issuer))
+                .chain(&provider.aliases)
+                .any(|name| candidates.contains(&normalize(name)))
+        })
+    }
+}
+
+/// Format a code for display using the first matching rule
+pub fn format_code(code: &str, entry: &Entry, rules: &[FormatRule]) -> String {
+    match rules.iter().find(|rule| rule.matches(entry, code)) {
+        Some(rule) => rule.apply(code),
+        None => code.to_string(),
+    }
+}
+
+/// Seconds since the unix epoch
+fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as i64
 }
 
 pub fn generate_otp(entry_info: &EntryInfo) -> Result<String> {
-    let code = match entry_info {
-        // TODO: Add full support for HOTP
-        /*
-        EntryType::Hotp(info) => HOTPBuilder::new()
-            .base32_key(&info.secret.to_string())
-            .hash_function(info.algo.into())
-            .output_len(info.digits.try_into()?)
-            .counter(info.counter)
-            .finalize()?
-            .generate(),
-        */
-        EntryInfo::Totp(info) => TOTPBuilder::new()
-            .base32_key(&info.secret.to_string())
-            .hash_function(info.algo.into())
-            .output_len(info.digits.try_into()?)
-            .period(info.period.try_into()?)
-            .finalize()?
-            .generate(),
-        _ => return Err(eyre!("Not implemented")),
+    Generator::default().generate(entry_info)
+}
+
+/// Generate the code valid at `timestamp` seconds since the unix epoch
+pub fn generate_otp_at(entry_info: &EntryInfo, timestamp: i64) -> Result<String> {
+    Generator::default().generate_at(entry_info, timestamp)
+}
+
+/// Codes of the entries valid at `timestamp`, until `cancel` is cancelled
+///
+/// An entry without a code doesn't stop the others, its error is in its place.
+pub fn generate_all(
+    entries: &[EntryInfo],
+    timestamp: i64,
+    cancel: Option<&CancellationToken>,
+) -> Result<Vec<Result<String>>> {
+    let mut generator = Generator::default();
+    entries
+        .iter()
+        .map(|entry_info| {
+            if let Some(cancel) = cancel {
+                cancel.check("Code generation")?;
+            }
+            Ok(generator.generate_at(entry_info, timestamp))
+        })
+        .collect()
+}
+
+/// Code generator which keeps the decoded secret of the last entry
+///
+/// Meant for generating codes repeatedly, like in watch modes. The decoded secret is zeroized
+/// when the generator is dropped or the secret of the entry changes.
+#[derive(Default)]
+pub struct Generator {
+    /// Encoded secret with its decoded bytes
+    cache: Option<(Zeroizing<String>, Zeroizing<Vec<u8>>)>,
+}
+
+impl Generator {
+    pub fn generate(&mut self, entry_info: &EntryInfo) -> Result<String> {
+        self.generate_at(entry_info, current_timestamp())
+    }
+
+    /// Generate the code valid at `timestamp` seconds since the unix epoch
+    ///
+    /// HOTP codes don't depend on the time, they're generated for the counter of the entry.
+    pub fn generate_at(&mut self, entry_info: &EntryInfo, timestamp: i64) -> Result<String> {
+        let code = match entry_info {
+            EntryInfo::Hotp(info) => HOTPBuilder::new()
+                .key(self.secret(&info.secret)?)
+                .hash_function(info.algo.into())
+                .output_len(info.digits.try_into()?)
+                .counter(info.counter)
+                .finalize()?
+                .generate(),
+            EntryInfo::Totp(info) => TOTPBuilder::new()
+                .key(self.secret(&info.secret)?)
+                .hash_function(info.algo.into())
+                .output_len(info.digits.try_into()?)
+                .period(info.period.try_into()?)
+                .timestamp(timestamp)
+                .finalize()?
+                .generate(),
+            EntryInfo::Yandex(info) => {
+                let counter = counter_at(entry_info, timestamp)?;
+                yandex_code(self.secret(&info.secret)?, info, counter)?
+            }
+            EntryInfo::Motp(info) => {
+                let counter = counter_at(entry_info, timestamp)?;
+                motp_code(self.secret(&info.secret)?, info, counter)?
+            }
+            EntryInfo::Steam(info) => {
+                let counter = counter_at(entry_info, timestamp)?;
+                steam_code(
+                    self.secret(&info.secret)?,
+                    info.algo,
+                    counter,
+                    info.digits.try_into()?,
+                )?
+            }
+        };
+
+        Ok(code)
+    }
+
+    /// Decoded bytes of the base32 encoded secret
+    fn secret(&mut self, encoded: &str) -> Result<&[u8]> {
+        let cached = matches!(&self.cache, Some((key, _)) if key.as_str() == encoded);
+        if !cached {
+            let decoded = base32::decode(base32::Alphabet::RFC4648 { padding: false }, encoded)
+                .ok_or(eyre!("Invalid base32 secret"))?;
+            self.cache = Some((Zeroizing::new(encoded.to_string()), Zeroizing::new(decoded)));
+        }
+        Ok(self
+            .cache
+            .as_ref()
+            .map(|(_, decoded)| decoded.as_slice())
+            .unwrap_or_default())
+    }
+}
+
+/// Characters of Steam Guard codes, leaving out those easily mistaken for another
+const STEAM_ALPHABET: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+
+/// Steam Guard code, the dynamically truncated HMAC of HOTP written with `STEAM_ALPHABET`
+fn steam_code(secret: &[u8], algo: HashAlgorithm, counter: u64, length: usize) -> Result<String> {
+    let hash = hmac_digest(algo, secret, &counter.to_be_bytes())?;
+    let offset = usize::from(hash[hash.len() - 1] & 0xf);
+    let mut value = u32::from_be_bytes(hash[offset..offset + 4].try_into()?) & 0x7fff_ffff;
+    let alphabet_len = STEAM_ALPHABET.len() as u32;
+    Ok((0..length)
+        .map(|_| {
+            let c = STEAM_ALPHABET[(value % alphabet_len) as usize] as char;
+            value /= alphabet_len;
+            c
+        })
+        .collect())
+}
+
+/// Length of the secret Yandex codes are generated with, the rest of longer ones is a checksum
+const YANDEX_SECRET_LENGTH: usize = 16;
+
+/// Yandex.Key code, the HMAC of a key derived from the PIN and the secret in lower case letters
+///
+/// Like the reference implementation of Aegis, as Yandex doesn't publish the algorithm.
+fn yandex_code(secret: &[u8], info: &EntryInfoYandex, counter: u64) -> Result<String> {
+    let secret = secret.get(..YANDEX_SECRET_LENGTH).ok_or(eyre!(
+        "Yandex secrets have at least {} bytes",
+        YANDEX_SECRET_LENGTH
+    ))?;
+    let mut key = Sha256::new();
+    key.update(info.pin.as_bytes());
+    key.update(secret);
+    let key = Zeroizing::new(key.finalize().to_vec());
+    let key = match key[0] {
+        0 => &key[1..],
+        _ => &key[..],
     };
 
+    let mut hash = Zeroizing::new(hmac_digest(info.algo, key, &counter.to_be_bytes())?);
+    let offset = usize::from(hash[hash.len() - 1] & 0xf);
+    hash[offset] &= 0x7f;
+    let value = u64::from_be_bytes(
+        hash.get(offset..offset + 8)
+            .ok_or(eyre!("Hash too short"))?
+            .try_into()?,
+    );
+    // Digits come straight from the vault, generating doesn't validate them
+    if !(1..=MAX_DIGITS).contains(&info.digits) {
+        return Err(eyre!(
+            "Digits must be between 1 and {}, got {}",
+            MAX_DIGITS,
+            info.digits
+        ));
+    }
+    let digits = u32::try_from(info.digits)?;
+    let mut value = value % 26u64.pow(digits);
+    let mut code = vec![b'a'; digits as usize];
+    for c in code.iter_mut().rev() {
+        *c += (value % 26) as u8;
+        value /= 26;
+    }
+    Ok(String::from_utf8(code)?)
+}
+
+/// mOTP code, the start of the hex encoded MD5 of the counter, secret and PIN
+fn motp_code(secret: &[u8], info: &EntryInfoMotp, counter: u64) -> Result<String> {
+    let message = Zeroizing::new(format!("{}{}{}", counter, hex::encode(secret), info.pin));
+    let mut code = hex::encode(md5::md5(message.as_bytes()));
+    code.truncate(usize::try_from(info.digits)?);
+    Ok(code)
+}
+
+fn hmac_digest(algo: HashAlgorithm, key: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    fn digest<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+        let mut mac = <M as Mac>::new_from_slice(key).map_err(|_| eyre!("Invalid secret"))?;
+        mac.update(message);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+    match algo {
+        HashAlgorithm::Sha1 => digest::<Hmac<Sha1>>(key, message),
+        HashAlgorithm::Sha256 => digest::<Hmac<Sha256>>(key, message),
+        HashAlgorithm::Sha512 => digest::<Hmac<Sha512>>(key, message),
+    }
+}
+
+/// Increment the counter of an HOTP entry and generate the code for it
+///
+/// The counter of an entry is the one of the code shown last, like Aegis increments it before
+/// showing a new code. Callers have to save the incremented counter to the vault, e.g. with
+/// [`LazyEntry::set_counter`](crate::vault::LazyEntry::set_counter), so no code is handed out
+/// twice.
+pub fn generate_hotp(info: &mut EntryInfoHotp) -> Result<String> {
+    let next = EntryInfoHotp {
+        counter: info
+            .counter
+            .checked_add(1)
+            .ok_or(eyre!("The counter is at its maximum"))?,
+        ..info.clone()
+    };
+    let code = generate_otp(&EntryInfo::Hotp(next.clone()))?;
+    *info = next;
     Ok(code)
 }
 
+/// Counter value used to generate the code valid at `timestamp`
+pub fn counter_at(entry_info: &EntryInfo, timestamp: i64) -> Result<u64> {
+    let period = match entry_info {
+        EntryInfo::Totp(info) => info.period,
+        EntryInfo::Steam(info) => info.period,
+        EntryInfo::Yandex(info) => info.period,
+        EntryInfo::Motp(info) => info.period,
+        EntryInfo::Hotp(info) => return Ok(info.counter),
+    };
+    let period = u64::try_from(period)?;
+    if period == 0 {
+        return Err(eyre!("Period must be greater than zero"));
+    }
+    Ok(u64::try_from(timestamp)? / period)
+}
+
 pub fn calculate_remaining_time(entry_info: &EntryInfo) -> Result<i32> {
     let period_length_s = match entry_info {
         EntryInfo::Totp(info) => info.period,
-        _ => return Err(eyre!("Not implemented")),
-    } as i32;
-    let current_time = SystemTime::now();
-    let seconds_since_epoch = current_time
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let seconds = seconds_since_epoch.as_secs() as i32;
+        EntryInfo::Steam(info) => info.period,
+        EntryInfo::Yandex(info) => info.period,
+        EntryInfo::Motp(info) => info.period,
+        _ => return Err(eyre!("HOTP codes don't expire")),
+    };
+    if period_length_s <= 0 {
+        return Err(eyre!("Period must be greater than zero"));
+    }
+    let seconds = current_timestamp() as i32;
 
     Ok(period_length_s - (seconds % period_length_s))
 }
 
+/// RFC 6238 appendix B seeds, base32 encoded
+const RFC6238_SEEDS: [(HashAlgorithm, &str); 3] = [
+    (HashAlgorithm::Sha1, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+    (
+        HashAlgorithm::Sha256,
+        "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA",
+    ),
+    (
+        HashAlgorithm::Sha512,
+        "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA",
+    ),
+];
+
+/// RFC 6238 appendix B timestamps with the expected SHA1, SHA256 and SHA512 codes
+const RFC6238_VECTORS: [(i64, [&str; 3]); 6] = [
+    (59, ["94287082", "46119246", "90693936"]),
+    (1111111109, ["07081804", "68084774", "25091201"]),
+    (1111111111, ["14050471", "67062674", "99943326"]),
+    (1234567890, ["89005924", "91819424", "93441116"]),
+    (2000000000, ["69279037", "90698825", "38618901"]),
+    (20000000000, ["65353130", "77737706", "47863826"]),
+];
+
+/// RFC 4226 appendix D codes for the counters 0 to 9
+const RFC4226_VECTORS: [&str; 10] = [
+    "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871",
+    "520489",
+];
+
+/// Verify code generation against the official RFC 6238 and RFC 4226 test vectors
+///
+/// Meant to catch platforms where the crypto stack doesn't produce standard results.
+/// Returns the number of vectors that were checked.
+pub fn self_test() -> Result<usize> {
+    let mut failures = Vec::new();
+    let mut checked = 0;
+
+    for (timestamp, codes) in RFC6238_VECTORS {
+        for ((algo, secret), expected) in RFC6238_SEEDS.iter().zip(codes) {
+            let info = EntryInfo::Totp(EntryInfoTotp {
+                secret: secret.to_string(),
+                algo: *algo,
+                digits: 8,
+                period: 30,
+            });
+            let code = generate_otp_at(&info, timestamp)?;
+            if code != expected {
+                failures.push(format!(
+                    "TOTP {:?} at {}: expected {}, got {}",
+                    algo, timestamp, expected, code
+                ));
+            }
+            checked += 1;
+        }
+    }
+
+    for (counter, expected) in RFC4226_VECTORS.iter().enumerate() {
+        let code = HOTPBuilder::new()
+            .base32_key(RFC6238_SEEDS[0].1)
+            .counter(counter as u64)
+            .finalize()?
+            .generate();
+        if code != *expected {
+            failures.push(format!(
+                "HOTP at counter {}: expected {}, got {}",
+                counter, expected, code
+            ));
+        }
+        checked += 1;
+    }
+
+    if !failures.is_empty() {
+        return Err(eyre!(
+            "{} of {} test vectors failed:\n{}",
+            failures.len(),
+            checked,
+            failures.join("\n")
+        ));
+    }
+    Ok(checked)
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
+
+    use crate::cancel::CancellationToken;
     use crate::otp::{
-        Entry, EntryInfo, EntryInfoHotp, EntryInfoSteam, EntryInfoTotp, HashAlgorithm,
+        counter_at, format_code, generate_all, generate_hotp, generate_otp_at, random_secret,
+        self_test, Entry, EntryInfo, EntryInfoHotp, EntryInfoSteam, EntryInfoTotp, FormatRule,
+        Generator, HashAlgorithm, Provider, SECRET_BYTES,
     };
 
     #[test]
@@ -148,14 +857,43 @@ mod test {
                 digits: 8,
                 counter: 10300,
             }),
+            uuid: Some("b25f8815-007f-40f7-a700-ce058ac05435".to_string()),
             name: "Mason".to_string(),
             issuer: "WWE".to_string(),
+            note: String::new(),
+            favorite: false,
+            icon: None,
+            icon_mime: None,
+            icon_hash: None,
+            groups: Vec::new(),
+            extra: BTreeMap::new(),
         };
 
         let deserialized = serde_json::from_str::<Entry>(json).unwrap();
         assert_eq!(deserialized, hotp_entry);
     }
 
+    #[test]
+    fn generate_hotp_codes() {
+        let mut info = EntryInfoHotp {
+            secret: "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 6,
+            counter: 0,
+        };
+        assert_eq!(
+            generate_otp_at(&EntryInfo::Hotp(info.clone()), 0).unwrap(),
+            "755224"
+        );
+        assert_eq!(generate_hotp(&mut info).unwrap(), "287082");
+        assert_eq!(generate_hotp(&mut info).unwrap(), "359152");
+        assert_eq!(info.counter, 2);
+
+        info.counter = u64::MAX;
+        assert!(generate_hotp(&mut info).is_err());
+        assert_eq!(info.counter, u64::MAX);
+    }
+
     #[test]
     fn parse_totp() {
         let json = r#"
@@ -179,8 +917,16 @@ mod test {
                 digits: 6,
                 period: 30,
             }),
+            uuid: Some("3ae6f1ad-2e65-4ed2-a953-1ec0dff2386d".to_string()),
             name: "Mason".to_string(),
             issuer: "Deno".to_string(),
+            note: String::new(),
+            favorite: false,
+            icon: None,
+            icon_mime: None,
+            icon_hash: None,
+            groups: Vec::new(),
+            extra: BTreeMap::new(),
         };
 
         let deserialized = serde_json::from_str::<Entry>(json).unwrap();
@@ -206,14 +952,322 @@ mod test {
         let steam_entry = Entry {
             info: EntryInfo::Steam(EntryInfoSteam {
                 secret: "JRZCL47CMXVOQMNPZR2F7J4RGI".to_string(),
+                algo: HashAlgorithm::Sha1,
                 digits: 5,
                 period: 30,
             }),
+            uuid: Some("5b11ae3b-6fc3-4d46-8ca7-cf0aea7de920".to_string()),
             name: "Sophia".to_string(),
             issuer: "Boeing".to_string(),
+            note: String::new(),
+            favorite: false,
+            icon: None,
+            icon_mime: None,
+            icon_hash: None,
+            groups: Vec::new(),
+            extra: BTreeMap::new(),
         };
 
         let deserialized = serde_json::from_str::<Entry>(json).unwrap();
         assert_eq!(deserialized, steam_entry);
     }
+
+    #[test]
+    fn generate_yandex_codes() {
+        // Test vectors of Aegis
+        let vectors = [
+            (
+                "5239",
+                "6SB2IKNM6OBZPAVBVTOHDKS4FAAAAAAADFUTQMBTRY",
+                1641559648,
+                "umozdicq",
+            ),
+            (
+                "7586",
+                "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI",
+                1581064020,
+                "oactmacq",
+            ),
+            (
+                "7586",
+                "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI",
+                1581090810,
+                "wemdwrix",
+            ),
+            (
+                "5210481216086702",
+                "JBGSAU4G7IEZG6OY4UAXX62JU4AAAAAAHTSG4HXU3M",
+                1581091469,
+                "dfrpywob",
+            ),
+            (
+                "5210481216086702",
+                "JBGSAU4G7IEZG6OY4UAXX62JU4AAAAAAHTSG4HXU3M",
+                1581093059,
+                "vunyprpd",
+            ),
+        ];
+        for (pin, secret, timestamp, expected) in vectors {
+            let json = format!(
+                r#"{{"secret": "{}", "digits": 8, "period": 30, "pin": "{}"}}"#,
+                secret, pin
+            );
+            let info = EntryInfo::Yandex(serde_json::from_str(&json).unwrap());
+            info.validate().unwrap();
+            assert_eq!(generate_otp_at(&info, timestamp).unwrap(), expected);
+        }
+
+        // Too many digits fail instead of overflowing
+        let json = r#"{"secret": "LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI", "digits": 20,
+            "period": 30, "pin": "7586"}"#;
+        let info = EntryInfo::Yandex(serde_json::from_str(json).unwrap());
+        assert!(generate_otp_at(&info, 1581064020).is_err());
+    }
+
+    #[test]
+    fn generate_motp_codes() {
+        let json = r#"{
+            "type": "motp",
+            "uuid": "0b3e2a4b-1a5d-4c0a-9f6e-5d0f5b1c2a3d",
+            "name": "Mason",
+            "issuer": "VPN",
+            "info": {
+                "secret": "4MKSV7XGEWM4Q",
+                "algo": "MD5",
+                "digits": 6,
+                "period": 10,
+                "pin": "1234"
+            }
+        }"#;
+        let entry = serde_json::from_str::<Entry>(json).unwrap();
+        entry.info.validate().unwrap();
+        assert_eq!(generate_otp_at(&entry.info, 1165647377).unwrap(), "6270b4");
+        assert_eq!(generate_otp_at(&entry.info, 1165647399).unwrap(), "135633");
+        assert_eq!(generate_otp_at(&entry.info, 1700000000).unwrap(), "ac896a");
+    }
+
+    #[test]
+    fn generate_all_codes() {
+        let totp = |secret: &str| {
+            EntryInfo::Totp(EntryInfoTotp {
+                secret: secret.to_string(),
+                algo: HashAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+            })
+        };
+        let entries = [totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"), totp("1")];
+        let codes = generate_all(&entries, 59, None).unwrap();
+        assert_eq!(codes[0].as_ref().unwrap(), "287082");
+        assert!(codes[1].is_err());
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let e = generate_all(&entries, 59, Some(&cancel)).unwrap_err();
+        assert_eq!(e.to_string(), "Code generation cancelled");
+    }
+
+    #[test]
+    fn generate_steam_codes() {
+        let info = EntryInfo::Steam(EntryInfoSteam {
+            secret: "JRQXIZLSEB2GQZLSMUQHOYLTEBQSA43FMNZGK5A".to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 5,
+            period: 30,
+        });
+        assert_eq!(generate_otp_at(&info, 0).unwrap(), "2DW27");
+        assert_eq!(generate_otp_at(&info, 1469101520).unwrap(), "YBF9J");
+        assert_eq!(generate_otp_at(&info, 2000000000).unwrap(), "NXRCD");
+        assert_eq!(counter_at(&info, 1469101520).unwrap(), 48970050);
+    }
+
+    #[test]
+    fn parse_entry_metadata() {
+        let json = r#"
+            {
+              "type": "totp",
+              "uuid": "3ae6f1ad-2e65-4ed2-a953-1ec0dff2386d",
+              "name": "Mason",
+              "issuer": "Deno",
+              "note": "Backup codes in the safe",
+              "favorite": true,
+              "icon": "iVBORw0KGgo=",
+              "icon_mime": "image/png",
+              "icon_hash": "4c4b6a3be1314ab86138bef4314dde022e600960d8689a2c8f8631802d20dab6",
+              "groups": ["0d4bc9f5-1c1f-4cbb-9b6c-5e6e8a2f0c11"],
+              "info": {
+                "secret": "4SJHB4GSD43FZBAI7C2HLRJGPQ",
+                "algo": "SHA1",
+                "digits": 6,
+                "period": 30
+              }
+            }"#;
+        let entry = serde_json::from_str::<Entry>(json).unwrap();
+        assert_eq!(
+            entry.uuid.as_deref(),
+            Some("3ae6f1ad-2e65-4ed2-a953-1ec0dff2386d")
+        );
+        assert_eq!(entry.note, "Backup codes in the safe");
+        assert!(entry.favorite);
+        assert_eq!(entry.icon.as_deref(), Some("iVBORw0KGgo="));
+        assert_eq!(entry.icon_mime.as_deref(), Some("image/png"));
+        assert!(entry.icon_hash.is_some());
+        assert_eq!(entry.groups, vec!["0d4bc9f5-1c1f-4cbb-9b6c-5e6e8a2f0c11"]);
+        assert!(entry.extra.is_empty(), "{:?}", entry.extra);
+
+        // The group of databases before version 3 is not an extra field either
+        let old = json.replace(r#""groups": ["#, r#""group": "Work", "unknown": ["#);
+        let old = serde_json::from_str::<Entry>(&old).unwrap();
+        assert!(old.groups.is_empty());
+        assert_eq!(old.extra.keys().collect::<Vec<_>>(), ["unknown"]);
+        let (mime, data) = entry.icon().unwrap().unwrap();
+        assert_eq!(mime, "image/png");
+        assert_eq!(data, b"\x89PNG\r\n\x1a\n");
+
+        // Some writers leave out fields or set them to null
+        let json = json.replace(r#""note": "Backup codes in the safe""#, r#""note": null"#);
+        let json = json.replace(r#""favorite": true,"#, "");
+        let json = json.replace(r#""icon_mime": "image/png","#, "");
+        let entry = serde_json::from_str::<Entry>(&json).unwrap();
+        assert_eq!(entry.note, "");
+        assert!(!entry.favorite);
+        assert_eq!(entry.icon().unwrap().unwrap().0, "image/png");
+    }
+
+    #[test]
+    fn parse_battle_net_serial() {
+        let json = r#"
+            {
+              "type": "totp",
+              "uuid": "0c4fcff6-5bc6-4e0b-8d5b-5bbbb0c2be90",
+              "name": "johndoe",
+              "issuer": "Battle.net",
+              "note": "",
+              "icon": null,
+              "restore_code": "ABCDE12345",
+              "serial": "US-1234-5678-9012",
+              "info": {
+                "secret": "4SJHB4GSD43FZBAI7C2HLRJGPQ",
+                "algo": "SHA1",
+                "digits": 8,
+                "period": 30
+              }
+            }"#;
+
+        let deserialized = serde_json::from_str::<Entry>(json).unwrap();
+        assert_eq!(
+            deserialized.extra_fields(),
+            vec![
+                ("Serial".to_string(), "US-1234-5678-9012".to_string()),
+                ("Restore code".to_string(), "ABCDE12345".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_codes() {
+        let entry = |issuer: &str| Entry {
+            info: EntryInfo::Totp(EntryInfoTotp {
+                secret: "4SJHB4GSD43FZBAI7C2HLRJGPQ".to_string(),
+                algo: HashAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+            }),
+            uuid: None,
+            name: "Mason".to_string(),
+            issuer: issuer.to_string(),
+            note: String::new(),
+            favorite: false,
+            icon: None,
+            icon_mime: None,
+            icon_hash: None,
+            groups: Vec::new(),
+            extra: BTreeMap::new(),
+        };
+        let mut rules = vec![FormatRule {
+            issuer: Some("deno".to_string()),
+            groups: vec![3],
+            ..Default::default()
+        }];
+        rules.extend(FormatRule::builtin());
+
+        assert_eq!(format_code("123456", &entry("Deno"), &rules), "123 456");
+        assert_eq!(format_code("123456", &entry("WWE"), &rules), "123456");
+        assert_eq!(format_code("12345678", &entry("WWE"), &rules), "1234 5678");
+        assert_eq!(format_code("2bc4f", &entry("Steam"), &rules), "2BC4F");
+    }
+
+    #[test]
+    fn generate_totp_at_timestamp() {
+        let info = EntryInfo::Totp(EntryInfoTotp {
+            // RFC 6238 SHA1 seed
+            secret: "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 8,
+            period: 30,
+        });
+
+        assert_eq!(generate_otp_at(&info, 59).unwrap(), "94287082");
+        assert_eq!(generate_otp_at(&info, 1111111109).unwrap(), "07081804");
+        assert_eq!(counter_at(&info, 1111111109).unwrap(), 37037036);
+    }
+
+    #[test]
+    fn rfc_test_vectors() {
+        assert_eq!(self_test().unwrap(), 28);
+    }
+
+    #[test]
+    fn generator_decodes_changed_secret() {
+        let mut info = EntryInfoTotp {
+            secret: "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 8,
+            period: 30,
+        };
+        let mut generator = Generator::default();
+        let code = generator
+            .generate_at(&EntryInfo::Totp(info.clone()), 59)
+            .unwrap();
+        assert_eq!(code, "94287082");
+
+        info.secret = "4SJHB4GSD43FZBAI7C2HLRJGPQ".to_string();
+        let code = generator
+            .generate_at(&EntryInfo::Totp(info.clone()), 59)
+            .unwrap();
+        assert_eq!(code, generate_otp_at(&EntryInfo::Totp(info), 59).unwrap());
+        assert_ne!(code, "94287082");
+    }
+
+    #[test]
+    fn random_secrets() {
+        let secret = random_secret(SECRET_BYTES);
+        assert_eq!(secret.len(), 32);
+        let info = EntryInfo::Totp(EntryInfoTotp {
+            secret: secret.clone(),
+            algo: HashAlgorithm::Sha1,
+            digits: 6,
+            period: 30,
+        });
+        assert!(info.validate().is_ok());
+        assert_ne!(random_secret(SECRET_BYTES), secret);
+        assert_eq!(random_secret(10).len(), 16);
+    }
+
+    #[test]
+    fn match_issuers_to_providers() {
+        let providers = Provider::builtin();
+        let matched = |issuer: &str| Provider::match_issuer(&providers, issuer).map(|p| &*p.id);
+
+        assert_eq!(matched("google.com"), Some("google"));
+        assert_eq!(matched("accounts.google.com"), Some("google"));
+        assert_eq!(matched("https://github.com/login"), Some("github"));
+        assert_eq!(matched("GMail"), Some("google"));
+        assert_eq!(matched("battle.net"), Some("battlenet"));
+        assert_eq!(matched("Twitter"), Some("x"));
+        assert_eq!(matched("Git Lab"), Some("gitlab"));
+        assert_eq!(matched("Deno"), None);
+        assert_eq!(matched("Google Cloud"), None);
+        assert_eq!(matched(""), None);
+    }
 }
@@ -0,0 +1,422 @@
+use color_eyre::eyre::{eyre, Result};
+
+use crate::{
+    otp::{
+        Entry, EntryInfo, EntryInfoHotp, EntryInfoMotp, EntryInfoSteam, EntryInfoTotp,
+        EntryInfoYandex, HashAlgorithm, MotpAlgorithm,
+    },
+    qr::QrCode,
+};
+
+/// Entry described by an `otpauth://` URI, as encoded in the QR codes services show
+///
+/// See the [Key Uri Format](https://github.com/google/google-authenticator/wiki/Key-Uri-Format).
+#[derive(Debug, PartialEq)]
+pub struct OtpauthUri {
+    pub issuer: Option<String>,
+    pub name: String,
+    pub info: EntryInfoTotp,
+}
+
+impl OtpauthUri {
+    /// Parse a TOTP URI, e.g. `otpauth://totp/Example:john?secret=JBSWY3DPEHPK3PXP&issuer=Example`
+    pub fn parse(uri: &str) -> Result<OtpauthUri> {
+        let parts = UriParts::parse(uri)?;
+        if !parts.otp_type.eq_ignore_ascii_case("totp") {
+            return Err(eyre!(
+                "Only TOTP URIs are supported, not {}",
+                parts.otp_type
+            ));
+        }
+        let info = EntryInfoTotp {
+            secret: parts.secret.clone(),
+            algo: parts.algo.unwrap_or_default(),
+            digits: parts.digits.unwrap_or(6),
+            period: parts.period.unwrap_or(30),
+        };
+        EntryInfo::Totp(info.clone()).validate()?;
+        Ok(OtpauthUri {
+            issuer: parts.issuer,
+            name: parts.name,
+            info,
+        })
+    }
+}
+
+/// Label and query parameters of an `otpauth://` URI
+struct UriParts {
+    otp_type: String,
+    /// Issuer of the query, or else of the label
+    issuer: Option<String>,
+    name: String,
+    secret: String,
+    algo: Option<HashAlgorithm>,
+    digits: Option<i32>,
+    period: Option<i32>,
+    counter: Option<u64>,
+    pin: Option<String>,
+}
+
+impl UriParts {
+    fn parse(uri: &str) -> Result<UriParts> {
+        let rest = uri
+            .trim()
+            .strip_prefix("otpauth://")
+            .ok_or(eyre!("Not an otpauth:// URI"))?;
+        let (otp_type, rest) = rest.split_once('/').ok_or(eyre!("No label in the URI"))?;
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let label = percent_decode(label)?;
+        let (label_issuer, name) = match label.split_once(':') {
+            Some((issuer, name)) => (Some(issuer.trim().to_string()), name.trim().to_string()),
+            None => (None, label.trim().to_string()),
+        };
+
+        let mut parts = UriParts {
+            otp_type: otp_type.to_lowercase(),
+            issuer: None,
+            name,
+            secret: String::new(),
+            algo: None,
+            digits: None,
+            period: None,
+            counter: None,
+            pin: None,
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value)?;
+            match key {
+                "secret" => {
+                    parts.secret = value
+                        .chars()
+                        .filter(|c| !c.is_whitespace() && *c != '=')
+                        .collect::<String>()
+                        .to_uppercase()
+                }
+                "issuer" => parts.issuer = Some(value),
+                "digits" => {
+                    parts.digits = Some(
+                        value
+                            .parse()
+                            .map_err(|_| eyre!("Invalid digits {}", value))?,
+                    )
+                }
+                "period" => {
+                    parts.period = Some(
+                        value
+                            .parse()
+                            .map_err(|_| eyre!("Invalid period {}", value))?,
+                    )
+                }
+                "counter" => {
+                    parts.counter = Some(
+                        value
+                            .parse()
+                            .map_err(|_| eyre!("Invalid counter {}", value))?,
+                    )
+                }
+                "algorithm" => {
+                    parts.algo = Some(
+                        serde_json::from_value(value.to_uppercase().into())
+                            .map_err(|_| eyre!("Unsupported algorithm {}", value))?,
+                    )
+                }
+                "pin" => parts.pin = Some(value),
+                // Like image, which some services add
+                _ => {}
+            }
+        }
+        if parts.secret.is_empty() {
+            return Err(eyre!("No secret in the URI"));
+        }
+        parts.issuer = parts
+            .issuer
+            .or(label_issuer)
+            .filter(|issuer| !issuer.is_empty());
+        Ok(parts)
+    }
+}
+
+impl Entry {
+    /// Entry of an `otpauth://` URI, like those of QR codes or exported by other apps
+    ///
+    /// Parameters missing from the URI get the defaults of Aegis for the type. The entry has no
+    /// UUID, an issuer only in the label is used if the query has none.
+    pub fn from_otpauth_uri(uri: &str) -> Result<Entry> {
+        let parts = UriParts::parse(uri)?;
+        let secret = parts.secret.clone();
+        let pin = || parts.pin.clone().ok_or(eyre!("No PIN in the URI"));
+        let info = match parts.otp_type.as_str() {
+            "totp" => EntryInfo::Totp(EntryInfoTotp {
+                secret,
+                algo: parts.algo.unwrap_or_default(),
+                digits: parts.digits.unwrap_or(6),
+                period: parts.period.unwrap_or(30),
+            }),
+            "hotp" => EntryInfo::Hotp(EntryInfoHotp {
+                secret,
+                algo: parts.algo.unwrap_or_default(),
+                digits: parts.digits.unwrap_or(6),
+                counter: parts.counter.ok_or(eyre!("No counter in the HOTP URI"))?,
+            }),
+            "steam" => EntryInfo::Steam(EntryInfoSteam {
+                secret,
+                algo: HashAlgorithm::Sha1,
+                digits: parts.digits.unwrap_or(5),
+                period: parts.period.unwrap_or(30),
+            }),
+            "yandex" => EntryInfo::Yandex(EntryInfoYandex {
+                secret,
+                algo: parts.algo.unwrap_or(HashAlgorithm::Sha256),
+                digits: parts.digits.unwrap_or(8),
+                period: parts.period.unwrap_or(30),
+                pin: pin()?,
+            }),
+            "motp" => EntryInfo::Motp(EntryInfoMotp {
+                secret,
+                algo: MotpAlgorithm::Md5,
+                digits: parts.digits.unwrap_or(6),
+                period: parts.period.unwrap_or(10),
+                pin: pin()?,
+            }),
+            otp_type => return Err(eyre!("Unsupported OTP type {}", otp_type)),
+        };
+        info.validate()?;
+        Ok(Entry::new(
+            parts.issuer.unwrap_or_default(),
+            parts.name,
+            info,
+        ))
+    }
+
+    /// `otpauth://` URI of the entry, for importing it into other authenticator apps
+    ///
+    /// Steam, Yandex and mOTP entries get the types Aegis uses for them, with the PIN in the URI.
+    pub fn to_otpauth_uri(&self) -> String {
+        let algorithm = |algo: HashAlgorithm| match algo {
+            HashAlgorithm::Sha1 => "SHA1",
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Sha512 => "SHA512",
+        };
+        let (otp_type, secret, parameters) = match &self.info {
+            EntryInfo::Totp(info) => (
+                "totp",
+                &info.secret,
+                format!(
+                    "&algorithm={}&digits={}&period={}",
+                    algorithm(info.algo),
+                    info.digits,
+                    info.period
+                ),
+            ),
+            EntryInfo::Hotp(info) => (
+                "hotp",
+                &info.secret,
+                format!(
+                    "&algorithm={}&digits={}&counter={}",
+                    algorithm(info.algo),
+                    info.digits,
+                    info.counter
+                ),
+            ),
+            EntryInfo::Steam(info) => ("steam", &info.secret, String::new()),
+            EntryInfo::Yandex(info) => (
+                "yandex",
+                &info.secret,
+                format!("&pin={}", percent_encode(&info.pin)),
+            ),
+            EntryInfo::Motp(info) => (
+                "motp",
+                &info.secret,
+                format!(
+                    "&digits={}&period={}&pin={}",
+                    info.digits,
+                    info.period,
+                    percent_encode(&info.pin)
+                ),
+            ),
+        };
+        let (label, issuer) = match self.issuer.trim() {
+            "" => (percent_encode(&self.name), String::new()),
+            issuer => (
+                format!("{}:{}", percent_encode(issuer), percent_encode(&self.name)),
+                format!("&issuer={}", percent_encode(issuer)),
+            ),
+        };
+        format!(
+            "otpauth://{}/{}?secret={}{}{}",
+            otp_type, label, secret, issuer, parameters
+        )
+    }
+
+    /// QR code of the [otpauth:// URI](Entry::to_otpauth_uri), for scanning the entry into
+    /// another phone, drawn with [QrCode::to_unicode]
+    pub fn qr_code(&self) -> Result<String> {
+        Ok(QrCode::encode(self.to_otpauth_uri().as_bytes())?.to_unicode())
+    }
+}
+
+/// Escape everything but unreserved characters as %XX
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Decode %XX escapes and + as space, as found in URI labels and query values
+pub(crate) fn percent_decode(input: &str) -> Result<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(eyre!("Invalid escape in the URI"))?;
+                decoded.push(byte);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| eyre!("The URI is not UTF-8"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::OtpauthUri;
+    use crate::otp::{
+        Entry, EntryInfo, EntryInfoHotp, EntryInfoTotp, EntryInfoYandex, HashAlgorithm,
+    };
+
+    #[test]
+    fn parse_uris() {
+        let uri = OtpauthUri::parse(
+            "otpauth://totp/ACME%20Co:john.doe%40example.com?secret=jbswy3dpehpk3pxp&issuer=ACME+Co&algorithm=SHA256&digits=8&period=60",
+        )
+        .unwrap();
+        assert_eq!(uri.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(uri.name, "john.doe@example.com");
+        assert_eq!(uri.info.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(uri.info.algo, HashAlgorithm::Sha256);
+        assert_eq!((uri.info.digits, uri.info.period), (8, 60));
+
+        let uri = OtpauthUri::parse("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(uri.issuer, None);
+        assert_eq!((uri.info.digits, uri.info.period), (6, 30));
+
+        assert!(OtpauthUri::parse("otpauth://hotp/john?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(OtpauthUri::parse("otpauth://totp/john?digits=6").is_err());
+        assert!(OtpauthUri::parse("otpauth://totp/john?secret=10AB").is_err());
+    }
+
+    #[test]
+    fn render_uris() {
+        let entry: Entry = serde_json::from_str(
+            r#"{
+                "type": "totp",
+                "name": "john.doe@example.com",
+                "issuer": "ACME Co",
+                "info": {"secret": "JBSWY3DPEHPK3PXP", "algo": "SHA256", "digits": 8, "period": 60}
+            }"#,
+        )
+        .unwrap();
+        let uri = entry.to_otpauth_uri();
+        assert_eq!(
+            uri,
+            "otpauth://totp/ACME%20Co:john.doe%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60"
+        );
+        let parsed = OtpauthUri::parse(&uri).unwrap();
+        assert_eq!(parsed.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(parsed.name, entry.name);
+        // Version 8 has 49 modules, 57 with the quiet zone
+        assert_eq!(entry.qr_code().unwrap().lines().count(), 29);
+
+        let entry: Entry = serde_json::from_str(
+            r#"{
+                "type": "hotp",
+                "name": "Mason",
+                "issuer": "",
+                "info": {"secret": "5VAML3X35THCEBVRLV24CGBKOY", "algo": "SHA1", "digits": 6, "counter": 7}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entry.to_otpauth_uri(),
+            "otpauth://hotp/Mason?secret=5VAML3X35THCEBVRLV24CGBKOY&algorithm=SHA1&digits=6&counter=7"
+        );
+    }
+
+    #[test]
+    fn parse_entries() {
+        let entry = Entry::from_otpauth_uri(
+            "otpauth://totp/Label%20Co:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co",
+        )
+        .unwrap();
+        assert_eq!(
+            (entry.issuer.as_str(), entry.name.as_str()),
+            ("ACME Co", "john")
+        );
+        assert_eq!(
+            entry.info,
+            EntryInfo::Totp(EntryInfoTotp {
+                secret: "JBSWY3DPEHPK3PXP".to_string(),
+                algo: HashAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+            })
+        );
+
+        let entry = Entry::from_otpauth_uri(
+            "otpauth://hotp/Mason?secret=5VAML3X35THCEBVRLV24CGBKOY&counter=7",
+        )
+        .unwrap();
+        assert_eq!(entry.issuer, "");
+        assert!(matches!(
+            entry.info,
+            EntryInfo::Hotp(EntryInfoHotp { counter: 7, .. })
+        ));
+        assert!(Entry::from_otpauth_uri("otpauth://hotp/Mason?secret=JBSWY3DPEHPK3PXP").is_err());
+
+        let entry = Entry::from_otpauth_uri(
+            "otpauth://yandex/Yandex:john?secret=JBSWY3DPEHPK3PXP&pin=1234",
+        )
+        .unwrap();
+        assert_eq!(entry.issuer, "Yandex");
+        assert!(matches!(
+            entry.info,
+            EntryInfo::Yandex(EntryInfoYandex {
+                digits: 8,
+                algo: HashAlgorithm::Sha256,
+                ..
+            })
+        ));
+        assert!(Entry::from_otpauth_uri("otpauth://motp/x?secret=JBSWY3DPEHPK3PXP").is_err());
+        assert!(Entry::from_otpauth_uri("otpauth://push/x?secret=JBSWY3DPEHPK3PXP").is_err());
+
+        // Every entry of the test vault survives the round trip
+        let vault: serde_json::Value =
+            serde_json::from_str(include_str!("../res/aegis_plain.json")).unwrap();
+        for raw in vault["db"]["entries"].as_array().unwrap() {
+            let entry: Entry = serde_json::from_value(raw.clone()).unwrap();
+            let parsed = Entry::from_otpauth_uri(&entry.to_otpauth_uri()).unwrap();
+            assert_eq!(parsed.info, entry.info);
+            assert_eq!((parsed.issuer, parsed.name), (entry.issuer, entry.name));
+        }
+    }
+}
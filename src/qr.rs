@@ -0,0 +1,604 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Error correction codewords per block with level M, by version
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+
+/// Error correction blocks with level M, by version
+const ERROR_CORRECTION_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// Light modules around the code, the standard asks for 4
+const QUIET_ZONE: usize = 4;
+
+/// QR code of bytes with error correction level M, which survives 15% of the code being
+/// unreadable
+///
+/// Encoded here as otpauth:// URIs need nothing but the byte mode, the smallest version the
+/// data fits and the mask with the lowest penalty are chosen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    /// Dark modules, row by row
+    modules: Vec<bool>,
+    /// Modules of the finder, timing and alignment patterns and the format and version info
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<QrCode> {
+        let version = (1..=40)
+            .find(|&version| data_bits(data.len(), version) <= data_codewords(version) * 8)
+            .ok_or(eyre!("{} bytes don't fit in a QR code", data.len()))?;
+
+        let mut bits = Bits::default();
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, if version < 10 { 8 } else { 16 });
+        for &byte in data {
+            bits.push(byte.into(), 8);
+        }
+        let capacity = data_codewords(version) * 8;
+        let terminator = (capacity - bits.0.len()).min(4);
+        bits.push(0, terminator);
+        let padding = (8 - bits.0.len() % 8) % 8;
+        bits.push(0, padding);
+        let mut codewords: Vec<u8> = bits
+            .0
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | u8::from(bit)))
+            .collect();
+        for pad in [0xec, 0x11].into_iter().cycle() {
+            if codewords.len() == data_codewords(version) {
+                break;
+            }
+            codewords.push(pad);
+        }
+
+        let size = version * 4 + 17;
+        let mut code = QrCode {
+            version,
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        code.draw_function_patterns();
+        code.draw_codewords(&add_error_correction(&codewords, version));
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                let mut masked = code.clone();
+                masked.apply_mask(mask);
+                masked.draw_format_bits(mask);
+                masked.penalty()
+            })
+            .unwrap_or_default();
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+        Ok(code)
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Modules per side, without the quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module in column `x` and row `y` is dark
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Lines of half blocks, two rows of modules per line, dark modules are drawn
+    ///
+    /// Meant for terminals with dark text on a light background, or the ANSI colors to make one.
+    pub fn to_unicode(&self) -> String {
+        let quiet = QUIET_ZONE as isize;
+        let dark = |x: isize, y: isize| x >= 0 && y >= 0 && self.is_dark(x as usize, y as usize);
+        let end = (self.size + QUIET_ZONE) as isize;
+        let mut lines = Vec::new();
+        for y in (-quiet..end).step_by(2) {
+            let line: String = (-quiet..end)
+                .map(|x| match (dark(x, y), dark(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect();
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    /// SVG image with a square of one unit per module
+    pub fn to_svg(&self) -> String {
+        let width = self.size + 2 * QUIET_ZONE;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.is_dark(x, y) {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+                }
+            }
+        }
+        format!(
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {0} {0}" "#,
+                r#"shape-rendering="crispEdges">"#,
+                r##"<rect width="{0}" height="{0}" fill="#fff"/>"##,
+                r##"<path d="{1}" fill="#000"/></svg>"##,
+                "\n"
+            ),
+            width, path
+        )
+    }
+
+    /// Black and white PNG image, `scale` pixels wide per module
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let width = (self.size + 2 * QUIET_ZONE) * scale;
+        let row_bytes = width.div_ceil(8);
+        // Each row starts with filter type 0, set bits are white
+        let mut pixels = Vec::with_capacity((row_bytes + 1) * width);
+        for py in 0..width {
+            pixels.push(0);
+            let mut row = vec![0u8; row_bytes];
+            for px in 0..width {
+                let (x, y) = (px / scale, py / scale);
+                let dark = x >= QUIET_ZONE
+                    && y >= QUIET_ZONE
+                    && self.is_dark(x - QUIET_ZONE, y - QUIET_ZONE);
+                if !dark {
+                    row[px / 8] |= 0x80 >> (px % 8);
+                }
+            }
+            pixels.extend(row);
+        }
+
+        let mut header = Vec::new();
+        header.extend((width as u32).to_be_bytes());
+        header.extend((width as u32).to_be_bytes());
+        // 1 bit grayscale, no interlacing
+        header.extend([1, 0, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            self.draw_pattern(x, y, 4, |distance| distance != 2 && distance != 4);
+        }
+        let positions = alignment_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // The corners with finder patterns have none
+                if (i, j) != (0, 0) && (i, j) != (0, last) && (i, j) != (last, 0) {
+                    self.draw_pattern(x, y, 2, |distance| distance != 1);
+                }
+            }
+        }
+        // Reserved until the mask is chosen
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    /// Square pattern around the center, `dark` tells the color by the distance from it
+    fn draw_pattern(&mut self, x: usize, y: usize, radius: isize, dark: impl Fn(isize) -> bool) {
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (xx, yy) = (x as isize + dx, y as isize + dy);
+                if (0..self.size as isize).contains(&xx) && (0..self.size as isize).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, dark(distance));
+                }
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        // Level M is 00
+        let data = mask;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let version = self.version as u32;
+        let mut remainder = version;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+        }
+        let bits = version << 12 | remainder;
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Place the codewords in the zigzag of two module wide columns, from the bottom right
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            // The vertical timing pattern is skipped
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward {
+                        size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let i = y * self.size + x;
+                if invert && !self.function[i] {
+                    self.modules[i] = !self.modules[i];
+                }
+            }
+        }
+    }
+
+    /// Penalty of the standard for patterns which confuse readers, the lower the better
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        let lines = (0..size).flat_map(|i| {
+            [
+                (0..size)
+                    .map(|j| self.modules[i * size + j])
+                    .collect::<Vec<bool>>(),
+                (0..size).map(|j| self.modules[j * size + i]).collect(),
+            ]
+        });
+        for line in lines {
+            // Runs of five or more modules of the same color
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            // Patterns looking like finders
+            for window in line.windows(11) {
+                let finder = [true, false, true, true, true, false, true];
+                if (window[..7] == finder && window[7..].iter().all(|&dark| !dark))
+                    || (window[4..] == finder && window[..4].iter().all(|&dark| !dark))
+                {
+                    penalty += 40;
+                }
+            }
+        }
+        // Blocks of 2x2 modules of the same color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.modules[y * size + x];
+                if self.modules[y * size + x + 1] == color
+                    && self.modules[(y + 1) * size + x] == color
+                    && self.modules[(y + 1) * size + x + 1] == color
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        // Dark modules far from half of them
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        penalty += (dark * 20).abs_diff(total * 10) / total * 10;
+        penalty
+    }
+}
+
+/// Bits of the mode, length and data
+fn data_bits(length: usize, version: usize) -> usize {
+    4 + if version < 10 { 8 } else { 16 } + length * 8
+}
+
+/// Modules left for codewords after the function patterns
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[version] * ERROR_CORRECTION_BLOCKS[version]
+}
+
+/// Centers of the alignment patterns in both directions
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let alignments = version / 7 + 2;
+    let step = match version {
+        32 => 26,
+        _ => (version * 4 + alignments * 2 + 1) / (alignments * 2 - 2) * 2,
+    };
+    let size = version * 4 + 17;
+    let mut positions = vec![6];
+    for i in 0..alignments - 1 {
+        positions.insert(1, size - 7 - i * step);
+    }
+    positions
+}
+
+/// Split the data into blocks, add the error correction codewords of each and interleave them
+fn add_error_correction(data: &[u8], version: usize) -> Vec<u8> {
+    let blocks = ERROR_CORRECTION_BLOCKS[version];
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_length = raw_codewords / blocks;
+    let divisor = reed_solomon_divisor(ecc_length);
+
+    let mut split = Vec::new();
+    let mut start = 0;
+    for i in 0..blocks {
+        let length = short_length - ecc_length + usize::from(i >= short_blocks);
+        let block = &data[start..start + length];
+        start += length;
+        split.push((block, reed_solomon_remainder(block, &divisor)));
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_length - ecc_length {
+        for (block, _) in &split {
+            if let Some(&codeword) = block.get(i) {
+                result.push(codeword);
+            }
+        }
+    }
+    for i in 0..ecc_length {
+        for (_, ecc) in &split {
+            result.push(ecc[i]);
+        }
+    }
+    result
+}
+
+/// Generator polynomial of the degree, without its leading term
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+/// Product in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= u16::from((y >> i) & 1) * u16::from(x);
+    }
+    z as u8
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// zlib stream of uncompressed deflate blocks, QR codes are small enough
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(u8::from(blocks.peek().is_none()));
+        let length = block.len() as u16;
+        stream.extend(length.to_le_bytes());
+        stream.extend((!length).to_le_bytes());
+        stream.extend(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend((b << 16 | a).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[derive(Default)]
+struct Bits(Vec<bool>);
+
+impl Bits {
+    /// Append the lowest `count` bits of `value`, highest first
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            self.0.push((value >> i) & 1 != 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{add_error_correction, crc32, data_bits, data_codewords, QrCode};
+
+    #[test]
+    fn error_correction() {
+        // Version 1-M of HELLO WORLD, from the tutorial at thonky.com
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            add_error_correction(&data, 1)[16..],
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        // Byte capacities of level M
+        for (version, bytes) in [(1, 14), (2, 26), (7, 122), (10, 213), (40, 2331)] {
+            assert!(data_bits(bytes, version) <= data_codewords(version) * 8);
+            assert!(data_bits(bytes + 1, version) > data_codewords(version) * 8);
+        }
+    }
+
+    #[test]
+    fn encode_uris() {
+        let uri = "otpauth://totp/Deno:Mason?secret=4SJHB4GSD43FZBAI7C2HLRJGPQ&issuer=Deno&algorithm=SHA1&digits=6&period=30";
+        let code = QrCode::encode(uri.as_bytes()).unwrap();
+        assert_eq!(code.version(), 6);
+        assert_eq!(code.size(), 41);
+        // Finder patterns and the dark module
+        for (x, y) in [(0, 0), (6, 6), (40, 0), (34, 6), (0, 40), (8, 33)] {
+            assert!(code.is_dark(x, y));
+        }
+        assert!(!code.is_dark(7, 7));
+        // Both copies of the format bits say level M
+        let first = |i: usize| match i {
+            0..=5 => (8, i),
+            6 => (8, 7),
+            7 => (8, 8),
+            8 => (7, 8),
+            _ => (14 - i, 8),
+        };
+        let second = |i: usize| match i {
+            0..=7 => (40 - i, 8),
+            _ => (8, 26 + i),
+        };
+        let format = (0..15).fold(0, |bits, i| {
+            let (x, y) = first(i);
+            assert_eq!(code.is_dark(x, y), code.is_dark(second(i).0, second(i).1));
+            bits | u32::from(code.is_dark(x, y)) << i
+        }) ^ 0x5412;
+        assert_eq!(format >> 13, 0b00);
+
+        let unicode = code.to_unicode();
+        let lines: Vec<&str> = unicode.lines().collect();
+        assert_eq!(lines.len(), 25);
+        assert!(lines.iter().all(|line| line.chars().count() == 49));
+
+        let svg = code.to_svg();
+        assert!(svg.starts_with("<svg") && svg.contains(r#"viewBox="0 0 49 49""#));
+        assert!(svg.contains("M4,4h1v1h-1z"));
+        let png = code.to_png(2);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(png[16..24], [0, 0, 0, 98, 0, 0, 0, 98]);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+
+        assert_eq!(QrCode::encode(&[b'a'; 300]).unwrap().version(), 13);
+        assert!(QrCode::encode(&[0; 3000]).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use serde::Deserialize;
+
+use crate::otp::{EntryInfo, EntryInfoSteam, HashAlgorithm};
+
+/// Steam Guard codes are always this long
+const STEAM_DIGITS: i32 = 5;
+/// Seconds a Steam Guard code is valid
+const STEAM_PERIOD: i32 = 30;
+
+/// Steam Guard secret with what else the source tells about the account
+#[derive(Debug, PartialEq)]
+pub struct SteamAccount {
+    /// Base32 encoded shared secret
+    pub secret: String,
+    pub account_name: Option<String>,
+    /// Code for removing the authenticator from the account, e.g. "R12345"
+    pub revocation_code: Option<String>,
+}
+
+/// Fields of a Steam Desktop Authenticator maFile which are of use
+#[derive(Deserialize)]
+struct MaFile {
+    shared_secret: String,
+    #[serde(default)]
+    account_name: Option<String>,
+    #[serde(default)]
+    revocation_code: Option<String>,
+}
+
+impl SteamAccount {
+    /// Parse a maFile of Steam Desktop Authenticator, which holds the secret base64 encoded
+    pub fn from_mafile(contents: &str) -> Result<SteamAccount> {
+        let contents = contents.trim_start_matches('\u{feff}').trim();
+        if !contents.starts_with('{') {
+            return Err(eyre!(
+                "Not a maFile, or encrypted by Steam Desktop Authenticator which has to decrypt it first"
+            ));
+        }
+        let file: MaFile =
+            serde_json::from_str(contents).map_err(|e| eyre!("Failed to parse maFile: {}", e))?;
+        let secret = general_purpose::STANDARD
+            .decode(file.shared_secret.trim())
+            .map_err(|_| eyre!("The shared_secret of the maFile is not base64"))?;
+        let account = SteamAccount {
+            secret: base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret),
+            account_name: file.account_name.filter(|name| !name.trim().is_empty()),
+            revocation_code: file.revocation_code.filter(|code| !code.trim().is_empty()),
+        };
+        account.info().validate()?;
+        Ok(account)
+    }
+
+    /// Parse a `steam://` URI, which holds the secret base32 encoded
+    pub fn from_uri(uri: &str) -> Result<SteamAccount> {
+        let secret = uri
+            .trim()
+            .strip_prefix("steam://")
+            .ok_or(eyre!("Not a steam:// URI"))?
+            .trim_end_matches('/');
+        let account = SteamAccount {
+            secret: secret.trim_end_matches('=').to_uppercase(),
+            account_name: None,
+            revocation_code: None,
+        };
+        account.info().validate()?;
+        Ok(account)
+    }
+
+    /// Parameters of the Steam entry
+    pub fn info(&self) -> EntryInfo {
+        EntryInfo::Steam(EntryInfoSteam {
+            secret: self.secret.clone(),
+            algo: HashAlgorithm::Sha1,
+            digits: STEAM_DIGITS,
+            period: STEAM_PERIOD,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SteamAccount;
+
+    #[test]
+    fn parse_steam_secrets() {
+        let mafile = r#"{
+            "shared_secret": "TGF0ZXIgdGhlcmUgd2FzIGEgc2VjcmV0",
+            "serial_number": "1234567890",
+            "revocation_code": "R12345",
+            "account_name": "gaben",
+            "identity_secret": "c2VjcmV0"
+        }"#;
+        let account = SteamAccount::from_mafile(mafile).unwrap();
+        assert_eq!(account.secret, "JRQXIZLSEB2GQZLSMUQHOYLTEBQSA43FMNZGK5A");
+        assert_eq!(account.account_name.as_deref(), Some("gaben"));
+        assert_eq!(account.revocation_code.as_deref(), Some("R12345"));
+        assert!(SteamAccount::from_mafile("c2VjcmV0IGZpbGU=").is_err());
+
+        let account =
+            SteamAccount::from_uri("steam://jrqxizlseb2gqzlsmuqhoyltebqsa43fmnzgk5a").unwrap();
+        assert_eq!(account.secret, "JRQXIZLSEB2GQZLSMUQHOYLTEBQSA43FMNZGK5A");
+        assert_eq!(account.account_name, None);
+        assert!(SteamAccount::from_uri("otpauth://totp/x?secret=AAAA").is_err());
+        assert!(SteamAccount::from_uri("steam://not base32!").is_err());
+    }
+}
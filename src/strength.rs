@@ -0,0 +1,232 @@
+/// Passwords which are among the first tried by any attacker
+const COMMON: &[&str] = &[
+    "123456",
+    "password",
+    "123456789",
+    "12345678",
+    "12345",
+    "qwerty",
+    "abc123",
+    "football",
+    "1234567",
+    "monkey",
+    "111111",
+    "letmein",
+    "1234",
+    "1234567890",
+    "dragon",
+    "baseball",
+    "sunshine",
+    "iloveyou",
+    "trustno1",
+    "princess",
+    "adobe123",
+    "welcome",
+    "login",
+    "admin",
+    "qwerty123",
+    "solo",
+    "1q2w3e4r",
+    "master",
+    "666666",
+    "photoshop",
+    "1qaz2wsx",
+    "qwertyuiop",
+    "ashley",
+    "mustang",
+    "121212",
+    "starwars",
+    "654321",
+    "bailey",
+    "access",
+    "flower",
+    "555555",
+    "passw0rd",
+    "shadow",
+    "lovely",
+    "7777777",
+    "michael",
+    "superman",
+    "696969",
+    "hottie",
+    "freedom",
+    "aa123456",
+    "qazwsx",
+    "ninja",
+    "azerty",
+    "loveme",
+    "whatever",
+    "donald",
+    "batman",
+    "zaq1zaq1",
+    "000000",
+    "charlie",
+    "secret",
+    "hello",
+    "jordan",
+    "hunter2",
+    "aegis",
+    "changeme",
+];
+
+/// Keyboard rows, runs along them are as easy to guess as sequences
+const KEYBOARD_ROWS: &[&str] = &[
+    "1234567890",
+    "qwertyuiop",
+    "asdfghjkl",
+    "zxcvbnm",
+    "azertyuiop",
+];
+
+/// Estimated strength of a password
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strength {
+    /// Score from 0 (guessed right away) to 4 (very hard to guess), like zxcvbn
+    pub score: u8,
+    /// Estimated number of guesses to find the password, as log10
+    pub guesses_log10: f64,
+    /// Why the password is weak, if a pattern was found
+    pub warning: Option<&'static str>,
+}
+
+/// Estimate how hard `password` is to guess
+///
+/// A simplified version of the zxcvbn approach: common passwords, repeats, sequences and
+/// keyboard runs count as a few guesses each, other characters by the size of their alphabet.
+pub fn estimate(password: &str) -> Strength {
+    let lower = password.to_lowercase();
+    let stripped = lower.trim_end_matches(|c: char| c.is_ascii_digit() || "!?.".contains(c));
+    if COMMON.contains(&lower.as_str()) || COMMON.contains(&stripped) {
+        return strength(1.0, Some("This is a commonly used password"));
+    }
+
+    let chars: Vec<char> = lower.chars().collect();
+    let alphabet = alphabet_size(password);
+    let mut guesses_log10 = 0.0;
+    let mut warning = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let run = pattern_run(&chars[i..]);
+        if run >= 3 {
+            // A pattern is guessed by its start, its kind and its length
+            guesses_log10 += (alphabet * 4.0 * run as f64).log10();
+            warning = Some(if chars[i] == chars[i + 1] {
+                "Repeated characters are easy to guess"
+            } else {
+                "Sequences like abc or 1234 are easy to guess"
+            });
+            i += run;
+        } else {
+            guesses_log10 += alphabet.log10();
+            i += 1;
+        }
+    }
+    if chars.len() < 8 && warning.is_none() {
+        warning = Some("Short passwords are easy to guess");
+    }
+    strength(guesses_log10, warning)
+}
+
+fn strength(guesses_log10: f64, warning: Option<&'static str>) -> Strength {
+    // Thresholds of zxcvbn, for guesses against a slow hash like the vault's scrypt
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    Strength {
+        score,
+        guesses_log10,
+        warning,
+    }
+}
+
+/// Number of possible characters of the classes used in `password`
+fn alphabet_size(password: &str) -> f64 {
+    let classes = [
+        (password.chars().any(|c| c.is_ascii_lowercase()), 26.0),
+        (password.chars().any(|c| c.is_ascii_uppercase()), 26.0),
+        (password.chars().any(|c| c.is_ascii_digit()), 10.0),
+        (
+            password
+                .chars()
+                .any(|c| c.is_ascii_punctuation() || c == ' '),
+            33.0,
+        ),
+        (!password.is_ascii(), 100.0),
+    ];
+    classes
+        .iter()
+        .filter(|(used, _)| *used)
+        .map(|(_, size)| size)
+        .sum::<f64>()
+        .max(10.0)
+}
+
+/// Length of the repeat, sequence or keyboard run at the start of `chars`
+fn pattern_run(chars: &[char]) -> usize {
+    if chars.len() < 2 {
+        return chars.len();
+    }
+    let step = |a: char, b: char| {
+        if a == b {
+            return Some(0);
+        }
+        let delta = b as i64 - a as i64;
+        if delta.abs() == 1 {
+            return Some(delta);
+        }
+        KEYBOARD_ROWS.iter().find_map(|row| {
+            let a = row.find(a)? as i64;
+            let b = row.find(b)? as i64;
+            matches!(b - a, 1 | -1).then_some(10 * (b - a))
+        })
+    };
+    let first = match step(chars[0], chars[1]) {
+        Some(first) => first,
+        None => return 1,
+    };
+    let mut run = 2;
+    while run < chars.len() && step(chars[run - 1], chars[run]) == Some(first) {
+        run += 1;
+    }
+    run
+}
+
+#[cfg(test)]
+mod test {
+    use super::estimate;
+
+    #[test]
+    fn weak_passwords() {
+        for password in [
+            "password",
+            "Password1!",
+            "123456",
+            "aaaaaaaaaaaa",
+            "abcdefgh",
+            "qwertyui",
+            "ab1",
+        ] {
+            assert!(
+                estimate(password).score <= 1,
+                "{} scored too high",
+                password
+            );
+            assert!(estimate(password).warning.is_some());
+        }
+    }
+
+    #[test]
+    fn strong_passwords() {
+        for password in [
+            "correct horse battery staple",
+            "Xk9#mQ2$vL7p",
+            "tr0ub4dor&3-elephant",
+        ] {
+            assert!(estimate(password).score >= 3, "{} scored too low", password);
+        }
+    }
+}
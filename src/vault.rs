@@ -1,7 +1,16 @@
+use base64::{engine::general_purpose, Engine as _};
 use color_eyre::eyre::{eyre, Result};
-use serde::Deserialize;
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{value::RawValue, Map, Value};
+use std::{
+    collections::HashMap, env, fs, io, mem, path::Path, str::FromStr, sync::OnceLock, thread,
+};
 
-use crate::otp;
+use zeroize::{Zeroize, Zeroizing};
+
+use sha2::{Digest, Sha256};
+
+use crate::{cancel::CancellationToken, otp};
 
 /// Cryptographic functions and data structures used to decrypt database with OTP entries
 ///
@@ -9,18 +18,561 @@ use crate::otp;
 /// [here](https://github.com/beemdevelopment/Aegis/blob/master/docs/vault.md#aegis-vault).
 mod crypto;
 
+/// Reading vault files, memory mapped where possible
+mod file;
+
+/// Recovery codes of entries, kept in an encrypted file of their own
+mod recovery;
+
+/// Writing modified vaults back
+mod write;
+
+/// Files attached to entries
+mod attachment;
+
+/// Log of generated codes, encrypted like the recovery codes
+mod audit;
+
+/// Database shared between threads
+mod handle;
+
+/// Changes planned before they are applied, for dry runs
+mod plan;
+
+pub use attachment::{guess_mime, Attachment, ATTACHMENTS_FIELD, MAX_ATTACHMENT_SIZE};
+pub use audit::{AuditLog, AuditRecord};
+pub use crypto::{scrypt_key, seal, unseal, MasterKey};
+pub use file::{create_private_dir, join_split_vault, write_atomic, write_private, VaultFile};
+pub use handle::VaultHandle;
+pub use plan::{Change, Op, Plan};
+pub use recovery::{RecoveryCode, RecoveryCodes};
+pub use write::{
+    create_vault, create_vault_with, reencrypt_vault, rekey_vault, set_password, write_vault,
+    Provenance, PROVENANCE_FIELD,
+};
+
+use write::RawFields;
+
+/// Database versions written by Aegis so far
+///
+/// Version 3 replaced the group name of entries with a list of group UUIDs, and added the list
+/// of groups to the database. Other changes only add fields, which are kept as they are.
+const DATABASE_VERSIONS: std::ops::RangeInclusive<u32> = 1..=3;
+
 /// Database containing OTP entries
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 pub struct Database {
     /// Database version
     version: u32,
     /// List of OTP entries
-    pub entries: Vec<otp::Entry>,
+    entries: Vec<LazyEntry>,
+    /// Fields which aren't used here, like groups, kept to write them back unchanged
+    other: RawFields,
+    /// Lookup index, built on first use
+    index: OnceLock<EntryIndex>,
+}
+
+impl<'de> Deserialize<'de> for Database {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut other = RawFields::deserialize(deserializer)?;
+        let mut field = |key: &'static str| {
+            other
+                .take(key)
+                .ok_or_else(|| serde::de::Error::missing_field(key))
+        };
+        let version = field("version")?;
+        let entries = field("entries")?;
+        Ok(Database {
+            version: serde_json::from_str(version.get()).map_err(serde::de::Error::custom)?,
+            entries: serde_json::from_str(entries.get()).map_err(serde::de::Error::custom)?,
+            other,
+            index: OnceLock::new(),
+        })
+    }
+}
+
+impl Serialize for Database {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("version", &self.version)?;
+        map.serialize_entry("entries", &self.entries)?;
+        for (key, value) in self.other.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl Database {
+    /// Database version
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Entries matching the selector exactly, ignoring case and surrounding whitespace
+    ///
+    /// An index is built on the first lookup, following lookups take constant time.
+    pub fn find(&self, selector: &Selector) -> Vec<&LazyEntry> {
+        self.index
+            .get_or_init(|| EntryIndex::new(&self.entries))
+            .find(selector)
+            .into_iter()
+            .map(|i| &self.entries[i])
+            .collect()
+    }
+
+    /// Add the entries of another copy of the vault which are missing here
+    ///
+    /// Entries are matched by UUID, entries without one by their contents. Entries which exist
+    /// in both copies with different contents are conflicts, for them the entry here is kept.
+    pub fn merge(&mut self, other: Database) -> MergeReport {
+        let mut plan = Plan::default();
+        let report = plan.merge(self, other);
+        for op in plan.0 {
+            self.apply_op(op)
+                .expect("Adding entries and groups which parsed doesn't fail");
+        }
+        report
+    }
+
+    /// Add an entry with a new UUID, returns the UUID
+    pub fn add_entry(&mut self, entry: NewEntry) -> Result<String> {
+        let mut plan = Plan::default();
+        let uuid = plan.add_entry(self, entry)?;
+        for op in plan.0 {
+            self.apply_op(op)?;
+        }
+        Ok(uuid)
+    }
+
+    /// Change the entry with the UUID with `edit`, returns what `edit` returns
+    ///
+    /// `edit` works on a copy which only replaces the entry if it succeeds, an edit failing or
+    /// panicking halfway leaves the entry as it was.
+    pub fn edit_entry<T>(
+        &mut self,
+        uuid: &str,
+        edit: impl FnOnce(&mut LazyEntry) -> Result<T>,
+    ) -> Result<T> {
+        let position = self.position(uuid)?;
+        let mut edited = self.entries[position].clone();
+        let result = edit(&mut edited)?;
+        self.entries[position] = edited;
+        self.index = OnceLock::new();
+        Ok(result)
+    }
+
+    /// Remove the entry with the UUID, returns it
+    pub fn remove_entry(&mut self, uuid: &str) -> Result<LazyEntry> {
+        let position = self.position(uuid)?;
+        self.index = OnceLock::new();
+        Ok(self.entries.remove(position))
+    }
+
+    fn position(&self, uuid: &str) -> Result<usize> {
+        self.index
+            .get_or_init(|| EntryIndex::new(&self.entries))
+            .find(&Selector::Uuid(uuid.to_string()))
+            .first()
+            .copied()
+            .ok_or(eyre!("No entry with the UUID {}", uuid))
+    }
+
+    /// Groups of the entries, only version 3 databases have groups
+    pub fn groups(&self) -> Result<Vec<Group>> {
+        match self.other.get("groups") {
+            Some(groups) => Ok(serde_json::from_str(groups.get())?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Copy of the database with only the entries `keep` returns true for
+    ///
+    /// Groups without any of the kept entries are left out.
+    pub fn subset(&self, keep: impl Fn(&LazyEntry) -> bool) -> Result<Database> {
+        let entries: Vec<LazyEntry> = self.entries.iter().filter(|e| keep(e)).cloned().collect();
+        let mut other = self.other.clone();
+        if other.get("groups").is_some() {
+            let used: Vec<String> = entries.iter().flat_map(LazyEntry::groups).collect();
+            let groups: Vec<Group> = self
+                .groups()?
+                .into_iter()
+                .filter(|group| used.contains(&group.uuid))
+                .collect();
+            set_groups(&mut other, &groups)?;
+        }
+        Ok(Database {
+            version: self.version,
+            entries,
+            other,
+            index: OnceLock::new(),
+        })
+    }
+
+    /// Replace the groups of version 3 databases, see [Database::groups]
+    pub(crate) fn set_groups(&mut self, groups: &[Group]) -> Result<()> {
+        set_groups(&mut self.other, groups)
+    }
+
+    pub fn entries(&self) -> &[LazyEntry] {
+        &self.entries
+    }
+
+    /// Entries to change directly, the lookup index is rebuilt on the next [Database::find]
+    pub fn entries_mut(&mut self) -> &mut Vec<LazyEntry> {
+        self.index = OnceLock::new();
+        &mut self.entries
+    }
+
+    /// The entries without deserializing them
+    pub fn into_lazy_entries(self) -> Vec<LazyEntry> {
+        self.entries
+    }
+
+    /// Deserialize all entries
+    pub fn into_entries(self) -> Result<Vec<otp::Entry>> {
+        self.entries.iter().map(LazyEntry::deserialize).collect()
+    }
+}
+
+/// Entry to add with [Database::add_entry]
+pub struct NewEntry {
+    pub name: String,
+    pub issuer: String,
+    pub info: otp::EntryInfo,
+    /// MIME type and contents of the icon image
+    pub icon: Option<(String, Vec<u8>)>,
+}
+
+/// Group of entries in version 3 databases
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Group {
+    pub uuid: String,
+    pub name: String,
+}
+
+fn set_groups(fields: &mut RawFields, groups: &[Group]) -> Result<()> {
+    fields.set(
+        "groups",
+        RawValue::from_string(serde_json::to_string(groups)?)?,
+    );
+    Ok(())
+}
+
+/// Outcome of [Database::merge]
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Entries added from the other copy
+    pub added: Vec<String>,
+    /// Names of the groups added with them
+    pub groups: Vec<String>,
+    /// Entries which differ between the copies, kept as they were
+    pub conflicts: Vec<String>,
+}
+
+/// Fields needed to list and filter entries
+#[derive(Debug, Clone, Deserialize)]
+struct EntrySummary {
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(rename = "type")]
+    entry_type: String,
+    name: String,
+    issuer: String,
+}
+
+/// Database entry which is only fully deserialized on demand
+///
+/// Entries are kept as raw JSON so that a vault with many entries doesn't have to validate
+/// every one of them when only a single entry is used.
+#[derive(Debug, Clone)]
+pub struct LazyEntry {
+    summary: EntrySummary,
+    raw: RawEntry,
+}
+
+/// JSON of an entry, with its secret, which is zeroized when dropped
+#[derive(Debug, Clone)]
+struct RawEntry(Box<RawValue>);
+
+impl RawEntry {
+    fn new(json: String) -> Result<RawEntry> {
+        Ok(RawEntry(RawValue::from_string(json)?))
+    }
+}
+
+impl std::ops::Deref for RawEntry {
+    type Target = RawValue;
+
+    fn deref(&self) -> &RawValue {
+        &self.0
+    }
+}
+
+impl Drop for RawEntry {
+    fn drop(&mut self) {
+        let placeholder = RawValue::from_string("null".to_string()).expect("null is JSON");
+        let mut json: Box<str> = mem::replace(&mut self.0, placeholder).into();
+        json.zeroize();
+    }
+}
+
+impl<'de> Deserialize<'de> for LazyEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = RawEntry(Box::<RawValue>::deserialize(deserializer)?);
+        let summary = serde_json::from_str(raw.get()).map_err(serde::de::Error::custom)?;
+        Ok(LazyEntry { summary, raw })
+    }
+}
+
+impl Serialize for LazyEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.raw.serialize(serializer)
+    }
+}
+
+impl LazyEntry {
+    /// Entry type as found in the vault, e.g. "totp"
+    pub fn entry_type(&self) -> &str {
+        &self.summary.entry_type
+    }
+
+    pub fn uuid(&self) -> Option<&str> {
+        self.summary.uuid.as_deref()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.summary.name
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.summary.issuer
+    }
+
+    /// Issuer and name for messages
+    fn label(&self) -> String {
+        format!("{} ({})", self.issuer().trim(), self.name().trim())
+    }
+
+    /// Length of the entry JSON
+    fn raw_len(&self) -> usize {
+        self.raw.get().len()
+    }
+
+    /// UUIDs of the groups the entry is in
+    pub fn groups(&self) -> Vec<String> {
+        #[derive(Deserialize)]
+        struct Groups {
+            #[serde(default)]
+            groups: Vec<String>,
+        }
+        serde_json::from_str::<Groups>(self.raw.get())
+            .map(|fields| fields.groups)
+            .unwrap_or_default()
+    }
+
+    /// Name of the group of entries in databases before version 3, which had one group at most
+    pub fn group_name(&self) -> Option<String> {
+        #[derive(Deserialize)]
+        struct Group {
+            #[serde(default)]
+            group: Option<String>,
+        }
+        serde_json::from_str::<Group>(self.raw.get())
+            .ok()
+            .and_then(|fields| fields.group)
+    }
+
+    /// Free text note of the entry, empty if it has none
+    pub fn note(&self) -> String {
+        #[derive(Deserialize)]
+        struct Note {
+            #[serde(default)]
+            note: Option<String>,
+        }
+        serde_json::from_str::<Note>(self.raw.get())
+            .ok()
+            .and_then(|fields| fields.note)
+            .unwrap_or_default()
+    }
+
+    /// Whether the entry has an icon image
+    pub fn has_icon(&self) -> bool {
+        #[derive(Deserialize)]
+        struct Icon {
+            #[serde(default)]
+            icon: Option<String>,
+        }
+        serde_json::from_str::<Icon>(self.raw.get()).is_ok_and(|fields| fields.icon.is_some())
+    }
+
+    pub fn set_issuer(&mut self, issuer: &str) -> Result<()> {
+        self.update(|fields| {
+            fields.insert("issuer".to_string(), issuer.into());
+        })
+    }
+
+    /// Save the counter of an HOTP entry, as incremented by [`otp::generate_hotp`]
+    pub fn set_counter(&mut self, counter: u64) -> Result<()> {
+        if self.entry_type() != "hotp" {
+            return Err(eyre!("Only HOTP entries have a counter"));
+        }
+        self.update(|fields| {
+            if let Some(Value::Object(info)) = fields.get_mut("info") {
+                info.insert("counter".to_string(), counter.into());
+            }
+        })
+    }
+
+    /// Replace the icon with an image of the MIME type
+    pub fn set_icon(&mut self, mime: &str, data: &[u8]) -> Result<()> {
+        self.update(|fields| {
+            fields.insert(
+                "icon".to_string(),
+                general_purpose::STANDARD.encode(data).into(),
+            );
+            fields.insert("icon_mime".to_string(), mime.into());
+            fields.insert(
+                "icon_hash".to_string(),
+                hex::encode(Sha256::digest(data)).into(),
+            );
+        })
+    }
+
+    /// Empty the secret and PIN and drop the attachments, e.g. for sharing a list of the accounts
+    ///
+    /// The PIN of Yandex and mOTP entries is emptied rather than removed, so the entry still
+    /// deserializes.
+    pub fn redact_secrets(&mut self) -> Result<()> {
+        self.update(|fields| {
+            if let Some(Value::Object(info)) = fields.get_mut("info") {
+                info.insert("secret".to_string(), "".into());
+                if let Some(pin) = info.get_mut("pin") {
+                    *pin = "".into();
+                }
+            }
+            fields.remove(ATTACHMENTS_FIELD);
+        })
+    }
+
+    /// Change fields of the raw entry, keeping the summary in sync
+    fn update(&mut self, change: impl FnOnce(&mut Map<String, Value>)) -> Result<()> {
+        let mut fields: Map<String, Value> = serde_json::from_str(self.raw.get())?;
+        change(&mut fields);
+        self.raw = RawEntry::new(serde_json::to_string(&fields)?)?;
+        self.summary = serde_json::from_str(self.raw.get())?;
+        Ok(())
+    }
+
+    /// Deserialize the complete entry
+    pub fn deserialize(&self) -> Result<otp::Entry> {
+        serde_json::from_str(self.raw.get()).map_err(|e| {
+            eyre!(
+                "Failed to parse entry {} ({}): {}",
+                self.issuer(),
+                self.name(),
+                e
+            )
+        })
+    }
+}
+
+/// Entry lookup criteria
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Uuid(String),
+    Issuer(String),
+    Name(String),
+    /// Either the issuer or the name
+    Any(String),
+}
+
+impl FromStr for Selector {
+    type Err = std::convert::Infallible;
+
+    /// Parse "uuid:<uuid>", "issuer:<issuer>", "name:<name>", or an issuer or name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once(':') {
+            Some(("uuid", uuid)) => Selector::Uuid(uuid.to_string()),
+            Some(("issuer", issuer)) => Selector::Issuer(issuer.to_string()),
+            Some(("name", name)) => Selector::Name(name.to_string()),
+            _ => Selector::Any(s.to_string()),
+        })
+    }
+}
+
+/// Index of entries by normalized uuid, issuer and name
+#[derive(Debug, Default)]
+pub struct EntryIndex {
+    by_uuid: HashMap<String, usize>,
+    by_issuer: HashMap<String, Vec<usize>>,
+    by_name: HashMap<String, Vec<usize>>,
+}
+
+fn normalize(key: &str) -> String {
+    key.trim().to_lowercase()
+}
+
+impl EntryIndex {
+    pub fn new(entries: &[LazyEntry]) -> EntryIndex {
+        let mut index = EntryIndex::default();
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(uuid) = entry.uuid() {
+                index.by_uuid.insert(normalize(uuid), i);
+            }
+            index
+                .by_issuer
+                .entry(normalize(entry.issuer()))
+                .or_default()
+                .push(i);
+            index
+                .by_name
+                .entry(normalize(entry.name()))
+                .or_default()
+                .push(i);
+        }
+        index
+    }
+
+    /// Positions of the entries matching the selector, in vault order
+    pub fn find(&self, selector: &Selector) -> Vec<usize> {
+        let lookup = |map: &HashMap<String, Vec<usize>>, key: &str| {
+            map.get(&normalize(key)).cloned().unwrap_or_default()
+        };
+        match selector {
+            Selector::Uuid(uuid) => self
+                .by_uuid
+                .get(&normalize(uuid))
+                .copied()
+                .into_iter()
+                .collect(),
+            Selector::Issuer(issuer) => lookup(&self.by_issuer, issuer),
+            Selector::Name(name) => lookup(&self.by_name, name),
+            Selector::Any(key) => {
+                let mut positions = lookup(&self.by_issuer, key);
+                positions.extend(lookup(&self.by_name, key));
+                positions.sort_unstable();
+                positions.dedup();
+                positions
+            }
+        }
+    }
 }
 
 /// Vault database as found in the JSON file
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug)]
 pub enum VaultDatabase {
     /// Database in plain text
     Plain(Database),
@@ -28,11 +580,96 @@ pub enum VaultDatabase {
     Encrypted(String),
 }
 
+impl<'de> Deserialize<'de> for VaultDatabase {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Not an untagged enum, as the lazily parsed entries can't be deserialized from the
+        // content buffered for untagged enums
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        let db = if raw.get().starts_with('"') {
+            serde_json::from_str(raw.get()).map(VaultDatabase::Encrypted)
+        } else {
+            serde_json::from_str(raw.get()).map(VaultDatabase::Plain)
+        };
+        db.map_err(serde::de::Error::custom)
+    }
+}
+
+/// Source of a vault password
+///
+/// The library never asks for passwords itself, the sources here cover common cases and
+/// closures returning the password can be used for anything else, like a secret store.
 pub trait PasswordGetter {
     /// Get the password from the user or from the environment
     fn get_password(&self) -> Result<String>;
 }
 
+impl<F: Fn() -> Result<String>> PasswordGetter for F {
+    fn get_password(&self) -> Result<String> {
+        self()
+    }
+}
+
+/// Password in an environment variable
+pub struct EnvPassword<'a>(pub &'a str);
+
+impl PasswordGetter for EnvPassword<'_> {
+    fn get_password(&self) -> Result<String> {
+        env::var(self.0).map_err(|_| eyre!("{} is not set", self.0))
+    }
+}
+
+/// Password in the first line of a file
+pub struct FilePassword<'a>(pub &'a Path);
+
+impl PasswordGetter for FilePassword<'_> {
+    fn get_password(&self) -> Result<String> {
+        let contents = Zeroizing::new(
+            fs::read_to_string(self.0)
+                .map_err(|e| eyre!("Failed to read {}: {}", self.0.display(), e))?,
+        );
+        Ok(contents.lines().next().unwrap_or_default().to_string())
+    }
+}
+
+/// Password in the first line of stdin, for piping it in
+pub struct StdinPassword;
+
+impl PasswordGetter for StdinPassword {
+    fn get_password(&self) -> Result<String> {
+        let mut line = Zeroizing::new(String::new());
+        io::stdin().read_line(&mut line)?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Password which is only asked for once, for trying it on several vaults
+pub struct CachedPassword<P> {
+    getter: P,
+    password: OnceLock<Zeroizing<String>>,
+}
+
+impl<P: PasswordGetter> CachedPassword<P> {
+    pub fn new(getter: P) -> CachedPassword<P> {
+        CachedPassword {
+            getter,
+            password: OnceLock::new(),
+        }
+    }
+}
+
+impl<P: PasswordGetter> PasswordGetter for &CachedPassword<P> {
+    fn get_password(&self) -> Result<String> {
+        if let Some(password) = self.password.get() {
+            return Ok(password.to_string());
+        }
+        let password = Zeroizing::new(self.getter.get_password()?);
+        Ok(self.password.get_or_init(|| password).to_string())
+    }
+}
+
 /// Aegis vault backup
 #[derive(Debug, Deserialize)]
 pub struct Vault {
@@ -41,6 +678,48 @@ pub struct Vault {
     /// Information to decrypt master key
     pub header: crypto::Header,
     pub db: VaultDatabase,
+    /// What wrote the vault last, `None` if it was the Aegis app
+    #[serde(default, rename = "aegis_rs_provenance")]
+    pub provenance: Option<Provenance>,
+}
+
+/// Options for decrypting a vault
+#[derive(Debug, Clone, Default)]
+pub struct DecryptOptions {
+    /// Maximum number of threads used by the key derivation, as many as there are CPU cores if
+    /// not set. Only scrypt slots with p > 1 can make use of more than one thread.
+    pub kdf_threads: Option<usize>,
+    /// Largest accepted key derivation memory usage in bytes, in addition to the fixed limit
+    /// of 4 GiB. Each scrypt thread needs 128 * r * n bytes, fewer threads than `kdf_threads`
+    /// are used where they would exceed the limit together.
+    pub max_kdf_memory: Option<u64>,
+    /// Stops the key derivation with an error once cancelled, e.g. from a signal handler
+    pub cancel: Option<CancellationToken>,
+}
+
+impl DecryptOptions {
+    fn kdf_threads(&self) -> usize {
+        self.kdf_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+/// Name of the AES implementation used for vault decryption on this machine
+///
+/// Hardware acceleration is detected at runtime. Without it a constant time software
+/// implementation is used, which is considerably slower on large vaults.
+pub fn aes_backend() -> &'static str {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(aes_force_soft)))]
+    if std::arch::is_x86_feature_detected!("aes")
+        && std::arch::is_x86_feature_detected!("pclmulqdq")
+    {
+        return "AES-NI";
+    }
+    #[cfg(all(target_arch = "aarch64", aes_armv8, not(aes_force_soft)))]
+    if std::arch::is_aarch64_feature_detected!("aes") {
+        return "ARMv8 crypto extensions";
+    }
+    "software"
 }
 
 /// Parse vault from JSON. A list of entries are returned.
@@ -48,26 +727,778 @@ pub fn parse_vault(
     vault_backup_contents: &str,
     password_getter: impl PasswordGetter,
 ) -> Result<Vec<otp::Entry>> {
-    let vault: Vault = serde_json::from_str(vault_backup_contents)?;
-    if vault.version != 1 {
+    parse_vault_with_options(
+        vault_backup_contents,
+        password_getter,
+        &DecryptOptions::default(),
+    )
+}
+
+/// Parse vault from JSON with a password that is already known, like one given by a GUI.
+/// A list of entries are returned.
+pub fn parse_vault_with_password(
+    vault_backup_contents: &str,
+    password: &str,
+) -> Result<Vec<otp::Entry>> {
+    parse_vault(vault_backup_contents, || Ok(password.to_string()))
+}
+
+/// Parse vault from JSON with custom decryption options. A list of entries are returned.
+pub fn parse_vault_with_options(
+    vault_backup_contents: &str,
+    password_getter: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<Vec<otp::Entry>> {
+    parse_database(vault_backup_contents, password_getter, options)?.into_entries()
+}
+
+/// Parse vault from JSON and return the database, without deserializing the entries
+pub fn parse_database(
+    vault_backup_contents: &str,
+    password_getter: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<Database> {
+    Vault::parse(vault_backup_contents)?.unlock(password_getter, options)
+}
+
+impl Vault {
+    /// Parse the vault from JSON without decrypting it
+    pub fn parse(vault_backup_contents: &str) -> Result<Vault> {
+        let vault: Vault = serde_json::from_str(vault_backup_contents)?;
+        if vault.version != 1 {
+            return Err(eyre!(format!(
+                "Unsupported vault version: {}, Aegis has only published version 1 so far",
+                vault.version
+            )));
+        }
+        Ok(vault)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self.db, VaultDatabase::Encrypted(_))
+    }
+
+    /// Descriptions of the master key slots, e.g. "password (scrypt n=32768 r=8 p=1)"
+    pub fn slots(&self) -> Vec<String> {
+        self.header.slot_descriptions()
+    }
+
+    /// Problems of the encryption parameters, like reused nonces or weak scrypt parameters
+    pub fn weaknesses(&self) -> Vec<String> {
+        self.header.weaknesses()
+    }
+
+    /// Decrypt the database if needed
+    ///
+    /// The password is only requested from `password_getter` if the database is encrypted.
+    pub fn unlock(
+        self,
+        password_getter: impl PasswordGetter,
+        options: &DecryptOptions,
+    ) -> Result<Database> {
+        Ok(self.unlock_with_key(password_getter, options)?.0)
+    }
+
+    /// Decrypt the database if needed and keep the master key, which plain vaults don't have
+    pub fn unlock_with_key(
+        self,
+        password_getter: impl PasswordGetter,
+        options: &DecryptOptions,
+    ) -> Result<(Database, Option<MasterKey>)> {
+        let (db, master_key) = match self.db {
+            VaultDatabase::Plain(db) => (db, None),
+            VaultDatabase::Encrypted(_) => {
+                let password = Zeroizing::new(password_getter.get_password()?);
+                let (db, master_key) = crypto::decrypt(&password, self, options)?;
+                (db, Some(master_key))
+            }
+        };
+        check_database_version(&db)?;
+        Ok((db, master_key))
+    }
+
+    /// Decrypt the database with a known key instead of the password
+    ///
+    /// `key` may be the master key or the key of a raw or biometric slot, see
+    /// [MasterKey::from_hex]. Plain databases are returned without a master key.
+    pub fn unlock_with_raw_key(self, key: &MasterKey) -> Result<(Database, Option<MasterKey>)> {
+        let (db, master_key) = match self.db {
+            VaultDatabase::Plain(db) => (db, None),
+            VaultDatabase::Encrypted(_) => {
+                let (db, master_key) = crypto::decrypt_with_key(key, self)?;
+                (db, Some(master_key))
+            }
+        };
+        check_database_version(&db)?;
+        Ok((db, master_key))
+    }
+}
+
+fn check_database_version(db: &Database) -> Result<()> {
+    if !DATABASE_VERSIONS.contains(&db.version) {
         return Err(eyre!(format!(
-            "Unsupported vault version: {}",
-            vault.version
+            "Unsupported database version: {}, supported are {} to {}",
+            db.version,
+            DATABASE_VERSIONS.start(),
+            DATABASE_VERSIONS.end()
         )));
     }
-    let db = match vault.db {
-        VaultDatabase::Plain(db) => Ok(db),
-        VaultDatabase::Encrypted(_) => {
-            let password = password_getter.get_password()?;
-            crypto::decrypt(&password, vault)
+    Ok(())
+}
+
+/// Limits for vaults from untrusted sources, see [parse_untrusted]
+#[derive(Debug, Clone)]
+pub struct UntrustedLimits {
+    /// Largest accepted vault file in bytes
+    pub max_file_size: usize,
+    /// Largest accepted number of entries
+    pub max_entries: usize,
+    /// Largest accepted entry JSON in bytes, icons included
+    pub max_entry_size: usize,
+    /// Largest accepted key derivation memory usage in bytes
+    pub max_kdf_memory: u64,
+    /// Largest accepted number of key slots, as a key may be derived for every one of them
+    pub max_slots: usize,
+}
+
+impl Default for UntrustedLimits {
+    /// Generous limits for vaults exported by Aegis, Aegis itself uses 32 MiB for scrypt
+    fn default() -> Self {
+        UntrustedLimits {
+            max_file_size: 64 << 20,
+            max_entries: 10_000,
+            max_entry_size: 1 << 20,
+            max_kdf_memory: 256 << 20,
+            max_slots: 8,
         }
-    }?;
-    if db.version != 2 {
-        return Err(eyre!(format!(
-            "Unsupported database version: {}",
-            db.version
-        )));
+    }
+}
+
+/// Parse a vault received from someone else
+///
+/// Unlike [parse_database] the resources spent on the vault are bounded by `limits` and every
+/// entry is validated up front, so any malformed input results in an error. No input reaches a
+/// panic, which is checked by the fuzz target in `fuzz/`.
+pub fn parse_untrusted(
+    vault_backup_contents: &str,
+    password_getter: impl PasswordGetter,
+    options: &DecryptOptions,
+    limits: &UntrustedLimits,
+) -> Result<Database> {
+    if vault_backup_contents.len() > limits.max_file_size {
+        return Err(eyre!(
+            "Vault is {} bytes, limit is {} bytes",
+            vault_backup_contents.len(),
+            limits.max_file_size
+        ));
+    }
+    let options = DecryptOptions {
+        max_kdf_memory: Some(
+            options
+                .max_kdf_memory
+                .map_or(limits.max_kdf_memory, |max| max.min(limits.max_kdf_memory)),
+        ),
+        ..options.clone()
+    };
+    // Checked before any key is derived, or thousands of slots would each run the KDF
+    let vault = Vault::parse(vault_backup_contents)?;
+    let slots = vault.slots().len();
+    if slots > limits.max_slots {
+        return Err(eyre!(
+            "Vault has {} key slots, limit is {}",
+            slots,
+            limits.max_slots
+        ));
+    }
+    let db = vault.unlock(password_getter, &options)?;
+
+    if db.entries.len() > limits.max_entries {
+        return Err(eyre!(
+            "Vault has {} entries, limit is {}",
+            db.entries.len(),
+            limits.max_entries
+        ));
+    }
+    for entry in &db.entries {
+        if entry.raw_len() > limits.max_entry_size {
+            return Err(eyre!(
+                "Entry {} ({}) is {} bytes, limit is {} bytes",
+                entry.issuer(),
+                entry.name(),
+                entry.raw_len(),
+                limits.max_entry_size
+            ));
+        }
+        entry
+            .deserialize()?
+            .info
+            .validate()
+            .map_err(|e| eyre!("Invalid entry {} ({}): {}", entry.issuer(), entry.name(), e))?;
+    }
+
+    Ok(db)
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::Result;
+
+    use color_eyre::eyre::eyre;
+
+    use super::{
+        create_vault, create_vault_with, parse_database, parse_untrusted, parse_vault,
+        parse_vault_with_password, reencrypt_vault, rekey_vault, set_password, write_vault,
+        Database, DecryptOptions, EnvPassword, FilePassword, Group, NewEntry, PasswordGetter,
+        Selector, UntrustedLimits, Vault,
+    };
+    use crate::otp::{EntryInfo, EntryInfoTotp, HashAlgorithm};
+
+    struct TestPassword(&'static str);
+
+    /// Fails the test if the password is requested
+    struct NoPassword;
+
+    impl PasswordGetter for NoPassword {
+        fn get_password(&self) -> Result<String> {
+            Err(eyre!("Password requested"))
+        }
+    }
+
+    impl PasswordGetter for TestPassword {
+        fn get_password(&self) -> Result<String> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    #[test]
+    fn parse_plain_vault() {
+        let vault = include_str!("../res/aegis_plain.json");
+        let entries = parse_vault(vault, TestPassword("unused")).unwrap();
+        assert_eq!(entries.len(), 4);
+    }
+
+    #[test]
+    fn parse_encrypted_vault() {
+        let vault = include_str!("../res/aegis_encrypted.json");
+        let entries = parse_vault(vault, TestPassword("test")).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].issuer, "Deno");
+    }
+
+    #[test]
+    fn parse_encrypted_vault_with_known_password() {
+        let vault = include_str!("../res/aegis_encrypted.json");
+        assert_eq!(parse_vault_with_password(vault, "test").unwrap().len(), 4);
+        assert!(parse_vault_with_password(vault, "wrong").is_err());
+    }
+
+    #[test]
+    fn password_sources() {
+        let vault = include_str!("../res/aegis_encrypted.json");
+        let path = std::env::temp_dir().join(format!("aegis-rs-password-{}", std::process::id()));
+        std::fs::write(&path, "test\nsecond line\n").unwrap();
+        assert_eq!(parse_vault(vault, FilePassword(&path)).unwrap().len(), 4);
+        std::fs::remove_file(&path).unwrap();
+
+        let from_store = || Ok("test".to_string());
+        assert_eq!(parse_vault(vault, from_store).unwrap().len(), 4);
+        let e = parse_vault(vault, EnvPassword("AEGIS_RS_TEST_UNSET")).unwrap_err();
+        assert_eq!(e.to_string(), "AEGIS_RS_TEST_UNSET is not set");
+    }
+
+    #[test]
+    fn parse_encrypted_vault_with_wrong_password() {
+        let vault = include_str!("../res/aegis_encrypted.json");
+        let e = parse_vault(vault, TestPassword("wrong")).unwrap_err();
+        assert_eq!(e.to_string(), "Failed to decrypt master key");
+    }
+
+    #[test]
+    fn parse_vault_with_unsupported_kdf() {
+        let vault = include_str!("../res/aegis_encrypted.json").replace(
+            r#""n": 1024,
+                "r": 8,
+                "p": 1,"#,
+            r#""memory": 65536,
+                "iterations": 3,"#,
+        );
+        let e = parse_vault(&vault, TestPassword("test")).unwrap_err();
+        assert!(e.to_string().contains("Unsupported KDF"), "{}", e);
+        assert!(e.to_string().contains("iterations, memory"), "{}", e);
+    }
+
+    #[test]
+    fn parse_entries_lazily() {
+        let vault = include_str!("../res/aegis_plain.json")
+            .replace(r#""algo": "SHA512","#, r#""algo": "MD5","#);
+        let db =
+            parse_database(&vault, TestPassword("unused"), &DecryptOptions::default()).unwrap();
+        assert_eq!(db.entries.len(), 4);
+        assert_eq!(db.entries[2].issuer(), "WWE");
+        assert_eq!(db.entries[2].entry_type(), "hotp");
+
+        assert!(db.entries[0].deserialize().is_ok());
+        assert!(db.entries[2].deserialize().is_err());
     }
 
-    Ok(db.entries)
+    #[test]
+    fn find_entries() {
+        let vault = include_str!("../res/aegis_plain.json");
+        let db = parse_database(vault, TestPassword("unused"), &DecryptOptions::default()).unwrap();
+        let issuers = |selector: &str| {
+            db.find(&selector.parse::<Selector>().unwrap())
+                .iter()
+                .map(|e| e.issuer().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            issuers("uuid:B25F8815-007F-40F7-A700-CE058AC05435"),
+            ["WWE"]
+        );
+        assert_eq!(issuers("issuer:deno"), ["Deno"]);
+        assert_eq!(issuers("name:mason"), ["Deno", "WWE"]);
+        assert_eq!(issuers(" Mason "), ["Deno", "WWE"]);
+        assert_eq!(issuers("spdx"), ["SPDX"]);
+        assert!(issuers("name:deno").is_empty());
+        assert!(issuers("mas").is_empty());
+    }
+
+    #[test]
+    fn find_after_changing_entries() {
+        let vault = include_str!("../res/aegis_plain.json");
+        let mut db =
+            parse_database(vault, TestPassword("unused"), &DecryptOptions::default()).unwrap();
+        let boeing = Selector::Issuer("boeing".to_string());
+        assert_eq!(db.find(&boeing).len(), 1);
+        db.entries_mut().truncate(1);
+        assert!(db.find(&boeing).is_empty());
+        db.entries_mut().reverse();
+        assert_eq!(db.find(&Selector::Issuer("deno".to_string())).len(), 1);
+    }
+
+    #[test]
+    fn inspect_vault_without_password() {
+        let vault = Vault::parse(include_str!("../res/aegis_encrypted.json")).unwrap();
+        assert!(vault.is_encrypted());
+        assert_eq!(vault.slots(), ["password (scrypt n=1024 r=8 p=1)"]);
+        assert!(vault
+            .unlock(NoPassword, &DecryptOptions::default())
+            .is_err());
+
+        let vault = Vault::parse(include_str!("../res/aegis_plain.json")).unwrap();
+        assert!(!vault.is_encrypted());
+        let db = vault
+            .unlock(NoPassword, &DecryptOptions::default())
+            .unwrap();
+        assert_eq!(db.entries.len(), 4);
+    }
+
+    #[test]
+    fn detect_weak_parameters() {
+        let contents = include_str!("../res/aegis_encrypted.json");
+        let weaknesses = Vault::parse(contents).unwrap().weaknesses();
+        assert_eq!(weaknesses.len(), 1, "{:?}", weaknesses);
+        assert!(weaknesses[0].contains("n=1024"));
+
+        let broken = contents
+            .replace("e165b102cfb3275c1f811c49", "52e110752061438c975456b0")
+            .replace(
+                "10f9265b89ecb4a470c189fdf2561ec4c44b6fed216e01d068caa8cf8191305e",
+                "",
+            );
+        let weaknesses = Vault::parse(&broken).unwrap().weaknesses();
+        assert_eq!(weaknesses.len(), 3, "{:?}", weaknesses);
+        assert!(weaknesses[0].contains("used 2 times"));
+        assert!(weaknesses[2].contains("empty salt"));
+
+        let plain = Vault::parse(include_str!("../res/aegis_plain.json")).unwrap();
+        assert!(plain.weaknesses().is_empty());
+    }
+
+    #[test]
+    fn parse_untrusted_vaults() {
+        let options = DecryptOptions::default();
+        let limits = UntrustedLimits::default();
+        let plain = include_str!("../res/aegis_plain.json");
+        let encrypted = include_str!("../res/aegis_encrypted.json");
+        let parse = |vault: &str, limits: &UntrustedLimits| {
+            parse_untrusted(vault, TestPassword("test"), &options, limits)
+        };
+
+        assert_eq!(parse(encrypted, &limits).unwrap().entries.len(), 4);
+        assert!(parse(plain, &limits).is_ok());
+
+        let small = |limits: UntrustedLimits| parse(encrypted, &limits).unwrap_err().to_string();
+        assert!(small(UntrustedLimits {
+            max_file_size: 100,
+            ..UntrustedLimits::default()
+        })
+        .contains("limit is 100 bytes"));
+        assert!(small(UntrustedLimits {
+            max_entries: 3,
+            ..UntrustedLimits::default()
+        })
+        .contains("limit is 3"));
+        assert!(small(UntrustedLimits {
+            max_entry_size: 100,
+            ..UntrustedLimits::default()
+        })
+        .contains("limit is 100 bytes"));
+        assert!(small(UntrustedLimits {
+            max_kdf_memory: 1 << 19,
+            ..UntrustedLimits::default()
+        })
+        .contains("limit is 0 MiB"));
+
+        // Many slots fail before deriving anything, even with a wrong password
+        let mut many_slots: serde_json::Value = serde_json::from_str(encrypted).unwrap();
+        let slot = many_slots["header"]["slots"][0].clone();
+        many_slots["header"]["slots"] = serde_json::Value::Array(vec![slot; 1000]);
+        let e = parse_untrusted(
+            &many_slots.to_string(),
+            TestPassword("wrong"),
+            &options,
+            &limits,
+        )
+        .unwrap_err();
+        assert_eq!(e.to_string(), "Vault has 1000 key slots, limit is 8");
+
+        let zero_period = plain.replace(r#""period": 30"#, r#""period": 0"#);
+        let e = parse(&zero_period, &limits).unwrap_err();
+        assert!(e.to_string().contains("Period"), "{}", e);
+    }
+
+    #[test]
+    fn create_vault_and_change_password() {
+        let options = DecryptOptions::default();
+        let contents = create_vault("first", &options).unwrap();
+        let vault = Vault::parse(&contents).unwrap();
+        assert!(vault.is_encrypted());
+        let (db, master_key) = vault
+            .unlock_with_key(TestPassword("first"), &options)
+            .unwrap();
+        assert!(db.entries.is_empty());
+
+        let contents = set_password(&contents, &master_key.unwrap(), "second", &options).unwrap();
+        let vault = Vault::parse(&contents).unwrap();
+        assert_eq!(vault.slots().len(), 1);
+        assert!(vault.unlock(TestPassword("first"), &options).is_err());
+        let vault = Vault::parse(&contents).unwrap();
+        assert!(vault.unlock(TestPassword("second"), &options).is_ok());
+    }
+
+    #[test]
+    fn rekey() {
+        let options = DecryptOptions::default();
+        let contents = include_str!("../res/aegis_encrypted.json");
+        let (db, old_key) = Vault::parse(contents)
+            .unwrap()
+            .unlock_with_key(TestPassword("test"), &options)
+            .unwrap();
+        let (rekeyed, new_key) = rekey_vault(contents, &db, "test", &options).unwrap();
+
+        let vault = Vault::parse(&rekeyed).unwrap();
+        assert_eq!(vault.slots().len(), 1);
+        let (rekeyed_db, unlocked_key) = vault
+            .unlock_with_key(TestPassword("test"), &options)
+            .unwrap();
+        let unlocked_key = unlocked_key.unwrap();
+        assert_eq!(unlocked_key.id(), new_key.id());
+        assert_ne!(unlocked_key.id(), old_key.unwrap().id());
+        assert_eq!(rekeyed_db.entries.len(), db.entries.len());
+    }
+
+    #[test]
+    fn reencrypt() {
+        let options = DecryptOptions::default();
+        let contents = include_str!("../res/aegis_encrypted.json");
+        assert!(reencrypt_vault(contents, TestPassword("test"), "test", &options).is_err());
+        assert!(reencrypt_vault(contents, TestPassword("wrong"), "new", &options).is_err());
+        let reencrypted = reencrypt_vault(contents, TestPassword("test"), "new", &options).unwrap();
+        assert!(Vault::parse(&reencrypted)
+            .unwrap()
+            .unlock(TestPassword("test"), &options)
+            .is_err());
+        let db = Vault::parse(&reencrypted)
+            .unwrap()
+            .unlock(TestPassword("new"), &options)
+            .unwrap();
+        assert_eq!(db.entries.len(), 4);
+
+        let plain = include_str!("../res/aegis_plain.json");
+        let encrypted = reencrypt_vault(plain, NoPassword, "new", &options).unwrap();
+        let vault = Vault::parse(&encrypted).unwrap();
+        assert!(vault.is_encrypted());
+        assert_eq!(
+            vault
+                .unlock(TestPassword("new"), &options)
+                .unwrap()
+                .entries
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn record_provenance() {
+        let contents = include_str!("../res/aegis_plain.json");
+        assert_eq!(Vault::parse(contents).unwrap().provenance, None);
+        let db = parse_database(contents, NoPassword, &DecryptOptions::default()).unwrap();
+
+        let written = write_vault(contents, &db, None).unwrap();
+        let provenance = Vault::parse(&written).unwrap().provenance.unwrap();
+        assert_eq!(provenance.tool, "aegis-rs");
+        assert_eq!(provenance.version, env!("CARGO_PKG_VERSION"));
+        assert!(provenance.written_at > 1_700_000_000);
+    }
+
+    #[test]
+    fn parse_database_versions() {
+        let vault = include_str!("../res/aegis_plain.json");
+        for version in 1..=3 {
+            let contents =
+                vault.replace(r#""version": 2,"#, &format!(r#""version": {},"#, version));
+            let db = parse_database(&contents, NoPassword, &DecryptOptions::default()).unwrap();
+            assert_eq!(db.version(), version);
+            assert_eq!(db.entries.len(), 4);
+        }
+        let contents = vault.replace(r#""version": 2,"#, r#""version": 4,"#);
+        let e = parse_database(&contents, NoPassword, &DecryptOptions::default()).unwrap_err();
+        assert!(
+            e.to_string().contains("Unsupported database version: 4"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn subset_keeps_used_groups() {
+        let vault: serde_json::Value =
+            serde_json::from_str(include_str!("../res/aegis_plain.json")).unwrap();
+        let mut db = vault["db"].clone();
+        db["version"] = 3.into();
+        db["groups"] = serde_json::json!([
+            { "uuid": "g1", "name": "shared" },
+            { "uuid": "g2", "name": "private" },
+        ]);
+        db["entries"][0]["groups"] = serde_json::json!(["g1"]);
+        db["entries"][1]["groups"] = serde_json::json!(["g2"]);
+        let db: Database = serde_json::from_value(db).unwrap();
+
+        let subset = db
+            .subset(|e| e.groups().contains(&"g1".to_string()))
+            .unwrap();
+        assert_eq!(subset.entries.len(), 1);
+        let shared = Group {
+            uuid: "g1".to_string(),
+            name: "shared".to_string(),
+        };
+        assert_eq!(subset.groups().unwrap(), [shared]);
+    }
+
+    #[test]
+    fn redact_entry_secrets() {
+        let mut db = parse_database(
+            include_str!("../res/aegis_plain.json"),
+            TestPassword("unused"),
+            &DecryptOptions::default(),
+        )
+        .unwrap();
+        let entry = &mut db.entries[0];
+        entry.redact_secrets().unwrap();
+        let redacted = entry.deserialize().unwrap();
+        assert_eq!(redacted.issuer, "Deno");
+        match redacted.info {
+            EntryInfo::Totp(info) => assert_eq!(info.secret, ""),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn save_hotp_counter() {
+        let mut db = parse_database(
+            include_str!("../res/aegis_plain.json"),
+            TestPassword("unused"),
+            &DecryptOptions::default(),
+        )
+        .unwrap();
+        let hotp = db
+            .entries
+            .iter()
+            .position(|e| e.entry_type() == "hotp")
+            .unwrap();
+        let mut info = match db.entries[hotp].deserialize().unwrap().info {
+            EntryInfo::Hotp(info) => info,
+            _ => unreachable!(),
+        };
+        crate::otp::generate_hotp(&mut info).unwrap();
+        db.entries[hotp].set_counter(info.counter).unwrap();
+
+        let entry = db.entries[hotp].deserialize().unwrap();
+        assert!(matches!(entry.info, EntryInfo::Hotp(info) if info.counter == 10301));
+        assert_eq!(entry.name, "Mason");
+        assert!(db.entries[0].set_counter(1).is_err());
+    }
+
+    #[test]
+    fn save_hotp_counter_encrypted() {
+        let options = DecryptOptions::default();
+        let contents = include_str!("../res/aegis_encrypted.json");
+        let (mut db, master_key) = Vault::parse(contents)
+            .unwrap()
+            .unlock_with_key(TestPassword("test"), &options)
+            .unwrap();
+        let hotp = db
+            .entries
+            .iter()
+            .position(|e| e.entry_type() == "hotp")
+            .unwrap();
+        db.entries[hotp].set_counter(10301).unwrap();
+        // Only the database is encrypted again, with the master key the vault was unlocked with
+        let written = write_vault(contents, &db, master_key.as_ref()).unwrap();
+
+        let old: serde_json::Value = serde_json::from_str(contents).unwrap();
+        let new: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(new["header"]["slots"], old["header"]["slots"]);
+        assert_ne!(
+            new["header"]["params"]["nonce"],
+            old["header"]["params"]["nonce"]
+        );
+        let (db, key) = Vault::parse(&written)
+            .unwrap()
+            .unlock_with_key(TestPassword("test"), &options)
+            .unwrap();
+        assert_eq!(key.unwrap().id(), master_key.unwrap().id());
+        let entry = db.entries[hotp].deserialize().unwrap();
+        assert!(matches!(entry.info, EntryInfo::Hotp(info) if info.counter == 10301));
+    }
+
+    #[test]
+    fn entry_note() {
+        let vault: serde_json::Value =
+            serde_json::from_str(include_str!("../res/aegis_plain.json")).unwrap();
+        let mut db = vault["db"].clone();
+        db["entries"][0]["note"] = "Recovery codes in the safe".into();
+        db["entries"][1].as_object_mut().unwrap().remove("note");
+        let db: Database = serde_json::from_value(db).unwrap();
+        assert_eq!(db.entries[0].note(), "Recovery codes in the safe");
+        assert_eq!(db.entries[1].note(), "");
+    }
+
+    #[test]
+    fn extract_entries_to_new_vault() {
+        let options = DecryptOptions::default();
+        let db = parse_database(
+            include_str!("../res/aegis_encrypted.json"),
+            TestPassword("test"),
+            &options,
+        )
+        .unwrap();
+        let subset = db.subset(|e| e.issuer() == "SPDX").unwrap();
+        let contents = create_vault_with("new", &subset, &options).unwrap();
+        let vault = Vault::parse(&contents).unwrap();
+        assert!(vault.unlock(TestPassword("test"), &options).is_err());
+        let extracted = Vault::parse(&contents)
+            .unwrap()
+            .unlock(TestPassword("new"), &options)
+            .unwrap();
+        assert_eq!(extracted.entries.len(), 1);
+        assert_eq!(extracted.entries[0].issuer(), "SPDX");
+    }
+
+    #[test]
+    fn add_entries() {
+        let vault = include_str!("../res/aegis_plain.json");
+        let mut db =
+            parse_database(vault, TestPassword("unused"), &DecryptOptions::default()).unwrap();
+        let info = EntryInfoTotp {
+            secret: "JBSWY3DPEHPK3PXP".to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 6,
+            period: 30,
+        };
+        let uuid = db
+            .add_entry(NewEntry {
+                name: "john@example.com".to_string(),
+                issuer: "GitHub".to_string(),
+                info: EntryInfo::Totp(info.clone()),
+                icon: Some(("image/png".to_string(), vec![1, 2, 3])),
+            })
+            .unwrap();
+
+        let added = db.find(&Selector::Uuid(uuid));
+        assert_eq!(added.len(), 1);
+        let entry = added[0].deserialize().unwrap();
+        assert_eq!(entry.issuer, "GitHub");
+        assert_eq!(entry.info, EntryInfo::Totp(info.clone()));
+        assert!(entry.extra.is_empty(), "{:?}", entry.extra);
+        assert!(added[0].has_icon());
+        assert!(!db.entries[0].has_icon());
+
+        db.entries[0].set_issuer("Deno Land").unwrap();
+        assert_eq!(db.entries[0].issuer(), "Deno Land");
+        assert_eq!(db.entries[0].deserialize().unwrap().issuer, "Deno Land");
+
+        let invalid = db.add_entry(NewEntry {
+            name: "john".to_string(),
+            issuer: "GitHub".to_string(),
+            info: EntryInfo::Totp(EntryInfoTotp {
+                secret: "not base32!".to_string(),
+                ..info
+            }),
+            icon: None,
+        });
+        assert!(invalid.is_err());
+        assert_eq!(db.entries.len(), 5);
+    }
+
+    #[test]
+    fn merge_databases() {
+        let vault = include_str!("../res/aegis_plain.json");
+        let parse = |vault: &str| {
+            parse_database(vault, TestPassword("unused"), &DecryptOptions::default()).unwrap()
+        };
+        let mut db = parse(vault);
+        db.entries_mut().truncate(2);
+        // Entry renamed in the other copy
+        let other = parse(&vault.replace(r#""issuer": "Deno""#, r#""issuer": "Deno Land""#));
+
+        let report = db.merge(other);
+        assert_eq!(report.added, ["WWE (Mason)", "Boeing (Sophia)"]);
+        assert_eq!(report.conflicts, ["Deno (Mason)"]);
+        assert_eq!(db.entries.len(), 4);
+        assert_eq!(db.entries[0].issuer(), "Deno");
+        assert_eq!(db.find(&Selector::Issuer("boeing".to_string())).len(), 1);
+    }
+
+    #[test]
+    fn merge_groups() {
+        let vault = include_str!("../res/aegis_plain.json")
+            .replace(r#""version": 2,"#, r#""version": 3, "groups": [GROUPS],"#)
+            .replace(
+                r#""issuer": "Deno""#,
+                r#""issuer": "Deno", "groups": ["g1"]"#,
+            )
+            .replace(
+                r#""issuer": "Boeing""#,
+                r#""issuer": "Boeing", "groups": ["g1", "g2"]"#,
+            );
+        let work = r#"{"uuid": "g1", "name": "Work"}"#;
+        let parse = |groups: &str| {
+            let vault = vault.replace("GROUPS", groups);
+            parse_database(&vault, TestPassword("unused"), &DecryptOptions::default()).unwrap()
+        };
+        let mut db = parse(work);
+        db.entries_mut().truncate(2);
+        // The other copy has a group only its entries are in, and the same one renamed
+        let other = parse(r#"{"uuid": "g1", "name": "Job"}, {"uuid": "g2", "name": "Home"}"#);
+
+        let report = db.merge(other);
+        assert_eq!(report.added, ["WWE (Mason)", "Boeing (Sophia)"]);
+        assert_eq!(report.groups, ["Home"]);
+        let names: Vec<String> = db.groups().unwrap().into_iter().map(|g| g.name).collect();
+        assert_eq!(names, ["Work", "Home"]);
+        assert_eq!(db.entries[3].groups(), ["g1", "g2"]);
+    }
 }
@@ -0,0 +1,174 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::vault::{LazyEntry, RawEntry};
+
+/// Entry field holding the attachments
+///
+/// The Android app ignores fields it doesn't know, but keeps them when it exports a vault.
+pub const ATTACHMENTS_FIELD: &str = "aegis_rs_attachments";
+
+/// Largest accepted attachment, vaults are loaded into memory as a whole
+pub const MAX_ATTACHMENT_SIZE: usize = 1 << 20;
+
+/// Small file attached to an entry, e.g. the original QR code screenshot
+///
+/// Attachments are stored in the entry, so they are encrypted along with it in encrypted vaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    /// MIME type of the contents
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+/// Fail if one of the attachments is larger than [MAX_ATTACHMENT_SIZE]
+pub(super) fn check_sizes(attachments: &[Attachment]) -> Result<()> {
+    match attachments
+        .iter()
+        .find(|a| a.data.len() > MAX_ATTACHMENT_SIZE)
+    {
+        Some(attachment) => Err(eyre!(
+            "Attachment {} is {} bytes, limit is {} bytes",
+            attachment.name,
+            attachment.data.len(),
+            MAX_ATTACHMENT_SIZE
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Attachment as stored in the entry
+#[derive(Deserialize, Serialize)]
+struct StoredAttachment {
+    name: String,
+    mime: String,
+    /// Base64 encoded contents
+    data: String,
+}
+
+impl LazyEntry {
+    /// Attachments of the entry, in the order they were added
+    pub fn attachments(&self) -> Result<Vec<Attachment>> {
+        #[derive(Deserialize)]
+        struct Attachments {
+            #[serde(default, rename = "aegis_rs_attachments")]
+            attachments: Vec<StoredAttachment>,
+        }
+        let stored: Attachments = serde_json::from_str(self.raw.get())?;
+        stored
+            .attachments
+            .into_iter()
+            .map(|stored| {
+                let data = general_purpose::STANDARD
+                    .decode(&stored.data)
+                    .map_err(|e| eyre!("Failed to decode attachment {}: {}", stored.name, e))?;
+                Ok(Attachment {
+                    name: stored.name,
+                    mime: stored.mime,
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace the attachments of the entry, removing the field if there are none
+    pub fn set_attachments(&mut self, attachments: &[Attachment]) -> Result<()> {
+        check_sizes(attachments)?;
+        let mut fields: Map<String, Value> = serde_json::from_str(self.raw.get())?;
+        if attachments.is_empty() {
+            fields.remove(ATTACHMENTS_FIELD);
+        } else {
+            let stored: Vec<StoredAttachment> = attachments
+                .iter()
+                .map(|attachment| StoredAttachment {
+                    name: attachment.name.clone(),
+                    mime: attachment.mime.clone(),
+                    data: general_purpose::STANDARD.encode(&attachment.data),
+                })
+                .collect();
+            fields.insert(ATTACHMENTS_FIELD.to_string(), serde_json::to_value(stored)?);
+        }
+        self.raw = RawEntry::new(serde_json::to_string(&fields)?)?;
+        Ok(())
+    }
+}
+
+/// MIME type guessed from the extension of `name`
+pub fn guess_mime(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("pdf") => "application/pdf",
+        Some("txt") => "text/plain",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{guess_mime, Attachment, MAX_ATTACHMENT_SIZE};
+    use crate::vault::{write_vault, DecryptOptions, PasswordGetter, Vault};
+
+    struct TestPassword;
+
+    impl PasswordGetter for TestPassword {
+        fn get_password(&self) -> color_eyre::eyre::Result<String> {
+            Ok("test".to_string())
+        }
+    }
+
+    #[test]
+    fn attachments_round_trip_through_encrypted_vault() {
+        let contents = include_str!("../../res/aegis_encrypted.json");
+        let (mut db, master_key) = Vault::parse(contents)
+            .unwrap()
+            .unlock_with_key(TestPassword, &DecryptOptions::default())
+            .unwrap();
+        let attachment = Attachment {
+            name: "qr.png".to_string(),
+            mime: guess_mime("qr.png").to_string(),
+            data: vec![0x89, b'P', b'N', b'G'],
+        };
+        db.entries[1]
+            .set_attachments(std::slice::from_ref(&attachment))
+            .unwrap();
+
+        let written = write_vault(contents, &db, master_key.as_ref()).unwrap();
+        assert!(!written.contains("qr.png"));
+        let db = Vault::parse(&written)
+            .unwrap()
+            .unlock(TestPassword, &DecryptOptions::default())
+            .unwrap();
+        assert_eq!(db.entries.len(), 4);
+        assert!(db.entries[0].attachments().unwrap().is_empty());
+        assert_eq!(db.entries[1].attachments().unwrap(), [attachment]);
+        // Not an extra field of the entry
+        assert!(db.entries[1].deserialize().unwrap().extra.is_empty());
+
+        let mut entry = db.into_entries().unwrap();
+        assert_eq!(entry.remove(1).issuer, "SPDX");
+    }
+
+    #[test]
+    fn attachment_size_limit() {
+        let contents = include_str!("../../res/aegis_plain.json");
+        let mut db = Vault::parse(contents)
+            .unwrap()
+            .unlock(TestPassword, &DecryptOptions::default())
+            .unwrap();
+        let attachment = Attachment {
+            name: "big.pdf".to_string(),
+            mime: guess_mime("big.pdf").to_string(),
+            data: vec![0; MAX_ATTACHMENT_SIZE + 1],
+        };
+        assert!(db.entries[0].set_attachments(&[attachment]).is_err());
+    }
+}
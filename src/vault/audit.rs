@@ -0,0 +1,183 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use zeroize::Zeroizing;
+
+use crate::vault::{
+    crypto::{decrypt_in_place, encrypt_in_place, KeyParams, MasterKey},
+    write_atomic,
+};
+
+/// Purpose of the key derived from the master key
+const KEY_PURPOSE: &str = "aegis-rs audit log";
+
+/// Code generation, as recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AuditRecord {
+    /// Seconds since the unix epoch
+    pub time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub issuer: String,
+    pub name: String,
+    /// Part of aegis-rs the code was shown by, e.g. "list"
+    pub frontend: String,
+}
+
+/// Line of an audit log file
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "encryption", rename_all = "snake_case")]
+enum AuditLine {
+    /// Record of a plain vault
+    None { record: AuditRecord },
+    Aes256Gcm {
+        params: KeyParams,
+        /// Base64 encoded encrypted JSON of the record
+        data: String,
+    },
+}
+
+/// Log of generated codes, a line per code appended to a file
+///
+/// For encrypted vaults every line is encrypted with a key derived from the vault's master key,
+/// so the log only reveals how many codes were generated.
+pub struct AuditLog<'a> {
+    path: PathBuf,
+    master_key: Option<&'a MasterKey>,
+}
+
+impl<'a> AuditLog<'a> {
+    /// Log at `path` of the vault with `master_key`, `None` for plain vaults
+    pub fn new(path: &Path, master_key: Option<&'a MasterKey>) -> AuditLog<'a> {
+        AuditLog {
+            path: path.to_path_buf(),
+            master_key,
+        }
+    }
+
+    pub fn append(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_string(&self.encrypt(record)?)?;
+        line.push('\n');
+        let mut options = fs::OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| eyre!("Failed to write {}: {}", self.path.display(), e))
+    }
+
+    /// All records, oldest first, none if the log doesn't exist
+    pub fn read(&self) -> Result<Vec<AuditRecord>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| eyre!("Failed to read {}: {}", self.path.display(), e))?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| self.decrypt(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Remove the records from before `time`, returns how many were removed
+    pub fn prune(&self, time: u64) -> Result<usize> {
+        let records = self.read()?;
+        let kept: Vec<&AuditRecord> = records.iter().filter(|r| r.time >= time).collect();
+        let removed = records.len() - kept.len();
+        if removed > 0 {
+            let mut contents = String::new();
+            for record in kept {
+                contents += &serde_json::to_string(&self.encrypt(record)?)?;
+                contents.push('\n');
+            }
+            write_atomic(&self.path, contents.as_bytes())?;
+        }
+        Ok(removed)
+    }
+
+    fn encrypt(&self, record: &AuditRecord) -> Result<AuditLine> {
+        Ok(match self.master_key {
+            Some(master_key) => {
+                let mut buffer = Zeroizing::new(serde_json::to_vec(record)?);
+                let params = encrypt_in_place(&master_key.derive(KEY_PURPOSE), &mut buffer)?;
+                AuditLine::Aes256Gcm {
+                    params,
+                    data: general_purpose::STANDARD.encode(buffer.as_slice()),
+                }
+            }
+            None => AuditLine::None {
+                record: record.clone(),
+            },
+        })
+    }
+
+    fn decrypt(&self, line: AuditLine) -> Result<AuditRecord> {
+        match (line, self.master_key) {
+            (AuditLine::None { record }, None) => Ok(record),
+            (AuditLine::Aes256Gcm { params, data }, Some(master_key)) => {
+                let mut buffer = Zeroizing::new(general_purpose::STANDARD.decode(data)?);
+                if !decrypt_in_place(&master_key.derive(KEY_PURPOSE), &params, &mut buffer)? {
+                    return Err(eyre!("Failed to decrypt the audit log"));
+                }
+                Ok(serde_json::from_slice(&buffer)?)
+            }
+            (AuditLine::None { .. }, Some(_)) => {
+                Err(eyre!("Audit log is not encrypted, but the vault is"))
+            }
+            (AuditLine::Aes256Gcm { .. }, None) => {
+                Err(eyre!("Audit log is encrypted, but the vault is not"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::{AuditLog, AuditRecord};
+    use crate::vault::{DecryptOptions, PasswordGetter, Vault};
+
+    struct TestPassword;
+
+    impl PasswordGetter for TestPassword {
+        fn get_password(&self) -> color_eyre::eyre::Result<String> {
+            Ok("test".to_string())
+        }
+    }
+
+    #[test]
+    fn append_read_and_prune() {
+        let (_, master_key) = Vault::parse(include_str!("../../res/aegis_encrypted.json"))
+            .unwrap()
+            .unlock_with_key(TestPassword, &DecryptOptions::default())
+            .unwrap();
+        let master_key = master_key.unwrap();
+        let path = env::temp_dir().join(format!("aegis-rs-audit-{}.log", std::process::id()));
+        let log = AuditLog::new(&path, Some(&master_key));
+        let record = |time| AuditRecord {
+            time,
+            uuid: None,
+            issuer: "Deno".to_string(),
+            name: "Mason".to_string(),
+            frontend: "list".to_string(),
+        };
+        log.append(&record(100)).unwrap();
+        log.append(&record(200)).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("Deno"));
+        assert_eq!(log.read().unwrap(), [record(100), record(200)]);
+
+        assert_eq!(log.prune(150).unwrap(), 1);
+        assert_eq!(log.read().unwrap(), [record(200)]);
+        assert!(AuditLog::new(&path, None).read().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
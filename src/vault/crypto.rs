@@ -1,32 +1,301 @@
-use aes_gcm::{aead::AeadMut, Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::{
+    aead::{consts::U12, rand_core::RngCore, AeadCore, AeadInPlace, OsRng},
+    Aes256Gcm, KeyInit, Nonce, Tag,
+};
 use base64::{engine::general_purpose, Engine as _};
 use color_eyre::eyre::{eyre, Result};
 use hex::FromHex;
-use password_hash::Output;
-use scrypt::{
-    password_hash::{PasswordHasher, SaltString},
-    Scrypt,
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::{
+    cancel::CancellationToken,
+    vault::{Database, DecryptOptions, PasswordGetter, Vault, VaultDatabase},
 };
-use serde::Deserialize;
 
-use crate::vault::{Database, Vault, VaultDatabase};
+/// scrypt with parallel lanes
+mod scrypt;
 
 /// AES-GCM encryption parameters
-#[derive(Debug, Deserialize)]
-struct KeyParams {
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct KeyParams {
     nonce: String,
     tag: String,
 }
 
-/// Password slot parameters (scrypt parameters + salt)
+impl KeyParams {
+    fn nonce(&self) -> Result<Nonce<U12>> {
+        let nonce = Vec::from_hex(&self.nonce).map_err(|_| eyre!("Failed to decode nonce"))?;
+        if nonce.len() != 12 {
+            return Err(eyre!("Nonce has invalid length {}", nonce.len()));
+        }
+        Ok(*Nonce::from_slice(&nonce))
+    }
+
+    fn tag(&self) -> Result<Tag> {
+        let tag = Vec::from_hex(&self.tag).map_err(|_| eyre!("Failed to decode tag"))?;
+        if tag.len() != 16 {
+            return Err(eyre!("Tag has invalid length {}", tag.len()));
+        }
+        Ok(*Tag::from_slice(&tag))
+    }
+}
+
+/// Encrypt `buffer` in place with AES-256-GCM under a random nonce
+pub(crate) fn encrypt_in_place(key: &[u8], buffer: &mut [u8]) -> Result<KeyParams> {
+    if key.len() != 32 {
+        return Err(eyre!("Key has invalid length {}", key.len()));
+    }
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let cipher = Aes256Gcm::new(key.into());
+    let tag = cipher
+        .encrypt_in_place_detached(&nonce, b"", buffer)
+        .map_err(|e| eyre!("Failed to encrypt: {}", e))?;
+    Ok(KeyParams {
+        nonce: hex::encode(nonce),
+        tag: hex::encode(tag),
+    })
+}
+
+/// Decrypt AES-256-GCM encrypted `buffer` in place
+///
+/// Returns `Ok(false)` if the key is wrong or the data has been tampered with.
+pub(crate) fn decrypt_in_place(key: &[u8], params: &KeyParams, buffer: &mut [u8]) -> Result<bool> {
+    if key.len() != 32 {
+        return Err(eyre!("Key has invalid length {}", key.len()));
+    }
+    let nonce = params.nonce()?;
+    let tag = params.tag()?;
+    let cipher = Aes256Gcm::new(key.into());
+    Ok(cipher
+        .decrypt_in_place_detached(&nonce, b"", buffer, &tag)
+        .is_ok())
+}
+
+/// Key derivation function used to derive a password slot key from the password
+trait Kdf {
+    /// Derive the 32 byte key which decrypts the master key in the slot
+    fn derive_key(&self, password: &[u8], options: &DecryptOptions) -> Result<Zeroizing<Vec<u8>>>;
+}
+
+/// scrypt parameters + salt
 #[derive(Debug, Deserialize)]
-struct PasswordSlot {
+struct ScryptParams {
     n: u32,
     r: u32,
     p: u32,
     salt: String,
 }
 
+/// Largest accepted scrypt block size
+const SCRYPT_MAX_R: u32 = 32;
+/// Largest accepted scrypt parallelization
+const SCRYPT_MAX_P: u32 = 16;
+/// Largest accepted scrypt memory usage, 128 * r * n bytes for every thread
+const SCRYPT_MAX_MEMORY: u64 = 4 << 30;
+/// Smallest scrypt cost not warned about, the interactive login cost of the scrypt paper
+const SCRYPT_MIN_N: u32 = 1 << 14;
+
+impl ScryptParams {
+    /// Validate the parameters and return log2 of n
+    ///
+    /// Malformed headers are rejected instead of deriving a wrong key.
+    fn log_n(&self) -> Result<u8> {
+        if self.n < 2 || !self.n.is_power_of_two() {
+            return Err(eyre!(
+                "Invalid scrypt parameter n={}, must be a power of two greater than 1",
+                self.n
+            ));
+        }
+        if !(1..=SCRYPT_MAX_R).contains(&self.r) {
+            return Err(eyre!(
+                "Invalid scrypt parameter r={}, must be between 1 and {}",
+                self.r,
+                SCRYPT_MAX_R
+            ));
+        }
+        if !(1..=SCRYPT_MAX_P).contains(&self.p) {
+            return Err(eyre!(
+                "Invalid scrypt parameter p={}, must be between 1 and {}",
+                self.p,
+                SCRYPT_MAX_P
+            ));
+        }
+        let memory = 128 * u64::from(self.r) * u64::from(self.n);
+        if memory > SCRYPT_MAX_MEMORY {
+            return Err(eyre!(
+                "Invalid scrypt parameters n={} r={}, would use {} MiB of memory",
+                self.n,
+                self.r,
+                memory >> 20
+            ));
+        }
+
+        Ok(self.n.trailing_zeros() as u8)
+    }
+}
+
+impl ScryptParams {
+    /// Threads the derivation may use within the memory limit of `options`
+    ///
+    /// Every thread works on its own lanes and needs 128 * r * n bytes, so fewer threads are
+    /// used than asked for where they would exceed the limit together.
+    fn threads(&self, options: &DecryptOptions) -> Result<usize> {
+        let max_memory = options
+            .max_kdf_memory
+            .map_or(SCRYPT_MAX_MEMORY, |max| max.min(SCRYPT_MAX_MEMORY));
+        let lane_memory = 128 * u64::from(self.r) * u64::from(self.n);
+        if lane_memory > max_memory {
+            return Err(eyre!(
+                "scrypt parameters n={} r={} would use {} MiB of memory, limit is {} MiB",
+                self.n,
+                self.r,
+                lane_memory >> 20,
+                max_memory >> 20
+            ));
+        }
+        let affordable = usize::try_from(max_memory / lane_memory).unwrap_or(usize::MAX);
+        Ok(options
+            .kdf_threads()
+            .clamp(1, self.p as usize)
+            .min(affordable))
+    }
+}
+
+impl Kdf for ScryptParams {
+    fn derive_key(&self, password: &[u8], options: &DecryptOptions) -> Result<Zeroizing<Vec<u8>>> {
+        let log_n = self.log_n()?;
+        let threads = self.threads(options)?;
+        let salt =
+            Vec::from_hex(&self.salt).map_err(|e| eyre!("Failed to decode salt hex: {}", e))?;
+
+        let mut derived_key = Zeroizing::new(vec![0u8; 32]);
+        scrypt::scrypt(
+            password,
+            &salt,
+            log_n,
+            self.r,
+            self.p,
+            threads,
+            options.cancel.as_ref().map(CancellationToken::flag),
+            &mut derived_key,
+        )?;
+        Ok(derived_key)
+    }
+}
+
+/// Derive a password slot key with scrypt, e.g. to measure how long unlocking takes
+///
+/// The parameters are validated as they are for slots in a vault.
+pub fn scrypt_key(
+    password: &[u8],
+    salt: &[u8],
+    n: u32,
+    r: u32,
+    p: u32,
+    options: &DecryptOptions,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let params = ScryptParams {
+        n,
+        r,
+        p,
+        salt: hex::encode(salt),
+    };
+    params.derive_key(password, options)
+}
+
+/// scrypt parameters the Android app uses for new password slots, cheaper for tests
+#[cfg(not(test))]
+const NEW_SLOT_N: u32 = 1 << 15;
+#[cfg(test)]
+const NEW_SLOT_N: u32 = 1 << 10;
+const NEW_SLOT_R: u32 = 8;
+const NEW_SLOT_P: u32 = 1;
+
+/// JSON of a new password slot, holding `master_key` encrypted with a key derived from `password`
+pub(crate) fn new_password_slot(
+    password: &str,
+    master_key: &MasterKey,
+    options: &DecryptOptions,
+) -> Result<Value> {
+    let mut salt = [0u8; 32];
+    OsRng.fill_bytes(&mut salt);
+    let derived_key = scrypt_key(
+        password.as_bytes(),
+        &salt,
+        NEW_SLOT_N,
+        NEW_SLOT_R,
+        NEW_SLOT_P,
+        options,
+    )?;
+    // Holds the plain master key until it's encrypted
+    let mut key = Zeroizing::new(master_key.key().to_vec());
+    let key_params = encrypt_in_place(&derived_key, &mut key)?;
+    Ok(json!({
+        "type": 1,
+        "uuid": random_uuid(),
+        "key": hex::encode(key.as_slice()),
+        "key_params": key_params,
+        "n": NEW_SLOT_N,
+        "r": NEW_SLOT_R,
+        "p": NEW_SLOT_P,
+        "salt": hex::encode(salt),
+        "repaired": true,
+        "is_backup": false,
+    }))
+}
+
+/// Random version 4 UUID, as used for slot and entry ids
+pub(crate) fn random_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Slot fields which aren't KDF parameters
+const SLOT_FIELDS: &[&str] = &["type", "uuid", "key", "key_params", "repaired", "is_backup"];
+
+/// Password slot parameters, determining the KDF used
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PasswordSlot {
+    Scrypt(ScryptParams),
+    /// Parameters of a KDF which isn't supported
+    Unknown(Map<String, Value>),
+}
+
+impl PasswordSlot {
+    fn kdf(&self) -> Result<&dyn Kdf> {
+        match self {
+            PasswordSlot::Scrypt(params) => Ok(params),
+            PasswordSlot::Unknown(fields) => {
+                let params: Vec<&str> = fields
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|key| !SLOT_FIELDS.contains(key))
+                    .collect();
+                Err(eyre!(
+                    "Unsupported KDF in password slot with parameters: {}",
+                    params.join(", ")
+                ))
+            }
+        }
+    }
+}
+
 /// Master key decryption slot types supported by Aegis
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
@@ -58,28 +327,78 @@ pub struct Header {
     params: Option<KeyParams>,
 }
 
+impl Header {
+    pub fn slot_descriptions(&self) -> Vec<String> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|slot| match &slot.slot_type {
+                SlotType::Raw => "raw".to_string(),
+                SlotType::Password(PasswordSlot::Scrypt(params)) => format!(
+                    "password (scrypt n={} r={} p={})",
+                    params.n, params.r, params.p
+                ),
+                SlotType::Password(PasswordSlot::Unknown(_)) => {
+                    "password (unsupported KDF)".to_string()
+                }
+                SlotType::Biometric => "biometric".to_string(),
+            })
+            .collect()
+    }
+
+    /// Problems of the encryption parameters which weaken the vault, e.g. reused nonces
+    ///
+    /// Such vaults still open, some third-party tools have written them.
+    pub fn weaknesses(&self) -> Vec<String> {
+        let mut weaknesses = Vec::new();
+        let slots = self.slots.iter().flatten();
+        let nonces: Vec<&str> = slots
+            .clone()
+            .map(|slot| &slot.key_params)
+            .chain(&self.params)
+            .map(|params| params.nonce.as_str())
+            .collect();
+        for (i, nonce) in nonces.iter().enumerate() {
+            let uses = nonces.iter().filter(|other| *other == nonce).count();
+            if uses > 1 && !nonces[..i].contains(nonce) {
+                weaknesses.push(format!(
+                    "Nonce {} is used {} times, which can reveal the keys",
+                    nonce, uses
+                ));
+            }
+        }
+        for (i, slot) in slots.enumerate() {
+            if let SlotType::Password(PasswordSlot::Scrypt(params)) = &slot.slot_type {
+                if params.n < SCRYPT_MIN_N {
+                    weaknesses.push(format!(
+                        "Slot {} uses scrypt n={}, less than {} makes guessing the password cheap",
+                        i + 1,
+                        params.n,
+                        SCRYPT_MIN_N
+                    ));
+                }
+                if params.salt.is_empty() {
+                    weaknesses.push(format!(
+                        "Slot {} has an empty salt, its password can be attacked with precomputed tables",
+                        i + 1
+                    ));
+                }
+            }
+        }
+        weaknesses
+    }
+}
+
 enum DecryptionError {
     IncorrectPassword,
     ParamError(String),
 }
 
-/// Derive master key from password
-fn derive_key(password: &[u8], slot: &PasswordSlot) -> Result<Output> {
-    let salt_bytes =
-        Vec::from_hex(&slot.salt).map_err(|e| eyre!("Failed to decode salt hex: {}", e))?;
-    let salt = SaltString::encode_b64(&salt_bytes)?;
-
-    let n = (slot.n as f32).log2() as u8;
-    let scrypt_params = scrypt::Params::new(n, slot.r, slot.p, 32)?;
-    let derived_key =
-        Scrypt.hash_password_customized(password, None, None, scrypt_params, &salt)?;
-
-    derived_key
-        .hash
-        .ok_or(eyre!("Failed to get hash of derived key"))
-}
-
-fn decrypt_master_key(password: &str, slot: &Slot) -> Result<Vec<u8>, DecryptionError> {
+fn decrypt_master_key(
+    password: &str,
+    slot: &Slot,
+    options: &DecryptOptions,
+) -> Result<Zeroizing<Vec<u8>>, DecryptionError> {
     let password_slot = match &slot.slot_type {
         SlotType::Password(slot) => slot,
         _ => {
@@ -88,35 +407,43 @@ fn decrypt_master_key(password: &str, slot: &Slot) -> Result<Vec<u8>, Decryption
             ))
         }
     };
-    let derived_key = derive_key(password.as_bytes(), password_slot)
+    let kdf = password_slot
+        .kdf()
+        .map_err(|e| DecryptionError::ParamError(e.to_string()))?;
+    let derived_key = kdf
+        .derive_key(password.as_bytes(), options)
         .map_err(|e| DecryptionError::ParamError(format!("Failed to derive key: {}", e)))?;
 
-    let key_nonce = Vec::from_hex(&slot.key_params.nonce)
-        .map_err(|_| DecryptionError::ParamError("Failed to decode nonce".to_string()))?;
-
-    let mut master_key_cipher = Vec::from_hex(&slot.key)
-        .map_err(|_| DecryptionError::ParamError("Failed to decode master key cipher".to_string()))?
-        .to_vec();
-    master_key_cipher.extend_from_slice(
-        &Vec::from_hex(&slot.key_params.tag)
-            .map_err(|_| DecryptionError::ParamError("Failed to decode tag".to_string()))?,
-    );
+    let mut master_key = Zeroizing::new(Vec::from_hex(&slot.key).map_err(|_| {
+        DecryptionError::ParamError("Failed to decode master key cipher".to_string())
+    })?);
 
     // Decrypt master key
-    let mut cipher = Aes256Gcm::new(derived_key.as_bytes().into());
-    cipher
-        .decrypt(Nonce::from_slice(&key_nonce), master_key_cipher.as_ref())
-        .map_err(|_| DecryptionError::IncorrectPassword)
+    match decrypt_in_place(&derived_key, &slot.key_params, &mut master_key) {
+        Ok(true) => Ok(master_key),
+        Ok(false) => Err(DecryptionError::IncorrectPassword),
+        Err(e) => Err(DecryptionError::ParamError(e.to_string())),
+    }
 }
 
-fn try_decrypt_master_key(password: &str, slots: &[Slot]) -> Result<Vec<u8>> {
+fn try_decrypt_master_key(
+    password: &str,
+    slots: &[Slot],
+    options: &DecryptOptions,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let mut param_errors = Vec::new();
+
     // Only password based master key decryptions are supported
     for slot in slots
         .iter()
         .filter(|s| matches!(s.slot_type, SlotType::Password(_)))
         .collect::<Vec<&Slot>>()
     {
-        let master_key = match decrypt_master_key(password, slot) {
+        // Not every slot is tried again after the derivation of one was cancelled
+        if let Some(cancel) = &options.cancel {
+            cancel.check("Key derivation")?;
+        }
+        let master_key = match decrypt_master_key(password, slot, options) {
             Ok(key) => key,
             Err(DecryptionError::IncorrectPassword) => {
                 // Either the password is incorrect or the slot is not a password slot
@@ -124,7 +451,7 @@ fn try_decrypt_master_key(password: &str, slots: &[Slot]) -> Result<Vec<u8>> {
                 continue;
             }
             Err(DecryptionError::ParamError(e)) => {
-                eprintln!("{}", e);
+                param_errors.push(e);
                 continue;
             }
         };
@@ -132,6 +459,17 @@ fn try_decrypt_master_key(password: &str, slots: &[Slot]) -> Result<Vec<u8>> {
         return Ok(master_key);
     }
 
+    let password_slots = slots
+        .iter()
+        .filter(|s| matches!(s.slot_type, SlotType::Password(_)))
+        .count();
+    if !param_errors.is_empty() && param_errors.len() == password_slots {
+        // No slot could even be tried with the password
+        return Err(eyre!(
+            "Failed to decrypt master key: {}",
+            param_errors.join(", ")
+        ));
+    }
     Err(eyre!("Failed to decrypt master key"))
 }
 
@@ -143,40 +481,269 @@ fn try_decrypt_master_key(password: &str, slots: &[Slot]) -> Result<Vec<u8>> {
 /// * `encrypted_db` - AES-GCM encrypted database in base64
 /// # Returns
 /// * Decrypted database
-fn decrypt_database(
-    params: &KeyParams,
-    master_key: &Vec<u8>,
-    encrypted_db: &str,
-) -> Result<Database> {
-    // Prepare database cipher
-    let db_contents_cipher = general_purpose::STANDARD.decode(encrypted_db)?;
-    let mut db_cipher: Vec<u8> = db_contents_cipher;
-    let db_tag = Vec::from_hex(&params.tag)?;
-    db_cipher.extend_from_slice(&db_tag);
-
-    // Decrypt database
-    let mut aes_context = Aes256Gcm::new(master_key.as_slice().into());
-    let db_nonce = Vec::from_hex(&params.nonce)?;
-    let db_contents = aes_context
-        .decrypt(Nonce::from_slice(&db_nonce), db_cipher.as_ref())
-        .map_err(|e| eyre!("Failed to decrypt database: {}", e))?;
-
-    // Parse database from string
-    let db_contents_str = String::from_utf8(db_contents)?;
-    let db: Database = serde_json::from_str(&db_contents_str)?;
+fn decrypt_database(params: &KeyParams, master_key: &[u8], encrypted_db: &str) -> Result<Database> {
+    // The decoded buffer is decrypted in place and parsed without further copies
+    let mut db_contents = Zeroizing::new(general_purpose::STANDARD.decode(encrypted_db)?);
+    if !decrypt_in_place(master_key, params, &mut db_contents)? {
+        return Err(eyre!("Failed to decrypt database"));
+    }
 
+    let db: Database = serde_json::from_slice(&db_contents)?;
     Ok(db)
 }
 
-pub fn decrypt(password: &str, vault: Vault) -> Result<Database> {
+/// Decrypted master key of a vault
+///
+/// The key itself isn't exposed, only keys derived from it for data kept outside of the vault.
+pub struct MasterKey(Zeroizing<Vec<u8>>);
+
+impl MasterKey {
+    /// Key given in hex, e.g. the master key or the key of a raw or biometric slot read from the
+    /// Android keystore of a rooted phone
+    pub fn from_hex(key: &str) -> Result<MasterKey> {
+        let key = Zeroizing::new(
+            Vec::from_hex(key.trim()).map_err(|e| eyre!("The key is not valid hex: {}", e))?,
+        );
+        if key.len() != 32 {
+            return Err(eyre!("The key has {} bytes instead of 32", key.len()));
+        }
+        Ok(MasterKey(key))
+    }
+
+    /// New random key, for a new vault
+    pub(crate) fn generate() -> MasterKey {
+        let mut key = Zeroizing::new(vec![0u8; 32]);
+        OsRng.fill_bytes(&mut key);
+        MasterKey(key)
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Key for `purpose`, derived with HMAC-SHA256
+    pub(crate) fn derive(&self, purpose: &str) -> Zeroizing<Vec<u8>> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0)
+            .expect("HMAC accepts keys of any length");
+        mac.update(purpose.as_bytes());
+        Zeroizing::new(mac.finalize().into_bytes().to_vec())
+    }
+
+    /// Identifier of the key which reveals nothing about it, the same for every backup of a
+    /// vault as long as Aegis keeps its master key
+    pub fn id(&self) -> String {
+        hex::encode(&self.derive("aegis-rs key id")[..8])
+    }
+}
+
+pub fn decrypt(
+    password: &str,
+    vault: Vault,
+    options: &DecryptOptions,
+) -> Result<(Database, MasterKey)> {
     let slots = vault.header.slots.ok_or(eyre!("No slots in header"))?;
     let params = vault.header.params.ok_or(eyre!("No params in header"))?;
-    let master_key = try_decrypt_master_key(password, &slots)?;
+    let master_key = MasterKey(try_decrypt_master_key(password, &slots, options)?);
+
+    let encrypted_db = match vault.db {
+        VaultDatabase::Encrypted(db) => db,
+        _ => return Err(eyre!("Database in vault is not encrypted")),
+    };
+
+    let db = decrypt_database(&params, &master_key.0, &encrypted_db)?;
+    Ok((db, master_key))
+}
 
+/// Decrypt the database with a known key instead of a password
+///
+/// `key` is the master key itself or the key of a raw or biometric slot, which Aegis skips
+/// otherwise as they can only be unlocked on the phone.
+pub fn decrypt_with_key(key: &MasterKey, vault: Vault) -> Result<(Database, MasterKey)> {
+    let params = vault.header.params.ok_or(eyre!("No params in header"))?;
     let encrypted_db = match vault.db {
         VaultDatabase::Encrypted(db) => db,
         _ => return Err(eyre!("Database in vault is not encrypted")),
     };
+    let mut candidates = vec![Zeroizing::new(key.0.to_vec())];
+    for slot in vault.header.slots.iter().flatten() {
+        if !matches!(slot.slot_type, SlotType::Raw | SlotType::Biometric) {
+            continue;
+        }
+        let mut master_key = match Vec::from_hex(&slot.key) {
+            Ok(cipher) => Zeroizing::new(cipher),
+            Err(_) => continue,
+        };
+        if let Ok(true) = decrypt_in_place(&key.0, &slot.key_params, &mut master_key) {
+            candidates.push(master_key);
+        }
+    }
+    for candidate in candidates {
+        if let Ok(db) = decrypt_database(&params, &candidate, &encrypted_db) {
+            return Ok((db, MasterKey(candidate)));
+        }
+    }
+    Err(eyre!(
+        "The key is neither the master key nor the key of a raw or biometric slot"
+    ))
+}
 
-    decrypt_database(&params, &master_key, &encrypted_db)
+/// Data encrypted with a password, with a header like the one of vaults
+#[derive(Deserialize)]
+struct Sealed {
+    version: u32,
+    header: Header,
+    /// AES-GCM encrypted data in base64
+    data: String,
+}
+
+/// Encrypt `data` with a password, the same way vaults are encrypted
+pub fn seal(password: &str, data: &[u8], options: &DecryptOptions) -> Result<String> {
+    let master_key = MasterKey::generate();
+    let slot = new_password_slot(password, &master_key, options)?;
+    let mut buffer = Zeroizing::new(data.to_vec());
+    let params = encrypt_in_place(master_key.key(), &mut buffer)?;
+    Ok(json!({
+        "version": 1,
+        "header": { "slots": [slot], "params": params },
+        "data": general_purpose::STANDARD.encode(&buffer),
+    })
+    .to_string())
+}
+
+/// Decrypt data encrypted with [seal]
+pub fn unseal(
+    contents: &str,
+    password_getter: impl PasswordGetter,
+    options: &DecryptOptions,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let sealed: Sealed = serde_json::from_str(contents)?;
+    if sealed.version != 1 {
+        return Err(eyre!("Unsupported version {}", sealed.version));
+    }
+    let slots = sealed.header.slots.ok_or(eyre!("No slots in header"))?;
+    let params = sealed.header.params.ok_or(eyre!("No params in header"))?;
+    let password = Zeroizing::new(password_getter.get_password()?);
+    let master_key = try_decrypt_master_key(&password, &slots, options)?;
+    let mut data = Zeroizing::new(general_purpose::STANDARD.decode(&sealed.data)?);
+    if !decrypt_in_place(&master_key, &params, &mut data)? {
+        return Err(eyre!("Failed to decrypt data"));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::Result;
+
+    use serde_json::{json, Value};
+
+    use super::{
+        decrypt, decrypt_with_key, encrypt_in_place, seal, unseal, MasterKey, ScryptParams,
+    };
+    use crate::vault::{DecryptOptions, PasswordGetter, Vault};
+
+    struct TestPassword(&'static str);
+
+    impl PasswordGetter for TestPassword {
+        fn get_password(&self) -> Result<String> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    fn params(n: u32, r: u32, p: u32) -> ScryptParams {
+        ScryptParams {
+            n,
+            r,
+            p,
+            salt: String::new(),
+        }
+    }
+
+    #[test]
+    fn scrypt_log_n() {
+        assert_eq!(params(2, 8, 1).log_n().unwrap(), 1);
+        assert_eq!(params(32768, 8, 1).log_n().unwrap(), 15);
+        assert_eq!(params(1 << 22, 1, 1).log_n().unwrap(), 22);
+    }
+
+    #[test]
+    fn scrypt_invalid_params() {
+        // Not a power of two, these were truncated to a nearby power of two before
+        assert!(params(32767, 8, 1).log_n().is_err());
+        assert!(params(40000, 8, 1).log_n().is_err());
+        assert!(params(0, 8, 1).log_n().is_err());
+        assert!(params(1, 8, 1).log_n().is_err());
+
+        assert!(params(32768, 0, 1).log_n().is_err());
+        assert!(params(32768, 33, 1).log_n().is_err());
+        assert!(params(32768, 8, 0).log_n().is_err());
+        assert!(params(32768, 8, 17).log_n().is_err());
+        assert!(params(1 << 31, 8, 1).log_n().is_err());
+    }
+
+    #[test]
+    fn scrypt_threads_within_memory() {
+        let options = |threads, max_memory| DecryptOptions {
+            kdf_threads: Some(threads),
+            max_kdf_memory: max_memory,
+            ..Default::default()
+        };
+        // 1 MiB for each thread
+        let lanes = params(1 << 10, 8, 16);
+        assert_eq!(lanes.threads(&options(16, None)).unwrap(), 16);
+        assert_eq!(lanes.threads(&options(32, None)).unwrap(), 16);
+        assert_eq!(lanes.threads(&options(16, Some(4 << 20))).unwrap(), 4);
+        assert_eq!(lanes.threads(&options(2, Some(4 << 20))).unwrap(), 2);
+        assert!(lanes.threads(&options(16, Some(1 << 19))).is_err());
+
+        // The fixed limit counts every thread too
+        let large = params(1 << 20, 32, 16);
+        assert_eq!(large.threads(&options(16, None)).unwrap(), 1);
+    }
+
+    #[test]
+    fn seal_round_trip() {
+        let options = DecryptOptions::default();
+        let sealed = seal("secret", b"settings", &options).unwrap();
+        assert!(!sealed.contains("settings"));
+        let data = unseal(&sealed, TestPassword("secret"), &options).unwrap();
+        assert_eq!(&data[..], b"settings");
+
+        let e = unseal(&sealed, TestPassword("wrong"), &options).unwrap_err();
+        assert_eq!(e.to_string(), "Failed to decrypt master key");
+    }
+
+    #[test]
+    fn raw_keys() {
+        let contents = include_str!("../../res/aegis_encrypted.json");
+        let options = DecryptOptions::default();
+        let vault = Vault::parse(contents).unwrap();
+        let (_, master_key) = decrypt("test", vault, &options).unwrap();
+        let hex_key = hex::encode(master_key.key());
+
+        let key = MasterKey::from_hex(&hex_key).unwrap();
+        let (db, _) = decrypt_with_key(&key, Vault::parse(contents).unwrap()).unwrap();
+        assert_eq!(db.entries.len(), 4);
+
+        // Only a raw slot holding the master key, encrypted with the key of the slot
+        let slot_key = MasterKey::generate();
+        let mut cipher = master_key.key().to_vec();
+        let key_params = encrypt_in_place(slot_key.key(), &mut cipher).unwrap();
+        let mut value: Value = serde_json::from_str(contents).unwrap();
+        value["header"]["slots"] = json!([{
+            "type": 0,
+            "uuid": "1a0ae9e7-4bd7-4e3b-8a6a-1b2b8ba3ddc4",
+            "key": hex::encode(cipher),
+            "key_params": key_params,
+        }]);
+        let vault = Vault::parse(&value.to_string()).unwrap();
+        let (db, unlocked_key) = decrypt_with_key(&slot_key, vault).unwrap();
+        assert_eq!(db.entries.len(), 4);
+        assert_eq!(unlocked_key.id(), master_key.id());
+
+        let vault = Vault::parse(&value.to_string()).unwrap();
+        assert!(decrypt_with_key(&MasterKey::generate(), vault).is_err());
+        assert!(MasterKey::from_hex("00ff").is_err());
+        assert!(MasterKey::from_hex("not hex").is_err());
+    }
 }
@@ -0,0 +1,196 @@
+use color_eyre::eyre::{eyre, Result};
+use pbkdf2::pbkdf2_hmac;
+use salsa20::{
+    cipher::{typenum::U4, StreamCipherCore},
+    SalsaCore,
+};
+use sha2::Sha256;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+use zeroize::Zeroizing;
+
+type Salsa20_8 = SalsaCore<U4>;
+
+/// scrypt as specified in [RFC 7914](https://datatracker.ietf.org/doc/html/rfc7914)
+///
+/// The `p` independent ROMix lanes are spread over up to `threads` threads. Every thread
+/// allocates its own `128 * r * 2^log_n` bytes of scratch memory. Setting `cancel` stops all
+/// lanes early and results in an error.
+#[allow(clippy::too_many_arguments)]
+pub fn scrypt(
+    password: &[u8],
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+    threads: usize,
+    cancel: Option<&AtomicBool>,
+    output: &mut [u8],
+) -> Result<()> {
+    if log_n == 0 || log_n >= usize::BITS as u8 || r == 0 || p == 0 {
+        return Err(eyre!("Invalid scrypt parameters"));
+    }
+    let n = 1usize << log_n;
+    let lane_len = 128 * r as usize;
+
+    // All buffers depend on the password, they are zeroized when dropped
+    let mut b = Zeroizing::new(vec![0u8; lane_len * p as usize]);
+    pbkdf2_hmac::<Sha256>(password, salt, 1, &mut b);
+
+    let threads = threads.clamp(1, p as usize);
+    if threads == 1 {
+        let mut scratch = Scratch::new(lane_len, n);
+        for lane in b.chunks_mut(lane_len) {
+            ro_mix(lane, &mut scratch, n, cancel);
+        }
+    } else {
+        let lanes_per_thread = (p as usize).div_ceil(threads);
+        thread::scope(|scope| {
+            for lanes in b.chunks_mut(lane_len * lanes_per_thread) {
+                scope.spawn(move || {
+                    let mut scratch = Scratch::new(lane_len, n);
+                    for lane in lanes.chunks_mut(lane_len) {
+                        ro_mix(lane, &mut scratch, n, cancel);
+                    }
+                });
+            }
+        });
+    }
+
+    if is_cancelled(cancel) {
+        return Err(eyre!("Key derivation cancelled"));
+    }
+    pbkdf2_hmac::<Sha256>(password, &b, 1, output);
+    Ok(())
+}
+
+/// Temporary buffers of a ROMix lane
+struct Scratch {
+    v: Zeroizing<Vec<u8>>,
+    t: Zeroizing<Vec<u8>>,
+}
+
+impl Scratch {
+    fn new(lane_len: usize, n: usize) -> Scratch {
+        Scratch {
+            v: Zeroizing::new(vec![0u8; lane_len * n]),
+            t: Zeroizing::new(vec![0u8; lane_len]),
+        }
+    }
+}
+
+/// Number of ROMix iterations between checks for cancellation
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+}
+
+/// The ROMix operation, in place on `b`
+///
+/// Returns early, leaving `b` in an unusable state, once `cancel` is set.
+fn ro_mix(b: &mut [u8], scratch: &mut Scratch, n: usize, cancel: Option<&AtomicBool>) {
+    let len = b.len();
+    let Scratch { v, t } = scratch;
+
+    for (i, chunk) in v.chunks_mut(len).enumerate() {
+        if i % CANCEL_CHECK_INTERVAL == 0 && is_cancelled(cancel) {
+            return;
+        }
+        chunk.copy_from_slice(b);
+        block_mix(chunk, b);
+    }
+
+    for i in 0..n {
+        if i % CANCEL_CHECK_INTERVAL == 0 && is_cancelled(cancel) {
+            return;
+        }
+        let j = integerify(b, n);
+        xor(b, &v[j * len..(j + 1) * len], t);
+        block_mix(t, b);
+    }
+}
+
+/// Interpret the last 64 byte block as a little endian integer, modulo `n`
+fn integerify(x: &[u8], n: usize) -> usize {
+    let block = &x[x.len() - 64..];
+    let value = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+    value as usize & (n - 1)
+}
+
+/// The BlockMix operation with Salsa20/8 as hash function
+fn block_mix(input: &[u8], output: &mut [u8]) {
+    let mut x = [0u8; 64];
+    x.copy_from_slice(&input[input.len() - 64..]);
+    let mut t = [0u8; 64];
+
+    for (i, chunk) in input.chunks(64).enumerate() {
+        xor(&x, chunk, &mut t);
+
+        let mut state = [0u32; 16];
+        for (word, bytes) in state.iter_mut().zip(t.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Salsa20_8::from_raw_state(state).write_keystream_block((&mut x).into());
+
+        // Even blocks go to the first half of the output, odd blocks to the second
+        let pos = if i % 2 == 0 {
+            (i / 2) * 64
+        } else {
+            (i / 2) * 64 + input.len() / 2
+        };
+        output[pos..pos + 64].copy_from_slice(&x);
+    }
+}
+
+fn xor(x: &[u8], y: &[u8], output: &mut [u8]) {
+    for ((out, x), y) in output.iter_mut().zip(x).zip(y) {
+        *out = x ^ y;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicBool;
+
+    use super::scrypt;
+
+    #[test]
+    fn rfc7914_test_vectors() {
+        let mut output = [0u8; 64];
+        scrypt(b"", b"", 4, 1, 1, 1, None, &mut output).unwrap();
+        assert_eq!(
+            hex::encode(output),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+
+        for threads in [1, 4] {
+            scrypt(b"password", b"NaCl", 10, 8, 16, threads, None, &mut output).unwrap();
+            assert_eq!(
+                hex::encode(output),
+                "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+                 2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+            );
+        }
+    }
+
+    #[test]
+    fn cancelled() {
+        let mut output = [0u8; 64];
+        let cancel = AtomicBool::new(true);
+        let e = scrypt(
+            b"password",
+            b"NaCl",
+            10,
+            8,
+            16,
+            4,
+            Some(&cancel),
+            &mut output,
+        );
+        assert_eq!(e.unwrap_err().to_string(), "Key derivation cancelled");
+    }
+}
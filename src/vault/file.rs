@@ -0,0 +1,356 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// First bytes of gzip streams
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Contents of a vault file
+///
+/// Large backups with many icons can be memory mapped instead of being copied into memory. Where
+/// mapping is unavailable, e.g. on pipes, empty files or non unix platforms, the file is read.
+pub enum VaultFile {
+    #[cfg(unix)]
+    Mapped(mmap::Mmap),
+    Buffered(Vec<u8>),
+}
+
+impl VaultFile {
+    /// Read the file at `path`, memory mapped if `mmap` is set and mapping is possible
+    pub fn open(path: &Path, mmap: bool) -> Result<VaultFile> {
+        #[cfg(unix)]
+        if mmap {
+            if let Ok(mapped) = mmap::Mmap::open(path) {
+                return Ok(VaultFile::Mapped(mapped));
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = mmap;
+
+        Ok(VaultFile::Buffered(fs::read(path)?))
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self, VaultFile::Buffered(_))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            #[cfg(unix)]
+            VaultFile::Mapped(mapped) => mapped.as_bytes(),
+            VaultFile::Buffered(contents) => contents,
+        }
+    }
+
+    /// Contents as UTF-8, which vault JSON must be
+    pub fn as_str(&self) -> Result<&str> {
+        std::str::from_utf8(self.as_bytes()).map_err(|e| eyre!("Vault file is not UTF-8: {}", e))
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.as_bytes().starts_with(&GZIP_MAGIC)
+    }
+
+    /// Contents of gzip compressed exports decompressed, other files are returned as they are
+    pub fn decompressed(self) -> Result<VaultFile> {
+        if !self.is_compressed() {
+            return Ok(self);
+        }
+        let mut contents = Vec::new();
+        flate2::read::MultiGzDecoder::new(self.as_bytes())
+            .read_to_end(&mut contents)
+            .map_err(|e| eyre!("Failed to decompress the vault file: {}", e))?;
+        Ok(VaultFile::Buffered(contents))
+    }
+}
+
+/// Vault JSON of an export split into the header and the database
+///
+/// `header` is the header object, or a vault whose header is taken. `db` is the plain database
+/// JSON, or the encrypted database as base64 text, a JSON string or the raw ciphertext.
+pub fn join_split_vault(header: &[u8], db: &[u8]) -> Result<String> {
+    let header: Value =
+        serde_json::from_slice(header).map_err(|e| eyre!("The header file is not JSON: {}", e))?;
+    let header = match header.get("header") {
+        Some(header) => header.clone(),
+        None => header,
+    };
+    if !header.is_object() {
+        return Err(eyre!("The header file holds no header object"));
+    }
+    let text = std::str::from_utf8(db).map(str::trim);
+    let db = match text {
+        Ok(text) if text.starts_with(['{', '"']) => serde_json::from_str(text)
+            .map_err(|e| eyre!("The database file is not valid JSON: {}", e))?,
+        Ok(text) if general_purpose::STANDARD.decode(text).is_ok() => Value::from(text),
+        _ => Value::from(general_purpose::STANDARD.encode(db)),
+    };
+    Ok(json!({"version": 1, "header": header, "db": db}).to_string())
+}
+
+/// Replace the file at `path` with `contents`, without a window in which it is incomplete
+///
+/// The contents are written to a temporary file next to it, which is then renamed. The
+/// permissions of an existing file are kept, new files are only readable by the user.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, false)
+}
+
+/// Like [write_atomic], but the file is only readable by the user even if it was readable by
+/// others before, for files holding secrets
+///
+/// The temporary file is restricted before anything is written to it, so the contents are never
+/// readable by others.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, true)
+}
+
+fn replace(path: &Path, contents: &[u8], private: bool) -> Result<()> {
+    let name = path
+        .file_name()
+        .ok_or(eyre!("{} is not a file path", path.display()))?;
+    let mut temporary_name = std::ffi::OsString::from(".");
+    temporary_name.push(name);
+    temporary_name.push(".tmp");
+    let temporary = path.with_file_name(temporary_name);
+
+    let result = (|| {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temporary)?;
+        match (private, fs::metadata(path)) {
+            // A temporary file left behind keeps its permissions when opened
+            #[cfg(unix)]
+            (true, _) => {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(fs::Permissions::from_mode(0o600))?
+            }
+            (false, Ok(metadata)) => file.set_permissions(metadata.permissions())?,
+            _ => {}
+        }
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temporary);
+        return Err(eyre!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+/// Create the directory at `path` and its missing parents, only accessible by the user
+///
+/// Existing directories which others can access are restricted, ones owned by another user are
+/// refused, as they might have been created to read or replace the files put there.
+pub fn create_private_dir(path: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(path)
+        .map_err(|e| eyre!("Failed to create directory {}: {}", path.display(), e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let metadata = fs::symlink_metadata(path)?;
+        // SAFETY: geteuid has no preconditions and can't fail
+        let uid = unsafe { libc::geteuid() };
+        if !metadata.is_dir() || metadata.uid() != uid {
+            return Err(eyre!(
+                "{} is not a directory owned by the current user",
+                path.display()
+            ));
+        }
+        if metadata.mode() & 0o077 != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o700)).map_err(|e| {
+                eyre!(
+                    "Failed to restrict permissions of {}: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod mmap {
+    use std::{fs::File, io, os::unix::io::AsRawFd, path::Path, ptr, slice};
+
+    /// Read only private mapping of a whole file
+    pub struct Mmap {
+        ptr: *mut libc::c_void,
+        len: usize,
+    }
+
+    impl Mmap {
+        pub fn open(path: &Path) -> io::Result<Mmap> {
+            let file = File::open(path)?;
+            let metadata = file.metadata()?;
+            if !metadata.is_file() || metadata.len() == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Only non-empty regular files can be mapped",
+                ));
+            }
+            let len = usize::try_from(metadata.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::Unsupported, "File is too large"))?;
+
+            // SAFETY: A fresh mapping is requested for a valid file descriptor, the descriptor
+            // may be closed afterwards as the mapping keeps its own reference
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Mmap { ptr, len })
+        }
+
+        pub fn as_bytes(&self) -> &[u8] {
+            // SAFETY: The mapping is valid for `len` bytes until dropped. Truncating the file
+            // while it's mapped is not guarded against, just like with any other mmap reader.
+            unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for Mmap {
+        fn drop(&mut self) {
+            // SAFETY: `ptr` and `len` describe a mapping created in `open`
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, path::Path};
+
+    use super::{create_private_dir, join_split_vault, write_atomic, write_private, VaultFile};
+    use crate::vault::parse_vault_with_password;
+
+    #[test]
+    fn mapped_and_buffered_contents_match() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res/aegis_plain.json"));
+        let mapped = VaultFile::open(path, true).unwrap();
+        let buffered = VaultFile::open(path, false).unwrap();
+        assert_eq!(mapped.is_mapped(), cfg!(unix));
+        assert!(!buffered.is_mapped());
+        assert_eq!(mapped.as_str().unwrap(), buffered.as_str().unwrap());
+        assert_eq!(
+            buffered.as_str().unwrap(),
+            include_str!("../../res/aegis_plain.json")
+        );
+    }
+
+    #[test]
+    fn split_and_compressed_vaults() {
+        use base64::{engine::general_purpose, Engine as _};
+        use serde_json::Value;
+        use std::io::Write;
+
+        let vault = include_str!("../../res/aegis_encrypted.json");
+        let value: Value = serde_json::from_str(vault).unwrap();
+        let header = value["header"].to_string();
+        let db = value["db"].as_str().unwrap();
+        let ciphertext = general_purpose::STANDARD.decode(db).unwrap();
+        for db in [
+            db.as_bytes(),
+            value["db"].to_string().as_bytes(),
+            &ciphertext,
+        ] {
+            let joined = join_split_vault(header.as_bytes(), db).unwrap();
+            assert_eq!(parse_vault_with_password(&joined, "test").unwrap().len(), 4);
+        }
+        // A whole vault works as the header file
+        let joined = join_split_vault(vault.as_bytes(), db.as_bytes()).unwrap();
+        assert_eq!(parse_vault_with_password(&joined, "test").unwrap().len(), 4);
+
+        let plain: Value =
+            serde_json::from_str(include_str!("../../res/aegis_plain.json")).unwrap();
+        let joined = join_split_vault(
+            plain["header"].to_string().as_bytes(),
+            plain["db"].to_string().as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(parse_vault_with_password(&joined, "").unwrap().len(), 4);
+        assert!(join_split_vault(b"[]", db.as_bytes()).is_err());
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(vault.as_bytes()).unwrap();
+        let compressed = VaultFile::Buffered(encoder.finish().unwrap());
+        assert_eq!(compressed.decompressed().unwrap().as_str().unwrap(), vault);
+        let uncompressed = VaultFile::Buffered(vault.as_bytes().to_vec());
+        assert_eq!(
+            uncompressed.decompressed().unwrap().as_str().unwrap(),
+            vault
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_dirs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let root = env::temp_dir().join(format!("aegis-rs-private-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        create_private_dir(&nested).unwrap();
+        assert_eq!(mode(&nested), 0o700);
+        assert_eq!(mode(&root.join("a")), 0o700);
+
+        fs::set_permissions(&nested, fs::Permissions::from_mode(0o755)).unwrap();
+        create_private_dir(&nested).unwrap();
+        assert_eq!(mode(&nested), 0o700);
+
+        let file = root.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(create_private_dir(&file).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_private_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let root = env::temp_dir().join(format!("aegis-rs-replace-{}", std::process::id()));
+        create_private_dir(&root).unwrap();
+        let path = root.join("config.json");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_atomic(&path, b"kept").unwrap();
+        assert_eq!(mode(&path), 0o644);
+
+        // Neither the old file nor a temporary file left behind lend it their permissions
+        let temporary = root.join(".config.json.tmp");
+        fs::write(&temporary, "").unwrap();
+        fs::set_permissions(&temporary, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, b"secret").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(fs::read(&path).unwrap(), b"secret");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
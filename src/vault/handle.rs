@@ -0,0 +1,154 @@
+use color_eyre::eyre::Result;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::vault::{Database, LazyEntry, NewEntry};
+
+/// Decrypted database shared between threads, clones share the same database
+///
+/// Every change holds the lock for writing and is made completely or not at all, even if it
+/// fails or panics, so readers never see half of one. Writing
+/// the vault file is left to the owner, e.g. with [crate::vault::write_vault] on a
+/// [VaultHandle::snapshot].
+#[derive(Debug, Clone)]
+pub struct VaultHandle(Arc<RwLock<Database>>);
+
+impl VaultHandle {
+    pub fn new(db: Database) -> VaultHandle {
+        VaultHandle(Arc::new(RwLock::new(db)))
+    }
+
+    /// The database to read, changes wait until the guard is dropped
+    pub fn read(&self) -> RwLockReadGuard<'_, Database> {
+        // Changes are complete or not made when they fail, a panic leaves nothing half done, see
+        // Database::edit_entry
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Database> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add an entry with a new UUID, returns the UUID
+    pub fn add_entry(&self, entry: NewEntry) -> Result<String> {
+        self.write().add_entry(entry)
+    }
+
+    /// Change the entry with the UUID with `edit`, see [Database::edit_entry]
+    pub fn edit_entry<T>(
+        &self,
+        uuid: &str,
+        edit: impl FnOnce(&mut LazyEntry) -> Result<T>,
+    ) -> Result<T> {
+        self.write().edit_entry(uuid, edit)
+    }
+
+    /// Remove the entry with the UUID, returns it
+    pub fn remove_entry(&self, uuid: &str) -> Result<LazyEntry> {
+        self.write().remove_entry(uuid)
+    }
+
+    /// Copy of the database as it is now, e.g. for writing it to the vault file
+    pub fn snapshot(&self) -> Result<Database> {
+        self.read().subset(|_| true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::{eyre, Result};
+    use std::thread;
+
+    use super::VaultHandle;
+    use crate::{
+        otp::{EntryInfo, EntryInfoTotp, HashAlgorithm},
+        vault::{NewEntry, Selector, Vault},
+    };
+
+    #[test]
+    fn share_database() {
+        let vault = Vault::parse(include_str!("../../res/aegis_plain.json")).unwrap();
+        let handle = VaultHandle::new(
+            vault
+                .unlock(|| unreachable!(), &Default::default())
+                .unwrap(),
+        );
+
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    handle
+                        .add_entry(NewEntry {
+                            name: format!("Thread {}", i),
+                            issuer: "ACME".to_string(),
+                            info: EntryInfo::Totp(EntryInfoTotp {
+                                secret: "JBSWY3DPEHPK3PXP".to_string(),
+                                algo: HashAlgorithm::Sha1,
+                                digits: 6,
+                                period: 30,
+                            }),
+                            icon: None,
+                        })
+                        .unwrap()
+                })
+            })
+            .collect();
+        let uuids: Vec<String> = writers.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(handle.read().entries.len(), 8);
+
+        let snapshot = handle.snapshot().unwrap();
+        handle
+            .edit_entry(&uuids[0], |entry| entry.set_issuer("Renamed"))
+            .unwrap();
+        let renamed = handle
+            .read()
+            .find(&Selector::Issuer("Renamed".to_string()))
+            .len();
+        assert_eq!(renamed, 1);
+        assert_eq!(handle.remove_entry(&uuids[1]).unwrap().name(), "Thread 1");
+        assert!(handle.remove_entry(&uuids[1]).is_err());
+        assert_eq!(handle.read().entries.len(), 7);
+        // The snapshot doesn't change with the handle
+        assert_eq!(snapshot.entries.len(), 8);
+        assert!(snapshot
+            .find(&Selector::Issuer("Renamed".to_string()))
+            .is_empty());
+    }
+
+    #[test]
+    fn failed_edits_change_nothing() {
+        let vault = Vault::parse(include_str!("../../res/aegis_plain.json")).unwrap();
+        let handle = VaultHandle::new(
+            vault
+                .unlock(|| unreachable!(), &Default::default())
+                .unwrap(),
+        );
+        let uuid = handle.read().entries[0].uuid().unwrap().to_string();
+        let renamed = |handle: &VaultHandle| {
+            handle
+                .read()
+                .find(&Selector::Issuer("Half done".to_string()))
+                .len()
+        };
+
+        let failed = handle.edit_entry(&uuid, |entry| {
+            entry.set_issuer("Half done")?;
+            Err::<(), _>(eyre!("second change failed"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(renamed(&handle), 0);
+
+        let panicking = handle.clone();
+        let uuid_copy = uuid.clone();
+        let panicked = thread::spawn(move || {
+            panicking.edit_entry(&uuid_copy, |entry| -> Result<()> {
+                entry.set_issuer("Half done")?;
+                panic!("edit panicked")
+            })
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert_eq!(renamed(&handle), 0);
+        assert_eq!(handle.read().entries[0].issuer(), "Deno");
+    }
+}
@@ -0,0 +1,373 @@
+use color_eyre::eyre::{eyre, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, sync::OnceLock};
+
+use crate::vault::{
+    attachment, crypto, Attachment, Database, Group, LazyEntry, MergeReport, NewEntry, RawEntry,
+};
+
+/// Change of one entry, planned before it is applied
+///
+/// Serialized without secrets, icons or attachment contents, so plans can be printed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Op {
+    /// UUID of the entry, only entries merged from old vaults may have none
+    pub uuid: Option<String>,
+    /// Issuer and name of the entry for messages
+    #[serde(rename = "entry")]
+    pub label: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    AddEntry {
+        #[serde(rename = "type")]
+        entry_type: String,
+        #[serde(skip)]
+        entry: LazyEntry,
+    },
+    SetIssuer {
+        issuer: String,
+    },
+    SetCounter {
+        counter: u64,
+    },
+    SetIcon {
+        mime: String,
+        #[serde(skip)]
+        data: Vec<u8>,
+    },
+    SetAttachments {
+        /// Names of all attachments the entry has afterwards
+        names: Vec<String>,
+        #[serde(skip)]
+        attachments: Vec<Attachment>,
+    },
+    RemoveEntry,
+    /// Group of version 3 databases the added entries are in, the op's UUID is the group's
+    AddGroup {
+        #[serde(skip)]
+        group: Group,
+    },
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.change {
+            Change::AddEntry { entry_type, .. } => write!(f, "Add {}, {}", self.label, entry_type),
+            Change::SetIssuer { issuer } => write!(f, "Rename {} to {}", self.label, issuer),
+            Change::SetCounter { counter } => {
+                write!(f, "Set the counter of {} to {}", self.label, counter)
+            }
+            Change::SetIcon { mime, data } => write!(
+                f,
+                "Set the icon of {} to a {} of {} bytes",
+                self.label,
+                mime,
+                data.len()
+            ),
+            Change::SetAttachments { names, .. } if names.is_empty() => {
+                write!(f, "Remove the attachments of {}", self.label)
+            }
+            Change::SetAttachments { names, .. } => write!(
+                f,
+                "Set the attachments of {} to {}",
+                self.label,
+                names.join(", ")
+            ),
+            Change::RemoveEntry => write!(f, "Remove {}", self.label),
+            Change::AddGroup { .. } => write!(f, "Add the group {}", self.label),
+        }
+    }
+}
+
+/// Changes of a database, applied all at once with [Database::apply]
+///
+/// Commands collect their changes in a plan, which can be printed instead of applied for a dry
+/// run. The changes are checked when planned where possible, so a plan rarely fails to apply.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct Plan(pub(super) Vec<Op>);
+
+impl Plan {
+    pub fn ops(&self) -> &[Op] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add an entry with a new UUID to `db`, returns the UUID
+    pub fn add_entry(&mut self, db: &Database, entry: NewEntry) -> Result<String> {
+        entry.info.validate()?;
+        let uuid = crypto::random_uuid();
+        // The type and info fields
+        let mut fields = serde_json::to_value(&entry.info)?;
+        for (key, value) in [
+            ("uuid", uuid.clone().into()),
+            ("name", entry.name.into()),
+            ("issuer", entry.issuer.into()),
+            ("note", "".into()),
+            ("favorite", false.into()),
+            ("icon", Value::Null),
+        ] {
+            fields[key] = value;
+        }
+        // Entries of version 3 databases list the UUIDs of their groups
+        if db.version >= 3 {
+            fields["groups"] = serde_json::json!([]);
+        }
+        let raw = RawEntry::new(fields.to_string())?;
+        let mut new_entry = LazyEntry {
+            summary: serde_json::from_str(raw.get())?,
+            raw,
+        };
+        if let Some((mime, data)) = &entry.icon {
+            new_entry.set_icon(mime, data)?;
+        }
+        self.push_add(new_entry);
+        Ok(uuid)
+    }
+
+    fn push_add(&mut self, entry: LazyEntry) {
+        self.0.push(Op {
+            uuid: entry.uuid().map(str::to_string),
+            label: entry.label(),
+            change: Change::AddEntry {
+                entry_type: entry.entry_type().to_string(),
+                entry,
+            },
+        });
+    }
+
+    pub fn set_issuer(&mut self, entry: &LazyEntry, issuer: &str) -> Result<()> {
+        self.push(
+            entry,
+            Change::SetIssuer {
+                issuer: issuer.to_string(),
+            },
+        )
+    }
+
+    /// Save the counter of an HOTP entry, see [LazyEntry::set_counter]
+    pub fn set_counter(&mut self, entry: &LazyEntry, counter: u64) -> Result<()> {
+        if entry.entry_type() != "hotp" {
+            return Err(eyre!("Only HOTP entries have a counter"));
+        }
+        self.push(entry, Change::SetCounter { counter })
+    }
+
+    /// Replace the icon with an image of the MIME type
+    pub fn set_icon(&mut self, entry: &LazyEntry, mime: &str, data: Vec<u8>) -> Result<()> {
+        self.push(
+            entry,
+            Change::SetIcon {
+                mime: mime.to_string(),
+                data,
+            },
+        )
+    }
+
+    /// Replace all attachments of the entry, see [LazyEntry::set_attachments]
+    pub fn set_attachments(
+        &mut self,
+        entry: &LazyEntry,
+        attachments: Vec<Attachment>,
+    ) -> Result<()> {
+        attachment::check_sizes(&attachments)?;
+        let names = attachments.iter().map(|a| a.name.clone()).collect();
+        self.push(entry, Change::SetAttachments { names, attachments })
+    }
+
+    pub fn remove_entry(&mut self, entry: &LazyEntry) -> Result<()> {
+        self.push(entry, Change::RemoveEntry)
+    }
+
+    /// Add the entries of another copy of the vault which are missing in `db`, see
+    /// [Database::merge]
+    ///
+    /// The groups of the added entries which `db` is missing are added along with them, matched
+    /// by UUID. None are if the groups of either copy don't parse.
+    pub fn merge(&mut self, db: &Database, other: Database) -> MergeReport {
+        let mut report = MergeReport::default();
+        let (mut known, other_groups) = match (db.groups(), other.groups()) {
+            (Ok(groups), Ok(other_groups)) => (
+                groups.into_iter().map(|group| group.uuid).collect(),
+                other_groups,
+            ),
+            _ => (Vec::new(), Vec::new()),
+        };
+        for entry in other.entries {
+            let existing = match entry.uuid() {
+                Some(uuid) => db.entries.iter().find(|e| e.uuid() == Some(uuid)),
+                None => db
+                    .entries
+                    .iter()
+                    .find(|e| e.uuid().is_none() && e.raw.get() == entry.raw.get()),
+            };
+            match existing {
+                None => {
+                    for uuid in entry.groups() {
+                        let Some(group) = other_groups.iter().find(|group| group.uuid == uuid)
+                        else {
+                            continue;
+                        };
+                        if known.contains(&uuid) {
+                            continue;
+                        }
+                        known.push(uuid.clone());
+                        report.groups.push(group.name.clone());
+                        self.0.push(Op {
+                            uuid: Some(uuid),
+                            label: group.name.clone(),
+                            change: Change::AddGroup {
+                                group: group.clone(),
+                            },
+                        });
+                    }
+                    report.added.push(entry.label());
+                    self.push_add(entry);
+                }
+                Some(existing) if existing.raw.get() != entry.raw.get() => {
+                    report.conflicts.push(existing.label());
+                }
+                Some(_) => {}
+            }
+        }
+        report
+    }
+
+    fn push(&mut self, entry: &LazyEntry, change: Change) -> Result<()> {
+        let uuid = entry.uuid().ok_or(eyre!("{} has no UUID", entry.label()))?;
+        self.0.push(Op {
+            uuid: Some(uuid.to_string()),
+            label: entry.label(),
+            change,
+        });
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Apply all changes of the plan, or none if one of them fails
+    pub fn apply(&mut self, plan: Plan) -> Result<()> {
+        let mut changed = self.subset(|_| true)?;
+        for op in plan.0 {
+            changed.apply_op(op)?;
+        }
+        *self = changed;
+        Ok(())
+    }
+
+    /// Apply one change, the others of its plan may already be applied if it fails
+    pub(super) fn apply_op(&mut self, op: Op) -> Result<()> {
+        let position = match (&op.change, &op.uuid) {
+            (Change::AddEntry { .. } | Change::AddGroup { .. }, _) => None,
+            (_, Some(uuid)) => Some(self.position(uuid)?),
+            (_, None) => return Err(eyre!("{} has no UUID", op.label)),
+        };
+        self.index = OnceLock::new();
+        match (op.change, position) {
+            (Change::AddEntry { entry, .. }, _) => self.entries.push(entry),
+            (Change::AddGroup { group }, _) => {
+                let mut groups = self.groups()?;
+                if !groups.iter().any(|known| known.uuid == group.uuid) {
+                    groups.push(group);
+                }
+                self.set_groups(&groups)?;
+            }
+            (Change::RemoveEntry, Some(position)) => {
+                self.entries.remove(position);
+            }
+            (change, Some(position)) => {
+                let entry = &mut self.entries[position];
+                match change {
+                    Change::SetIssuer { issuer } => entry.set_issuer(&issuer)?,
+                    Change::SetCounter { counter } => entry.set_counter(counter)?,
+                    Change::SetIcon { mime, data } => entry.set_icon(&mime, &data)?,
+                    Change::SetAttachments { attachments, .. } => {
+                        entry.set_attachments(&attachments)?
+                    }
+                    Change::AddEntry { .. } | Change::RemoveEntry | Change::AddGroup { .. } => {
+                        unreachable!()
+                    }
+                }
+            }
+            (_, None) => unreachable!("Changes of entries have a position"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Plan;
+    use crate::{
+        otp::{EntryInfo, EntryInfoTotp, HashAlgorithm},
+        vault::{NewEntry, Vault},
+    };
+
+    #[test]
+    fn apply_plans() {
+        let mut db = Vault::parse(include_str!("../../res/aegis_plain.json"))
+            .unwrap()
+            .unlock(|| unreachable!(), &Default::default())
+            .unwrap();
+        let mut plan = Plan::default();
+        let uuid = plan
+            .add_entry(
+                &db,
+                NewEntry {
+                    name: "Ada".to_string(),
+                    issuer: "Planned".to_string(),
+                    info: EntryInfo::Totp(EntryInfoTotp {
+                        secret: "JBSWY3DPEHPK3PXP".to_string(),
+                        algo: HashAlgorithm::Sha1,
+                        digits: 6,
+                        period: 30,
+                    }),
+                    icon: None,
+                },
+            )
+            .unwrap();
+        plan.set_issuer(&db.entries[0], "Deno Land").unwrap();
+        plan.set_counter(&db.entries[2], 10301).unwrap();
+        plan.remove_entry(&db.entries[1]).unwrap();
+        assert!(plan.set_counter(&db.entries[0], 1).is_err());
+
+        let ops = serde_json::to_value(&plan).unwrap();
+        assert_eq!(ops[0]["op"], "add_entry");
+        assert_eq!(ops[0]["uuid"], uuid);
+        assert_eq!(ops[0]["type"], "totp");
+        assert_eq!(ops[1]["op"], "set_issuer");
+        assert_eq!(ops[1]["entry"], "Deno (Mason)");
+        assert!(!ops.to_string().contains("JBSWY3DPEHPK3PXP"));
+        assert_eq!(
+            plan.ops()[1].to_string(),
+            "Rename Deno (Mason) to Deno Land"
+        );
+
+        // Planning leaves the database as it is
+        assert_eq!(db.entries.len(), 4);
+        db.apply(plan).unwrap();
+        assert_eq!(db.entries.len(), 4);
+        assert_eq!(db.entries[0].issuer(), "Deno Land");
+        assert_eq!(db.entries[1].issuer(), "WWE");
+        assert_eq!(db.entries[3].uuid(), Some(uuid.as_str()));
+
+        // A failing change leaves out the ones before it
+        let mut plan = Plan::default();
+        plan.set_issuer(&db.entries[0], "Deno").unwrap();
+        let removed = db.entries[1].clone();
+        plan.remove_entry(&removed).unwrap();
+        plan.remove_entry(&removed).unwrap();
+        assert!(db.apply(plan).is_err());
+        assert_eq!(db.entries.len(), 4);
+        assert_eq!(db.entries[0].issuer(), "Deno Land");
+    }
+}
@@ -0,0 +1,196 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+use zeroize::Zeroizing;
+
+use crate::vault::{
+    crypto::{decrypt_in_place, encrypt_in_place, KeyParams, MasterKey},
+    write_atomic,
+};
+
+/// Purpose of the key derived from the master key
+const KEY_PURPOSE: &str = "aegis-rs recovery codes";
+
+/// Backup code of a service, for when the OTP generator is lost
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RecoveryCode {
+    pub code: String,
+    /// Codes can only be used once
+    #[serde(default)]
+    pub used: bool,
+}
+
+/// Recovery codes by entry UUID
+///
+/// The Android app doesn't know about recovery codes, so they are kept in a file of their own
+/// instead of the vault. For encrypted vaults the file is encrypted with a key derived from the
+/// vault's master key, which stays the same across backups.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RecoveryCodes {
+    entries: BTreeMap<String, Vec<RecoveryCode>>,
+}
+
+/// Contents of a recovery codes file
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "encryption", rename_all = "snake_case")]
+enum RecoveryFile {
+    /// Codes of a plain vault
+    None { codes: RecoveryCodes },
+    Aes256Gcm {
+        /// Id of the master key the file key is derived from
+        key_id: String,
+        params: KeyParams,
+        /// Base64 encoded encrypted JSON of the codes
+        data: String,
+    },
+}
+
+impl RecoveryCodes {
+    /// Load codes from `path`, an empty set if it doesn't exist
+    ///
+    /// `master_key` is the key of the vault the codes belong to, `None` for plain vaults.
+    pub fn load(path: &Path, master_key: Option<&MasterKey>) -> Result<RecoveryCodes> {
+        if !path.exists() {
+            return Ok(RecoveryCodes::default());
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+        let file: RecoveryFile = serde_json::from_str(&contents)
+            .map_err(|e| eyre!("Failed to parse {}: {}", path.display(), e))?;
+
+        match (file, master_key) {
+            (RecoveryFile::None { codes }, None) => Ok(codes),
+            (
+                RecoveryFile::Aes256Gcm {
+                    key_id,
+                    params,
+                    data,
+                },
+                Some(master_key),
+            ) => {
+                if key_id != master_key.id() {
+                    return Err(eyre!("Recovery codes belong to another vault"));
+                }
+                let mut buffer = Zeroizing::new(general_purpose::STANDARD.decode(data)?);
+                if !decrypt_in_place(&master_key.derive(KEY_PURPOSE), &params, &mut buffer)? {
+                    return Err(eyre!("Failed to decrypt recovery codes"));
+                }
+                Ok(serde_json::from_slice(&buffer)?)
+            }
+            (RecoveryFile::None { .. }, Some(_)) => {
+                Err(eyre!("Recovery codes are not encrypted, but the vault is"))
+            }
+            (RecoveryFile::Aes256Gcm { .. }, None) => {
+                Err(eyre!("Recovery codes are encrypted, but the vault is not"))
+            }
+        }
+    }
+
+    /// Write the codes to `path`, encrypted if a master key is given
+    ///
+    /// The file is replaced atomically so an interrupted write can't lose the codes.
+    pub fn save(&self, path: &Path, master_key: Option<&MasterKey>) -> Result<()> {
+        let file = match master_key {
+            Some(master_key) => {
+                let mut buffer = Zeroizing::new(serde_json::to_vec(self)?);
+                let params = encrypt_in_place(&master_key.derive(KEY_PURPOSE), &mut buffer)?;
+                RecoveryFile::Aes256Gcm {
+                    key_id: master_key.id(),
+                    params,
+                    data: general_purpose::STANDARD.encode(buffer.as_slice()),
+                }
+            }
+            None => RecoveryFile::None {
+                codes: RecoveryCodes {
+                    entries: self.entries.clone(),
+                },
+            },
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(path, serde_json::to_string_pretty(&file)?.as_bytes())
+    }
+
+    /// Codes of the entry with `uuid`, in the order they were added
+    pub fn codes(&self, uuid: &str) -> &[RecoveryCode] {
+        self.entries
+            .get(uuid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Add unused codes to the entry with `uuid`, codes it already has are skipped
+    ///
+    /// Returns the number of added codes.
+    pub fn add(&mut self, uuid: &str, codes: impl IntoIterator<Item = String>) -> usize {
+        let entry = self.entries.entry(uuid.to_string()).or_default();
+        let before = entry.len();
+        for code in codes {
+            let code = code.trim().to_string();
+            if !code.is_empty() && !entry.iter().any(|c| c.code == code) {
+                entry.push(RecoveryCode { code, used: false });
+            }
+        }
+        entry.len() - before
+    }
+
+    /// Mark the first unused code of the entry with `uuid` as used and return it
+    pub fn use_next(&mut self, uuid: &str) -> Option<String> {
+        let code = self
+            .entries
+            .get_mut(uuid)?
+            .iter_mut()
+            .find(|code| !code.used)?;
+        code.used = true;
+        Some(code.code.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::RecoveryCodes;
+    use crate::vault::{DecryptOptions, PasswordGetter, Vault};
+
+    struct TestPassword;
+
+    impl PasswordGetter for TestPassword {
+        fn get_password(&self) -> color_eyre::eyre::Result<String> {
+            Ok("test".to_string())
+        }
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let vault = Vault::parse(include_str!("../../res/aegis_encrypted.json")).unwrap();
+        let (_, master_key) = vault
+            .unlock_with_key(TestPassword, &DecryptOptions::default())
+            .unwrap();
+        let master_key = master_key.unwrap();
+
+        let mut codes = RecoveryCodes::default();
+        let added = codes.add(
+            "uuid",
+            ["aaaa-1111", " bbbb-2222 ", "aaaa-1111", ""].map(String::from),
+        );
+        assert_eq!(added, 2);
+        assert_eq!(codes.use_next("uuid").as_deref(), Some("aaaa-1111"));
+
+        let path = env::temp_dir().join(format!("aegis-rs-recovery-{}.json", std::process::id()));
+        codes.save(&path, Some(&master_key)).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("bbbb-2222"));
+
+        let mut loaded = RecoveryCodes::load(&path, Some(&master_key)).unwrap();
+        assert_eq!(loaded.codes("uuid").len(), 2);
+        assert!(loaded.codes("uuid")[0].used);
+        assert_eq!(loaded.use_next("uuid").as_deref(), Some("bbbb-2222"));
+        assert_eq!(loaded.use_next("uuid"), None);
+        assert!(RecoveryCodes::load(&path, None).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
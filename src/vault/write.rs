@@ -0,0 +1,268 @@
+use base64::{engine::general_purpose, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{json, value::RawValue, Map, Value};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
+
+use crate::vault::{crypto, Database, DecryptOptions, MasterKey, PasswordGetter, Vault};
+
+/// Vault field recording what wrote the vault last
+///
+/// Aegis ignores fields it doesn't know and leaves it out when it exports the vault again, so a
+/// vault without it was last written by the app.
+pub const PROVENANCE_FIELD: &str = "aegis_rs_provenance";
+
+/// Tool which wrote a vault, and when
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Provenance {
+    pub tool: String,
+    pub version: String,
+    /// Seconds since the Unix epoch
+    pub written_at: u64,
+}
+
+impl Provenance {
+    fn now() -> Provenance {
+        Provenance {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            written_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Fields of a JSON object as they are found, in their original order
+///
+/// Used to write objects back without losing fields this crate doesn't know about.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RawFields(Vec<(String, Box<RawValue>)>);
+
+impl RawFields {
+    /// Remove the field `key` and return its value
+    pub(crate) fn take(&mut self, key: &str) -> Option<Box<RawValue>> {
+        let position = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(position).1)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &RawValue)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_ref()))
+    }
+
+    /// Replace the value of `key`, appending it if missing
+    pub(crate) fn set(&mut self, key: &str, value: Box<RawValue>) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = value,
+            None => self.0.push((key.to_string(), value)),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&RawValue> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, value)| value)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawFields {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = RawFields;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a JSON object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<RawFields, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(RawFields(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+impl Serialize for RawFields {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Vault JSON with `db` in place of the original database
+///
+/// `vault_backup_contents` is the vault `db` was read from and `master_key` its key, for
+/// encrypted vaults the database is encrypted again under a new nonce. Everything else, like the
+/// key slots, is written back as it was, except for the [`Provenance`].
+pub fn write_vault(
+    vault_backup_contents: &str,
+    db: &Database,
+    master_key: Option<&MasterKey>,
+) -> Result<String> {
+    let mut vault: RawFields = serde_json::from_str(vault_backup_contents)?;
+    let encrypted = vault
+        .get("db")
+        .is_some_and(|db| db.get().trim_start().starts_with('"'));
+
+    let db_json = Zeroizing::new(serde_json::to_vec(db)?);
+    match (encrypted, master_key) {
+        (true, Some(master_key)) => {
+            let mut buffer = db_json;
+            let params = crypto::encrypt_in_place(master_key.key(), &mut buffer)?;
+            let mut header: Map<String, Value> = match vault.get("header") {
+                Some(header) => serde_json::from_str(header.get())?,
+                None => return Err(eyre!("No header in vault")),
+            };
+            header.insert("params".to_string(), serde_json::to_value(params)?);
+            vault.set("header", to_raw(&header)?);
+            vault.set(
+                "db",
+                to_raw(&general_purpose::STANDARD.encode(buffer.as_slice()))?,
+            );
+        }
+        (true, None) => {
+            return Err(eyre!(
+                "The master key is needed to write an encrypted vault"
+            ))
+        }
+        (false, _) => {
+            let db_json = String::from_utf8(db_json.to_vec())?;
+            vault.set("db", RawValue::from_string(db_json)?);
+        }
+    }
+    vault.set(PROVENANCE_FIELD, to_raw(&Provenance::now())?);
+
+    Ok(serde_json::to_string(&vault)?)
+}
+
+/// New encrypted vault without entries, unlocked by `password`
+pub fn create_vault(password: &str, options: &DecryptOptions) -> Result<String> {
+    let db: Database = serde_json::from_str(r#"{"version": 2, "entries": []}"#)?;
+    create_vault_with(password, &db, options)
+}
+
+/// New encrypted vault of `db`, with a new master key unlocked by `password`
+pub fn create_vault_with(
+    password: &str,
+    db: &Database,
+    options: &DecryptOptions,
+) -> Result<String> {
+    let master_key = MasterKey::generate();
+    let slot = crypto::new_password_slot(password, &master_key, options)?;
+    let vault = json!({
+        "version": 1,
+        "header": { "slots": [slot], "params": null },
+        "db": "",
+    });
+    write_vault(&vault.to_string(), db, Some(&master_key))
+}
+
+/// Vault JSON of `db` under a new master key, unlocked by `password` only
+///
+/// All slots are replaced by a single password slot with a new salt and the database is
+/// encrypted under a new nonce. Returns the new key as well, data derived from the old one like
+/// recovery codes has to be moved to it.
+pub fn rekey_vault(
+    vault_backup_contents: &str,
+    db: &Database,
+    password: &str,
+    options: &DecryptOptions,
+) -> Result<(String, MasterKey)> {
+    let mut vault: RawFields = serde_json::from_str(vault_backup_contents)?;
+    let mut header: Map<String, Value> = match vault.get("header") {
+        Some(header) => serde_json::from_str(header.get())?,
+        None => return Err(eyre!("No header in vault")),
+    };
+    if !header.get("slots").is_some_and(Value::is_array) {
+        return Err(eyre!("No slots in header, the vault is not encrypted"));
+    }
+    let master_key = MasterKey::generate();
+    let slot = crypto::new_password_slot(password, &master_key, options)?;
+    header.insert("slots".to_string(), json!([slot]));
+    vault.set("header", to_raw(&header)?);
+    let contents = write_vault(&serde_json::to_string(&vault)?, db, Some(&master_key))?;
+    Ok((contents, master_key))
+}
+
+/// Encrypted copy of a vault under a new master key and `new_password`, for rotating the
+/// password of backups without the app
+///
+/// Encrypted vaults are unlocked with the old password first, which has to differ from the new
+/// one. Plain vaults get encrypted. Like [rekey_vault], the other slots are left out.
+pub fn reencrypt_vault(
+    vault_backup_contents: &str,
+    old_password: impl PasswordGetter,
+    new_password: &str,
+    options: &DecryptOptions,
+) -> Result<String> {
+    let vault = Vault::parse(vault_backup_contents)?;
+    if !vault.is_encrypted() {
+        let db = vault.unlock(|| Err(eyre!("The vault is not encrypted")), options)?;
+        return create_vault_with(new_password, &db, options);
+    }
+    let old_password = Zeroizing::new(old_password.get_password()?);
+    if *old_password == new_password {
+        return Err(eyre!("The new password is the same as the old one"));
+    }
+    let db = vault.unlock(|| Ok(old_password.to_string()), options)?;
+    Ok(rekey_vault(vault_backup_contents, &db, new_password, options)?.0)
+}
+
+/// Vault JSON with its password slots replaced by one for `password`
+///
+/// The master key stays the same, so other slots, data derived from the key like recovery codes
+/// and the database are unaffected.
+pub fn set_password(
+    vault_backup_contents: &str,
+    master_key: &MasterKey,
+    password: &str,
+    options: &DecryptOptions,
+) -> Result<String> {
+    let mut vault: RawFields = serde_json::from_str(vault_backup_contents)?;
+    let mut header: Map<String, Value> = match vault.get("header") {
+        Some(header) => serde_json::from_str(header.get())?,
+        None => return Err(eyre!("No header in vault")),
+    };
+    let mut slots = match header.remove("slots") {
+        Some(Value::Array(slots)) => slots,
+        _ => return Err(eyre!("No slots in header, the vault is not encrypted")),
+    };
+    slots.retain(|slot| slot.get("type") != Some(&json!(1)));
+    slots.push(crypto::new_password_slot(password, master_key, options)?);
+    header.insert("slots".to_string(), Value::Array(slots));
+    vault.set("header", to_raw(&header)?);
+    vault.set(PROVENANCE_FIELD, to_raw(&Provenance::now())?);
+    Ok(serde_json::to_string(&vault)?)
+}
+
+fn to_raw(value: &impl Serialize) -> Result<Box<RawValue>> {
+    Ok(RawValue::from_string(serde_json::to_string(value)?)?)
+}
@@ -0,0 +1,279 @@
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ffi::OsString, fs, path::Path};
+
+use crate::{
+    otp::{counter_at, EntryInfo, EntryInfoHotp, Generator},
+    vault::write_atomic,
+};
+
+/// Outcome of checking a submitted code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Verdict {
+    /// The code of `counter` is valid and was recorded
+    Accepted {
+        counter: u64,
+    },
+    /// The code is valid, but a code of the same or a later counter was accepted before
+    Replayed {
+        counter: u64,
+    },
+    Rejected,
+}
+
+/// Counters of the codes accepted last, by entry UUID
+///
+/// Kept by servers checking codes, so a code seen once is never accepted again, not even
+/// within its period.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct VerifyState {
+    accepted: BTreeMap<String, u64>,
+}
+
+/// Exclusive lock of a state file, held from loading the state until it is saved
+///
+/// Without it two logins at the same moment could both load the old counter and both accept the
+/// same code. The lock is taken on a file next to the state, as saving replaces the state file.
+/// Released when dropped, there is no locking on other platforms than unix.
+pub struct StateLock {
+    #[cfg(unix)]
+    _file: fs::File,
+}
+
+impl StateLock {
+    /// Wait for the lock of the state file at `path`
+    pub fn acquire(path: &Path) -> Result<StateLock> {
+        let mut lock_path = OsString::from(path);
+        lock_path.push(".lock");
+        #[cfg(unix)]
+        {
+            use std::os::unix::{fs::OpenOptionsExt, io::AsRawFd};
+
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .mode(0o600)
+                .open(&lock_path)
+                .map_err(|e| eyre!("Failed to open {}: {}", Path::new(&lock_path).display(), e))?;
+            // SAFETY: flock is called with a descriptor owned by `file`, which outlives the lock
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(eyre!(
+                    "Failed to lock {}: {}",
+                    Path::new(&lock_path).display(),
+                    std::io::Error::last_os_error()
+                ));
+            }
+            Ok(StateLock { _file: file })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = lock_path;
+            Ok(StateLock {})
+        }
+    }
+}
+
+impl VerifyState {
+    /// Lock the state file at `path` and load it, keep the lock until [VerifyState::save]
+    pub fn load_locked(path: &Path) -> Result<(VerifyState, StateLock)> {
+        let lock = StateLock::acquire(path)?;
+        Ok((VerifyState::load(path)?, lock))
+    }
+
+    /// Load the state from `path`, an empty one if it doesn't exist
+    pub fn load(path: &Path) -> Result<VerifyState> {
+        if !path.exists() {
+            return Ok(VerifyState::default());
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| eyre!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Counter of the code accepted last for the entry
+    pub fn last_accepted(&self, uuid: &str) -> Option<u64> {
+        self.accepted.get(uuid).copied()
+    }
+
+    /// Check `code` against the entry at `timestamp`, recording it if accepted
+    ///
+    /// Time based codes are looked for up to `skew` periods before and after the current one, for
+    /// clocks which are a bit off. HOTP codes are looked for in the `skew` counters after the
+    /// next one, for codes generated but never submitted. Only codes with a counter above the
+    /// one accepted last are accepted.
+    pub fn verify(
+        &mut self,
+        uuid: &str,
+        info: &EntryInfo,
+        code: &str,
+        timestamp: i64,
+        skew: u64,
+    ) -> Result<Verdict> {
+        let last = self.last_accepted(uuid);
+        let counters = match info {
+            EntryInfo::Hotp(hotp) => {
+                let next = hotp.counter.max(last.unwrap_or(0)).saturating_add(1);
+                next..=next.saturating_add(skew)
+            }
+            _ => {
+                let current = counter_at(info, timestamp)?;
+                current.saturating_sub(skew)..=current.saturating_add(skew)
+            }
+        };
+
+        let mut generator = Generator::default();
+        for counter in counters {
+            let expected = match info {
+                EntryInfo::Hotp(hotp) => generator.generate_at(
+                    &EntryInfo::Hotp(EntryInfoHotp {
+                        counter,
+                        ..hotp.clone()
+                    }),
+                    timestamp,
+                )?,
+                _ => generator.generate_at(info, i64::try_from(counter)? * period(info)?)?,
+            };
+            if !same_code(&expected, code) {
+                continue;
+            }
+            if last.is_some_and(|last| counter <= last) {
+                return Ok(Verdict::Replayed { counter });
+            }
+            self.accepted.insert(uuid.to_string(), counter);
+            return Ok(Verdict::Accepted { counter });
+        }
+        Ok(Verdict::Rejected)
+    }
+}
+
+/// Period of time based codes in seconds
+fn period(info: &EntryInfo) -> Result<i64> {
+    let period = match info {
+        EntryInfo::Totp(info) => info.period,
+        EntryInfo::Steam(info) => info.period,
+        EntryInfo::Yandex(info) => info.period,
+        EntryInfo::Motp(info) => info.period,
+        EntryInfo::Hotp(_) => return Err(eyre!("HOTP codes have no period")),
+    };
+    Ok(period.into())
+}
+
+/// Compare codes in constant time, so the time taken doesn't tell how much of a guess is right
+fn same_code(expected: &str, code: &str) -> bool {
+    let (expected, code) = (expected.as_bytes(), code.trim().as_bytes());
+    expected.len() == code.len()
+        && expected
+            .iter()
+            .zip(code)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, thread, time::Duration};
+
+    use super::{Verdict, VerifyState};
+    use crate::otp::{EntryInfo, EntryInfoHotp, EntryInfoTotp, HashAlgorithm};
+
+    const SEED: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn verify_codes() {
+        let totp = EntryInfo::Totp(EntryInfoTotp {
+            secret: SEED.to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 8,
+            period: 30,
+        });
+        let mut state = VerifyState::default();
+        assert_eq!(
+            state.verify("t", &totp, "94287082", 59, 1).unwrap(),
+            Verdict::Accepted { counter: 1 }
+        );
+        assert_eq!(
+            state.verify("t", &totp, " 94287082\n", 70, 1).unwrap(),
+            Verdict::Replayed { counter: 1 }
+        );
+        assert_eq!(
+            state.verify("t", &totp, "94287082", 70, 0).unwrap(),
+            Verdict::Rejected
+        );
+        assert_eq!(
+            state.verify("other", &totp, "94287082", 89, 1).unwrap(),
+            Verdict::Accepted { counter: 1 }
+        );
+        assert_eq!(
+            state.verify("t", &totp, "9428708", 59, 1).unwrap(),
+            Verdict::Rejected
+        );
+
+        let hotp = EntryInfo::Hotp(EntryInfoHotp {
+            secret: SEED.to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 6,
+            counter: 0,
+        });
+        assert_eq!(
+            state.verify("h", &hotp, "969429", 0, 2).unwrap(),
+            Verdict::Accepted { counter: 3 }
+        );
+        assert_eq!(
+            state.verify("h", &hotp, "338314", 0, 0).unwrap(),
+            Verdict::Accepted { counter: 4 }
+        );
+        assert_eq!(
+            state.verify("h", &hotp, "287082", 0, 10).unwrap(),
+            Verdict::Rejected
+        );
+
+        let state: VerifyState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(state.last_accepted("t"), Some(1));
+        assert_eq!(state.last_accepted("h"), Some(4));
+    }
+
+    #[test]
+    fn concurrent_logins() {
+        let totp = EntryInfo::Totp(EntryInfoTotp {
+            secret: SEED.to_string(),
+            algo: HashAlgorithm::Sha1,
+            digits: 8,
+            period: 30,
+        });
+        let path = env::temp_dir().join(format!("aegis-rs-verify-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // The second login loads the state while the first one holds it, before it is saved
+        let (mut first, lock) = VerifyState::load_locked(&path).unwrap();
+        let second = {
+            let (path, totp) = (path.clone(), totp.clone());
+            thread::spawn(move || {
+                let (mut state, _lock) = VerifyState::load_locked(&path).unwrap();
+                let verdict = state.verify("t", &totp, "94287082", 59, 1).unwrap();
+                state.save(&path).unwrap();
+                verdict
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            first.verify("t", &totp, "94287082", 59, 1).unwrap(),
+            Verdict::Accepted { counter: 1 }
+        );
+        first.save(&path).unwrap();
+        drop(lock);
+        assert_eq!(second.join().unwrap(), Verdict::Replayed { counter: 1 });
+
+        let _ = fs::remove_file(&path);
+        let mut lock_path = path.into_os_string();
+        lock_path.push(".lock");
+        let _ = fs::remove_file(lock_path);
+    }
+}